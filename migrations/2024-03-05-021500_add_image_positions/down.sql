@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE book_images DROP COLUMN position;
+ALTER TABLE album_images DROP COLUMN position;
+ALTER TABLE map_images DROP COLUMN position;
+ALTER TABLE map_pack_images DROP COLUMN position;
+ALTER TABLE stl_images DROP COLUMN position;
+ALTER TABLE token_pack_images DROP COLUMN position;
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+
+ALTER TABLE book_images ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE album_images ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE map_images ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE map_pack_images ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE stl_images ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE token_pack_images ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+
+ALTER TABLE users ADD COLUMN password_hash VARCHAR(255);
+
+CREATE TABLE sessions (
+session_key VARCHAR(64) PRIMARY KEY,
+state TEXT NOT NULL,
+expires_at BIGINT NOT NULL
+);
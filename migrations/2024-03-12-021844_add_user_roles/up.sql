@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+ALTER TABLE users ADD COLUMN role VARCHAR(20) NOT NULL DEFAULT 'customer';
+
+UPDATE users SET role = 'creator' WHERE id IN (SELECT id FROM creators);
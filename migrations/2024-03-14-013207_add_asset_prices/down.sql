@@ -0,0 +1,18 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE asset_prices;
+
+ALTER TABLE token_packs DROP COLUMN currency;
+ALTER TABLE token_packs DROP COLUMN price;
+
+ALTER TABLE stls DROP COLUMN currency;
+ALTER TABLE stls DROP COLUMN price;
+
+ALTER TABLE map_packs DROP COLUMN currency;
+ALTER TABLE map_packs DROP COLUMN price;
+
+ALTER TABLE albums DROP COLUMN currency;
+ALTER TABLE albums DROP COLUMN price;
+
+ALTER TABLE books DROP COLUMN currency;
+ALTER TABLE books DROP COLUMN price;
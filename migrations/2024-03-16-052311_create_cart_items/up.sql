@@ -0,0 +1,13 @@
+-- Your SQL goes here
+
+CREATE TABLE cart_items (
+  id SERIAL PRIMARY KEY,
+  user_id INTEGER,
+  FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE,
+  guest_key VARCHAR(64),
+  asset_type VARCHAR(20) NOT NULL,
+  asset_id INTEGER NOT NULL,
+  CHECK (user_id IS NOT NULL OR guest_key IS NOT NULL),
+  UNIQUE(user_id, asset_type, asset_id),
+  UNIQUE(guest_key, asset_type, asset_id)
+);
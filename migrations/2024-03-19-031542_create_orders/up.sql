@@ -0,0 +1,21 @@
+-- Your SQL goes here
+
+CREATE TABLE orders (
+  id SERIAL PRIMARY KEY,
+  user_id INTEGER NOT NULL,
+  FOREIGN KEY(user_id) REFERENCES users(id),
+  status VARCHAR(20) NOT NULL DEFAULT 'pending',
+  total INTEGER NOT NULL CHECK (total >= 0),
+  currency VARCHAR(3) NOT NULL,
+  payment_reference VARCHAR(100),
+  created_at BIGINT NOT NULL
+);
+
+CREATE TABLE order_items (
+  id SERIAL PRIMARY KEY,
+  order_id INTEGER NOT NULL,
+  FOREIGN KEY(order_id) REFERENCES orders(id) ON DELETE CASCADE,
+  asset_type VARCHAR(20) NOT NULL,
+  asset_id INTEGER NOT NULL,
+  amount INTEGER NOT NULL CHECK (amount >= 0)
+);
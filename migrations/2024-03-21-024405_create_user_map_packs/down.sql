@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE maps DROP COLUMN is_free;
+
+DROP TABLE user_map_packs;
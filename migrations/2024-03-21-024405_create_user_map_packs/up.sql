@@ -0,0 +1,11 @@
+-- Your SQL goes here
+
+CREATE TABLE user_map_packs (
+  user_id INTEGER NOT NULL,
+  map_pack_id INTEGER NOT NULL,
+  FOREIGN KEY(user_id) REFERENCES users(id),
+  FOREIGN KEY(map_pack_id) REFERENCES map_packs(id),
+  PRIMARY KEY(user_id, map_pack_id)
+);
+
+ALTER TABLE maps ADD COLUMN is_free BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE tokens DROP CONSTRAINT tokens_price_currency;
+ALTER TABLE tokens DROP COLUMN currency;
+ALTER TABLE tokens DROP COLUMN price;
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+
+-- A token without a price is only sold as part of its pack
+ALTER TABLE tokens ADD COLUMN price INTEGER CHECK (price >= 0);
+ALTER TABLE tokens ADD COLUMN currency VARCHAR(3);
+ALTER TABLE tokens ADD CONSTRAINT tokens_price_currency CHECK ((price IS NULL) = (currency IS NULL));
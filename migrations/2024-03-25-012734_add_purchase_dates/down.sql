@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE user_tokens DROP COLUMN purchased_at;
+ALTER TABLE user_token_packs DROP COLUMN purchased_at;
+ALTER TABLE user_stls DROP COLUMN purchased_at;
+ALTER TABLE user_map_packs DROP COLUMN purchased_at;
+ALTER TABLE user_maps DROP COLUMN purchased_at;
+ALTER TABLE user_albums DROP COLUMN purchased_at;
+ALTER TABLE user_books DROP COLUMN purchased_at;
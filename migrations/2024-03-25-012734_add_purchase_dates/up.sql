@@ -0,0 +1,10 @@
+-- Your SQL goes here
+
+-- unix seconds, rows granted before this migration count as bought now
+ALTER TABLE user_books ADD COLUMN purchased_at BIGINT NOT NULL DEFAULT EXTRACT(EPOCH FROM now())::BIGINT;
+ALTER TABLE user_albums ADD COLUMN purchased_at BIGINT NOT NULL DEFAULT EXTRACT(EPOCH FROM now())::BIGINT;
+ALTER TABLE user_maps ADD COLUMN purchased_at BIGINT NOT NULL DEFAULT EXTRACT(EPOCH FROM now())::BIGINT;
+ALTER TABLE user_map_packs ADD COLUMN purchased_at BIGINT NOT NULL DEFAULT EXTRACT(EPOCH FROM now())::BIGINT;
+ALTER TABLE user_stls ADD COLUMN purchased_at BIGINT NOT NULL DEFAULT EXTRACT(EPOCH FROM now())::BIGINT;
+ALTER TABLE user_token_packs ADD COLUMN purchased_at BIGINT NOT NULL DEFAULT EXTRACT(EPOCH FROM now())::BIGINT;
+ALTER TABLE user_tokens ADD COLUMN purchased_at BIGINT NOT NULL DEFAULT EXTRACT(EPOCH FROM now())::BIGINT;
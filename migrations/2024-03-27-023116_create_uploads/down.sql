@@ -0,0 +1,18 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE books ALTER COLUMN thumb TYPE VARCHAR(35);
+ALTER TABLE books ALTER COLUMN file TYPE VARCHAR(50);
+ALTER TABLE books ALTER COLUMN main_image TYPE VARCHAR(50);
+ALTER TABLE stls ALTER COLUMN thumb TYPE VARCHAR(35);
+ALTER TABLE stls ALTER COLUMN file TYPE VARCHAR(50);
+ALTER TABLE stls ALTER COLUMN main_image TYPE VARCHAR(50);
+ALTER TABLE maps ALTER COLUMN thumb TYPE VARCHAR(35);
+ALTER TABLE maps ALTER COLUMN file TYPE VARCHAR(50);
+ALTER TABLE maps ALTER COLUMN main_image TYPE VARCHAR(50);
+ALTER TABLE tokens ALTER COLUMN thumb TYPE VARCHAR(35);
+ALTER TABLE tokens ALTER COLUMN file TYPE VARCHAR(50);
+ALTER TABLE tokens ALTER COLUMN main_image TYPE VARCHAR(50);
+ALTER TABLE tracks ALTER COLUMN file TYPE VARCHAR(50);
+ALTER TABLE tracks ALTER COLUMN main_image TYPE VARCHAR(50);
+
+DROP TABLE uploads;
@@ -0,0 +1,30 @@
+-- Your SQL goes here
+
+CREATE TABLE uploads (
+  id SERIAL PRIMARY KEY,
+  asset_type VARCHAR(20) NOT NULL,
+  asset_id INTEGER NOT NULL,
+  slot VARCHAR(20) NOT NULL,
+  file_key VARCHAR(100) NOT NULL,
+  content_type VARCHAR(100) NOT NULL,
+  size BIGINT NOT NULL CHECK (size >= 0),
+  sha256 VARCHAR(64) NOT NULL,
+  uploaded_at BIGINT NOT NULL,
+  UNIQUE(asset_type, asset_id, slot)
+);
+
+-- uploaded files are stored under keys longer than the hand written paths
+ALTER TABLE books ALTER COLUMN thumb TYPE VARCHAR(100);
+ALTER TABLE books ALTER COLUMN file TYPE VARCHAR(100);
+ALTER TABLE books ALTER COLUMN main_image TYPE VARCHAR(100);
+ALTER TABLE stls ALTER COLUMN thumb TYPE VARCHAR(100);
+ALTER TABLE stls ALTER COLUMN file TYPE VARCHAR(100);
+ALTER TABLE stls ALTER COLUMN main_image TYPE VARCHAR(100);
+ALTER TABLE maps ALTER COLUMN thumb TYPE VARCHAR(100);
+ALTER TABLE maps ALTER COLUMN file TYPE VARCHAR(100);
+ALTER TABLE maps ALTER COLUMN main_image TYPE VARCHAR(100);
+ALTER TABLE tokens ALTER COLUMN thumb TYPE VARCHAR(100);
+ALTER TABLE tokens ALTER COLUMN file TYPE VARCHAR(100);
+ALTER TABLE tokens ALTER COLUMN main_image TYPE VARCHAR(100);
+ALTER TABLE tracks ALTER COLUMN file TYPE VARCHAR(100);
+ALTER TABLE tracks ALTER COLUMN main_image TYPE VARCHAR(100);
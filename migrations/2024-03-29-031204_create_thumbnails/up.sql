@@ -0,0 +1,14 @@
+-- Your SQL goes here
+
+CREATE TABLE thumbnails (
+  id SERIAL PRIMARY KEY,
+  asset_type VARCHAR(20) NOT NULL,
+  asset_id INTEGER NOT NULL,
+  size INTEGER NOT NULL CHECK (size > 0),
+  format VARCHAR(10) NOT NULL,
+  file_key VARCHAR(100) NOT NULL,
+  width INTEGER NOT NULL,
+  height INTEGER NOT NULL,
+  created_at BIGINT NOT NULL,
+  UNIQUE(asset_type, asset_id, size, format)
+);
@@ -0,0 +1,15 @@
+-- Your SQL goes here
+
+CREATE TABLE stl_metadata (
+  id SERIAL PRIMARY KEY,
+  stl_id INTEGER NOT NULL UNIQUE,
+  FOREIGN KEY(stl_id) REFERENCES stls(id),
+  triangles BIGINT NOT NULL CHECK (triangles > 0),
+  width DOUBLE PRECISION NOT NULL,
+  depth DOUBLE PRECISION NOT NULL,
+  height DOUBLE PRECISION NOT NULL,
+  is_watertight BOOLEAN NOT NULL,
+  is_manifold BOOLEAN NOT NULL,
+  file_size BIGINT NOT NULL CHECK (file_size >= 0),
+  analyzed_at BIGINT NOT NULL
+);
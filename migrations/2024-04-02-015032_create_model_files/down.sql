@@ -0,0 +1,24 @@
+-- This file should undo anything in `up.sql`
+
+CREATE TABLE stl_metadata (
+  id SERIAL PRIMARY KEY,
+  stl_id INTEGER NOT NULL UNIQUE,
+  FOREIGN KEY(stl_id) REFERENCES stls(id),
+  triangles BIGINT NOT NULL CHECK (triangles > 0),
+  width DOUBLE PRECISION NOT NULL,
+  depth DOUBLE PRECISION NOT NULL,
+  height DOUBLE PRECISION NOT NULL,
+  is_watertight BOOLEAN NOT NULL,
+  is_manifold BOOLEAN NOT NULL,
+  file_size BIGINT NOT NULL CHECK (file_size >= 0),
+  analyzed_at BIGINT NOT NULL
+);
+
+INSERT INTO stl_metadata (stl_id, triangles, width, depth, height, is_watertight,
+  is_manifold, file_size, analyzed_at)
+SELECT DISTINCT ON (stl_id) stl_id, triangles, width, depth, height, is_watertight,
+  is_manifold, file_size, uploaded_at
+FROM model_files WHERE format = 'stl'
+ORDER BY stl_id, id;
+
+DROP TABLE model_files;
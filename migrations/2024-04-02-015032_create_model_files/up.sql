@@ -0,0 +1,31 @@
+-- Your SQL goes here
+
+CREATE TABLE model_files (
+  id SERIAL PRIMARY KEY,
+  stl_id INTEGER NOT NULL,
+  FOREIGN KEY(stl_id) REFERENCES stls(id),
+  format VARCHAR(10) NOT NULL,
+  label VARCHAR(100) NOT NULL,
+  is_supported BOOLEAN NOT NULL,
+  file_key VARCHAR(100) NOT NULL,
+  file_size BIGINT NOT NULL CHECK (file_size >= 0),
+  parts INTEGER NOT NULL CHECK (parts > 0),
+  triangles BIGINT NOT NULL CHECK (triangles > 0),
+  width DOUBLE PRECISION NOT NULL,
+  depth DOUBLE PRECISION NOT NULL,
+  height DOUBLE PRECISION NOT NULL,
+  is_watertight BOOLEAN NOT NULL,
+  is_manifold BOOLEAN NOT NULL,
+  uploaded_at BIGINT NOT NULL,
+  UNIQUE(stl_id, file_key)
+);
+
+-- the single measured file of each model becomes its first model file
+INSERT INTO model_files (stl_id, format, label, is_supported, file_key, file_size, parts,
+  triangles, width, depth, height, is_watertight, is_manifold, uploaded_at)
+SELECT stl_metadata.stl_id, 'stl', stls.title, FALSE, stls.file, stl_metadata.file_size, 1,
+  stl_metadata.triangles, stl_metadata.width, stl_metadata.depth, stl_metadata.height,
+  stl_metadata.is_watertight, stl_metadata.is_manifold, stl_metadata.analyzed_at
+FROM stl_metadata JOIN stls ON stls.id = stl_metadata.stl_id;
+
+DROP TABLE stl_metadata;
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+DELETE FROM stl_images WHERE is_preview;
+ALTER TABLE stl_images ALTER COLUMN file TYPE VARCHAR(50);
+ALTER TABLE stl_images DROP COLUMN is_preview;
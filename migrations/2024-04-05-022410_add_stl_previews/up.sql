@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+-- rendered previews sit in the gallery alongside the creator's own images
+ALTER TABLE stl_images ADD COLUMN is_preview BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE stl_images ALTER COLUMN file TYPE VARCHAR(100);
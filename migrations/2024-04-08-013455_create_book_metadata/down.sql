@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE book_contents;
+DROP TABLE book_fonts;
+DROP TABLE book_metadata;
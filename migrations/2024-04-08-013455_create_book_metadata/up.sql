@@ -0,0 +1,33 @@
+-- Your SQL goes here
+
+CREATE TABLE book_metadata (
+  id SERIAL PRIMARY KEY,
+  book_id INTEGER NOT NULL UNIQUE,
+  FOREIGN KEY(book_id) REFERENCES books(id),
+  page_count INTEGER NOT NULL CHECK (page_count > 0),
+  is_encrypted BOOLEAN NOT NULL,
+  fonts_embedded BOOLEAN NOT NULL,
+  file_size BIGINT NOT NULL CHECK (file_size >= 0),
+  analyzed_at BIGINT NOT NULL
+);
+
+CREATE TABLE book_fonts (
+  id SERIAL PRIMARY KEY,
+  book_id INTEGER NOT NULL,
+  FOREIGN KEY(book_id) REFERENCES books(id),
+  name VARCHAR(100) NOT NULL,
+  subtype VARCHAR(20) NOT NULL,
+  is_embedded BOOLEAN NOT NULL,
+  UNIQUE(book_id, name)
+);
+
+CREATE TABLE book_contents (
+  id SERIAL PRIMARY KEY,
+  book_id INTEGER NOT NULL,
+  FOREIGN KEY(book_id) REFERENCES books(id),
+  position INTEGER NOT NULL,
+  level INTEGER NOT NULL CHECK (level > 0),
+  title VARCHAR(280) NOT NULL,
+  page INTEGER NOT NULL CHECK (page > 0),
+  UNIQUE(book_id, position)
+);
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+
+-- one stamped copy of a book per customer, replaced when the book's file is
+CREATE TABLE watermarks (
+  id SERIAL PRIMARY KEY,
+  user_id INTEGER NOT NULL,
+  FOREIGN KEY(user_id) REFERENCES users(id),
+  book_id INTEGER NOT NULL,
+  FOREIGN KEY(book_id) REFERENCES books(id),
+  source_key VARCHAR(100) NOT NULL,
+  file_key VARCHAR(100) NOT NULL,
+  created_at BIGINT NOT NULL,
+  UNIQUE(user_id, book_id)
+);
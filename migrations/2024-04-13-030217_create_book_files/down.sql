@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+
+DELETE FROM watermarks WHERE format <> 'pdf';
+ALTER TABLE watermarks DROP CONSTRAINT watermarks_user_id_book_id_format_key;
+ALTER TABLE watermarks ADD CONSTRAINT watermarks_user_id_book_id_key UNIQUE(user_id, book_id);
+ALTER TABLE watermarks DROP COLUMN format;
+DROP TABLE book_chapters;
+DROP TABLE book_files;
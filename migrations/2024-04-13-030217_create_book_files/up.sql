@@ -0,0 +1,32 @@
+-- Your SQL goes here
+
+-- every format a book is sold in; the PDF is also the book's own file
+CREATE TABLE book_files (
+  id SERIAL PRIMARY KEY,
+  book_id INTEGER NOT NULL,
+  FOREIGN KEY(book_id) REFERENCES books(id),
+  format VARCHAR(20) NOT NULL,
+  file_key VARCHAR(100) NOT NULL,
+  file_size BIGINT NOT NULL,
+  -- as the EPUB's own metadata gives them
+  title VARCHAR(280),
+  author VARCHAR(280),
+  uploaded_at BIGINT NOT NULL,
+  UNIQUE(book_id, format)
+);
+
+-- the EPUB's table of contents, in reading order
+CREATE TABLE book_chapters (
+  id SERIAL PRIMARY KEY,
+  book_file_id INTEGER NOT NULL,
+  FOREIGN KEY(book_file_id) REFERENCES book_files(id),
+  position INTEGER NOT NULL,
+  level INTEGER NOT NULL,
+  title VARCHAR(280) NOT NULL,
+  UNIQUE(book_file_id, position)
+);
+
+-- the print friendly PDF is stamped for each customer too
+ALTER TABLE watermarks ADD COLUMN format VARCHAR(20) NOT NULL DEFAULT 'pdf';
+ALTER TABLE watermarks DROP CONSTRAINT watermarks_user_id_book_id_key;
+ALTER TABLE watermarks ADD CONSTRAINT watermarks_user_id_book_id_format_key UNIQUE(user_id, book_id, format);
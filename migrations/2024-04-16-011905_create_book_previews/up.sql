@@ -0,0 +1,14 @@
+-- Your SQL goes here
+
+-- the pages of a book anyone may read before buying it, cut from its PDF
+CREATE TABLE book_previews (
+  id SERIAL PRIMARY KEY,
+  book_id INTEGER NOT NULL UNIQUE,
+  FOREIGN KEY(book_id) REFERENCES books(id),
+  first_page INTEGER NOT NULL,
+  last_page INTEGER NOT NULL,
+  source_key VARCHAR(100) NOT NULL,
+  file_key VARCHAR(100) NOT NULL,
+  created_at BIGINT NOT NULL,
+  CHECK (first_page >= 1 AND last_page >= first_page)
+);
@@ -0,0 +1,21 @@
+-- Your SQL goes here
+
+-- what a track's audio file says about itself, read when it is uploaded
+CREATE TABLE track_metadata (
+  id SERIAL PRIMARY KEY,
+  track_id INTEGER NOT NULL UNIQUE,
+  FOREIGN KEY(track_id) REFERENCES tracks(id),
+  format VARCHAR(10) NOT NULL,
+  duration_ms BIGINT NOT NULL,
+  bitrate INTEGER NOT NULL,
+  sample_rate INTEGER NOT NULL,
+  channels INTEGER NOT NULL,
+  file_size BIGINT NOT NULL,
+  title VARCHAR(280),
+  artist VARCHAR(280),
+  album VARCHAR(280),
+  genre VARCHAR(100),
+  date VARCHAR(30),
+  track_number INTEGER,
+  analyzed_at BIGINT NOT NULL
+);
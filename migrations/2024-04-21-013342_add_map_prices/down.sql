@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE maps DROP CONSTRAINT maps_price_currency;
+ALTER TABLE maps DROP COLUMN currency;
+ALTER TABLE maps DROP COLUMN price;
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+
+-- A map without a price is only sold as part of its pack
+ALTER TABLE maps ADD COLUMN price INTEGER CHECK (price >= 0);
+ALTER TABLE maps ADD COLUMN currency VARCHAR(3);
+ALTER TABLE maps ADD CONSTRAINT maps_price_currency CHECK ((price IS NULL) = (currency IS NULL));
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+-- Registration relies on these to turn away names that are already taken
+ALTER TABLE users ADD CONSTRAINT users_username_key UNIQUE (username);
+ALTER TABLE users ADD CONSTRAINT users_email_key UNIQUE (email);
@@ -1,11 +1,17 @@
+use super::audio::{AlbumDetails, TrackMetadata};
 use super::creator::Creator;
+use super::images::albums::AlbumImage;
 use super::ownership::albums::UserAlbum;
+use super::prices::AssetPrice;
 use crate::schema::albums;
 use crate::schema::tracks;
-use crate::types::asset::{Asset, AssetType, Ownership, Page, Summary};
+use crate::types::asset::{Asset, AssetType, Details, Ownership, Page, Summary};
+use crate::types::error::AppError;
+use crate::types::price::{Price, Pricing};
 use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Queryable, Selectable, AsChangeset)]
+#[derive(Queryable, Selectable, Serialize, Deserialize)]
 #[diesel(table_name = tracks)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct Track {
@@ -13,7 +19,9 @@ pub struct Track {
     pub creator_id: i32,
     pub album_id: i32,
     pub title: String,
+    #[serde(skip_deserializing)]
     pub file: String,
+    #[serde(skip_deserializing)]
     pub main_image: Option<String>,
 }
 
@@ -27,7 +35,7 @@ pub struct TrackCreate {
     pub main_image: String,
 }
 
-#[derive(Queryable, Selectable, Identifiable, AsChangeset)]
+#[derive(Queryable, Selectable, Identifiable, Serialize)]
 #[diesel(table_name = albums)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct AlbumQuery {
@@ -39,33 +47,47 @@ pub struct AlbumQuery {
     pub directory: String,
     pub is_free: bool,
     pub main_image: String,
+    pub price: i32,
+    pub currency: String,
 }
 
-#[derive(Insertable)]
+#[derive(Insertable, Deserialize)]
 #[diesel(table_name = albums)]
 pub struct AlbumCreate {
     pub creator_id: i32,
     pub title: String,
+    #[serde(skip_deserializing)]
     pub thumb: String,
     pub summary: String,
+    #[serde(skip_deserializing)]
     pub directory: String,
     pub is_free: bool,
+    #[serde(skip_deserializing)]
     pub main_image: String,
+    pub price: i32,
+    pub currency: String,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Album {
     pub id: i32,
     pub creator_id: i32,
     pub title: String,
+    #[serde(skip_deserializing)]
     pub thumb: String,
     pub summary: String,
+    #[serde(skip_deserializing)]
     pub directory: String,
     pub is_free: bool,
+    #[serde(skip_deserializing)]
     pub main_image: String,
+    pub price: i32,
+    pub currency: String,
     pub tracks: Vec<Track>,
 }
 
 impl AlbumCreate {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         creator_id: i32,
         title: String,
@@ -74,6 +96,7 @@ impl AlbumCreate {
         directory: String,
         is_free: bool,
         main_image: String,
+        price: Price,
     ) -> Self {
         AlbumCreate {
             creator_id,
@@ -83,15 +106,20 @@ impl AlbumCreate {
             directory,
             is_free,
             main_image,
+            price: price.amount,
+            currency: String::from(price.currency.store()),
         }
     }
 
-    pub fn create(&self, conn: &mut PgConnection) -> AlbumQuery {
-        diesel::insert_into(albums::table)
+    pub fn create(&self, conn: &mut PgConnection) -> Result<AlbumQuery, AppError> {
+        Price::stored(self.price, &self.currency)?;
+
+        let album = diesel::insert_into(albums::table)
             .values(self)
             .returning(AlbumQuery::as_returning())
-            .get_result(conn)
-            .expect("Error saving Book")
+            .get_result(conn)?;
+
+        Ok(album)
     }
 }
 
@@ -114,28 +142,28 @@ impl TrackCreate {
         }
     }
 
-    pub fn create(&self, conn: &mut PgConnection) -> Track {
-        diesel::insert_into(tracks::table)
+    pub fn create(&self, conn: &mut PgConnection) -> Result<Track, AppError> {
+        let track = diesel::insert_into(tracks::table)
             .values(self)
             .returning(Track::as_returning())
-            .get_result(conn)
-            .expect("Error saving Book")
+            .get_result(conn)?;
+
+        Ok(track)
     }
 }
 
 impl Asset for Album {
-    fn read(conn: &mut PgConnection, a_id: i32) -> Album {
+    fn read(conn: &mut PgConnection, a_id: i32) -> Result<Album, AppError> {
         use crate::schema::tracks::dsl::*;
 
-        let album = get_album(conn, a_id);
+        let album = get_album(conn, a_id)?;
 
         let track = tracks
             .filter(album_id.eq(a_id))
             .select(Track::as_select())
-            .get_results(conn)
-            .expect("Error loading posts");
+            .get_results(conn)?;
 
-        Album {
+        Ok(Album {
             id: album.id,
             creator_id: album.creator_id,
             title: album.title,
@@ -144,121 +172,183 @@ impl Asset for Album {
             directory: album.directory,
             is_free: album.is_free,
             main_image: album.main_image,
+            price: album.price,
+            currency: album.currency,
             tracks: track,
-        }
+        })
     }
 
-    fn destroy(conn: &mut PgConnection, a_id: i32) -> usize {
+    fn destroy(conn: &mut PgConnection, a_id: i32) -> Result<usize, AppError> {
         use crate::schema::tracks::dsl::*;
 
-        let changes = diesel::delete(tracks.filter(album_id.eq(a_id)))
-            .execute(conn)
-            .expect("Error deleting posts");
-
-        changes + destroy_album(conn, a_id)
+        conn.transaction(|conn| {
+            // buyers keep their copies, so a bought album is never removed
+            if UserAlbum::is_owned(conn, a_id)? {
+                return Err(AppError::Conflict(format!(
+                    "album {} has been bought",
+                    a_id
+                )));
+            }
+
+            let images = AlbumImage::destroy_all(conn, a_id)?;
+            let prices = AssetPrice::destroy_all(conn, &AssetType::Album, a_id)?;
+            let metadata = TrackMetadata::destroy_all(conn, a_id)?;
+            let changes = diesel::delete(tracks.filter(album_id.eq(a_id))).execute(conn)?;
+
+            Ok(images + prices + metadata + changes + destroy_album(conn, a_id)?)
+        })
     }
 
-    fn update(&self, conn: &mut PgConnection) -> usize {
+    fn update(&self, conn: &mut PgConnection) -> Result<usize, AppError> {
         use crate::schema::albums::dsl::*;
 
-        let album = AlbumQuery {
-            id: self.id,
-            creator_id: self.creator_id.to_owned(),
-            title: self.title.to_owned(),
-            thumb: self.thumb.to_owned(),
-            summary: self.summary.to_owned(),
-            directory: self.directory.to_owned(),
-            is_free: self.is_free,
-            main_image: self.main_image.to_owned(),
-        };
+        Price::stored(self.price, &self.currency)?;
 
         let changes = diesel::update(albums)
-            .filter(id.eq(album.id))
-            .set(album)
-            .execute(conn)
-            .expect("Failed to update user");
-
-        update_tracks(conn, &self.tracks) + changes
+            .filter(id.eq(self.id))
+            .set((
+                creator_id.eq(self.creator_id),
+                title.eq(&self.title),
+                summary.eq(&self.summary),
+                is_free.eq(self.is_free),
+                price.eq(self.price),
+                currency.eq(&self.currency),
+            ))
+            .execute(conn)?;
+
+        Ok(update_tracks(conn, &self.tracks)? + changes)
     }
 
-    fn summarize(&self, conn: &mut PgConnection, user_id: i32) -> Summary {
-        let (creator, user) = Creator::creator_with_user(conn, self.creator_id);
+    fn summarize(&self, conn: &mut PgConnection, user_id: i32) -> Result<Summary, AppError> {
+        let (creator, user) = Creator::creator_with_user(conn, self.creator_id)?;
         let asset_type = AssetType::Album;
         let display_name = creator.get_display_name();
-        let ownership = self.check_ownership(conn, user_id);
+        let ownership = self.check_ownership(conn, user_id)?;
+        let pricing = self.pricing(conn)?;
 
-        Summary {
+        Ok(Summary {
+            title: self.title.to_owned(),
             display_name,
             ownership,
             asset_type,
             logo: user.logo,
-        }
+            pricing,
+        })
     }
 
-    fn paginate(&self, conn: &mut PgConnection, user_id: i32) -> Page {
-        let (creator, user) = Creator::creator_with_user(conn, self.creator_id);
+    fn paginate(&self, conn: &mut PgConnection, user_id: i32) -> Result<Page, AppError> {
+        let (creator, user) = Creator::creator_with_user(conn, self.creator_id)?;
         let display_name = creator.get_display_name();
         let asset_type = AssetType::Album;
-        let extra_images = asset_type.images();
-        let ownership = self.check_ownership(conn, user_id);
+        let extra_images = asset_type.images(conn, self.id)?;
+        let ownership = self.check_ownership(conn, user_id)?;
+        let pricing = self.pricing(conn)?;
+        let details = AlbumDetails::read(conn, self.id)?.map(Details::Album);
 
-        Page {
+        Ok(Page {
             display_name,
             ownership,
             asset_type,
             logo: user.logo,
             extra_images,
-        }
+            pricing,
+            details,
+        })
     }
 
-    fn check_ownership(&self, conn: &mut PgConnection, user_id: i32) -> Ownership {
+    fn check_ownership(
+        &self,
+        conn: &mut PgConnection,
+        user_id: i32,
+    ) -> Result<Ownership, AppError> {
         if self.is_free {
-            Ownership::Free
+            Ok(Ownership::Free)
         } else {
             UserAlbum::check_ownership(conn, user_id, self.id)
         }
     }
 }
 
-fn get_album(conn: &mut PgConnection, album_id: i32) -> AlbumQuery {
+fn get_album(conn: &mut PgConnection, album_id: i32) -> Result<AlbumQuery, AppError> {
     use crate::schema::albums::dsl::*;
 
-    albums
+    let album = albums
         .filter(id.eq(album_id))
         .select(AlbumQuery::as_select())
-        .get_result(conn)
-        .expect("Error loading posts")
+        .get_result(conn)?;
+
+    Ok(album)
 }
 
-fn destroy_album(conn: &mut PgConnection, album_id: i32) -> usize {
+fn destroy_album(conn: &mut PgConnection, album_id: i32) -> Result<usize, AppError> {
     use crate::schema::albums::dsl::*;
 
-    diesel::delete(albums.filter(id.eq(album_id)))
-        .execute(conn)
-        .expect("Error deleting posts")
+    let changes = diesel::delete(albums.filter(id.eq(album_id))).execute(conn)?;
+
+    Ok(changes)
 }
 
-fn update_tracks(conn: &mut PgConnection, tracks_vec: &Vec<Track>) -> usize {
+fn update_tracks(conn: &mut PgConnection, tracks_vec: &Vec<Track>) -> Result<usize, AppError> {
     use crate::schema::tracks::dsl::*;
 
     let mut changes: usize = 0;
     for track in tracks_vec {
         let result = diesel::update(tracks)
             .filter(id.eq(track.id))
-            .set(track)
-            .execute(conn)
-            .expect("Failed to update user");
+            .set((
+                creator_id.eq(track.creator_id),
+                album_id.eq(track.album_id),
+                title.eq(&track.title),
+            ))
+            .execute(conn)?;
         changes += result;
     }
-    changes
+    Ok(changes)
+}
+
+impl Track {
+    pub fn read(conn: &mut PgConnection, t_id: i32) -> Result<Track, AppError> {
+        use crate::schema::tracks::dsl::*;
+
+        let track = tracks
+            .filter(id.eq(t_id))
+            .select(Track::as_select())
+            .get_result(conn)?;
+
+        Ok(track)
+    }
+
+    // Tracks are only sold with their album
+    pub fn check_ownership(
+        &self,
+        conn: &mut PgConnection,
+        user_id: i32,
+    ) -> Result<Ownership, AppError> {
+        let album = get_album(conn, self.album_id)?;
+
+        if album.is_free {
+            Ok(Ownership::Free)
+        } else {
+            UserAlbum::check_ownership(conn, user_id, album.id)
+        }
+    }
+}
+
+impl Album {
+    pub fn pricing(&self, conn: &mut PgConnection) -> Result<Pricing, AppError> {
+        let price = Price::stored(self.price, &self.currency)?;
+        AssetPrice::pricing(conn, &AssetType::Album, self.id, price)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::handlers::audio::{AudioFormat, AudioInfo, AudioTags};
     use crate::handlers::connect;
     use crate::handlers::creator::{CreatorNew, Creators};
     use crate::handlers::user::{User, UserNew};
+    use crate::types::price::Currency;
     use crate::types::user::DisplayName;
 
     #[test]
@@ -267,10 +357,11 @@ mod tests {
 
         let user = UserNew::create(
             conn,
-            String::from("naokotani"),
-            String::from("nao@gmail.com"),
+            String::from("album_full"),
+            String::from("album_full@gmail.com"),
             String::from("logo.svg"),
-        );
+        )
+        .unwrap();
 
         let creator = CreatorNew::create(
             conn,
@@ -280,7 +371,8 @@ mod tests {
             Some(String::from("frank")),
             Some(String::from("Random House")),
             DisplayName::Other,
-        );
+        )
+        .unwrap();
 
         let album = AlbumCreate::new(
             creator.id,
@@ -290,31 +382,59 @@ mod tests {
             String::from("directory/"),
             false,
             String::from("image.jpg"),
+            Price::new(999, Currency::Usd),
         )
-        .create(conn);
+        .create(conn)
+        .unwrap();
 
-        vec![TrackCreate::new(
+        let track = TrackCreate::new(
             creator.id,
             album.id,
             String::from("Doomsday"),
             &album.directory,
             String::from("track.jpg"),
         )
-        .create(conn)];
+        .create(conn)
+        .unwrap();
 
-        let album_full = Album::read(conn, album.id);
+        let album_full = Album::read(conn, album.id).unwrap();
 
         assert_eq!(album_full.tracks[0].title, "Doomsday");
 
-        let page = album_full.paginate(conn, user.id);
+        let page = album_full.paginate(conn, user.id).unwrap();
 
         assert_eq!(page.display_name, "frank");
+        assert_eq!(page.details, None);
+
+        let info = AudioInfo {
+            format: AudioFormat::Mp3,
+            duration_ms: 184000,
+            bitrate: 192000,
+            sample_rate: 44100,
+            channels: 2,
+            tags: AudioTags {
+                title: Some(String::from("Doomsday")),
+                ..AudioTags::default()
+            },
+        };
+        TrackMetadata::save(conn, track.id, &info, 4416000).unwrap();
+        let metadata = TrackMetadata::save(conn, track.id, &info, 4416000).unwrap();
+
+        let page = album_full.paginate(conn, user.id).unwrap();
+
+        assert_eq!(
+            page.details,
+            Some(Details::Album(AlbumDetails {
+                runtime_ms: 184000,
+                tracks: vec![metadata],
+            }))
+        );
 
-        let delete = Album::destroy(conn, album.id);
+        let delete = Album::destroy(conn, album.id).unwrap();
 
-        assert_eq!(delete, 2);
+        assert_eq!(delete, 3);
 
-        Creators::destroy(conn, creator.id);
-        User::destroy(conn, user.id);
+        Creators::destroy(conn, creator.id).unwrap();
+        User::destroy(conn, user.id).unwrap();
     }
 }
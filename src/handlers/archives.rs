@@ -0,0 +1,420 @@
+use super::album::Album;
+use super::entitlements::Downloadable;
+use super::map::MapPack;
+use super::models::ModelFile;
+use super::stl::Stl;
+use super::storage::StorageBackend;
+use super::tokens::TokenPack;
+use super::uploads::{FileSlot, Upload, UploadTarget};
+use crate::types::asset::Asset;
+use crate::types::error::AppError;
+use actix_web::web::Bytes;
+use diesel::prelude::*;
+use futures_util::Stream;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+// Files are copied into the archive this much at a time
+const CHUNK: u64 = 1024 * 1024;
+const LICENSE: &str = "These files are licensed to the purchaser for personal use, \
+including in their own games, streams and recordings. They may not be \
+redistributed, resold or shared, in whole or in part.";
+
+pub struct ArchiveEntry {
+    // path inside the archive
+    pub name: String,
+    pub key: String,
+}
+
+// A pack, album or multi-file model as a single ZIP: every item's file plus a README carrying
+// the license
+pub struct Archive {
+    pub kind: Downloadable,
+    pub id: i32,
+    pub filename: String,
+    pub readme: String,
+    pub entries: Vec<ArchiveEntry>,
+}
+
+// Built archives are kept in storage when ARCHIVE_CACHE is set
+#[derive(Clone, Copy)]
+pub struct ArchiveCache {
+    pub enabled: bool,
+}
+
+impl Archive {
+    pub fn read(conn: &mut PgConnection, kind: Downloadable, id: i32) -> Result<Archive, AppError> {
+        let (title, summary, directory, mut items) = match kind {
+            Downloadable::MapPack => {
+                let map_pack = MapPack::read(conn, id)?;
+                let items = map_pack.maps.into_iter().map(|map| (map.id, map.title));
+                let items = uploaded(conn, UploadTarget::Map, items.collect())?;
+                (map_pack.title, map_pack.summary, map_pack.directory, items)
+            }
+            Downloadable::TokenPack => {
+                let token_pack = TokenPack::read(conn, id)?;
+                let items = token_pack
+                    .tokens
+                    .into_iter()
+                    .map(|token| (token.id, token.title));
+                let items = uploaded(conn, UploadTarget::Token, items.collect())?;
+                (
+                    token_pack.title,
+                    token_pack.summary,
+                    token_pack.directory,
+                    items,
+                )
+            }
+            Downloadable::Album => {
+                let album = Album::read(conn, id)?;
+                let items = album
+                    .tracks
+                    .into_iter()
+                    .map(|track| (track.id, track.title));
+                let items = uploaded(conn, UploadTarget::Track, items.collect())?;
+                (album.title, album.summary, album.directory, items)
+            }
+            // variants are kept apart by whether they come with supports
+            Downloadable::Stl => {
+                let stl = Stl::read(conn, id)?;
+                let items = ModelFile::list(conn, id)?
+                    .into_iter()
+                    .map(|model_file| {
+                        let variant = match model_file.is_supported {
+                            true => "supported",
+                            false => "unsupported",
+                        };
+                        (
+                            model_file.id,
+                            format!("{}/{}", variant, slug(&model_file.label)),
+                            model_file.file_key,
+                        )
+                    })
+                    .collect();
+                (stl.title, stl.summary, String::new(), items)
+            }
+            _ => {
+                return Err(AppError::Validation(format!(
+                    "{} is not downloaded as an archive",
+                    kind.store()
+                )))
+            }
+        };
+
+        items.sort_by_key(|item| item.0);
+        let folder = Some(slug(&directory))
+            .filter(|folder| !folder.is_empty())
+            .unwrap_or_else(|| slug(&title));
+
+        let mut taken = HashSet::from([format!("{}/README.txt", folder)]);
+        let entries = items
+            .into_iter()
+            .enumerate()
+            .map(|(position, (_, item_title, key))| {
+                let stem = match kind {
+                    // tracks keep their running order
+                    Downloadable::Album => format!("{:02}-{}", position + 1, slug(&item_title)),
+                    Downloadable::Stl => item_title,
+                    _ => slug(&item_title),
+                };
+                let extension = key
+                    .rsplit('/')
+                    .next()
+                    .and_then(|name| name.rsplit_once('.'))
+                    .map_or(String::new(), |(_, extension)| format!(".{}", extension));
+
+                let mut name = format!("{}/{}{}", folder, stem, extension);
+                let mut copy = 1;
+                while !taken.insert(name.to_owned()) {
+                    copy += 1;
+                    name = format!("{}/{}-{}{}", folder, stem, copy, extension);
+                }
+
+                ArchiveEntry { name, key }
+            })
+            .collect::<Vec<ArchiveEntry>>();
+
+        let contents = entries
+            .iter()
+            .map(|entry| format!("- {}\n", entry.name))
+            .collect::<String>();
+        let readme = format!(
+            "{}\n{}\n\n{}\n\nContents\n{}\nLicense\n{}\n",
+            title,
+            "=".repeat(title.chars().count()),
+            summary,
+            contents,
+            LICENSE
+        );
+
+        Ok(Archive {
+            kind,
+            id,
+            filename: format!("{}.zip", folder),
+            readme,
+            entries,
+        })
+    }
+
+    // File keys change whenever their contents do, so hashing the keys and the
+    // README is enough to tell whether a cached archive is stale
+    pub fn digest(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.readme.as_bytes());
+        for entry in &self.entries {
+            hasher.update(format!("\n{}\0{}", entry.name, entry.key).as_bytes());
+        }
+        hex::encode(hasher.finalize())
+    }
+
+    pub fn cache_key(&self) -> String {
+        format!("archives/{}/{}.zip", self.kind.store(), self.id)
+    }
+
+    fn digest_key(&self) -> String {
+        format!("archives/{}/{}.sha256", self.kind.store(), self.id)
+    }
+
+    pub fn is_cached(&self, storage: &dyn StorageBackend) -> Result<bool, AppError> {
+        match storage.get(&self.digest_key()) {
+            Ok(digest) => Ok(digest == self.digest().as_bytes()),
+            Err(AppError::NotFound(_)) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    // The digest is written last so a half written cache is never served.
+    // The built archive is stored straight from its temporary file
+    pub fn store_cache(&self, storage: &dyn StorageBackend, copy: &Path) -> Result<(), AppError> {
+        storage.put_file(&self.cache_key(), copy)?;
+        storage.put(&self.digest_key(), self.digest().as_bytes())
+    }
+
+    // Files are stored rather than deflated; the maps, tokens and tracks
+    // inside are already compressed
+    pub fn write<W: Write>(&self, storage: &dyn StorageBackend, out: W) -> Result<W, AppError> {
+        let mut zip = ZipWriter::new_stream(out);
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        let folder = self.filename.trim_end_matches(".zip");
+
+        zip.start_file(format!("{}/README.txt", folder), options)
+            .map_err(zip_error)?;
+        zip.write_all(self.readme.as_bytes())?;
+
+        for entry in &self.entries {
+            let size = storage.size(&entry.key)?;
+            zip.start_file(&entry.name, options.large_file(size >= u32::MAX as u64))
+                .map_err(zip_error)?;
+
+            let mut start = 0;
+            while start < size {
+                let end = size.min(start + CHUNK);
+                zip.write_all(&storage.get_range(&entry.key, start, end)?)?;
+                start = end;
+            }
+        }
+
+        Ok(zip.finish().map_err(zip_error)?.into_inner())
+    }
+
+    // Builds the archive on a blocking thread, handing it over chunk by chunk;
+    // with the cache on a copy goes to a temporary file and is stored once the
+    // archive is complete
+    pub fn stream(
+        self,
+        storage: Arc<dyn StorageBackend>,
+        cache: ArchiveCache,
+    ) -> impl Stream<Item = Result<Bytes, io::Error>> {
+        let (sender, mut receiver) = mpsc::channel(4);
+
+        actix_web::rt::task::spawn_blocking(move || {
+            let copy = env::temp_dir().join(format!(
+                "alembic-archive-{}-{}-{}.zip",
+                self.kind.store(),
+                self.id,
+                rand::random::<u32>()
+            ));
+            let built = self.build(&*storage, sender.clone(), cache.enabled.then_some(&copy));
+
+            if let Err(err) = built {
+                let _ = sender.blocking_send(Err(io::Error::other(err.to_string())));
+            }
+            let _ = fs::remove_file(copy);
+        });
+
+        futures_util::stream::poll_fn(move |cx| receiver.poll_recv(cx))
+    }
+
+    fn build(
+        &self,
+        storage: &dyn StorageBackend,
+        sender: mpsc::Sender<Result<Bytes, io::Error>>,
+        copy: Option<&PathBuf>,
+    ) -> Result<(), AppError> {
+        let sink = ArchiveSink {
+            sender,
+            buffer: Vec::with_capacity(CHUNK as usize),
+            copy: copy.map(File::create).transpose()?,
+        };
+
+        let mut sink = self.write(storage, sink)?;
+        sink.flush()?;
+
+        if let Some(copy) = copy {
+            self.store_cache(storage, copy)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ArchiveCache {
+    pub fn from_env() -> Self {
+        ArchiveCache {
+            enabled: env::var("ARCHIVE_CACHE").is_ok_and(|cache| cache == "true" || cache == "1"),
+        }
+    }
+}
+
+struct ArchiveSink {
+    sender: mpsc::Sender<Result<Bytes, io::Error>>,
+    buffer: Vec<u8>,
+    copy: Option<File>,
+}
+
+impl Write for ArchiveSink {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        if let Some(copy) = &mut self.copy {
+            copy.write_all(bytes)?;
+        }
+        self.buffer.extend_from_slice(bytes);
+        if self.buffer.len() >= CHUNK as usize {
+            self.flush()?;
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(copy) = &mut self.copy {
+            copy.flush()?;
+        }
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        // the receiver is gone once the client disconnects
+        let chunk = Bytes::from(std::mem::take(&mut self.buffer));
+        self.sender
+            .blocking_send(Ok(chunk))
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+}
+
+// Pairs each item with the file uploaded for it; items without one are left out
+fn uploaded(
+    conn: &mut PgConnection,
+    target: UploadTarget,
+    items: Vec<(i32, String)>,
+) -> Result<Vec<(i32, String, String)>, AppError> {
+    let ids = items.iter().map(|item| item.0).collect::<Vec<i32>>();
+    let mut keys = Upload::keys(conn, target, &ids, FileSlot::File)?;
+
+    Ok(items
+        .into_iter()
+        .filter_map(|(id, title)| Some((id, title, keys.remove(&id)?)))
+        .collect())
+}
+
+fn slug(text: &str) -> String {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<&str>>()
+        .join("-")
+}
+
+fn zip_error(err: zip::result::ZipError) -> AppError {
+    AppError::Storage(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::storage::LocalStorage;
+    use std::io::{Cursor, Read};
+
+    #[test]
+    fn archive_contents() {
+        let root = env::temp_dir().join(format!("alembic-archives-{}", std::process::id()));
+        let storage = LocalStorage::new(&root);
+
+        storage.put("maps/1/file-aa.png", b"first map").unwrap();
+        storage.put("maps/2/file-bb.png", b"second map").unwrap();
+
+        let archive = Archive {
+            kind: Downloadable::MapPack,
+            id: 7,
+            filename: String::from("windy-glade.zip"),
+            readme: String::from("Windy Glade\n"),
+            entries: vec![
+                ArchiveEntry {
+                    name: String::from("windy-glade/clearing.png"),
+                    key: String::from("maps/1/file-aa.png"),
+                },
+                ArchiveEntry {
+                    name: String::from("windy-glade/clearing-2.png"),
+                    key: String::from("maps/2/file-bb.png"),
+                },
+            ],
+        };
+
+        let bytes = archive.write(&storage, Vec::new()).unwrap();
+        let mut zip = zip::ZipArchive::new(Cursor::new(bytes)).unwrap();
+
+        assert_eq!(
+            zip.file_names().collect::<HashSet<&str>>(),
+            HashSet::from([
+                "windy-glade/README.txt",
+                "windy-glade/clearing.png",
+                "windy-glade/clearing-2.png"
+            ])
+        );
+
+        let mut contents = String::new();
+        zip.by_name("windy-glade/clearing-2.png")
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+
+        assert_eq!(contents, "second map");
+
+        assert!(!archive.is_cached(&storage).unwrap());
+        let copy = root.join("built.zip");
+        fs::write(&copy, b"archive").unwrap();
+        archive.store_cache(&storage, &copy).unwrap();
+
+        assert_eq!(storage.get(&archive.cache_key()).unwrap(), b"archive");
+        assert!(archive.is_cached(&storage).unwrap());
+
+        let changed = Archive {
+            entries: vec![ArchiveEntry {
+                name: String::from("windy-glade/clearing.png"),
+                key: String::from("maps/2/file-cc.png"),
+            }],
+            ..archive
+        };
+
+        assert!(!changed.is_cached(&storage).unwrap());
+        assert_eq!(slug("  The Windy Glade: Night "), "the-windy-glade-night");
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
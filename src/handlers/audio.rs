@@ -0,0 +1,991 @@
+use crate::schema::{track_metadata, tracks};
+use crate::types::error::AppError;
+use crate::types::time::now;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+// How far into an MP3 a frame is looked for after its tag, so a file of
+// noise is refused without scanning all of it
+const MAX_SYNC_SEARCH: usize = 64 * 1024;
+// An Ogg stream's length comes from a 64 bit granule position; anything
+// claiming to run longer than this is taken to be corrupt
+const MAX_OGG_DURATION_MS: i64 = 24 * 60 * 60 * 1000;
+
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioFormat {
+    Mp3,
+    Ogg,
+    Flac,
+    Wav,
+}
+
+// What a track's file says about itself
+#[derive(PartialEq, Debug)]
+pub struct AudioInfo {
+    pub format: AudioFormat,
+    pub duration_ms: i64,
+    // bits per second, averaged over the file when it varies
+    pub bitrate: i32,
+    pub sample_rate: i32,
+    pub channels: i32,
+    pub tags: AudioTags,
+}
+
+// The ID3, Vorbis comment or RIFF INFO tags a file was published with
+#[derive(PartialEq, Debug, Default)]
+pub struct AudioTags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
+    pub date: Option<String>,
+    pub track_number: Option<i32>,
+}
+
+#[derive(Queryable, Selectable, Serialize, PartialEq, Debug)]
+#[diesel(table_name = track_metadata)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct TrackMetadata {
+    #[serde(skip)]
+    pub id: i32,
+    pub track_id: i32,
+    pub format: String,
+    pub duration_ms: i64,
+    pub bitrate: i32,
+    pub sample_rate: i32,
+    pub channels: i32,
+    pub file_size: i64,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
+    pub date: Option<String>,
+    pub track_number: Option<i32>,
+    // unix seconds
+    pub analyzed_at: i64,
+}
+
+#[derive(Insertable, AsChangeset)]
+#[diesel(table_name = track_metadata)]
+#[diesel(treat_none_as_null = true)]
+struct TrackMetadataNew {
+    track_id: i32,
+    format: String,
+    duration_ms: i64,
+    bitrate: i32,
+    sample_rate: i32,
+    channels: i32,
+    file_size: i64,
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    genre: Option<String>,
+    date: Option<String>,
+    track_number: Option<i32>,
+    analyzed_at: i64,
+}
+
+// Shown on the album's page so buyers can see how long it runs
+#[derive(Serialize, PartialEq, Debug)]
+pub struct AlbumDetails {
+    // tracks whose files have not been uploaded yet are not counted
+    pub runtime_ms: i64,
+    pub tracks: Vec<TrackMetadata>,
+}
+
+impl AudioFormat {
+    pub fn store(&self) -> &str {
+        match self {
+            Self::Mp3 => "mp3",
+            Self::Ogg => "ogg",
+            Self::Flac => "flac",
+            Self::Wav => "wav",
+        }
+    }
+
+    pub fn from_extension(extension: &str) -> Option<AudioFormat> {
+        match extension {
+            "mp3" => Some(Self::Mp3),
+            "ogg" => Some(Self::Ogg),
+            "flac" => Some(Self::Flac),
+            "wav" => Some(Self::Wav),
+            _ => None,
+        }
+    }
+}
+
+impl AudioInfo {
+    pub fn parse(format: AudioFormat, bytes: &[u8]) -> Result<AudioInfo, AppError> {
+        let info = match format {
+            AudioFormat::Mp3 => AudioInfo::parse_mp3(bytes)?,
+            AudioFormat::Ogg => AudioInfo::parse_ogg(bytes)?,
+            AudioFormat::Flac => AudioInfo::parse_flac(bytes)?,
+            AudioFormat::Wav => AudioInfo::parse_wav(bytes)?,
+        };
+
+        if info.duration_ms <= 0 || info.sample_rate <= 0 {
+            return Err(invalid("it has no audio"));
+        }
+
+        Ok(info)
+    }
+
+    // The first frame decides the stream's rate; its Xing or VBRI header,
+    // when there is one, counts the frames of a variable bitrate file
+    fn parse_mp3(bytes: &[u8]) -> Result<AudioInfo, AppError> {
+        let (mut tags, start) = match bytes.starts_with(b"ID3") {
+            true => id3v2(bytes).ok_or_else(|| invalid("its ID3 tag is malformed"))?,
+            false => (AudioTags::default(), 0),
+        };
+        let end = match bytes.len() >= 128 && bytes[bytes.len() - 128..].starts_with(b"TAG") {
+            true => {
+                tags.fill(id3v1(&bytes[bytes.len() - 128..]));
+                bytes.len() - 128
+            }
+            false => bytes.len(),
+        };
+
+        let (offset, frame) = (start..end.min(start + MAX_SYNC_SEARCH))
+            .find_map(|offset| {
+                let frame = MpegFrame::parse(bytes.get(offset..end)?)?;
+                // a lone sync pattern is too easily found in other data
+                match bytes.get(offset + frame.length..end) {
+                    Some(next) if next.len() >= 4 => {
+                        MpegFrame::parse(next).map(|_| (offset, frame))
+                    }
+                    _ => Some((offset, frame)),
+                }
+            })
+            .ok_or_else(|| invalid("it has no MPEG audio frames"))?;
+
+        let audio_bytes = (end - offset) as u64;
+        let duration_ms = match frame.counted_frames(&bytes[offset..end]) {
+            Some(frames) => frames * frame.samples as u64 * 1000 / frame.sample_rate as u64,
+            None => audio_bytes * 8 * 1000 / frame.bitrate as u64,
+        } as i64;
+        let bitrate = match duration_ms {
+            0 => frame.bitrate as i32,
+            duration => (audio_bytes as i64 * 8 * 1000 / duration) as i32,
+        };
+
+        Ok(AudioInfo {
+            format: AudioFormat::Mp3,
+            duration_ms,
+            bitrate,
+            sample_rate: frame.sample_rate as i32,
+            channels: frame.channels,
+            tags,
+        })
+    }
+
+    // Vorbis or Opus; the granule position of the stream's last page is its
+    // length in samples
+    fn parse_ogg(bytes: &[u8]) -> Result<AudioInfo, AppError> {
+        let pages = OggPages { bytes, offset: 0 }.collect::<Vec<OggPage>>();
+        let serial = pages
+            .first()
+            .map(|page| page.serial)
+            .ok_or_else(|| invalid("it has no Ogg pages"))?;
+        let stream = pages
+            .iter()
+            .filter(|page| page.serial == serial)
+            .collect::<Vec<&OggPage>>();
+
+        // the identification and comment headers are the stream's first
+        // packets, and the comments may run across pages
+        let mut packets: Vec<Vec<u8>> = Vec::new();
+        let mut packet = Vec::new();
+        'headers: for page in &stream {
+            let mut data = page.data;
+            for lace in page.lacing {
+                let (segment, rest) = data.split_at((*lace as usize).min(data.len()));
+                packet.extend_from_slice(segment);
+                data = rest;
+                if *lace < 255 {
+                    packets.push(std::mem::take(&mut packet));
+                    if packets.len() == 2 {
+                        break 'headers;
+                    }
+                }
+            }
+        }
+        let granule = stream
+            .iter()
+            .rev()
+            .map(|page| page.granule)
+            .find(|granule| *granule >= 0)
+            .unwrap_or(0);
+
+        let (head, comments) = match packets.as_slice() {
+            [head, comments] => (head, comments),
+            _ => return Err(invalid("its Ogg stream has no headers")),
+        };
+
+        let (sample_rate, channels, nominal, samples, tags) =
+            if head.starts_with(b"\x01vorbis") && head.len() >= 28 {
+                let sample_rate = u32_le(&head[12..16]);
+                let nominal = i32::from_le_bytes(head[20..24].try_into().unwrap_or_default());
+                let tags = comments
+                    .strip_prefix(b"\x03vorbis")
+                    .and_then(vorbis_comments)
+                    .unwrap_or_default();
+                (sample_rate, head[11], nominal, granule, tags)
+            } else if head.starts_with(b"OpusHead") && head.len() >= 19 {
+                // Opus always plays at 48kHz; the input rate is what it was made from
+                let pre_skip = u16::from_le_bytes([head[10], head[11]]) as i64;
+                let sample_rate = match u32_le(&head[12..16]) {
+                    0 => 48000,
+                    rate => rate,
+                };
+                let samples = (granule - pre_skip)
+                    .max(0)
+                    .checked_mul(sample_rate as i64)
+                    .ok_or_else(|| invalid("its Ogg stream is impossibly long"))?
+                    / 48000;
+                let tags = comments
+                    .strip_prefix(b"OpusTags")
+                    .and_then(vorbis_comments)
+                    .unwrap_or_default();
+                (sample_rate, head[9], 0, samples, tags)
+            } else {
+                return Err(invalid("its Ogg stream is neither Vorbis nor Opus"));
+            };
+
+        if sample_rate == 0 {
+            return Err(invalid("its Ogg stream has no sample rate"));
+        }
+        let duration_ms = samples
+            .checked_mul(1000)
+            .map(|total| total / sample_rate as i64)
+            .filter(|duration_ms| *duration_ms <= MAX_OGG_DURATION_MS)
+            .ok_or_else(|| invalid("its Ogg stream is impossibly long"))?;
+        let bitrate = match (nominal, duration_ms) {
+            (nominal, _) if nominal > 0 => nominal,
+            (_, 0) => 0,
+            (_, duration) => (bytes.len() as i64 * 8 * 1000 / duration) as i32,
+        };
+
+        Ok(AudioInfo {
+            format: AudioFormat::Ogg,
+            duration_ms,
+            bitrate,
+            sample_rate: sample_rate as i32,
+            channels: channels as i32,
+            tags,
+        })
+    }
+
+    fn parse_flac(bytes: &[u8]) -> Result<AudioInfo, AppError> {
+        // some taggers put an ID3 tag in front of the stream
+        let start = match bytes.starts_with(b"ID3") {
+            true => id3v2(bytes).map_or(0, |(_, end)| end),
+            false => 0,
+        };
+        let stream = bytes
+            .get(start..)
+            .filter(|stream| stream.starts_with(b"fLaC"))
+            .ok_or_else(|| invalid("it has no FLAC stream"))?;
+
+        let mut offset = 4;
+        let mut stream_info = None;
+        let mut tags = AudioTags::default();
+        while let Some(header) = stream.get(offset..offset + 4) {
+            let (is_last, kind) = (header[0] & 0x80 != 0, header[0] & 0x7f);
+            let length = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+            let block = stream
+                .get(offset + 4..offset + 4 + length)
+                .ok_or_else(|| invalid("a FLAC metadata block is cut short"))?;
+            match kind {
+                0 if block.len() >= 18 => stream_info = Some(block),
+                4 => tags = vorbis_comments(block).unwrap_or_default(),
+                _ => {}
+            }
+            offset += 4 + length;
+            if is_last {
+                break;
+            }
+        }
+
+        let info = stream_info.ok_or_else(|| invalid("it has no FLAC stream info"))?;
+        let sample_rate = (info[10] as u32) << 12 | (info[11] as u32) << 4 | (info[12] as u32) >> 4;
+        let channels = ((info[12] >> 1) & 0x07) as i32 + 1;
+        let samples = ((info[13] & 0x0f) as u64) << 32
+            | u32::from_be_bytes(info[14..18].try_into().unwrap_or_default()) as u64;
+
+        if sample_rate == 0 {
+            return Err(invalid("its FLAC stream has no sample rate"));
+        }
+        let duration_ms = (samples * 1000 / sample_rate as u64) as i64;
+        let audio_bytes = stream.len().saturating_sub(offset) as i64;
+        let bitrate = match duration_ms {
+            0 => 0,
+            duration => (audio_bytes * 8 * 1000 / duration) as i32,
+        };
+
+        Ok(AudioInfo {
+            format: AudioFormat::Flac,
+            duration_ms,
+            bitrate,
+            sample_rate: sample_rate as i32,
+            channels,
+            tags,
+        })
+    }
+
+    fn parse_wav(bytes: &[u8]) -> Result<AudioInfo, AppError> {
+        if !(bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WAVE")) {
+            return Err(invalid("it is not a RIFF WAVE file"));
+        }
+
+        let mut offset = 12;
+        let mut format = None;
+        let mut data_size = None;
+        let mut tags = AudioTags::default();
+        while let Some(header) = bytes.get(offset..offset + 8) {
+            let size = u32_le(&header[4..8]) as usize;
+            // a streamed file may not know its data's size; the rest of the
+            // file is taken to be the data
+            let body = &bytes[offset + 8..(offset + 8).saturating_add(size).min(bytes.len())];
+            match &header[..4] {
+                b"fmt " if body.len() >= 16 => format = Some(body),
+                b"data" => data_size = Some(body.len()),
+                b"LIST" if body.starts_with(b"INFO") => tags = riff_info(&body[4..]),
+                _ => {}
+            }
+            // chunks are padded to an even length
+            offset = match (offset + 8).checked_add(size + size % 2) {
+                Some(next) => next,
+                None => break,
+            };
+        }
+
+        let format = format.ok_or_else(|| invalid("it has no fmt chunk"))?;
+        let data_size = data_size.ok_or_else(|| invalid("it has no data chunk"))?;
+        let channels = u16::from_le_bytes([format[2], format[3]]) as i32;
+        let sample_rate = u32_le(&format[4..8]);
+        let byte_rate = u32_le(&format[8..12]);
+
+        if byte_rate == 0 {
+            return Err(invalid("its fmt chunk has no byte rate"));
+        }
+
+        Ok(AudioInfo {
+            format: AudioFormat::Wav,
+            duration_ms: (data_size as u64 * 1000 / byte_rate as u64) as i64,
+            bitrate: (byte_rate as u64 * 8).min(i32::MAX as u64) as i32,
+            sample_rate: sample_rate as i32,
+            channels,
+            tags,
+        })
+    }
+}
+
+impl AudioTags {
+    // Keeps what is already known, taking the rest from a lesser tag
+    fn fill(&mut self, other: AudioTags) {
+        self.title = self.title.take().or(other.title);
+        self.artist = self.artist.take().or(other.artist);
+        self.album = self.album.take().or(other.album);
+        self.genre = self.genre.take().or(other.genre);
+        self.date = self.date.take().or(other.date);
+        self.track_number = self.track_number.or(other.track_number);
+    }
+
+    // Field names as ID3, Vorbis comments and RIFF INFO spell them
+    fn set(&mut self, name: &str, value: &str) {
+        let value = value.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+        if value.is_empty() {
+            return;
+        }
+        let text = |length: usize| Some(value.chars().take(length).collect::<String>());
+
+        match name.to_ascii_uppercase().as_str() {
+            "TITLE" | "TIT2" | "TT2" | "INAM" if self.title.is_none() => self.title = text(280),
+            "ARTIST" | "TPE1" | "TP1" | "IART" if self.artist.is_none() => self.artist = text(280),
+            "ALBUM" | "TALB" | "TAL" | "IPRD" if self.album.is_none() => self.album = text(280),
+            "GENRE" | "TCON" | "TCO" | "IGNR" if self.genre.is_none() => self.genre = text(100),
+            "DATE" | "TDRC" | "TYER" | "TYE" | "ICRD" if self.date.is_none() => {
+                self.date = text(30)
+            }
+            // written as "3" or "3/12"
+            "TRACKNUMBER" | "TRCK" | "TRK" | "ITRK" if self.track_number.is_none() => {
+                self.track_number = value.split('/').next().and_then(|n| n.trim().parse().ok())
+            }
+            _ => {}
+        }
+    }
+}
+
+impl TrackMetadata {
+    // Replaces everything known about the track's file
+    pub fn save(
+        conn: &mut PgConnection,
+        t_id: i32,
+        info: &AudioInfo,
+        size: i64,
+    ) -> Result<TrackMetadata, AppError> {
+        let row = TrackMetadataNew {
+            track_id: t_id,
+            format: String::from(info.format.store()),
+            duration_ms: info.duration_ms,
+            bitrate: info.bitrate,
+            sample_rate: info.sample_rate,
+            channels: info.channels,
+            file_size: size,
+            title: info.tags.title.to_owned(),
+            artist: info.tags.artist.to_owned(),
+            album: info.tags.album.to_owned(),
+            genre: info.tags.genre.to_owned(),
+            date: info.tags.date.to_owned(),
+            track_number: info.tags.track_number,
+            analyzed_at: now(),
+        };
+
+        let metadata = diesel::insert_into(track_metadata::table)
+            .values(&row)
+            .on_conflict(track_metadata::track_id)
+            .do_update()
+            .set(&row)
+            .returning(TrackMetadata::as_returning())
+            .get_result(conn)?;
+
+        Ok(metadata)
+    }
+
+    pub fn destroy_all(conn: &mut PgConnection, a_id: i32) -> Result<usize, AppError> {
+        let album_tracks = tracks::table
+            .filter(tracks::album_id.eq(a_id))
+            .select(tracks::id);
+        let changes = diesel::delete(
+            track_metadata::table.filter(track_metadata::track_id.eq_any(album_tracks)),
+        )
+        .execute(conn)?;
+
+        Ok(changes)
+    }
+}
+
+impl AlbumDetails {
+    // None until a track's file has been uploaded
+    pub fn read(conn: &mut PgConnection, a_id: i32) -> Result<Option<AlbumDetails>, AppError> {
+        let metadata = track_metadata::table
+            .inner_join(tracks::table)
+            .filter(tracks::album_id.eq(a_id))
+            .order(track_metadata::track_id.asc())
+            .select(TrackMetadata::as_select())
+            .get_results(conn)?;
+
+        if metadata.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(AlbumDetails {
+            runtime_ms: metadata.iter().map(|track| track.duration_ms).sum(),
+            tracks: metadata,
+        }))
+    }
+}
+
+// One MPEG audio frame header
+struct MpegFrame {
+    // bits per second
+    bitrate: u32,
+    sample_rate: u32,
+    channels: i32,
+    samples: u32,
+    length: usize,
+    // where a Xing header would sit, past the side information
+    xing_offset: usize,
+}
+
+impl MpegFrame {
+    fn parse(bytes: &[u8]) -> Option<MpegFrame> {
+        let header = bytes.get(..4)?;
+        if header[0] != 0xff || header[1] & 0xe0 != 0xe0 {
+            return None;
+        }
+
+        // 0 is MPEG 2.5, 2 MPEG 2 and 3 MPEG 1; layers count down from 3 for layer I
+        let version = (header[1] >> 3) & 0x03;
+        let layer = (header[1] >> 1) & 0x03;
+        let bitrate_index = (header[2] >> 4) as usize;
+        let rate_index = ((header[2] >> 2) & 0x03) as usize;
+        let padding = ((header[2] >> 1) & 0x01) as usize;
+        let is_mono = header[3] >> 6 == 3;
+
+        if version == 1
+            || layer == 0
+            || bitrate_index == 0
+            || bitrate_index == 15
+            || rate_index == 3
+        {
+            return None;
+        }
+
+        let is_mpeg1 = version == 3;
+        let kbps: [u32; 14] = match (is_mpeg1, layer) {
+            (true, 3) => [
+                32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+            ],
+            (true, 2) => [
+                32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+            ],
+            (true, _) => [
+                32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+            ],
+            (false, 3) => [
+                32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+            ],
+            (false, _) => [8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+        };
+        let rates: [u32; 3] = match version {
+            3 => [44100, 48000, 32000],
+            2 => [22050, 24000, 16000],
+            _ => [11025, 12000, 8000],
+        };
+        let bitrate = kbps[bitrate_index - 1] * 1000;
+        let sample_rate = rates[rate_index];
+
+        let (samples, length) = match layer {
+            3 => (384, (12 * bitrate / sample_rate) as usize * 4 + padding * 4),
+            2 => (1152, (144 * bitrate / sample_rate) as usize + padding),
+            _ if is_mpeg1 => (1152, (144 * bitrate / sample_rate) as usize + padding),
+            _ => (576, (72 * bitrate / sample_rate) as usize + padding),
+        };
+        let side_info = match (is_mpeg1, is_mono) {
+            (true, true) => 17,
+            (true, false) => 32,
+            (false, true) => 9,
+            (false, false) => 17,
+        };
+
+        Some(MpegFrame {
+            bitrate,
+            sample_rate,
+            channels: if is_mono { 1 } else { 2 },
+            samples,
+            length,
+            xing_offset: 4 + side_info,
+        })
+    }
+
+    // The number of frames an encoder wrote into the first frame, if it did
+    fn counted_frames(&self, frame: &[u8]) -> Option<u64> {
+        if let Some(xing) = frame.get(self.xing_offset..self.xing_offset + 12) {
+            if (xing.starts_with(b"Xing") || xing.starts_with(b"Info")) && xing[7] & 0x01 != 0 {
+                return Some(u32::from_be_bytes(xing[8..12].try_into().ok()?) as u64);
+            }
+        }
+        let vbri = frame.get(36..54)?;
+        match vbri.starts_with(b"VBRI") {
+            true => Some(u32::from_be_bytes(vbri[14..18].try_into().ok()?) as u64),
+            false => None,
+        }
+    }
+}
+
+struct OggPage<'a> {
+    granule: i64,
+    serial: u32,
+    lacing: &'a [u8],
+    data: &'a [u8],
+}
+
+struct OggPages<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Iterator for OggPages<'a> {
+    type Item = OggPage<'a>;
+
+    fn next(&mut self) -> Option<OggPage<'a>> {
+        let header = self.bytes.get(self.offset..self.offset + 27)?;
+        if !header.starts_with(b"OggS") {
+            return None;
+        }
+        let segments = header[26] as usize;
+        let lacing = self
+            .bytes
+            .get(self.offset + 27..self.offset + 27 + segments)?;
+        let length = lacing.iter().map(|lace| *lace as usize).sum::<usize>();
+        let start = self.offset + 27 + segments;
+        let data = self.bytes.get(start..start + length)?;
+
+        self.offset = start + length;
+
+        Some(OggPage {
+            granule: i64::from_le_bytes(header[6..14].try_into().ok()?),
+            serial: u32_le(&header[14..18]),
+            lacing,
+            data,
+        })
+    }
+}
+
+// The tags of an ID3v2 tag, and where the audio after it begins
+fn id3v2(bytes: &[u8]) -> Option<(AudioTags, usize)> {
+    let header = bytes.get(..10)?;
+    let version = header[3];
+    let flags = header[5];
+    let size = syncsafe(&header[6..10]) as usize;
+    let end = 10 + size + if flags & 0x10 != 0 { 10 } else { 0 };
+    let tag = bytes.get(10..(10 + size).min(bytes.len()))?;
+
+    let mut offset = 0;
+    if flags & 0x40 != 0 && version >= 3 {
+        let extended = tag.get(..4)?;
+        offset = match version {
+            3 => 4 + u32::from_be_bytes(extended.try_into().ok()?) as usize,
+            _ => syncsafe(extended) as usize,
+        };
+    }
+
+    let mut tags = AudioTags::default();
+    let header_length = if version == 2 { 6 } else { 10 };
+    while let Some(frame) = tag.get(offset..offset + header_length) {
+        if frame[0] == 0 {
+            // padding
+            break;
+        }
+        let (id, length) = match version {
+            2 => (
+                &frame[..3],
+                u32::from_be_bytes([0, frame[3], frame[4], frame[5]]),
+            ),
+            3 => (
+                &frame[..4],
+                u32::from_be_bytes(frame[4..8].try_into().ok()?),
+            ),
+            _ => (&frame[..4], syncsafe(&frame[4..8])),
+        };
+        let body = tag.get(offset + header_length..offset + header_length + length as usize)?;
+        if id.starts_with(b"T") {
+            if let (Ok(id), Some(text)) = (std::str::from_utf8(id), id3_text(body)) {
+                tags.set(id, &text);
+            }
+        }
+        offset += header_length + length as usize;
+    }
+
+    Some((tags, end.min(bytes.len())))
+}
+
+// A text frame's first value, in whichever of ID3's encodings it was written
+fn id3_text(body: &[u8]) -> Option<String> {
+    let (encoding, text) = body.split_first()?;
+    let text = match encoding {
+        0 => text.iter().map(|byte| *byte as char).collect(),
+        1 | 2 => {
+            let (big_endian, text) = match text {
+                [0xfe, 0xff, rest @ ..] => (true, rest),
+                [0xff, 0xfe, rest @ ..] => (false, rest),
+                rest => (*encoding == 2, rest),
+            };
+            let units = text
+                .chunks_exact(2)
+                .map(|pair| match big_endian {
+                    true => u16::from_be_bytes([pair[0], pair[1]]),
+                    false => u16::from_le_bytes([pair[0], pair[1]]),
+                })
+                .collect::<Vec<u16>>();
+            String::from_utf16_lossy(&units)
+        }
+        _ => String::from_utf8_lossy(text).into_owned(),
+    };
+
+    text.split('\0').next().map(str::to_owned)
+}
+
+fn id3v1(tag: &[u8]) -> AudioTags {
+    let field = |range: std::ops::Range<usize>| {
+        tag[range]
+            .iter()
+            .take_while(|byte| **byte != 0)
+            .map(|byte| *byte as char)
+            .collect::<String>()
+    };
+
+    let mut tags = AudioTags::default();
+    tags.set("TITLE", &field(3..33));
+    tags.set("ARTIST", &field(33..63));
+    tags.set("ALBUM", &field(63..93));
+    tags.set("DATE", &field(93..97));
+    // ID3v1.1 keeps the track number at the end of the comment
+    if tag[125] == 0 && tag[126] != 0 {
+        tags.track_number = Some(tag[126] as i32);
+    }
+    tags
+}
+
+// A Vorbis comment block, as Ogg and FLAC both carry it
+fn vorbis_comments(block: &[u8]) -> Option<AudioTags> {
+    let vendor = u32_le(block.get(..4)?) as usize;
+    let mut offset = 4 + vendor;
+    let count = u32_le(block.get(offset..offset + 4)?);
+    offset += 4;
+
+    let mut tags = AudioTags::default();
+    for _ in 0..count {
+        let length = u32_le(block.get(offset..offset + 4)?) as usize;
+        let comment = block.get(offset + 4..offset + 4 + length)?;
+        if let Some((name, value)) = String::from_utf8_lossy(comment).split_once('=') {
+            tags.set(name, value);
+        }
+        offset += 4 + length;
+    }
+
+    Some(tags)
+}
+
+fn riff_info(list: &[u8]) -> AudioTags {
+    let mut tags = AudioTags::default();
+    let mut offset = 0;
+    while let Some(header) = list.get(offset..offset + 8) {
+        let size = u32_le(&header[4..8]) as usize;
+        let Some(value) = list.get(offset + 8..offset + 8 + size) else {
+            break;
+        };
+        if let Ok(name) = std::str::from_utf8(&header[..4]) {
+            tags.set(name, &String::from_utf8_lossy(value));
+        }
+        offset += 8 + size + size % 2;
+    }
+    tags
+}
+
+fn syncsafe(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .fold(0, |size, byte| size << 7 | (*byte & 0x7f) as u32)
+}
+
+fn u32_le(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes.try_into().unwrap_or_default())
+}
+
+fn invalid(reason: &str) -> AppError {
+    AppError::Validation(format!("not a usable audio file: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comments(fields: &[&str]) -> Vec<u8> {
+        let mut block = Vec::new();
+        block.extend_from_slice(&6u32.to_le_bytes());
+        block.extend_from_slice(b"tester");
+        block.extend_from_slice(&(fields.len() as u32).to_le_bytes());
+        for field in fields {
+            block.extend_from_slice(&(field.len() as u32).to_le_bytes());
+            block.extend_from_slice(field.as_bytes());
+        }
+        block
+    }
+
+    // Two seconds of 8kHz mono 16 bit silence
+    fn wav() -> Vec<u8> {
+        let mut info = b"INFO".to_vec();
+        for (name, value) in [(b"INAM", "Rain\0"), (b"IART", "Nao\0")] {
+            info.extend_from_slice(name);
+            info.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            info.extend_from_slice(value.as_bytes());
+            if value.len() % 2 == 1 {
+                info.push(0);
+            }
+        }
+
+        let mut body = b"WAVE".to_vec();
+        body.extend_from_slice(b"fmt ");
+        body.extend_from_slice(&16u32.to_le_bytes());
+        body.extend_from_slice(&1u16.to_le_bytes());
+        body.extend_from_slice(&1u16.to_le_bytes());
+        body.extend_from_slice(&8000u32.to_le_bytes());
+        body.extend_from_slice(&16000u32.to_le_bytes());
+        body.extend_from_slice(&2u16.to_le_bytes());
+        body.extend_from_slice(&16u16.to_le_bytes());
+        body.extend_from_slice(b"LIST");
+        body.extend_from_slice(&(info.len() as u32).to_le_bytes());
+        body.extend_from_slice(&info);
+        body.extend_from_slice(b"data");
+        body.extend_from_slice(&32000u32.to_le_bytes());
+        body.extend_from_slice(&[0; 32000]);
+
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+
+    // Three and a half seconds of 44.1kHz stereo
+    fn flac() -> Vec<u8> {
+        let samples: u64 = 154350;
+        let mut info = vec![0x10, 0x00, 0x10, 0x00, 0, 0, 0, 0, 0, 0];
+        info.push((44100 >> 12) as u8);
+        info.push((44100 >> 4) as u8);
+        info.push(((44100 & 0x0f) << 4) as u8 | (1 << 1));
+        info.push((15 << 4) | (samples >> 32) as u8);
+        info.extend_from_slice(&(samples as u32).to_be_bytes());
+        info.extend_from_slice(&[0; 16]);
+        let tags = comments(&["TITLE=Tavern", "tracknumber=4/9", "ALBUM=Inns"]);
+
+        let mut bytes = b"fLaC".to_vec();
+        bytes.extend_from_slice(&[0x00, 0, 0, 34]);
+        bytes.extend_from_slice(&info);
+        bytes.push(0x84);
+        bytes.extend_from_slice(&(tags.len() as u32).to_be_bytes()[1..]);
+        bytes.extend_from_slice(&tags);
+        bytes.extend_from_slice(&[0; 1000]);
+        bytes
+    }
+
+    fn ogg_page(granule: i64, packets: &[&[u8]]) -> Vec<u8> {
+        let mut lacing = Vec::new();
+        let mut data = Vec::new();
+        for packet in packets {
+            lacing.extend(std::iter::repeat_n(255, packet.len() / 255));
+            lacing.push((packet.len() % 255) as u8);
+            data.extend_from_slice(packet);
+        }
+
+        let mut page = b"OggS\0\0".to_vec();
+        page.extend_from_slice(&granule.to_le_bytes());
+        page.extend_from_slice(&7u32.to_le_bytes());
+        page.extend_from_slice(&[0; 8]);
+        page.push(lacing.len() as u8);
+        page.extend_from_slice(&lacing);
+        page.extend_from_slice(&data);
+        page
+    }
+
+    // 48kHz Vorbis ending at the given granule, with a long comment
+    fn ogg(granule: i64) -> Vec<u8> {
+        let mut head = b"\x01vorbis".to_vec();
+        head.extend_from_slice(&0u32.to_le_bytes());
+        head.push(2);
+        head.extend_from_slice(&48000u32.to_le_bytes());
+        head.extend_from_slice(&0u32.to_le_bytes());
+        head.extend_from_slice(&160000i32.to_le_bytes());
+        head.extend_from_slice(&0u32.to_le_bytes());
+        head.extend_from_slice(&[0xb8, 0x01]);
+        let long = format!("ARTIST={}", "Nao ".repeat(100));
+        let mut tags = b"\x03vorbis".to_vec();
+        tags.extend_from_slice(&comments(&[&long, "GENRE=Ambient"]));
+
+        let mut bytes = ogg_page(0, &[&head]);
+        bytes.extend_from_slice(&ogg_page(0, &[&tags]));
+        bytes.extend_from_slice(&ogg_page(48000 * 60, &[&[0; 100]]));
+        bytes.extend_from_slice(&ogg_page(granule, &[&[0; 100]]));
+        bytes
+    }
+
+    // 128kbps 44.1kHz stereo layer III frames behind an ID3v2.3 tag
+    fn mp3(frames: usize, xing: Option<u32>) -> Vec<u8> {
+        let mut tag = Vec::new();
+        for (id, text) in [(b"TIT2", "Storm"), (b"TRCK", "2/10")] {
+            tag.extend_from_slice(id);
+            tag.extend_from_slice(&(text.len() as u32 + 1).to_be_bytes());
+            tag.extend_from_slice(&[0, 0, 3]);
+            tag.extend_from_slice(text.as_bytes());
+        }
+        // an artist in UTF-16
+        let artist = [0xff, 0xfe, b'N', 0, b'a', 0, b'o', 0];
+        tag.extend_from_slice(b"TPE1");
+        tag.extend_from_slice(&(artist.len() as u32 + 1).to_be_bytes());
+        tag.extend_from_slice(&[0, 0, 1]);
+        tag.extend_from_slice(&artist);
+        tag.extend_from_slice(&[0; 20]);
+
+        let mut bytes = b"ID3\x03\x00\x00".to_vec();
+        let size = tag.len() as u32;
+        bytes.extend_from_slice(&[
+            (size >> 21) as u8 & 0x7f,
+            (size >> 14) as u8 & 0x7f,
+            (size >> 7) as u8 & 0x7f,
+            size as u8 & 0x7f,
+        ]);
+        bytes.extend_from_slice(&tag);
+
+        for number in 0..frames {
+            let mut frame = vec![0; 417];
+            frame[..4].copy_from_slice(&[0xff, 0xfb, 0x90, 0x00]);
+            if let (0, Some(count)) = (number, xing) {
+                frame[36..40].copy_from_slice(b"Xing");
+                frame[43] = 0x01;
+                frame[44..48].copy_from_slice(&count.to_be_bytes());
+            }
+            bytes.extend_from_slice(&frame);
+        }
+        bytes
+    }
+
+    #[test]
+    fn audio_probing() {
+        let info = AudioInfo::parse(AudioFormat::Wav, &wav()).unwrap();
+
+        assert_eq!(
+            info,
+            AudioInfo {
+                format: AudioFormat::Wav,
+                duration_ms: 2000,
+                bitrate: 128000,
+                sample_rate: 8000,
+                channels: 1,
+                tags: AudioTags {
+                    title: Some(String::from("Rain")),
+                    artist: Some(String::from("Nao")),
+                    ..AudioTags::default()
+                },
+            }
+        );
+
+        let info = AudioInfo::parse(AudioFormat::Flac, &flac()).unwrap();
+
+        assert_eq!(info.duration_ms, 3500);
+        assert_eq!((info.sample_rate, info.channels), (44100, 2));
+        assert_eq!(info.tags.title.as_deref(), Some("Tavern"));
+        assert_eq!(info.tags.album.as_deref(), Some("Inns"));
+        assert_eq!(info.tags.track_number, Some(4));
+
+        let info = AudioInfo::parse(AudioFormat::Ogg, &ogg(48000 * 90)).unwrap();
+
+        assert_eq!(info.duration_ms, 90000);
+        assert_eq!(info.bitrate, 160000);
+        assert_eq!((info.sample_rate, info.channels), (48000, 2));
+        assert_eq!(info.tags.artist.unwrap().chars().count(), 280);
+        assert_eq!(info.tags.genre.as_deref(), Some("Ambient"));
+
+        // a constant bitrate file is measured by its length
+        let info = AudioInfo::parse(AudioFormat::Mp3, &mp3(100, None)).unwrap();
+
+        assert_eq!(info.duration_ms, 2606);
+        assert_eq!((info.sample_rate, info.channels), (44100, 2));
+        assert_eq!(info.tags.title.as_deref(), Some("Storm"));
+        assert_eq!(info.tags.artist.as_deref(), Some("Nao"));
+        assert_eq!(info.tags.track_number, Some(2));
+
+        // and a variable one by the frame count its encoder left
+        let info = AudioInfo::parse(AudioFormat::Mp3, &mp3(10, Some(50))).unwrap();
+
+        assert_eq!(info.duration_ms, 1306);
+
+        for (format, bytes) in [
+            (
+                AudioFormat::Mp3,
+                b"ID3\x03\x00\x00\x00\x00\x00\x00 not audio".to_vec(),
+            ),
+            (AudioFormat::Ogg, b"OggS".to_vec()),
+            (AudioFormat::Ogg, ogg(i64::MAX)),
+            (AudioFormat::Ogg, ogg(48000 * 60 * 60 * 25)),
+            (AudioFormat::Flac, b"fLaC\x80\x00\x00\x02".to_vec()),
+            (AudioFormat::Wav, b"RIFF\x04\x00\x00\x00WAVE".to_vec()),
+        ] {
+            assert!(matches!(
+                AudioInfo::parse(format, &bytes),
+                Err(AppError::Validation(_))
+            ));
+        }
+    }
+}
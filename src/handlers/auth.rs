@@ -0,0 +1,169 @@
+use crate::handlers::user::User;
+use crate::schema::users;
+use crate::types::error::AppError;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+#[derive(Insertable)]
+#[diesel(table_name = users)]
+pub struct Registration {
+    pub username: String,
+    pub email: String,
+    pub logo: String,
+    pub password_hash: String,
+}
+
+impl Registration {
+    pub fn new(
+        username: String,
+        email: String,
+        logo: String,
+        password: &str,
+    ) -> Result<Self, AppError> {
+        if username.trim().is_empty() || !email.contains('@') {
+            return Err(AppError::Validation(String::from(
+                "a username and a valid email are required",
+            )));
+        }
+
+        let password_hash = hash_password(password)?;
+
+        Ok(Registration {
+            username,
+            email,
+            logo,
+            password_hash,
+        })
+    }
+
+    // Taken names are caught by the unique constraints, so two sign-ups
+    // racing for one name cannot both succeed
+    pub fn create(&self, conn: &mut PgConnection) -> Result<User, AppError> {
+        use crate::schema::users::dsl::*;
+
+        let user = diesel::insert_into(users)
+            .values(self)
+            .returning(User::as_returning())
+            .get_result(conn)
+            .map_err(|err| match err {
+                DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                    AppError::Validation(String::from("username or email is already registered"))
+                }
+                err => AppError::from(err),
+            })?;
+
+        Ok(user)
+    }
+}
+
+// Unknown users and wrong passwords are reported the same way
+pub fn authenticate(conn: &mut PgConnection, name: &str, password: &str) -> Result<User, AppError> {
+    use crate::schema::users::dsl::*;
+
+    let invalid = || AppError::Unauthorized(String::from("invalid username or password"));
+
+    let (user, hash) = users
+        .filter(username.eq(name))
+        .select((User::as_select(), password_hash))
+        .get_result::<(User, Option<String>)>(conn)
+        .optional()?
+        .ok_or_else(invalid)?;
+
+    let hash = hash.ok_or_else(invalid)?;
+    let parsed = PasswordHash::new(&hash).map_err(|_| invalid())?;
+
+    Argon2::default()
+        .verify_password(password.as_bytes(), &parsed)
+        .map_err(|_| invalid())?;
+
+    Ok(user)
+}
+
+pub fn hash_password(password: &str) -> Result<String, AppError> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(AppError::Validation(format!(
+            "password must be at least {} characters",
+            MIN_PASSWORD_LENGTH
+        )));
+    }
+
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|err| AppError::Validation(err.to_string()))?;
+
+    Ok(hash.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::connect;
+
+    #[test]
+    fn auth_full() {
+        let conn = &mut connect::establish_connection();
+
+        let short = Registration::new(
+            String::from("shorty"),
+            String::from("short@gmail.com"),
+            String::from("logo.svg"),
+            "short",
+        );
+
+        assert!(matches!(short, Err(AppError::Validation(_))));
+
+        let user = Registration::new(
+            String::from("auth_full"),
+            String::from("auth_full@gmail.com"),
+            String::from("logo.svg"),
+            "correct horse battery",
+        )
+        .unwrap()
+        .create(conn)
+        .unwrap();
+
+        assert_eq!(user.username, "auth_full");
+
+        let duplicate = Registration::new(
+            String::from("auth_full"),
+            String::from("other@gmail.com"),
+            String::from("logo.svg"),
+            "correct horse battery",
+        )
+        .unwrap()
+        .create(conn);
+
+        assert!(matches!(duplicate, Err(AppError::Validation(_))));
+
+        let duplicate = Registration::new(
+            String::from("other_auth_full"),
+            String::from("auth_full@gmail.com"),
+            String::from("logo.svg"),
+            "correct horse battery",
+        )
+        .unwrap()
+        .create(conn);
+
+        assert!(matches!(duplicate, Err(AppError::Validation(_))));
+
+        let login = authenticate(conn, "auth_full", "correct horse battery").unwrap();
+
+        assert_eq!(login.id, user.id);
+
+        let wrong = authenticate(conn, "auth_full", "incorrect horse battery");
+
+        assert!(matches!(wrong, Err(AppError::Unauthorized(_))));
+
+        let missing = authenticate(conn, "nobody_at_all", "correct horse battery");
+
+        assert!(matches!(missing, Err(AppError::Unauthorized(_))));
+
+        User::destroy(conn, user.id).unwrap();
+    }
+}
@@ -1,35 +1,54 @@
 use super::creator::Creator;
+use super::documents::{BookDetails, BookMetadata};
+use super::excerpts::BookPreview;
+use super::formats::BookFile;
+use super::images::books::BookImage;
 use super::ownership::books::UserBook;
+use super::prices::AssetPrice;
 use crate::schema::books;
-use crate::types::asset::{Asset, AssetType, Ownership, Page, Summary};
+use crate::types::asset::{Asset, AssetType, Details, Ownership, Page, Summary};
+use crate::types::error::AppError;
+use crate::types::price::{Price, Pricing};
 use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Queryable, Selectable, AsChangeset)]
+// The file and image columns are only ever written by uploads.
+#[derive(Queryable, Selectable, Serialize, Deserialize)]
 #[diesel(table_name = books)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct Book {
     pub id: i32,
     pub creator_id: i32,
     pub title: String,
+    #[serde(skip_deserializing)]
     pub thumb: String,
     pub summary: String,
+    #[serde(skip_deserializing)]
     pub file: String,
     pub pages: i32,
+    #[serde(skip_deserializing)]
     pub main_image: String,
     pub is_free: bool,
+    pub price: i32,
+    pub currency: String,
 }
 
-#[derive(Insertable)]
+#[derive(Insertable, Deserialize)]
 #[diesel(table_name = books)]
 pub struct BookCreate {
     pub creator_id: i32,
     pub title: String,
+    #[serde(skip_deserializing)]
     pub thumb: String,
     pub summary: String,
+    #[serde(skip_deserializing)]
     pub file: String,
     pub pages: i32,
+    #[serde(skip_deserializing)]
     pub main_image: String,
     pub is_free: bool,
+    pub price: i32,
+    pub currency: String,
 }
 
 impl BookCreate {
@@ -43,6 +62,7 @@ impl BookCreate {
         pages: i32,
         main_image: String,
         is_free: bool,
+        price: Price,
     ) -> Self {
         BookCreate {
             creator_id,
@@ -53,92 +73,143 @@ impl BookCreate {
             pages,
             main_image,
             is_free,
+            price: price.amount,
+            currency: String::from(price.currency.store()),
         }
     }
 
-    pub fn create(&self, conn: &mut PgConnection) -> Book {
-        diesel::insert_into(books::table)
+    pub fn create(&self, conn: &mut PgConnection) -> Result<Book, AppError> {
+        Price::stored(self.price, &self.currency)?;
+
+        let book = diesel::insert_into(books::table)
             .values(self)
             .returning(Book::as_returning())
-            .get_result(conn)
-            .expect("Error saving Book")
+            .get_result(conn)?;
+
+        Ok(book)
     }
 }
 
 impl Asset for Book {
-    fn read(conn: &mut PgConnection, book_id: i32) -> Self {
+    fn read(conn: &mut PgConnection, book_id: i32) -> Result<Self, AppError> {
         use crate::schema::books::dsl::*;
 
-        books
+        let book = books
             .filter(id.eq(book_id))
             .select(Book::as_select())
-            .get_result(conn)
-            .expect("Error loading posts")
+            .get_result(conn)?;
+
+        Ok(book)
     }
 
-    fn destroy(conn: &mut PgConnection, book_id: i32) -> usize {
+    fn destroy(conn: &mut PgConnection, book_id: i32) -> Result<usize, AppError> {
         use crate::schema::books::dsl::*;
 
-        diesel::delete(books.filter(id.eq(book_id)))
-            .execute(conn)
-            .expect("Error deleting posts")
+        conn.transaction(|conn| {
+            // buyers keep their copies, so a bought book is never removed
+            if UserBook::is_owned(conn, book_id)? {
+                return Err(AppError::Conflict(format!(
+                    "book {} has been bought",
+                    book_id
+                )));
+            }
+
+            let images = BookImage::destroy_all(conn, book_id)?;
+            let prices = AssetPrice::destroy_all(conn, &AssetType::Book, book_id)?;
+            let metadata = BookMetadata::destroy(conn, book_id)?;
+            let formats = BookFile::destroy_all(conn, book_id)?;
+            let previews = BookPreview::destroy_all(conn, book_id)?;
+            let changes = diesel::delete(books.filter(id.eq(book_id))).execute(conn)?;
+
+            Ok(images + prices + metadata + formats + previews + changes)
+        })
     }
 
-    fn update(&self, conn: &mut PgConnection) -> usize {
+    fn update(&self, conn: &mut PgConnection) -> Result<usize, AppError> {
         use crate::schema::books::dsl::*;
 
-        diesel::update(books)
+        Price::stored(self.price, &self.currency)?;
+
+        let changes = diesel::update(books)
             .filter(id.eq(self.id))
-            .set(self)
-            .execute(conn)
-            .expect("Failed to update user")
+            .set((
+                creator_id.eq(self.creator_id),
+                title.eq(&self.title),
+                summary.eq(&self.summary),
+                pages.eq(self.pages),
+                is_free.eq(self.is_free),
+                price.eq(self.price),
+                currency.eq(&self.currency),
+            ))
+            .execute(conn)?;
+
+        Ok(changes)
     }
 
-    fn summarize(&self, conn: &mut PgConnection, user_id: i32) -> Summary {
-        let (creator, user) = Creator::creator_with_user(conn, self.creator_id);
+    fn summarize(&self, conn: &mut PgConnection, user_id: i32) -> Result<Summary, AppError> {
+        let (creator, user) = Creator::creator_with_user(conn, self.creator_id)?;
         let asset_type = AssetType::Book;
         let display_name = creator.get_display_name();
-        let ownership = self.check_ownership(conn, user_id);
+        let ownership = self.check_ownership(conn, user_id)?;
+        let pricing = self.pricing(conn)?;
 
-        Summary {
+        Ok(Summary {
+            title: self.title.to_owned(),
             display_name,
             ownership,
             asset_type,
             logo: user.logo,
-        }
+            pricing,
+        })
     }
 
-    fn paginate(&self, conn: &mut PgConnection, user_id: i32) -> Page {
-        let (creator, user) = Creator::creator_with_user(conn, self.creator_id);
+    fn paginate(&self, conn: &mut PgConnection, user_id: i32) -> Result<Page, AppError> {
+        let (creator, user) = Creator::creator_with_user(conn, self.creator_id)?;
         let display_name = creator.get_display_name();
         let asset_type = AssetType::Book;
-        let extra_images = Vec::new();
-        let ownership = self.check_ownership(conn, user_id);
+        let extra_images = asset_type.images(conn, self.id)?;
+        let ownership = self.check_ownership(conn, user_id)?;
+        let pricing = self.pricing(conn)?;
 
-        Page {
+        Ok(Page {
             display_name,
             ownership,
             asset_type,
             logo: user.logo,
             extra_images,
-        }
+            pricing,
+            details: BookDetails::read(conn, self.id)?.map(Details::Book),
+        })
     }
 
-    fn check_ownership(&self, conn: &mut PgConnection, user_id: i32) -> Ownership {
+    fn check_ownership(
+        &self,
+        conn: &mut PgConnection,
+        user_id: i32,
+    ) -> Result<Ownership, AppError> {
         if self.is_free {
-            Ownership::Free
+            Ok(Ownership::Free)
         } else {
             UserBook::check_ownership(conn, user_id, self.id)
         }
     }
 }
 
+impl Book {
+    pub fn pricing(&self, conn: &mut PgConnection) -> Result<Pricing, AppError> {
+        let price = Price::stored(self.price, &self.currency)?;
+        AssetPrice::pricing(conn, &AssetType::Book, self.id, price)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::handlers::connect;
     use crate::handlers::creator::{CreatorNew, Creators};
+    use crate::handlers::documents::{ContentsInfo, FontInfo, PdfInfo};
     use crate::handlers::user::{User, UserNew};
+    use crate::types::price::Currency;
     use crate::types::user::DisplayName;
 
     #[test]
@@ -147,12 +218,13 @@ mod tests {
 
         let user = UserNew::create(
             conn,
-            String::from("naokotani"),
-            String::from("nao@gmail.com"),
+            String::from("book_full"),
+            String::from("book_full@gmail.com"),
             String::from("logo.svg"),
-        );
+        )
+        .unwrap();
 
-        assert_eq!(user.username, "naokotani");
+        assert_eq!(user.username, "book_full");
 
         let creator = CreatorNew::create(
             conn,
@@ -162,7 +234,8 @@ mod tests {
             Some(String::from("naokotani")),
             Some(String::from("Random House")),
             DisplayName::Name,
-        );
+        )
+        .unwrap();
 
         let mut book = BookCreate::new(
             creator.id,
@@ -173,8 +246,10 @@ mod tests {
             385,
             String::from("image.jpg"),
             false,
+            Price::new(1999, Currency::Usd),
         )
-        .create(conn);
+        .create(conn)
+        .unwrap();
 
         assert_eq!(book.title, "Dungeons and Dragons");
         assert_eq!(book.thumb, "thumb.jpg");
@@ -182,35 +257,257 @@ mod tests {
         assert_eq!(book.file, "file.pdf");
         assert_eq!(book.pages, 385);
         assert_eq!(book.main_image, "image.jpg");
-        assert_eq!(book.is_free, false);
+        assert!(!book.is_free);
 
-        let summary = book.summarize(conn, user.id);
+        let summary = book.summarize(conn, user.id).unwrap();
 
         assert_eq!(summary.display_name, "Chris Hughes");
         assert_eq!(summary.logo, "logo.svg");
 
-        let page = book.paginate(conn, user.id);
+        let page = book.paginate(conn, user.id).unwrap();
 
         assert_eq!(page.display_name, "Chris Hughes");
         assert_eq!(page.logo, "logo.svg");
         assert_eq!(page.ownership, Ownership::Unowned);
+        assert_eq!(page.details, None);
+
+        let info = PdfInfo {
+            page_count: 120,
+            is_encrypted: false,
+            fonts: vec![FontInfo {
+                name: String::from("Helvetica"),
+                subtype: String::from("Type1"),
+                is_embedded: false,
+            }],
+            contents: vec![
+                ContentsInfo {
+                    level: 1,
+                    title: String::from("Spells"),
+                    page: 4,
+                },
+                ContentsInfo {
+                    level: 1,
+                    title: String::from("Monsters"),
+                    page: 60,
+                },
+            ],
+            cover: None,
+        };
+        let details = BookMetadata::save(conn, book.id, &info, 4096).unwrap();
+
+        assert!(!details.metadata.as_ref().unwrap().fonts_embedded);
+        assert_eq!(details.contents[1].title, "Monsters");
+
+        let page = book.paginate(conn, user.id).unwrap();
+
+        assert_eq!(page.details, Some(Details::Book(details)));
+        assert_eq!(Book::read(conn, book.id).unwrap().pages, 120);
 
         book.title = String::from("For Whom the Bell Tolls");
 
-        let update = book.update(conn);
+        let update = book.update(conn).unwrap();
 
         assert_eq!(update, 1);
 
-        let book = Book::read(conn, book.id);
+        let book = Book::read(conn, book.id).unwrap();
 
         assert_eq!(book.title, "For Whom the Bell Tolls");
         assert_eq!(book.summary, "What a book!");
 
-        let delete = Book::destroy(conn, book.id);
+        // a bought book stays put, along with everything hanging off it
+        UserBook::new(user.id, book.id).create(conn).unwrap();
+
+        assert!(matches!(
+            Book::destroy(conn, book.id),
+            Err(AppError::Conflict(_))
+        ));
+        assert!(BookDetails::read(conn, book.id).unwrap().is_some());
+
+        UserBook::destroy(conn, user.id, book.id).unwrap();
+
+        let delete = Book::destroy(conn, book.id).unwrap();
+
+        assert_eq!(delete, 5);
+
+        Creators::destroy(conn, creator.id).unwrap();
+        User::destroy(conn, user.id).unwrap();
+    }
+
+    #[test]
+    fn book_gallery() {
+        let conn = &mut connect::establish_connection();
+
+        let user = UserNew::create(
+            conn,
+            String::from("book_gallery"),
+            String::from("book_gallery@gmail.com"),
+            String::from("logo.svg"),
+        )
+        .unwrap();
+
+        let creator = CreatorNew::create(
+            conn,
+            user.id,
+            Some(String::from("Chris")),
+            Some(String::from("Hughes")),
+            None,
+            None,
+            DisplayName::Name,
+        )
+        .unwrap();
+
+        let book = BookCreate::new(
+            creator.id,
+            String::from("Dungeons and Dragons"),
+            String::from("thumb.jpg"),
+            String::from("What a book!"),
+            String::from("file.pdf"),
+            385,
+            String::from("image.jpg"),
+            false,
+            Price::new(1999, Currency::Usd),
+        )
+        .create(conn)
+        .unwrap();
+
+        let cover = BookImage::create(conn, book.id, String::from("cover.jpg")).unwrap();
+        let spread = BookImage::create(conn, book.id, String::from("spread.jpg")).unwrap();
+
+        assert_eq!(cover.position, 0);
+        assert_eq!(spread.position, 1);
+
+        let page = book.paginate(conn, user.id).unwrap();
+
+        assert_eq!(page.extra_images, vec!["cover.jpg", "spread.jpg"]);
+
+        let reorder = BookImage::reorder(conn, book.id, &[spread.id, cover.id]).unwrap();
+
+        assert_eq!(reorder, 2);
+
+        let page = book.paginate(conn, user.id).unwrap();
+
+        assert_eq!(page.extra_images, vec!["spread.jpg", "cover.jpg"]);
+
+        let partial = BookImage::reorder(conn, book.id, &[cover.id]);
+
+        assert!(partial.is_err());
+
+        let duplicate = BookImage::reorder(conn, book.id, &[cover.id, cover.id]);
+
+        assert!(duplicate.is_err());
+
+        let missing = BookImage::reorder(conn, book.id, &[cover.id, i32::MAX]);
+
+        assert!(missing.is_err());
+
+        let remove = BookImage::destroy(conn, book.id, cover.id).unwrap();
+
+        assert_eq!(remove, 1);
+
+        let delete = Book::destroy(conn, book.id).unwrap();
+
+        assert_eq!(delete, 2);
+
+        Creators::destroy(conn, creator.id).unwrap();
+        User::destroy(conn, user.id).unwrap();
+    }
+
+    #[test]
+    fn book_pricing() {
+        let conn = &mut connect::establish_connection();
+
+        let user = UserNew::create(
+            conn,
+            String::from("book_pricing"),
+            String::from("book_pricing@gmail.com"),
+            String::from("logo.svg"),
+        )
+        .unwrap();
+
+        let creator = CreatorNew::create(
+            conn,
+            user.id,
+            Some(String::from("Chris")),
+            Some(String::from("Hughes")),
+            None,
+            None,
+            DisplayName::Name,
+        )
+        .unwrap();
+
+        let invalid = BookCreate::new(
+            creator.id,
+            String::from("Dungeons and Dragons"),
+            String::from("thumb.jpg"),
+            String::from("What a book!"),
+            String::from("file.pdf"),
+            385,
+            String::from("image.jpg"),
+            false,
+            Price::new(-1, Currency::Usd),
+        )
+        .create(conn);
+
+        assert!(matches!(invalid, Err(AppError::Validation(_))));
+
+        let book = BookCreate::new(
+            creator.id,
+            String::from("Dungeons and Dragons"),
+            String::from("thumb.jpg"),
+            String::from("What a book!"),
+            String::from("file.pdf"),
+            385,
+            String::from("image.jpg"),
+            false,
+            Price::new(1999, Currency::Usd),
+        )
+        .create(conn)
+        .unwrap();
+
+        AssetPrice::set(
+            conn,
+            &AssetType::Book,
+            book.id,
+            Price::new(1799, Currency::Eur),
+        )
+        .unwrap();
+        AssetPrice::set(
+            conn,
+            &AssetType::Book,
+            book.id,
+            Price::new(1599, Currency::Gbp),
+        )
+        .unwrap();
+        AssetPrice::set(
+            conn,
+            &AssetType::Book,
+            book.id,
+            Price::new(1699, Currency::Eur),
+        )
+        .unwrap();
+
+        let summary = book.summarize(conn, user.id).unwrap();
+
+        assert_eq!(summary.pricing.price, Price::new(1999, Currency::Usd));
+        assert_eq!(summary.pricing.overrides.len(), 2);
+
+        let page = book.paginate(conn, user.id).unwrap();
+
+        assert_eq!(
+            page.pricing.in_currency(Currency::Eur),
+            Some(Price::new(1699, Currency::Eur))
+        );
+        assert_eq!(page.pricing.in_currency(Currency::Jpy), None);
+
+        let remove = AssetPrice::destroy(conn, &AssetType::Book, book.id, Currency::Gbp).unwrap();
+
+        assert_eq!(remove, 1);
+
+        let delete = Book::destroy(conn, book.id).unwrap();
 
-        assert_eq!(delete, 1);
+        assert_eq!(delete, 2);
 
-        Creators::destroy(conn, creator.id);
-        User::destroy(conn, user.id);
+        Creators::destroy(conn, creator.id).unwrap();
+        User::destroy(conn, user.id).unwrap();
     }
 }
@@ -0,0 +1,355 @@
+use crate::schema::cart_items;
+use crate::types::asset::{AssetType, Ownership};
+use crate::types::error::AppError;
+use crate::types::price::{Currency, Price};
+use diesel::prelude::*;
+use serde::Serialize;
+
+// Guests have no user row, so nothing is ever recorded as owned by this id
+const GUEST_VIEWER: i32 = 0;
+
+// Signed in users keep their cart on their account, guests on their session
+pub enum CartOwner {
+    User(i32),
+    Guest(String),
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = cart_items)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+struct CartItemQuery {
+    id: i32,
+    asset_type: String,
+    asset_id: i32,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = cart_items)]
+pub struct CartItem {
+    user_id: Option<i32>,
+    guest_key: Option<String>,
+    asset_type: String,
+    asset_id: i32,
+}
+
+#[derive(Serialize)]
+pub struct CartLine {
+    pub id: i32,
+    pub asset_type: AssetType,
+    pub asset_id: i32,
+    pub title: String,
+    // the creator's name
+    pub display_name: String,
+    pub price: Price,
+}
+
+#[derive(Serialize)]
+pub struct Cart {
+    pub items: Vec<CartLine>,
+    // one total per currency the lines are charged in
+    pub totals: Vec<Price>,
+}
+
+impl CartOwner {
+    fn viewer(&self) -> i32 {
+        match self {
+            Self::User(id) => *id,
+            Self::Guest(_) => GUEST_VIEWER,
+        }
+    }
+}
+
+impl CartItem {
+    pub fn create(
+        conn: &mut PgConnection,
+        owner: &CartOwner,
+        kind: AssetType,
+        a_id: i32,
+    ) -> Result<i32, AppError> {
+        let summary = kind.summarize(conn, a_id, owner.viewer())?;
+
+        match summary.ownership {
+            Ownership::Owned => {
+                return Err(AppError::Conflict(format!(
+                    "{} {} is already owned",
+                    kind.store(),
+                    a_id
+                )))
+            }
+            Ownership::Free => {
+                return Err(AppError::Validation(format!(
+                    "{} {} is free",
+                    kind.store(),
+                    a_id
+                )))
+            }
+            Ownership::Unowned => {}
+        }
+
+        // only assets with a price of their own can be bought
+        kind.pricing(conn, a_id)?;
+
+        let (user_id, guest_key) = match owner {
+            CartOwner::User(id) => (Some(*id), None),
+            CartOwner::Guest(key) => (None, Some(key.to_owned())),
+        };
+
+        let item = diesel::insert_into(cart_items::table)
+            .values(CartItem {
+                user_id,
+                guest_key,
+                asset_type: String::from(kind.store()),
+                asset_id: a_id,
+            })
+            .returning(cart_items::id)
+            .get_result(conn)?;
+
+        Ok(item)
+    }
+
+    pub fn destroy(
+        conn: &mut PgConnection,
+        owner: &CartOwner,
+        item_id: i32,
+    ) -> Result<usize, AppError> {
+        use crate::schema::cart_items::dsl::*;
+
+        let item = cart_items.filter(id.eq(item_id));
+        let changes = match owner {
+            CartOwner::User(u_id) => diesel::delete(item.filter(user_id.eq(u_id))).execute(conn)?,
+            CartOwner::Guest(key) => {
+                diesel::delete(item.filter(guest_key.eq(key))).execute(conn)?
+            }
+        };
+
+        Ok(changes)
+    }
+
+    pub fn destroy_all(conn: &mut PgConnection, owner: &CartOwner) -> Result<usize, AppError> {
+        use crate::schema::cart_items::dsl::*;
+
+        let changes = match owner {
+            CartOwner::User(u_id) => {
+                diesel::delete(cart_items.filter(user_id.eq(u_id))).execute(conn)?
+            }
+            CartOwner::Guest(key) => {
+                diesel::delete(cart_items.filter(guest_key.eq(key))).execute(conn)?
+            }
+        };
+
+        Ok(changes)
+    }
+
+    // Moves a guest cart onto the account the guest just signed in to,
+    // dropping anything the user already owns or already has in their cart
+    pub fn claim(conn: &mut PgConnection, key: &str, u_id: i32) -> Result<usize, AppError> {
+        use crate::schema::cart_items::dsl::*;
+
+        let guest = CartOwner::Guest(String::from(key));
+        let user = CartOwner::User(u_id);
+
+        conn.transaction(|conn| {
+            let mut claimed: usize = 0;
+            for item in list(conn, &guest)? {
+                let kind = AssetType::retrieve(&item.asset_type)?;
+                let in_cart = list(conn, &user)?.iter().any(|other| {
+                    other.asset_type == item.asset_type && other.asset_id == item.asset_id
+                });
+                let owned =
+                    kind.summarize(conn, item.asset_id, u_id)?.ownership != Ownership::Unowned;
+
+                if in_cart || owned {
+                    diesel::delete(cart_items.filter(id.eq(item.id))).execute(conn)?;
+                } else {
+                    claimed += diesel::update(cart_items.filter(id.eq(item.id)))
+                        .set((user_id.eq(u_id), guest_key.eq(None::<String>)))
+                        .execute(conn)?;
+                }
+            }
+
+            Ok(claimed)
+        })
+    }
+}
+
+impl Cart {
+    // Lines are charged in the requested currency where the asset has a price
+    // for it and in the asset's own currency otherwise
+    pub fn read(
+        conn: &mut PgConnection,
+        owner: &CartOwner,
+        currency: Option<Currency>,
+    ) -> Result<Cart, AppError> {
+        let mut items = Vec::new();
+        let mut totals: Vec<Price> = Vec::new();
+
+        for item in list(conn, owner)? {
+            let kind = AssetType::retrieve(&item.asset_type)?;
+            let summary = kind.summarize(conn, item.asset_id, owner.viewer())?;
+            let pricing = kind.pricing(conn, item.asset_id)?;
+            let price = currency
+                .and_then(|currency| pricing.in_currency(currency))
+                .unwrap_or(pricing.price);
+
+            match totals
+                .iter_mut()
+                .find(|total| total.currency == price.currency)
+            {
+                Some(total) => total.amount += price.amount,
+                None => totals.push(price),
+            }
+
+            items.push(CartLine {
+                id: item.id,
+                asset_type: kind,
+                asset_id: item.asset_id,
+                title: summary.title,
+                display_name: summary.display_name,
+                price,
+            });
+        }
+
+        Ok(Cart { items, totals })
+    }
+}
+
+fn list(conn: &mut PgConnection, owner: &CartOwner) -> Result<Vec<CartItemQuery>, AppError> {
+    use crate::schema::cart_items::dsl::*;
+
+    let query = cart_items.select(CartItemQuery::as_select()).order(id);
+    let items = match owner {
+        CartOwner::User(u_id) => query.filter(user_id.eq(u_id)).get_results(conn)?,
+        CartOwner::Guest(key) => query.filter(guest_key.eq(key)).get_results(conn)?,
+    };
+
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::book::{Book, BookCreate};
+    use crate::handlers::connect;
+    use crate::handlers::creator::{CreatorNew, Creators};
+    use crate::handlers::ownership::books::UserBook;
+    use crate::handlers::prices::AssetPrice;
+    use crate::handlers::user::{User, UserNew};
+    use crate::types::asset::Asset;
+    use crate::types::user::DisplayName;
+
+    #[test]
+    fn cart_full() {
+        let conn = &mut connect::establish_connection();
+
+        let user = UserNew::create(
+            conn,
+            String::from("cart_full"),
+            String::from("cart_full@gmail.com"),
+            String::from("logo.svg"),
+        )
+        .unwrap();
+
+        let creator = CreatorNew::create(
+            conn,
+            user.id,
+            Some(String::from("Chris")),
+            Some(String::from("Hughes")),
+            None,
+            None,
+            DisplayName::Name,
+        )
+        .unwrap();
+
+        let mut books = Vec::new();
+        for (title, is_free) in [
+            ("Cart One", false),
+            ("Cart Two", false),
+            ("Cart Free", true),
+        ] {
+            let book = BookCreate::new(
+                creator.id,
+                String::from(title),
+                String::from("thumb.jpg"),
+                String::from("What a book!"),
+                String::from("file.pdf"),
+                100,
+                String::from("image.jpg"),
+                is_free,
+                Price::new(1000, Currency::Usd),
+            )
+            .create(conn)
+            .unwrap();
+            books.push(book);
+        }
+
+        AssetPrice::set(
+            conn,
+            &AssetType::Book,
+            books[0].id,
+            Price::new(900, Currency::Eur),
+        )
+        .unwrap();
+        UserBook::new(user.id, books[1].id).create(conn).unwrap();
+
+        let owner = CartOwner::User(user.id);
+
+        let owned = CartItem::create(conn, &owner, AssetType::Book, books[1].id);
+
+        assert!(matches!(owned, Err(AppError::Conflict(_))));
+
+        let free = CartItem::create(conn, &owner, AssetType::Book, books[2].id);
+
+        assert!(matches!(free, Err(AppError::Validation(_))));
+
+        let guest = CartOwner::Guest(String::from("cart_full_guest"));
+
+        CartItem::create(conn, &guest, AssetType::Book, books[0].id).unwrap();
+        CartItem::create(conn, &guest, AssetType::Book, books[1].id).unwrap();
+
+        let duplicate = CartItem::create(conn, &guest, AssetType::Book, books[0].id);
+
+        assert!(matches!(duplicate, Err(AppError::Conflict(_))));
+
+        let cart = Cart::read(conn, &guest, None).unwrap();
+
+        assert_eq!(cart.items.len(), 2);
+        assert_eq!(cart.totals, vec![Price::new(2000, Currency::Usd)]);
+
+        let cart = Cart::read(conn, &guest, Some(Currency::Eur)).unwrap();
+
+        assert_eq!(
+            cart.totals,
+            vec![
+                Price::new(900, Currency::Eur),
+                Price::new(1000, Currency::Usd)
+            ]
+        );
+
+        let claimed = CartItem::claim(conn, "cart_full_guest", user.id).unwrap();
+
+        assert_eq!(claimed, 1);
+
+        let cart = Cart::read(conn, &owner, Some(Currency::Eur)).unwrap();
+
+        assert_eq!(cart.items[0].asset_id, books[0].id);
+        assert_eq!(cart.items[0].title, "Cart One");
+        assert_eq!(cart.items[0].display_name, "Chris Hughes");
+        assert_eq!(cart.totals, vec![Price::new(900, Currency::Eur)]);
+        assert!(Cart::read(conn, &guest, None).unwrap().items.is_empty());
+
+        let remove = CartItem::destroy(conn, &guest, cart.items[0].id).unwrap();
+
+        assert_eq!(remove, 0);
+
+        let remove = CartItem::destroy(conn, &owner, cart.items[0].id).unwrap();
+
+        assert_eq!(remove, 1);
+
+        UserBook::destroy(conn, user.id, books[1].id).unwrap();
+        for book in books {
+            Book::destroy(conn, book.id).unwrap();
+        }
+        Creators::destroy(conn, creator.id).unwrap();
+        User::destroy(conn, user.id).unwrap();
+    }
+}
@@ -1,6 +1,47 @@
 use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
 use dotenvy::dotenv;
 use std::env;
+use std::time::Duration;
+
+pub type DbPool = Pool<ConnectionManager<PgConnection>>;
+pub type DbConnection = PooledConnection<ConnectionManager<PgConnection>>;
+
+pub struct PoolSettings {
+    pub max_size: u32,
+    pub min_idle: Option<u32>,
+    pub connection_timeout: Duration,
+    pub idle_timeout: Option<Duration>,
+}
+
+impl Default for PoolSettings {
+    fn default() -> Self {
+        PoolSettings {
+            max_size: 10,
+            min_idle: None,
+            connection_timeout: Duration::from_secs(5),
+            idle_timeout: Some(Duration::from_secs(600)),
+        }
+    }
+}
+
+impl PoolSettings {
+    // Reads DATABASE_POOL_* variables, falling back to the defaults for unset values
+    pub fn from_env() -> Self {
+        let default = PoolSettings::default();
+
+        PoolSettings {
+            max_size: env_var("DATABASE_POOL_MAX_SIZE").unwrap_or(default.max_size),
+            min_idle: env_var("DATABASE_POOL_MIN_IDLE").or(default.min_idle),
+            connection_timeout: env_var("DATABASE_POOL_TIMEOUT_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(default.connection_timeout),
+            idle_timeout: env_var("DATABASE_POOL_IDLE_TIMEOUT_SECS")
+                .map(Duration::from_secs)
+                .or(default.idle_timeout),
+        }
+    }
+}
 
 pub fn establish_connection() -> PgConnection {
     dotenv().ok();
@@ -9,3 +50,55 @@ pub fn establish_connection() -> PgConnection {
     PgConnection::establish(&database_url)
         .unwrap_or_else(|_| panic!("Error connecting to {}", database_url))
 }
+
+pub fn establish_pool() -> DbPool {
+    dotenv().ok();
+
+    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    build_pool(&database_url, PoolSettings::from_env())
+}
+
+// Connections are opened lazily so the server can start while the database is down
+pub fn build_pool(database_url: &str, settings: PoolSettings) -> DbPool {
+    let manager = ConnectionManager::<PgConnection>::new(database_url);
+
+    Pool::builder()
+        .max_size(settings.max_size)
+        .min_idle(settings.min_idle)
+        .connection_timeout(settings.connection_timeout)
+        .idle_timeout(settings.idle_timeout)
+        .test_on_check_out(true)
+        .build_unchecked(manager)
+}
+
+pub fn is_ready(pool: &DbPool) -> bool {
+    match pool.get() {
+        Ok(mut conn) => diesel::sql_query("SELECT 1").execute(&mut conn).is_ok(),
+        Err(_) => false,
+    }
+}
+
+fn env_var<T: std::str::FromStr>(key: &str) -> Option<T> {
+    env::var(key).ok().and_then(|value| value.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pool_ready() {
+        let pool = establish_pool();
+
+        assert!(is_ready(&pool));
+
+        let settings = PoolSettings {
+            max_size: 1,
+            connection_timeout: Duration::from_millis(250),
+            ..PoolSettings::default()
+        };
+        let pool = build_pool("postgres://nobody@127.0.0.1:1/missing", settings);
+
+        assert!(!is_ready(&pool));
+    }
+}
@@ -1,9 +1,11 @@
 use crate::handlers::user::User;
 use crate::schema::{creators, users};
-use crate::types::user::DisplayName;
+use crate::types::error::AppError;
+use crate::types::user::{DisplayName, Role};
 use diesel::prelude::*;
+use serde::Serialize;
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct Creator {
     pub id: i32,
     pub first_name: String,
@@ -38,34 +40,36 @@ pub struct CreatorNew {
 }
 
 impl Creator {
-    pub fn new(creator: Creators) -> Self {
+    pub fn new(creator: Creators) -> Result<Self, AppError> {
         let id = creator.id;
 
         let first_name = creator.first_name.unwrap_or_default();
         let last_name = creator.last_name.unwrap_or_default();
         let other_name = creator.other_name.unwrap_or_default();
         let publisher = creator.publisher.unwrap_or_default();
-        let default_name = DisplayName::retreieve(&creator.default_name);
+        let default_name = DisplayName::retreieve(&creator.default_name)?;
 
-        Creator {
+        Ok(Creator {
             id,
             first_name,
             last_name,
             other_name,
             publisher,
             default_name,
-        }
+        })
     }
 
-    pub fn creator_with_user(conn: &mut PgConnection, user_id: i32) -> (Self, User) {
+    pub fn creator_with_user(
+        conn: &mut PgConnection,
+        user_id: i32,
+    ) -> Result<(Self, User), AppError> {
         let (creators, user) = creators::table
             .inner_join(users::table)
             .filter(users::id.eq(user_id))
             .select((Creators::as_select(), User::as_select()))
-            .get_result::<(Creators, User)>(conn)
-            .expect("Failed to get user/creator");
+            .get_result::<(Creators, User)>(conn)?;
 
-        (Creator::new(creators), user)
+        Ok((Creator::new(creators)?, user))
     }
 
     pub fn get_display_name(&self) -> String {
@@ -94,7 +98,7 @@ impl CreatorNew {
         other_name: Option<String>,
         publisher: Option<String>,
         name: DisplayName,
-    ) -> Creator {
+    ) -> Result<Creator, AppError> {
         let name = String::from(name.store());
         let creator_new = CreatorNew {
             id,
@@ -105,24 +109,33 @@ impl CreatorNew {
             default_name: name,
         };
 
-        let creator = diesel::insert_into(creators::table)
-            .values(&creator_new)
-            .returning(Creators::as_returning())
-            .get_result(conn)
-            .expect("Error saving new post");
+        // having a creator profile is what makes a customer a creator
+        let creator = conn.transaction(|conn| {
+            let creator = diesel::insert_into(creators::table)
+                .values(&creator_new)
+                .returning(Creators::as_returning())
+                .get_result(conn)?;
+
+            diesel::update(users::table)
+                .filter(users::id.eq(id))
+                .filter(users::role.eq(Role::Customer.store()))
+                .set(users::role.eq(Role::Creator.store()))
+                .execute(conn)?;
+
+            Ok::<Creators, AppError>(creator)
+        })?;
 
         Creator::new(creator)
     }
 }
 
 impl Creators {
-    pub fn read(conn: &mut PgConnection, creator_id: i32) -> Creator {
+    pub fn read(conn: &mut PgConnection, creator_id: i32) -> Result<Creator, AppError> {
         use crate::schema::creators::dsl::*;
         let result = creators
             .filter(id.eq(creator_id))
             .select(Creators::as_select())
-            .get_result(conn)
-            .expect("Error loading creators");
+            .get_result(conn)?;
 
         Creator::new(result)
     }
@@ -135,10 +148,10 @@ impl Creators {
         other: Option<String>,
         publish: Option<String>,
         default: DisplayName,
-    ) -> usize {
+    ) -> Result<usize, AppError> {
         use crate::schema::creators::dsl::*;
 
-        diesel::update(creators)
+        let changes = diesel::update(creators)
             .filter(id.eq(creator_id))
             .set((
                 first_name.eq(first),
@@ -147,16 +160,17 @@ impl Creators {
                 publisher.eq(publish),
                 default_name.eq(default.store()),
             ))
-            .execute(conn)
-            .expect("Creator update failed")
+            .execute(conn)?;
+
+        Ok(changes)
     }
 
-    pub fn destroy(conn: &mut PgConnection, creator_id: i32) -> usize {
+    pub fn destroy(conn: &mut PgConnection, creator_id: i32) -> Result<usize, AppError> {
         use crate::schema::creators::dsl::*;
 
-        diesel::delete(creators.filter(id.eq(creator_id)))
-            .execute(conn)
-            .expect("Error deleting posts")
+        let changes = diesel::delete(creators.filter(id.eq(creator_id))).execute(conn)?;
+
+        Ok(changes)
     }
 }
 
@@ -172,10 +186,11 @@ mod tests {
 
         let user = user::UserNew::create(
             conn,
-            String::from("naokotani"),
-            String::from("nao@gmail.com"),
+            String::from("creator_full"),
+            String::from("creator_full@gmail.com"),
             String::from("logo.svg"),
-        );
+        )
+        .unwrap();
 
         let creator = CreatorNew::create(
             conn,
@@ -185,15 +200,17 @@ mod tests {
             Some(String::from("naokotani")),
             Some(String::from("Random House")),
             DisplayName::Name,
-        );
+        )
+        .unwrap();
 
         assert_eq!(creator.first_name, "Chris");
         assert_eq!(creator.last_name, "Hughes");
         assert_eq!(creator.other_name, "naokotani");
         assert_eq!(creator.publisher, "Random House");
         assert_eq!(creator.default_name, DisplayName::Name);
+        assert_eq!(User::role(conn, user.id).unwrap(), Role::Creator);
 
-        let creator = Creators::read(conn, creator.id);
+        let creator = Creators::read(conn, creator.id).unwrap();
 
         assert_eq!(creator.first_name, "Chris");
         assert_eq!(creator.last_name, "Hughes");
@@ -208,24 +225,25 @@ mod tests {
             Some(String::from("Galator")),
             None,
             DisplayName::Other,
-        );
+        )
+        .unwrap();
 
         assert_eq!(update, 1);
 
-        let creator = Creators::read(conn, creator.id);
+        let creator = Creators::read(conn, creator.id).unwrap();
 
         assert_eq!(creator.first_name, "");
         assert_eq!(creator.last_name, "");
         assert_eq!(creator.other_name, "Galator");
         assert_eq!(creator.publisher, "");
 
-        let delete = Creators::destroy(conn, creator.id);
+        let delete = Creators::destroy(conn, creator.id).unwrap();
 
         assert_eq!(delete, 1);
 
         let conn = &mut connect::establish_connection();
 
-        let delete = user::User::destroy(conn, user.id);
+        let delete = user::User::destroy(conn, user.id).unwrap();
 
         assert_eq!(delete, 1);
     }
@@ -0,0 +1,589 @@
+use super::excerpts::BookPreview;
+use super::formats::FormatDetails;
+use super::rendering::render;
+use super::storage::StorageBackend;
+use super::thumbnails::{encode, shrink, ThumbFormat, THUMB_SIZES};
+use super::uploads::{FileSlot, Upload, UploadTarget};
+use crate::schema::{book_contents, book_fonts, book_metadata, books};
+use crate::types::error::AppError;
+use crate::types::time::now;
+use diesel::prelude::*;
+use image::DynamicImage;
+use lopdf::{Dictionary, Document, Object};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+// What a book's PDF says about itself
+#[derive(PartialEq, Debug)]
+pub struct PdfInfo {
+    pub page_count: i32,
+    pub is_encrypted: bool,
+    pub fonts: Vec<FontInfo>,
+    pub contents: Vec<ContentsInfo>,
+    // the first page, drawn at the larger thumbnail size
+    pub cover: Option<DynamicImage>,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct FontInfo {
+    pub name: String,
+    pub subtype: String,
+    pub is_embedded: bool,
+}
+
+// One bookmark of the outline
+#[derive(PartialEq, Debug)]
+pub struct ContentsInfo {
+    // 1 at the top of the outline
+    pub level: i32,
+    pub title: String,
+    pub page: i32,
+}
+
+#[derive(Queryable, Selectable, Serialize, PartialEq, Debug)]
+#[diesel(table_name = book_metadata)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct BookMetadata {
+    #[serde(skip)]
+    pub id: i32,
+    pub book_id: i32,
+    pub page_count: i32,
+    pub is_encrypted: bool,
+    // false when a reader may substitute fonts
+    pub fonts_embedded: bool,
+    pub file_size: i64,
+    // unix seconds
+    pub analyzed_at: i64,
+}
+
+#[derive(Insertable, AsChangeset)]
+#[diesel(table_name = book_metadata)]
+struct BookMetadataNew {
+    book_id: i32,
+    page_count: i32,
+    is_encrypted: bool,
+    fonts_embedded: bool,
+    file_size: i64,
+    analyzed_at: i64,
+}
+
+#[derive(Queryable, Selectable, Serialize, PartialEq, Debug)]
+#[diesel(table_name = book_fonts)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct BookFont {
+    pub id: i32,
+    pub book_id: i32,
+    pub name: String,
+    pub subtype: String,
+    pub is_embedded: bool,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = book_fonts)]
+struct BookFontNew {
+    book_id: i32,
+    name: String,
+    subtype: String,
+    is_embedded: bool,
+}
+
+#[derive(Queryable, Selectable, Serialize, PartialEq, Debug)]
+#[diesel(table_name = book_contents)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ContentsEntry {
+    pub id: i32,
+    pub book_id: i32,
+    pub position: i32,
+    pub level: i32,
+    pub title: String,
+    pub page: i32,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = book_contents)]
+struct ContentsEntryNew {
+    book_id: i32,
+    position: i32,
+    level: i32,
+    title: String,
+    page: i32,
+}
+
+// Shown on the book's page so buyers can see what is inside and which
+// formats it comes in, and read the preview if there is one
+#[derive(Serialize, PartialEq, Debug)]
+pub struct BookDetails {
+    #[serde(flatten)]
+    pub metadata: Option<BookMetadata>,
+    pub fonts: Vec<BookFont>,
+    pub contents: Vec<ContentsEntry>,
+    pub formats: Vec<FormatDetails>,
+    pub preview: Option<BookPreview>,
+}
+
+impl PdfInfo {
+    // A PDF that cannot be read without a password is refused, since buyers
+    // would not be able to open it either
+    pub fn parse(bytes: &[u8]) -> Result<PdfInfo, AppError> {
+        let document = Document::load_mem(bytes).map_err(|err| invalid(&err.to_string()))?;
+        let is_encrypted = document.was_encrypted() || document.is_encrypted();
+        let pages = document.get_pages();
+
+        if pages.is_empty() {
+            return Err(invalid(match is_encrypted {
+                true => "it is protected by a password",
+                false => "it has no pages",
+            }));
+        }
+
+        let mut fonts = BTreeMap::new();
+        for page in pages.values() {
+            for font in document.get_page_fonts(*page).unwrap_or_default().values() {
+                let font = font_info(&document, font);
+                fonts.entry(font.name.to_owned()).or_insert(font);
+            }
+        }
+
+        // a missing or damaged outline leaves the contents empty
+        let contents = document
+            .get_toc()
+            .map(|toc| {
+                toc.toc
+                    .into_iter()
+                    .map(|entry| ContentsInfo {
+                        level: entry.level as i32,
+                        title: entry.title.trim().chars().take(280).collect(),
+                        page: entry.page as i32,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let cover = pages
+            .get(&1)
+            .and_then(|page| render(&document, *page, THUMB_SIZES[1]))
+            .map(DynamicImage::ImageRgba8);
+
+        Ok(PdfInfo {
+            page_count: pages.len() as i32,
+            is_encrypted,
+            fonts: fonts.into_values().collect(),
+            contents,
+            cover,
+        })
+    }
+}
+
+impl BookMetadata {
+    // Replaces everything known about the book's PDF and corrects its page count
+    pub fn save(
+        conn: &mut PgConnection,
+        b_id: i32,
+        info: &PdfInfo,
+        size: i64,
+    ) -> Result<BookDetails, AppError> {
+        let row = BookMetadataNew {
+            book_id: b_id,
+            page_count: info.page_count,
+            is_encrypted: info.is_encrypted,
+            fonts_embedded: info.fonts.iter().all(|font| font.is_embedded),
+            file_size: size,
+            analyzed_at: now(),
+        };
+        let fonts = info
+            .fonts
+            .iter()
+            .map(|font| BookFontNew {
+                book_id: b_id,
+                name: font.name.to_owned(),
+                subtype: font.subtype.to_owned(),
+                is_embedded: font.is_embedded,
+            })
+            .collect::<Vec<BookFontNew>>();
+        let contents = info
+            .contents
+            .iter()
+            .enumerate()
+            .map(|(position, entry)| ContentsEntryNew {
+                book_id: b_id,
+                position: position as i32,
+                level: entry.level,
+                title: entry.title.to_owned(),
+                page: entry.page,
+            })
+            .collect::<Vec<ContentsEntryNew>>();
+
+        conn.transaction(|conn| {
+            diesel::update(books::table.find(b_id))
+                .set(books::pages.eq(info.page_count))
+                .execute(conn)?;
+
+            let metadata = diesel::insert_into(book_metadata::table)
+                .values(&row)
+                .on_conflict(book_metadata::book_id)
+                .do_update()
+                .set(&row)
+                .returning(BookMetadata::as_returning())
+                .get_result(conn)?;
+
+            diesel::delete(book_fonts::table.filter(book_fonts::book_id.eq(b_id))).execute(conn)?;
+            let fonts = diesel::insert_into(book_fonts::table)
+                .values(&fonts)
+                .returning(BookFont::as_returning())
+                .get_results(conn)?;
+
+            diesel::delete(book_contents::table.filter(book_contents::book_id.eq(b_id)))
+                .execute(conn)?;
+            let contents = diesel::insert_into(book_contents::table)
+                .values(&contents)
+                .returning(ContentsEntry::as_returning())
+                .get_results(conn)?;
+
+            Ok(BookDetails {
+                metadata: Some(metadata),
+                fonts,
+                contents,
+                formats: FormatDetails::list(conn, b_id)?,
+                preview: BookPreview::read(conn, b_id)?,
+            })
+        })
+    }
+
+    pub fn destroy(conn: &mut PgConnection, b_id: i32) -> Result<usize, AppError> {
+        let contents = diesel::delete(book_contents::table.filter(book_contents::book_id.eq(b_id)))
+            .execute(conn)?;
+        let fonts =
+            diesel::delete(book_fonts::table.filter(book_fonts::book_id.eq(b_id))).execute(conn)?;
+        let metadata = diesel::delete(book_metadata::table.filter(book_metadata::book_id.eq(b_id)))
+            .execute(conn)?;
+
+        Ok(contents + fonts + metadata)
+    }
+
+    // Unless the creator has uploaded a main image of their own, the cover
+    // becomes the book's thumbnail
+    pub fn use_cover(
+        conn: &mut PgConnection,
+        storage: &dyn StorageBackend,
+        b_id: i32,
+        info: &PdfInfo,
+        checksum: &str,
+    ) -> Result<Option<String>, AppError> {
+        let Some(cover) = &info.cover else {
+            return Ok(None);
+        };
+        match Upload::read(conn, UploadTarget::Book, b_id, FileSlot::MainImage) {
+            Ok(_) => return Ok(None),
+            Err(AppError::NotFound(_)) => {}
+            Err(err) => return Err(err),
+        }
+
+        let key = format!("books/{}/cover-{}.png", b_id, &checksum[..16]);
+        storage.put(
+            &key,
+            &encode(&shrink(cover, THUMB_SIZES[0]), ThumbFormat::Png)?,
+        )?;
+
+        let previous = books::table
+            .find(b_id)
+            .select(books::thumb)
+            .get_result::<String>(conn)?;
+        diesel::update(books::table.find(b_id))
+            .set(books::thumb.eq(&key))
+            .execute(conn)?;
+
+        // an earlier edition's cover goes; hand written thumbs are left alone
+        if previous != key && previous.starts_with(&format!("books/{}/cover-", b_id)) {
+            storage.delete(&previous)?;
+        }
+
+        Ok(Some(key))
+    }
+}
+
+impl BookDetails {
+    // None until a file has been uploaded in any format
+    pub fn read(conn: &mut PgConnection, b_id: i32) -> Result<Option<BookDetails>, AppError> {
+        let metadata = book_metadata::table
+            .filter(book_metadata::book_id.eq(b_id))
+            .select(BookMetadata::as_select())
+            .get_result(conn)
+            .optional()?;
+        let formats = FormatDetails::list(conn, b_id)?;
+
+        if metadata.is_none() && formats.is_empty() {
+            return Ok(None);
+        }
+        let fonts = book_fonts::table
+            .filter(book_fonts::book_id.eq(b_id))
+            .order(book_fonts::name.asc())
+            .select(BookFont::as_select())
+            .get_results(conn)?;
+        let contents = book_contents::table
+            .filter(book_contents::book_id.eq(b_id))
+            .order(book_contents::position.asc())
+            .select(ContentsEntry::as_select())
+            .get_results(conn)?;
+
+        Ok(Some(BookDetails {
+            metadata,
+            fonts,
+            contents,
+            formats,
+            preview: BookPreview::read(conn, b_id)?,
+        }))
+    }
+}
+
+fn font_info(document: &Document, font: &Dictionary) -> FontInfo {
+    let name = |dictionary: &Dictionary, key: &[u8]| {
+        dictionary
+            .get(key)
+            .and_then(Object::as_name)
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .ok()
+    };
+    let subtype = name(font, b"Subtype").unwrap_or_else(|| String::from("Unknown"));
+    let base_font = name(font, b"BaseFont").unwrap_or_else(|| String::from("Unnamed"));
+
+    let is_embedded = subtype == "Type3"
+        || descendant_font(document, font)
+            .and_then(|font| font.get(b"FontDescriptor").ok())
+            .and_then(|descriptor| document.dereference(descriptor).ok())
+            .and_then(|(_, descriptor)| descriptor.as_dict().ok())
+            .is_some_and(|descriptor| {
+                [b"FontFile".as_slice(), b"FontFile2", b"FontFile3"]
+                    .iter()
+                    .any(|key| descriptor.has(key))
+            });
+
+    // subsets are named ABCDEF+Name
+    let name = match base_font.split_once('+') {
+        Some((tag, name)) if tag.len() == 6 && tag.chars().all(|c| c.is_ascii_uppercase()) => {
+            name.to_owned()
+        }
+        _ => base_font,
+    };
+
+    FontInfo {
+        name: name.chars().take(100).collect(),
+        subtype: subtype.chars().take(20).collect(),
+        is_embedded,
+    }
+}
+
+// Composite fonts keep their glyphs in the descendant font
+pub fn descendant_font<'a>(document: &'a Document, font: &'a Dictionary) -> Option<&'a Dictionary> {
+    match font.get(b"Subtype").and_then(Object::as_name) {
+        Ok(b"Type0") => font
+            .get(b"DescendantFonts")
+            .and_then(|fonts| document.dereference(fonts))
+            .and_then(|(_, fonts)| fonts.as_array())
+            .ok()
+            .and_then(|fonts| fonts.first())
+            .and_then(|first| document.dereference(first).ok())
+            .and_then(|(_, first)| first.as_dict().ok()),
+        _ => Some(font),
+    }
+}
+
+fn invalid(reason: &str) -> AppError {
+    AppError::Validation(format!("not a usable PDF: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GenericImageView;
+    use lopdf::content::{Content, Operation};
+    use lopdf::{dictionary, Bookmark, Stream};
+
+    // A PDF of the given number of pages, each saying which it is, with an
+    // unembedded Helvetica, an embedded subset font, a bookmark per chapter
+    // and a grey cover image in the lower left of the first page
+    fn sample_pdf(pages: usize, chapters: &[(&str, usize)]) -> Vec<u8> {
+        let mut document = Document::with_version("1.5");
+        let pages_id = document.new_object_id();
+
+        let helvetica = document.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Helvetica",
+        });
+        let font_file = document.add_object(Stream::new(dictionary! {}, b"glyphs".to_vec()));
+        let descriptor = document.add_object(dictionary! {
+            "Type" => "FontDescriptor",
+            "FontName" => "ABCDEF+Garamond",
+            "FontFile2" => font_file,
+        });
+        let garamond = document.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "TrueType",
+            "BaseFont" => "ABCDEF+Garamond",
+            "FontDescriptor" => descriptor,
+        });
+        let cover = document.add_object(Stream::new(
+            dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Image",
+                "Width" => 80,
+                "Height" => 100,
+                "ColorSpace" => "DeviceGray",
+                "BitsPerComponent" => 8,
+            },
+            vec![128; 80 * 100],
+        ));
+
+        let mut kids = Vec::new();
+        let mut page_ids = Vec::new();
+        for number in 1..=pages {
+            let mut operations = Vec::new();
+            if number == 1 {
+                operations.extend([
+                    Operation::new("q", vec![]),
+                    Operation::new(
+                        "cm",
+                        vec![
+                            306.into(),
+                            0.into(),
+                            0.into(),
+                            396.into(),
+                            0.into(),
+                            0.into(),
+                        ],
+                    ),
+                    Operation::new("Do", vec!["Cover".into()]),
+                    Operation::new("Q", vec![]),
+                ]);
+            }
+            operations.extend([
+                Operation::new("BT", vec![]),
+                Operation::new("Tf", vec!["F1".into(), 24.into()]),
+                Operation::new("Td", vec![72.into(), 720.into()]),
+                Operation::new(
+                    "Tj",
+                    vec![Object::string_literal(format!("Page {}", number))],
+                ),
+                Operation::new("ET", vec![]),
+            ]);
+            let content = Content { operations };
+            let content =
+                document.add_object(Stream::new(dictionary! {}, content.encode().unwrap()));
+            let mut xobjects = dictionary! {};
+            if number == 1 {
+                xobjects.set("Cover", cover);
+            }
+            let page = document.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "Contents" => content,
+                "Resources" => dictionary! {
+                    "Font" => dictionary! { "F1" => helvetica, "F2" => garamond },
+                    "XObject" => xobjects,
+                },
+            });
+            kids.push(page.into());
+            page_ids.push(page);
+        }
+
+        document.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => kids,
+                "Count" => pages as i64,
+                "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+            }),
+        );
+        let catalog = document.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        document.trailer.set("Root", catalog);
+
+        for (title, page) in chapters {
+            let chapter = document.add_bookmark(
+                Bookmark::new(title.to_string(), [0.0; 3], 0, page_ids[page - 1]),
+                None,
+            );
+            document.add_bookmark(
+                Bookmark::new(format!("{} notes", title), [0.0; 3], 0, page_ids[page - 1]),
+                Some(chapter),
+            );
+        }
+        if let Some(outline) = document.build_outline() {
+            document
+                .get_dictionary_mut(catalog)
+                .unwrap()
+                .set("Outlines", outline);
+        }
+
+        let mut bytes = Vec::new();
+        document.save_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn pdf_parsing() {
+        let info = PdfInfo::parse(&sample_pdf(3, &[("Spells", 1), ("Monsters", 3)])).unwrap();
+
+        assert_eq!(info.page_count, 3);
+        assert!(!info.is_encrypted);
+        assert_eq!(
+            info.fonts,
+            [
+                FontInfo {
+                    name: String::from("Garamond"),
+                    subtype: String::from("TrueType"),
+                    is_embedded: true,
+                },
+                FontInfo {
+                    name: String::from("Helvetica"),
+                    subtype: String::from("Type1"),
+                    is_embedded: false,
+                },
+            ]
+        );
+        assert_eq!(
+            info.contents,
+            [
+                ContentsInfo {
+                    level: 1,
+                    title: String::from("Spells"),
+                    page: 1,
+                },
+                ContentsInfo {
+                    level: 2,
+                    title: String::from("Spells notes"),
+                    page: 1,
+                },
+                ContentsInfo {
+                    level: 1,
+                    title: String::from("Monsters"),
+                    page: 3,
+                },
+                ContentsInfo {
+                    level: 2,
+                    title: String::from("Monsters notes"),
+                    page: 3,
+                },
+            ]
+        );
+        // the whole page is drawn, with the image where the page puts it
+        let cover = info.cover.unwrap();
+        assert_eq!(cover.dimensions(), (396, 512));
+        assert_eq!(cover.get_pixel(100, 400).0, [128, 128, 128, 255]);
+        assert_eq!(cover.get_pixel(300, 100).0, [255, 255, 255, 255]);
+
+        let plain = PdfInfo::parse(&sample_pdf(1, &[])).unwrap();
+
+        assert!(plain.contents.is_empty());
+
+        for bytes in [b"%PDF-1.7 not really".to_vec(), b"".to_vec()] {
+            assert!(matches!(
+                PdfInfo::parse(&bytes),
+                Err(AppError::Validation(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use super::album::{Album, Track};
+use super::book::Book;
+use super::map::{Map, MapPack};
+use super::stl::Stl;
+use super::tokens::{Token, TokenPack};
+use super::uploads::{FileSlot, Upload, UploadTarget};
+use crate::types::asset::{Asset, Ownership};
+use crate::types::error::AppError;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+// Everything a customer can download, packs and the items inside them
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Downloadable {
+    Book,
+    Album,
+    Track,
+    MapPack,
+    Map,
+    Stl,
+    TokenPack,
+    Token,
+}
+
+#[derive(Serialize)]
+pub struct Entitlement {
+    pub ownership: Ownership,
+    // the key of the uploaded file; packs are downloaded as archives instead
+    pub file: Option<String>,
+}
+
+impl Downloadable {
+    pub fn store(&self) -> &str {
+        match self {
+            Self::Book => "book",
+            Self::Album => "album",
+            Self::Track => "track",
+            Self::MapPack => "map_pack",
+            Self::Map => "map",
+            Self::Stl => "stl",
+            Self::TokenPack => "token_pack",
+            Self::Token => "token",
+        }
+    }
+
+    // Where the item's own file is uploaded; packs have none
+    pub fn upload_target(&self) -> Option<UploadTarget> {
+        match self {
+            Self::Book => Some(UploadTarget::Book),
+            Self::Track => Some(UploadTarget::Track),
+            Self::Map => Some(UploadTarget::Map),
+            Self::Stl => Some(UploadTarget::Stl),
+            Self::Token => Some(UploadTarget::Token),
+            Self::Album | Self::MapPack | Self::TokenPack => None,
+        }
+    }
+}
+
+impl Entitlement {
+    // Items inside a pack are covered by their own purchase, the pack's
+    // purchase, or either one being free
+    pub fn resolve(
+        conn: &mut PgConnection,
+        kind: Downloadable,
+        item_id: i32,
+        user_id: i32,
+    ) -> Result<Entitlement, AppError> {
+        let ownership = match kind {
+            Downloadable::Book => Book::read(conn, item_id)?.check_ownership(conn, user_id)?,
+            Downloadable::Album => Album::read(conn, item_id)?.check_ownership(conn, user_id)?,
+            Downloadable::Track => Track::read(conn, item_id)?.check_ownership(conn, user_id)?,
+            Downloadable::MapPack => {
+                MapPack::read(conn, item_id)?.check_ownership(conn, user_id)?
+            }
+            Downloadable::Map => Map::read(conn, item_id)?.check_ownership(conn, user_id)?,
+            Downloadable::Stl => Stl::read(conn, item_id)?.check_ownership(conn, user_id)?,
+            Downloadable::TokenPack => {
+                TokenPack::read(conn, item_id)?.check_ownership(conn, user_id)?
+            }
+            Downloadable::Token => Token::read(conn, item_id)?.check_ownership(conn, user_id)?,
+        };
+        // the file is the one uploaded for the item, whatever its row says
+        let file = match kind.upload_target() {
+            Some(target) => Upload::read(conn, target, item_id, FileSlot::File)
+                .map(|upload| Some(upload.file_key))
+                .or_else(|err| match err {
+                    AppError::NotFound(_) => Ok(None),
+                    err => Err(err),
+                })?,
+            None => None,
+        };
+
+        Ok(Entitlement { ownership, file })
+    }
+
+    pub fn require(
+        conn: &mut PgConnection,
+        kind: Downloadable,
+        item_id: i32,
+        user_id: i32,
+    ) -> Result<Entitlement, AppError> {
+        let entitlement = Entitlement::resolve(conn, kind, item_id, user_id)?;
+
+        match entitlement.ownership {
+            Ownership::Unowned => Err(AppError::Forbidden(format!(
+                "{} {} has not been purchased",
+                kind.store(),
+                item_id
+            ))),
+            _ => Ok(entitlement),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::album::AlbumCreate;
+    use crate::handlers::album::TrackCreate;
+    use crate::handlers::connect;
+    use crate::handlers::creator::{CreatorNew, Creators};
+    use crate::handlers::ownership::albums::UserAlbum;
+    use crate::handlers::ownership::token_packs::UserTokenPack;
+    use crate::handlers::ownership::tokens::UserToken;
+    use crate::handlers::tokens::{TokenCreate, TokenPackCreate};
+    use crate::handlers::user::{User, UserNew};
+    use crate::types::asset::AssetType;
+    use crate::types::price::{Currency, Price};
+    use crate::types::user::DisplayName;
+
+    #[test]
+    fn entitlements_cascade() {
+        let conn = &mut connect::establish_connection();
+
+        let user = UserNew::create(
+            conn,
+            String::from("entitlements_cascade"),
+            String::from("entitlements_cascade@gmail.com"),
+            String::from("logo.svg"),
+        )
+        .unwrap();
+
+        let creator = CreatorNew::create(
+            conn,
+            user.id,
+            Some(String::from("Chris")),
+            Some(String::from("Hughes")),
+            None,
+            None,
+            DisplayName::Name,
+        )
+        .unwrap();
+
+        let token_pack = TokenPackCreate::new(
+            creator.id,
+            String::from("Entitled Fights"),
+            String::from("thumb.jpg"),
+            String::from("Lots of great locations"),
+            String::from("entitled"),
+            false,
+            String::from("image.jpg"),
+            Price::new(750, Currency::Usd),
+        )
+        .create(conn)
+        .unwrap();
+
+        let mut tokens = Vec::new();
+        for (title, is_free, price) in [
+            ("Single", false, Some(Price::new(100, Currency::Usd))),
+            ("Pack Only", false, None),
+            ("Giveaway", true, None),
+        ] {
+            let token = TokenCreate::new(
+                creator.id,
+                token_pack.id,
+                String::from(title),
+                String::from("thumb.jpg"),
+                String::from("What a token!"),
+                None,
+                None,
+                &token_pack.directory,
+                String::from("image.jpg"),
+                is_free,
+                price,
+            )
+            .create(conn)
+            .unwrap();
+            tokens.push(token);
+        }
+
+        let mismatched = TokenCreate::new(
+            creator.id,
+            token_pack.id,
+            String::from("Broken"),
+            String::from("thumb.jpg"),
+            String::from("What a token!"),
+            None,
+            None,
+            &token_pack.directory,
+            String::from("image.jpg"),
+            false,
+            None,
+        );
+        let mismatched = TokenCreate {
+            price: Some(100),
+            ..mismatched
+        }
+        .create(conn);
+
+        assert!(matches!(mismatched, Err(AppError::Validation(_))));
+
+        let resolve = |conn: &mut PgConnection, token_id: i32| {
+            Entitlement::resolve(conn, Downloadable::Token, token_id, user.id)
+                .unwrap()
+                .ownership
+        };
+
+        assert_eq!(resolve(conn, tokens[0].id), Ownership::Unowned);
+        assert_eq!(resolve(conn, tokens[2].id), Ownership::Free);
+
+        let denied = Entitlement::require(conn, Downloadable::Token, tokens[0].id, user.id);
+
+        assert!(matches!(denied, Err(AppError::Forbidden(_))));
+        assert!(matches!(
+            AssetType::Token.pricing(conn, tokens[1].id),
+            Err(AppError::Validation(_))
+        ));
+
+        AssetType::Token.grant(conn, tokens[0].id, user.id).unwrap();
+
+        let single =
+            Entitlement::require(conn, Downloadable::Token, tokens[0].id, user.id).unwrap();
+
+        assert_eq!(single.ownership, Ownership::Owned);
+        // nothing has been uploaded, whatever the token's own column says
+        assert_eq!(single.file, None);
+        assert_eq!(resolve(conn, tokens[1].id), Ownership::Unowned);
+
+        AssetType::TokenPack
+            .grant(conn, token_pack.id, user.id)
+            .unwrap();
+
+        assert_eq!(resolve(conn, tokens[1].id), Ownership::Owned);
+
+        let album = AlbumCreate::new(
+            creator.id,
+            String::from("Entitled Album"),
+            String::from("thumb.jpg"),
+            String::from("A great album"),
+            String::from("entitled/"),
+            false,
+            String::from("image.jpg"),
+            Price::new(999, Currency::Usd),
+        )
+        .create(conn)
+        .unwrap();
+
+        let track = TrackCreate::new(
+            creator.id,
+            album.id,
+            String::from("Opening"),
+            &album.directory,
+            String::from("track.jpg"),
+        )
+        .create(conn)
+        .unwrap();
+
+        let denied = Entitlement::require(conn, Downloadable::Track, track.id, user.id);
+
+        assert!(matches!(denied, Err(AppError::Forbidden(_))));
+
+        UserAlbum::new(user.id, album.id).create(conn).unwrap();
+
+        let track_entitlement =
+            Entitlement::require(conn, Downloadable::Track, track.id, user.id).unwrap();
+
+        assert_eq!(track_entitlement.ownership, Ownership::Owned);
+        assert_eq!(track_entitlement.file, None);
+
+        UserAlbum::destroy(conn, user.id, album.id).unwrap();
+        Album::destroy(conn, album.id).unwrap();
+        UserToken::destroy(conn, user.id, tokens[0].id).unwrap();
+        UserTokenPack::destroy(conn, user.id, token_pack.id).unwrap();
+        TokenPack::destroy(conn, token_pack.id).unwrap();
+        Creators::destroy(conn, creator.id).unwrap();
+        User::destroy(conn, user.id).unwrap();
+    }
+}
@@ -0,0 +1,292 @@
+use super::storage::StorageBackend;
+use super::uploads::{FileSlot, Upload, UploadTarget};
+use crate::schema::book_previews;
+use crate::types::error::AppError;
+use crate::types::time::now;
+use diesel::prelude::*;
+use lopdf::Document;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+// The pages a creator has chosen to give away, kept as a PDF of their own
+#[derive(Queryable, Selectable, Serialize, PartialEq, Debug)]
+#[diesel(table_name = book_previews)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct BookPreview {
+    #[serde(skip)]
+    pub id: i32,
+    pub book_id: i32,
+    pub first_page: i32,
+    pub last_page: i32,
+    #[serde(skip)]
+    pub source_key: String,
+    #[serde(skip)]
+    pub file_key: String,
+    // unix seconds
+    pub created_at: i64,
+}
+
+#[derive(Insertable, AsChangeset)]
+#[diesel(table_name = book_previews)]
+struct BookPreviewNew {
+    book_id: i32,
+    first_page: i32,
+    last_page: i32,
+    source_key: String,
+    file_key: String,
+    created_at: i64,
+}
+
+#[derive(Deserialize)]
+pub struct PreviewRange {
+    pub first_page: i32,
+    pub last_page: i32,
+}
+
+impl BookPreview {
+    // Cuts the range out of the book's PDF; every page of it must exist
+    pub fn set(
+        conn: &mut PgConnection,
+        storage: &dyn StorageBackend,
+        b_id: i32,
+        range: PreviewRange,
+    ) -> Result<BookPreview, AppError> {
+        if range.first_page < 1 || range.last_page < range.first_page {
+            return Err(AppError::Validation(format!(
+                "pages {} to {} are not a range",
+                range.first_page, range.last_page
+            )));
+        }
+
+        let source = Upload::key(conn, UploadTarget::Book, b_id, FileSlot::File)?;
+        let bytes = storage.get(&source)?;
+        let page_count = Document::load_mem(&bytes)
+            .map(|document| document.get_pages().len() as i32)
+            .map_err(|err| AppError::Validation(format!("book has no usable PDF: {}", err)))?;
+
+        if range.last_page > page_count {
+            return Err(AppError::Validation(format!(
+                "book has {} pages, so page {} cannot be previewed",
+                page_count, range.last_page
+            )));
+        }
+
+        BookPreview::generate(conn, storage, b_id, &source, &bytes, range)
+    }
+
+    // The preview is cut again when the book has a new PDF since; a shorter
+    // edition shortens the range, and one too short for it ends the preview
+    pub fn prepare(
+        conn: &mut PgConnection,
+        storage: &dyn StorageBackend,
+        b_id: i32,
+    ) -> Result<BookPreview, AppError> {
+        let preview = BookPreview::read(conn, b_id)?
+            .ok_or_else(|| AppError::NotFound(format!("book {} has no preview", b_id)))?;
+        let source = Upload::key(conn, UploadTarget::Book, b_id, FileSlot::File)?;
+
+        if preview.source_key == source {
+            return Ok(preview);
+        }
+
+        let bytes = storage.get(&source)?;
+        let page_count = Document::load_mem(&bytes)
+            .map(|document| document.get_pages().len() as i32)
+            .unwrap_or(0);
+
+        if page_count < preview.first_page {
+            BookPreview::destroy(conn, storage, b_id)?;
+            return Err(AppError::NotFound(format!("book {} has no preview", b_id)));
+        }
+
+        let range = PreviewRange {
+            first_page: preview.first_page,
+            last_page: preview.last_page.min(page_count),
+        };
+        BookPreview::generate(conn, storage, b_id, &source, &bytes, range)
+    }
+
+    fn generate(
+        conn: &mut PgConnection,
+        storage: &dyn StorageBackend,
+        b_id: i32,
+        source: &str,
+        bytes: &[u8],
+        range: PreviewRange,
+    ) -> Result<BookPreview, AppError> {
+        let digest = hex::encode(Sha256::digest(format!(
+            "{}\n{}-{}",
+            source, range.first_page, range.last_page
+        )));
+        let key = format!("books/{}/preview-{}.pdf", b_id, &digest[..16]);
+        let previous = BookPreview::read(conn, b_id)?.map(|previous| previous.file_key);
+
+        if previous.as_deref() != Some(&key) {
+            storage.put(&key, &excerpt(bytes, range.first_page, range.last_page)?)?;
+        }
+
+        let row = BookPreviewNew {
+            book_id: b_id,
+            first_page: range.first_page,
+            last_page: range.last_page,
+            source_key: source.to_owned(),
+            file_key: key.to_owned(),
+            created_at: now(),
+        };
+        let preview = diesel::insert_into(book_previews::table)
+            .values(&row)
+            .on_conflict(book_previews::book_id)
+            .do_update()
+            .set(&row)
+            .returning(BookPreview::as_returning())
+            .get_result(conn)?;
+
+        if let Some(previous) = previous.filter(|previous| *previous != key) {
+            storage.delete(&previous)?;
+        }
+
+        Ok(preview)
+    }
+
+    pub fn read(conn: &mut PgConnection, b_id: i32) -> Result<Option<BookPreview>, AppError> {
+        use crate::schema::book_previews::dsl::*;
+
+        let preview = book_previews
+            .filter(book_id.eq(b_id))
+            .select(BookPreview::as_select())
+            .get_result(conn)
+            .optional()?;
+
+        Ok(preview)
+    }
+
+    pub fn destroy(
+        conn: &mut PgConnection,
+        storage: &dyn StorageBackend,
+        b_id: i32,
+    ) -> Result<usize, AppError> {
+        let key = diesel::delete(book_previews::table.filter(book_previews::book_id.eq(b_id)))
+            .returning(book_previews::file_key)
+            .get_result::<String>(conn)
+            .optional()?;
+
+        let Some(key) = key else {
+            return Ok(0);
+        };
+        storage.delete(&key)?;
+
+        Ok(1)
+    }
+
+    pub fn destroy_all(conn: &mut PgConnection, b_id: i32) -> Result<usize, AppError> {
+        use crate::schema::book_previews::dsl::*;
+
+        let changes = diesel::delete(book_previews.filter(book_id.eq(b_id))).execute(conn)?;
+
+        Ok(changes)
+    }
+}
+
+// A copy of the PDF holding only pages first to last, counted from 1. The
+// outline goes, since most of it would point at pages that are not there.
+pub fn excerpt(bytes: &[u8], first: i32, last: i32) -> Result<Vec<u8>, AppError> {
+    let mut document = Document::load_mem(bytes)
+        .map_err(|err| AppError::Validation(format!("book has no usable PDF: {}", err)))?;
+    let outside = document
+        .get_pages()
+        .into_keys()
+        .filter(|number| (*number as i32) < first || (*number as i32) > last)
+        .collect::<Vec<u32>>();
+
+    document.delete_pages(&outside);
+    if let Ok(catalog) = document.catalog_mut() {
+        catalog.remove(b"Outlines");
+    }
+    document.prune_objects();
+
+    let mut excerpt = Vec::new();
+    document
+        .save_to(&mut excerpt)
+        .map_err(|err| AppError::Storage(format!("preview could not be written: {}", err)))?;
+
+    Ok(excerpt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::content::{Content, Operation};
+    use lopdf::{dictionary, Bookmark, Object, Stream};
+
+    // Each page says which it is, with a bookmark to the last
+    fn book(pages: usize) -> Vec<u8> {
+        let mut document = Document::with_version("1.5");
+        let pages_id = document.new_object_id();
+        let mut kids = Vec::new();
+        for number in 1..=pages {
+            let content = Content {
+                operations: vec![Operation::new(
+                    "Tj",
+                    vec![Object::string_literal(format!("Page {}", number))],
+                )],
+            };
+            let content =
+                document.add_object(Stream::new(dictionary! {}, content.encode().unwrap()));
+            kids.push(Object::Reference(document.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "Contents" => content,
+            })));
+        }
+        let last = kids.last().unwrap().as_reference().unwrap();
+        document.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => kids,
+                "Count" => pages as i64,
+                "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+            }),
+        );
+        let catalog = document.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        document.trailer.set("Root", catalog);
+        document.add_bookmark(
+            Bookmark::new(String::from("Appendix"), [0.0; 3], 0, last),
+            None,
+        );
+        if let Some(outline) = document.build_outline() {
+            document
+                .get_dictionary_mut(catalog)
+                .unwrap()
+                .set("Outlines", outline);
+        }
+
+        let mut bytes = Vec::new();
+        document.save_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn excerpt_pages() {
+        let preview = Document::load_mem(&excerpt(&book(6), 2, 4).unwrap()).unwrap();
+        let pages = preview.get_pages();
+
+        assert_eq!(pages.len(), 3);
+        for (number, page) in pages {
+            let content = String::from_utf8_lossy(&preview.get_page_content(page)).into_owned();
+            assert!(content.contains(&format!("(Page {})", number + 1)));
+        }
+        assert!(preview.catalog().unwrap().get(b"Outlines").is_err());
+
+        let whole = Document::load_mem(&excerpt(&book(2), 1, 2).unwrap()).unwrap();
+
+        assert_eq!(whole.get_pages().len(), 2);
+        assert!(matches!(
+            excerpt(b"%PDF-1.7 not really", 1, 1),
+            Err(AppError::Validation(_))
+        ));
+    }
+}
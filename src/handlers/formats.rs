@@ -0,0 +1,750 @@
+use super::book::Book;
+use super::documents::PdfInfo;
+use super::storage::StorageBackend;
+use super::uploads::{Incoming, Upload};
+use crate::schema::{book_chapters, book_files};
+use crate::types::asset::Asset;
+use crate::types::error::AppError;
+use crate::types::time::now;
+use diesel::prelude::*;
+use roxmltree::{Document, Node, ParsingOptions};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use zip::ZipArchive;
+
+// The largest part of an EPUB that is unpacked for its metadata
+const MAX_PART: u64 = 16 * 1024 * 1024;
+// Tables of contents may nest; anything deeper is flattened into this level
+const MAX_LEVEL: i32 = 16;
+
+const DUBLIN_CORE: &str = "http://purl.org/dc/elements/1.1/";
+const OPS: &str = "http://www.idpf.org/2007/ops";
+
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BookFormat {
+    Pdf,
+    Epub,
+    // laid out for printing at home, without backgrounds or bleed
+    PrintPdf,
+}
+
+// What an EPUB says about itself
+#[derive(PartialEq, Debug)]
+pub struct EpubInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub chapters: Vec<ChapterInfo>,
+}
+
+// One entry of the table of contents
+#[derive(PartialEq, Debug)]
+pub struct ChapterInfo {
+    // 1 at the top of the table
+    pub level: i32,
+    pub title: String,
+}
+
+// One format of a book, downloaded on its own
+#[derive(Queryable, Selectable, Serialize, PartialEq, Debug)]
+#[diesel(table_name = book_files)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct BookFile {
+    pub id: i32,
+    pub book_id: i32,
+    pub format: String,
+    pub file_key: String,
+    pub file_size: i64,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    // unix seconds
+    pub uploaded_at: i64,
+}
+
+#[derive(Insertable, AsChangeset)]
+#[diesel(table_name = book_files)]
+#[diesel(treat_none_as_null = true)]
+struct BookFileNew {
+    book_id: i32,
+    format: String,
+    file_key: String,
+    file_size: i64,
+    title: Option<String>,
+    author: Option<String>,
+    uploaded_at: i64,
+}
+
+#[derive(Queryable, Selectable, Serialize, PartialEq, Debug)]
+#[diesel(table_name = book_chapters)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct BookChapter {
+    #[serde(skip)]
+    pub id: i32,
+    #[serde(skip)]
+    pub book_file_id: i32,
+    pub position: i32,
+    pub level: i32,
+    pub title: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = book_chapters)]
+struct BookChapterNew {
+    book_file_id: i32,
+    position: i32,
+    level: i32,
+    title: String,
+}
+
+#[derive(Serialize, PartialEq, Debug)]
+pub struct FormatDetails {
+    #[serde(flatten)]
+    pub file: BookFile,
+    // only EPUBs list their chapters here; a PDF's outline is the book's contents
+    pub chapters: Vec<BookChapter>,
+}
+
+impl BookFormat {
+    pub fn store(&self) -> &str {
+        match self {
+            Self::Pdf => "pdf",
+            Self::Epub => "epub",
+            Self::PrintPdf => "print_pdf",
+        }
+    }
+}
+
+impl EpubInfo {
+    pub fn parse(bytes: &[u8]) -> Result<EpubInfo, AppError> {
+        let mut package =
+            ZipArchive::new(Cursor::new(bytes)).map_err(|_| invalid("it is not a zip package"))?;
+
+        if read_part(&mut package, "mimetype")?.trim() != "application/epub+zip" {
+            return Err(invalid("its mimetype is not application/epub+zip"));
+        }
+
+        let container = read_part(&mut package, "META-INF/container.xml")?;
+        let opf_path = Document::parse(&container)
+            .ok()
+            .and_then(|container| {
+                container
+                    .descendants()
+                    .filter(|node| node.has_tag_name_local("rootfile"))
+                    .find(|node| {
+                        node.attribute("media-type")
+                            .is_none_or(|kind| kind == "application/oebps-package+xml")
+                    })
+                    .and_then(|node| node.attribute("full-path"))
+                    .map(str::to_owned)
+            })
+            .ok_or_else(|| invalid("its container names no package document"))?;
+        let opf = read_part(&mut package, &opf_path)?;
+        let opf = Document::parse(&opf).map_err(|_| invalid("its package is not valid XML"))?;
+
+        let metadata = |name: &str| {
+            opf.descendants()
+                .filter(|node| node.has_tag_name((DUBLIN_CORE, name)))
+                .filter_map(|node| node.text())
+                .map(|text| text.split_whitespace().collect::<Vec<&str>>().join(" "))
+                .filter(|text| !text.is_empty())
+                .collect::<Vec<String>>()
+        };
+        let title = metadata("title")
+            .into_iter()
+            .next()
+            .ok_or_else(|| invalid("it has no title"))?;
+        let authors = metadata("creator");
+
+        // the package's hrefs are relative to the package document
+        let manifest = opf
+            .descendants()
+            .filter(|node| node.has_tag_name_local("item"))
+            .filter_map(|node| {
+                Some((
+                    node.attribute("id")?,
+                    (
+                        resolve(&opf_path, node.attribute("href")?),
+                        node.attribute("properties").unwrap_or(""),
+                    ),
+                ))
+            })
+            .collect::<HashMap<&str, (String, &str)>>();
+        let nav = manifest
+            .values()
+            .find(|(_, properties)| properties.split_whitespace().any(|p| p == "nav"))
+            .map(|(path, _)| path.to_owned());
+        let ncx = opf
+            .descendants()
+            .find(|node| node.has_tag_name_local("spine"))
+            .and_then(|spine| spine.attribute("toc"))
+            .and_then(|id| manifest.get(id))
+            .map(|(path, _)| path.to_owned());
+
+        // a missing or damaged table of contents leaves the chapters empty;
+        // EPUB 3 navigation is preferred over the older NCX
+        let chapters = nav
+            .and_then(|path| read_part(&mut package, &path).ok())
+            .and_then(|xhtml| nav_chapters(&xhtml))
+            .filter(|chapters| !chapters.is_empty())
+            .or_else(|| {
+                ncx.and_then(|path| read_part(&mut package, &path).ok())
+                    .and_then(|xml| ncx_chapters(&xml))
+            })
+            .unwrap_or_default();
+
+        Ok(EpubInfo {
+            title: title.chars().take(280).collect(),
+            authors,
+            chapters,
+        })
+    }
+
+    // A book's title may leave out the EPUB's subtitle, or the EPUB the
+    // book's, but one must begin with the other. Authors are not checked,
+    // since creators publish writers other than themselves.
+    pub fn check_title(&self, book_title: &str) -> Result<(), AppError> {
+        let (epub, book) = (words(&self.title), words(book_title));
+        let begins = |long: &[String], short: &[String]| long.starts_with(short);
+
+        match !book.is_empty() && (begins(&epub, &book) || begins(&book, &epub)) {
+            true => Ok(()),
+            false => Err(AppError::Validation(format!(
+                "EPUB is titled \"{}\" but the book is \"{}\"",
+                self.title, book_title
+            ))),
+        }
+    }
+}
+
+impl BookFile {
+    // Adds or replaces one of the book's other formats. EPUBs are read first
+    // so one that does not parse, or is a different book, is refused
+    pub fn store(
+        conn: &mut PgConnection,
+        storage: &dyn StorageBackend,
+        b_id: i32,
+        format: BookFormat,
+        incoming: Incoming,
+    ) -> Result<FormatDetails, AppError> {
+        let (title, author, chapters) = match format {
+            BookFormat::Pdf => {
+                return Err(AppError::Validation(String::from(
+                    "the book's PDF is uploaded as its file",
+                )))
+            }
+            BookFormat::Epub => {
+                let info = EpubInfo::parse(&incoming.read()?)?;
+                info.check_title(&Book::read(conn, b_id)?.title)?;
+                let author = Some(info.authors.join(", "))
+                    .filter(|author| !author.is_empty())
+                    .map(|author| author.chars().take(280).collect());
+                (Some(info.title), author, info.chapters)
+            }
+            BookFormat::PrintPdf => {
+                PdfInfo::parse(&incoming.read()?)?;
+                Book::read(conn, b_id)?;
+                (None, None, Vec::new())
+            }
+        };
+
+        let (file_format, spooled, checksum) = incoming.finish()?;
+        let key = format!(
+            "books/{}/{}-{}.{}",
+            b_id,
+            format.store(),
+            &checksum[..16],
+            file_format.extension
+        );
+        let previous = BookFile::read(conn, b_id, format)
+            .ok()
+            .map(|previous| previous.file_key);
+
+        storage.put_file(&key, spooled.path())?;
+
+        let row = BookFileNew {
+            book_id: b_id,
+            format: String::from(format.store()),
+            file_key: key.to_owned(),
+            file_size: spooled.size as i64,
+            title,
+            author,
+            uploaded_at: now(),
+        };
+        let stored = conn.transaction(|conn| {
+            let file = BookFile::record(conn, &row)?;
+
+            diesel::delete(book_chapters::table.filter(book_chapters::book_file_id.eq(file.id)))
+                .execute(conn)?;
+            let chapters = chapters
+                .into_iter()
+                .enumerate()
+                .map(|(position, chapter)| BookChapterNew {
+                    book_file_id: file.id,
+                    position: position as i32,
+                    level: chapter.level,
+                    title: chapter.title,
+                })
+                .collect::<Vec<BookChapterNew>>();
+            let chapters = diesel::insert_into(book_chapters::table)
+                .values(&chapters)
+                .returning(BookChapter::as_returning())
+                .get_results(conn)?;
+
+            Ok(FormatDetails { file, chapters })
+        });
+
+        // an identical re-upload lands on the key it already had, which is kept
+        let unused = match &stored {
+            Ok(_) => previous.filter(|previous| *previous != key),
+            Err(_) => Some(key.to_owned()).filter(|_| previous.as_deref() != Some(&key)),
+        };
+        if let Some(unused) = unused {
+            storage.delete(&unused)?;
+        }
+
+        stored
+    }
+
+    // The file uploaded through the book's own `file` slot is its PDF
+    pub fn replace_main(
+        conn: &mut PgConnection,
+        b_id: i32,
+        upload: &Upload,
+    ) -> Result<BookFile, AppError> {
+        BookFile::record(
+            conn,
+            &BookFileNew {
+                book_id: b_id,
+                format: String::from(BookFormat::Pdf.store()),
+                file_key: upload.file_key.to_owned(),
+                file_size: upload.size,
+                title: None,
+                author: None,
+                uploaded_at: upload.uploaded_at,
+            },
+        )
+    }
+
+    fn record(conn: &mut PgConnection, row: &BookFileNew) -> Result<BookFile, AppError> {
+        let file = diesel::insert_into(book_files::table)
+            .values(row)
+            .on_conflict((book_files::book_id, book_files::format))
+            .do_update()
+            .set(row)
+            .returning(BookFile::as_returning())
+            .get_result(conn)?;
+
+        Ok(file)
+    }
+
+    pub fn read(
+        conn: &mut PgConnection,
+        b_id: i32,
+        book_format: BookFormat,
+    ) -> Result<BookFile, AppError> {
+        use crate::schema::book_files::dsl::*;
+
+        let file = book_files
+            .filter(book_id.eq(b_id))
+            .filter(format.eq(book_format.store()))
+            .select(BookFile::as_select())
+            .get_result(conn)?;
+
+        Ok(file)
+    }
+
+    // The PDF is the book's own file and is only ever replaced
+    pub fn destroy(
+        conn: &mut PgConnection,
+        storage: &dyn StorageBackend,
+        b_id: i32,
+        format: BookFormat,
+    ) -> Result<usize, AppError> {
+        if format == BookFormat::Pdf {
+            return Err(AppError::Validation(String::from(
+                "the book's PDF cannot be removed",
+            )));
+        }
+        let Ok(file) = BookFile::read(conn, b_id, format) else {
+            return Ok(0);
+        };
+
+        let chapters =
+            diesel::delete(book_chapters::table.filter(book_chapters::book_file_id.eq(file.id)))
+                .execute(conn)?;
+        let files = diesel::delete(book_files::table.find(file.id)).execute(conn)?;
+        storage.delete(&file.file_key)?;
+
+        Ok(chapters + files)
+    }
+
+    pub fn destroy_all(conn: &mut PgConnection, b_id: i32) -> Result<usize, AppError> {
+        let files = book_files::table
+            .filter(book_files::book_id.eq(b_id))
+            .select(book_files::id);
+        let chapters =
+            diesel::delete(book_chapters::table.filter(book_chapters::book_file_id.eq_any(files)))
+                .execute(conn)?;
+        let files =
+            diesel::delete(book_files::table.filter(book_files::book_id.eq(b_id))).execute(conn)?;
+
+        Ok(chapters + files)
+    }
+}
+
+impl FormatDetails {
+    pub fn list(conn: &mut PgConnection, b_id: i32) -> Result<Vec<FormatDetails>, AppError> {
+        let files = book_files::table
+            .filter(book_files::book_id.eq(b_id))
+            .order(book_files::id.asc())
+            .select(BookFile::as_select())
+            .get_results(conn)?;
+        let ids = files.iter().map(|file| file.id).collect::<Vec<i32>>();
+        let mut chapters = book_chapters::table
+            .filter(book_chapters::book_file_id.eq_any(ids))
+            .order(book_chapters::position.asc())
+            .select(BookChapter::as_select())
+            .get_results(conn)?
+            .into_iter()
+            .fold(HashMap::new(), |mut chapters, chapter| {
+                chapters
+                    .entry(chapter.book_file_id)
+                    .or_insert_with(Vec::new)
+                    .push(chapter);
+                chapters
+            });
+
+        Ok(files
+            .into_iter()
+            .map(|file| FormatDetails {
+                chapters: chapters.remove(&file.id).unwrap_or_default(),
+                file,
+            })
+            .collect())
+    }
+}
+
+// Chapters from the EPUB 3 navigation document's table of contents
+fn nav_chapters(xhtml: &str) -> Option<Vec<ChapterInfo>> {
+    let document = Document::parse_with_options(
+        xhtml,
+        ParsingOptions {
+            allow_dtd: true,
+            ..ParsingOptions::default()
+        },
+    )
+    .ok()?;
+    let navs = document
+        .descendants()
+        .filter(|node| node.has_tag_name_local("nav"))
+        .collect::<Vec<Node>>();
+    let toc = navs
+        .iter()
+        .find(|nav| {
+            nav.attribute((OPS, "type"))
+                .is_some_and(|kind| kind.split_whitespace().any(|kind| kind == "toc"))
+        })
+        .or(navs.first())?;
+    let list = toc.children().find(|node| node.has_tag_name_local("ol"))?;
+
+    let mut chapters = Vec::new();
+    nav_list(list, 1, &mut chapters);
+    Some(chapters)
+}
+
+fn nav_list(list: Node, level: i32, chapters: &mut Vec<ChapterInfo>) {
+    for item in list.children().filter(|node| node.has_tag_name_local("li")) {
+        let label = item
+            .children()
+            .find(|node| node.has_tag_name_local("a") || node.has_tag_name_local("span"));
+        if let Some(title) = label.map(text_of).filter(|title| !title.is_empty()) {
+            chapters.push(ChapterInfo { level, title });
+        }
+        if let Some(nested) = item.children().find(|node| node.has_tag_name_local("ol")) {
+            nav_list(nested, (level + 1).min(MAX_LEVEL), chapters);
+        }
+    }
+}
+
+// Chapters from an EPUB 2 NCX
+fn ncx_chapters(xml: &str) -> Option<Vec<ChapterInfo>> {
+    let document = Document::parse_with_options(
+        xml,
+        ParsingOptions {
+            allow_dtd: true,
+            ..ParsingOptions::default()
+        },
+    )
+    .ok()?;
+    let map = document
+        .descendants()
+        .find(|node| node.has_tag_name_local("navMap"))?;
+
+    let mut chapters = Vec::new();
+    nav_points(map, 1, &mut chapters);
+    Some(chapters)
+}
+
+fn nav_points(parent: Node, level: i32, chapters: &mut Vec<ChapterInfo>) {
+    for point in parent
+        .children()
+        .filter(|node| node.has_tag_name_local("navPoint"))
+    {
+        let title = point
+            .children()
+            .find(|node| node.has_tag_name_local("navLabel"))
+            .map(text_of)
+            .unwrap_or_default();
+        if !title.is_empty() {
+            chapters.push(ChapterInfo { level, title });
+        }
+        nav_points(point, (level + 1).min(MAX_LEVEL), chapters);
+    }
+}
+
+// All the text beneath a node, with its whitespace collapsed
+fn text_of(node: Node) -> String {
+    let text = node
+        .descendants()
+        .filter(|node| node.is_text())
+        .filter_map(|node| node.text())
+        .collect::<String>();
+
+    text.split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .chars()
+        .take(280)
+        .collect()
+}
+
+// A manifest href, which may be percent encoded, as a path in the package
+fn resolve(base: &str, href: &str) -> String {
+    let href = href.split('#').next().unwrap_or(href);
+    let mut parts = base.split('/').collect::<Vec<&str>>();
+    parts.pop();
+
+    for part in href.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+
+    decode(&parts.join("/"))
+}
+
+fn decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// Lower case words, so punctuation and case do not count as a different title
+fn words(title: &str) -> Vec<String> {
+    title
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+fn read_part(package: &mut ZipArchive<Cursor<&[u8]>>, name: &str) -> Result<String, AppError> {
+    let part = package
+        .by_name(name)
+        .map_err(|_| invalid(&format!("it has no {}", name)))?;
+    let mut text = String::new();
+    part.take(MAX_PART)
+        .read_to_string(&mut text)
+        .map_err(|_| invalid(&format!("its {} could not be read", name)))?;
+
+    Ok(text)
+}
+
+trait LocalName {
+    fn has_tag_name_local(&self, name: &str) -> bool;
+}
+
+// EPUBs mix the OPF, XHTML and NCX namespaces, so elements are matched on
+// their local name
+impl LocalName for Node<'_, '_> {
+    fn has_tag_name_local(&self, name: &str) -> bool {
+        self.is_element() && self.tag_name().name() == name
+    }
+}
+
+fn invalid(reason: &str) -> AppError {
+    AppError::Validation(format!("not a usable EPUB: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    fn package(parts: &[(&str, &str)]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, text) in parts {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(text.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    const CONTAINER: &str = r#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>"#;
+
+    const OPF: &str = r#"<?xml version="1.0"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:title>Dungeons  and Dragons: Player's Handbook</dc:title>
+    <dc:creator>Gary Gygax</dc:creator>
+    <dc:creator>Dave Arneson</dc:creator>
+  </metadata>
+  <manifest>
+    <item id="nav" href="text/nav%20doc.xhtml" properties="nav" media-type="application/xhtml+xml"/>
+    <item id="ncx" href="toc.ncx" media-type="application/x-dtbncx+xml"/>
+  </manifest>
+  <spine toc="ncx"/>
+</package>"#;
+
+    const NAV: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+  <body>
+    <nav epub:type="landmarks"><ol><li><a href="cover.xhtml">Cover</a></li></ol></nav>
+    <nav epub:type="toc">
+      <ol>
+        <li><a href="../spells.xhtml">Spells</a>
+          <ol><li><a href="../spells.xhtml#cantrips"><em>Cantrips</em></a></li></ol>
+        </li>
+        <li><span>Monsters</span></li>
+      </ol>
+    </nav>
+  </body>
+</html>"#;
+
+    const NCX: &str = r#"<?xml version="1.0"?>
+<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/" version="2005-1">
+  <navMap>
+    <navPoint id="one"><navLabel><text>Classes</text></navLabel><content src="classes.xhtml"/>
+      <navPoint id="two"><navLabel><text>Fighter</text></navLabel><content src="fighter.xhtml"/></navPoint>
+    </navPoint>
+  </navMap>
+</ncx>"#;
+
+    #[test]
+    fn epub_parsing() {
+        let epub = package(&[
+            ("mimetype", "application/epub+zip"),
+            ("META-INF/container.xml", CONTAINER),
+            ("OEBPS/content.opf", OPF),
+            ("OEBPS/text/nav doc.xhtml", NAV),
+            ("OEBPS/toc.ncx", NCX),
+        ]);
+        let info = EpubInfo::parse(&epub).unwrap();
+
+        assert_eq!(info.title, "Dungeons and Dragons: Player's Handbook");
+        assert_eq!(info.authors, ["Gary Gygax", "Dave Arneson"]);
+        assert_eq!(
+            info.chapters,
+            [
+                ChapterInfo {
+                    level: 1,
+                    title: String::from("Spells"),
+                },
+                ChapterInfo {
+                    level: 2,
+                    title: String::from("Cantrips"),
+                },
+                ChapterInfo {
+                    level: 1,
+                    title: String::from("Monsters"),
+                },
+            ]
+        );
+
+        assert!(info.check_title("Dungeons and Dragons!").is_ok());
+        assert!(info
+            .check_title("dungeons and dragons player's handbook")
+            .is_ok());
+        assert!(info.check_title("Dungeons").is_ok());
+        assert!(matches!(
+            info.check_title("For Whom the Bell Tolls"),
+            Err(AppError::Validation(_))
+        ));
+        assert!(matches!(info.check_title(""), Err(AppError::Validation(_))));
+
+        // without a navigation document the NCX is read instead
+        let epub2 = package(&[
+            ("mimetype", "application/epub+zip"),
+            ("META-INF/container.xml", CONTAINER),
+            (
+                "OEBPS/content.opf",
+                &OPF.replace(r#" properties="nav""#, ""),
+            ),
+            ("OEBPS/toc.ncx", NCX),
+        ]);
+        let info = EpubInfo::parse(&epub2).unwrap();
+
+        assert_eq!(
+            info.chapters,
+            [
+                ChapterInfo {
+                    level: 1,
+                    title: String::from("Classes"),
+                },
+                ChapterInfo {
+                    level: 2,
+                    title: String::from("Fighter"),
+                },
+            ]
+        );
+
+        let untitled = package(&[
+            ("mimetype", "application/epub+zip"),
+            ("META-INF/container.xml", CONTAINER),
+            (
+                "OEBPS/content.opf",
+                &OPF.replace("Dungeons  and Dragons: Player's Handbook", " "),
+            ),
+        ]);
+        let not_epub = package(&[("mimetype", "application/zip")]);
+
+        for bytes in [untitled, not_epub, b"PK\x03\x04 not really".to_vec()] {
+            assert!(matches!(
+                EpubInfo::parse(&bytes),
+                Err(AppError::Validation(_))
+            ));
+        }
+    }
+}
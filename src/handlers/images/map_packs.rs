@@ -0,0 +1,111 @@
+use crate::schema::map_pack_images;
+use crate::types::asset::GalleryImage;
+use crate::types::error::AppError;
+use diesel::prelude::*;
+
+#[derive(Insertable)]
+pub struct MapPackImage {
+    map_pack_id: i32,
+    file: String,
+    position: i32,
+}
+
+impl MapPackImage {
+    // New images are appended to the end of the gallery
+    pub fn create(
+        conn: &mut PgConnection,
+        p_id: i32,
+        image_file: String,
+    ) -> Result<GalleryImage, AppError> {
+        use crate::schema::map_pack_images::dsl::*;
+
+        let last = map_pack_images
+            .filter(map_pack_id.eq(p_id))
+            .select(diesel::dsl::max(position))
+            .get_result::<Option<i32>>(conn)?;
+
+        let image = diesel::insert_into(map_pack_images)
+            .values(MapPackImage {
+                map_pack_id: p_id,
+                file: image_file,
+                position: last.map_or(0, |p| p + 1),
+            })
+            .returning((id, map_pack_id, file, position))
+            .get_result(conn)?;
+
+        Ok(image)
+    }
+
+    pub fn list(conn: &mut PgConnection, p_id: i32) -> Result<Vec<GalleryImage>, AppError> {
+        use crate::schema::map_pack_images::dsl::*;
+
+        let images = map_pack_images
+            .filter(map_pack_id.eq(p_id))
+            .order((position, id))
+            .select((id, map_pack_id, file, position))
+            .get_results(conn)?;
+
+        Ok(images)
+    }
+
+    pub fn files(conn: &mut PgConnection, p_id: i32) -> Result<Vec<String>, AppError> {
+        let images = MapPackImage::list(conn, p_id)?;
+
+        Ok(images.into_iter().map(|image| image.file).collect())
+    }
+
+    // order holds every image id of the gallery in its new display order
+    pub fn reorder(conn: &mut PgConnection, p_id: i32, order: &[i32]) -> Result<usize, AppError> {
+        use crate::schema::map_pack_images::dsl::*;
+
+        let mut unique = order.to_vec();
+        unique.sort_unstable();
+        unique.dedup();
+
+        conn.transaction(|conn| {
+            let count = map_pack_images
+                .filter(map_pack_id.eq(p_id))
+                .count()
+                .get_result::<i64>(conn)?;
+
+            let mut changes: usize = 0;
+            for (index, image_id) in order.iter().enumerate() {
+                changes += diesel::update(map_pack_images)
+                    .filter(id.eq(image_id))
+                    .filter(map_pack_id.eq(p_id))
+                    .set(position.eq(index as i32))
+                    .execute(conn)?;
+            }
+
+            if changes == order.len() && unique.len() == order.len() && count == changes as i64 {
+                Ok(changes)
+            } else {
+                Err(AppError::Validation(format!(
+                    "order does not match the images of map pack {}",
+                    p_id
+                )))
+            }
+        })
+    }
+
+    pub fn destroy(conn: &mut PgConnection, p_id: i32, image_id: i32) -> Result<usize, AppError> {
+        use crate::schema::map_pack_images::dsl::*;
+
+        let changes = diesel::delete(
+            map_pack_images
+                .filter(id.eq(image_id))
+                .filter(map_pack_id.eq(p_id)),
+        )
+        .execute(conn)?;
+
+        Ok(changes)
+    }
+
+    pub fn destroy_all(conn: &mut PgConnection, p_id: i32) -> Result<usize, AppError> {
+        use crate::schema::map_pack_images::dsl::*;
+
+        let changes = diesel::delete(map_pack_images.filter(map_pack_id.eq(p_id))).execute(conn)?;
+
+        Ok(changes)
+    }
+}
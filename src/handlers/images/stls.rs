@@ -0,0 +1,149 @@
+use crate::schema::stl_images;
+use crate::types::asset::GalleryImage;
+use crate::types::error::AppError;
+use diesel::prelude::*;
+
+#[derive(Insertable)]
+pub struct StlImage {
+    stl_id: i32,
+    file: String,
+    position: i32,
+    is_preview: bool,
+}
+
+impl StlImage {
+    // New images are appended to the end of the gallery
+    pub fn create(
+        conn: &mut PgConnection,
+        s_id: i32,
+        image_file: String,
+    ) -> Result<GalleryImage, AppError> {
+        use crate::schema::stl_images::dsl::*;
+
+        let last = stl_images
+            .filter(stl_id.eq(s_id))
+            .select(diesel::dsl::max(position))
+            .get_result::<Option<i32>>(conn)?;
+
+        let image = diesel::insert_into(stl_images)
+            .values(StlImage {
+                stl_id: s_id,
+                file: image_file,
+                position: last.map_or(0, |p| p + 1),
+                is_preview: false,
+            })
+            .returning((id, stl_id, file, position))
+            .get_result(conn)?;
+
+        Ok(image)
+    }
+
+    // Swaps the rendered previews for a new set, placed after the creator's own
+    // images; returns the new entries and the files of the old ones
+    pub fn replace_previews(
+        conn: &mut PgConnection,
+        s_id: i32,
+        previews: Vec<String>,
+    ) -> Result<(Vec<GalleryImage>, Vec<String>), AppError> {
+        use crate::schema::stl_images::dsl::*;
+
+        conn.transaction(|conn| {
+            let previous = diesel::delete(stl_images.filter(stl_id.eq(s_id)).filter(is_preview))
+                .returning(file)
+                .get_results::<String>(conn)?;
+
+            let last = stl_images
+                .filter(stl_id.eq(s_id))
+                .select(diesel::dsl::max(position))
+                .get_result::<Option<i32>>(conn)?;
+            let first = last.map_or(0, |p| p + 1);
+
+            let rows = previews
+                .into_iter()
+                .enumerate()
+                .map(|(index, preview)| StlImage {
+                    stl_id: s_id,
+                    file: preview,
+                    position: first + index as i32,
+                    is_preview: true,
+                })
+                .collect::<Vec<StlImage>>();
+
+            let images = diesel::insert_into(stl_images)
+                .values(&rows)
+                .returning((id, stl_id, file, position))
+                .get_results(conn)?;
+
+            Ok((images, previous))
+        })
+    }
+
+    pub fn list(conn: &mut PgConnection, s_id: i32) -> Result<Vec<GalleryImage>, AppError> {
+        use crate::schema::stl_images::dsl::*;
+
+        let images = stl_images
+            .filter(stl_id.eq(s_id))
+            .order((position, id))
+            .select((id, stl_id, file, position))
+            .get_results(conn)?;
+
+        Ok(images)
+    }
+
+    pub fn files(conn: &mut PgConnection, s_id: i32) -> Result<Vec<String>, AppError> {
+        let images = StlImage::list(conn, s_id)?;
+
+        Ok(images.into_iter().map(|image| image.file).collect())
+    }
+
+    // order holds every image id of the gallery in its new display order
+    pub fn reorder(conn: &mut PgConnection, s_id: i32, order: &[i32]) -> Result<usize, AppError> {
+        use crate::schema::stl_images::dsl::*;
+
+        let mut unique = order.to_vec();
+        unique.sort_unstable();
+        unique.dedup();
+
+        conn.transaction(|conn| {
+            let count = stl_images
+                .filter(stl_id.eq(s_id))
+                .count()
+                .get_result::<i64>(conn)?;
+
+            let mut changes: usize = 0;
+            for (index, image_id) in order.iter().enumerate() {
+                changes += diesel::update(stl_images)
+                    .filter(id.eq(image_id))
+                    .filter(stl_id.eq(s_id))
+                    .set(position.eq(index as i32))
+                    .execute(conn)?;
+            }
+
+            if changes == order.len() && unique.len() == order.len() && count == changes as i64 {
+                Ok(changes)
+            } else {
+                Err(AppError::Validation(format!(
+                    "order does not match the images of stl {}",
+                    s_id
+                )))
+            }
+        })
+    }
+
+    pub fn destroy(conn: &mut PgConnection, s_id: i32, image_id: i32) -> Result<usize, AppError> {
+        use crate::schema::stl_images::dsl::*;
+
+        let changes = diesel::delete(stl_images.filter(id.eq(image_id)).filter(stl_id.eq(s_id)))
+            .execute(conn)?;
+
+        Ok(changes)
+    }
+
+    pub fn destroy_all(conn: &mut PgConnection, s_id: i32) -> Result<usize, AppError> {
+        use crate::schema::stl_images::dsl::*;
+
+        let changes = diesel::delete(stl_images.filter(stl_id.eq(s_id))).execute(conn)?;
+
+        Ok(changes)
+    }
+}
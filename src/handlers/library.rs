@@ -0,0 +1,277 @@
+use super::ownership::albums::UserAlbum;
+use super::ownership::books::UserBook;
+use super::ownership::map_packs::UserMapPack;
+use super::ownership::maps::UserMap;
+use super::ownership::stls::UserStl;
+use super::ownership::token_packs::UserTokenPack;
+use super::ownership::tokens::UserToken;
+use crate::types::asset::{AssetType, Summary};
+use crate::types::error::AppError;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub const MAX_PER_PAGE: i64 = 100;
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum LibrarySort {
+    #[default]
+    Newest,
+    Oldest,
+}
+
+#[derive(Serialize)]
+pub struct LibraryItem {
+    pub asset_type: AssetType,
+    pub asset_id: i32,
+    // unix seconds
+    pub purchased_at: i64,
+    pub summary: Summary,
+}
+
+#[derive(Serialize)]
+pub struct Library {
+    pub items: Vec<LibraryItem>,
+    pub page: i64,
+    pub per_page: i64,
+    // everything matching the filter, across all pages
+    pub total: usize,
+}
+
+const OWNABLE: [AssetType; 7] = [
+    AssetType::Book,
+    AssetType::Album,
+    AssetType::MapPack,
+    AssetType::Map,
+    AssetType::Stl,
+    AssetType::TokenPack,
+    AssetType::Token,
+];
+
+impl Library {
+    // Pages are numbered from 1; only the requested page is summarized
+    pub fn read(
+        conn: &mut PgConnection,
+        u_id: i32,
+        kind: Option<AssetType>,
+        sort: LibrarySort,
+        page: i64,
+        per_page: i64,
+    ) -> Result<Library, AppError> {
+        if page < 1 {
+            return Err(AppError::Validation(String::from("page starts at 1")));
+        }
+        if !(1..=MAX_PER_PAGE).contains(&per_page) {
+            return Err(AppError::Validation(format!(
+                "per_page must be between 1 and {}",
+                MAX_PER_PAGE
+            )));
+        }
+        let skipped = (page - 1)
+            .checked_mul(per_page)
+            .and_then(|skipped| usize::try_from(skipped).ok())
+            .ok_or_else(|| AppError::Validation(format!("page {} is out of range", page)))?;
+
+        let mut owned = Vec::new();
+        for asset_type in OWNABLE {
+            if kind.is_some_and(|kind| kind != asset_type) {
+                continue;
+            }
+            for (asset_id, purchased_at) in purchases(conn, asset_type, u_id)? {
+                owned.push((asset_type, asset_id, purchased_at));
+            }
+        }
+
+        owned.sort_by(|a, b| {
+            let order = a.2.cmp(&b.2).then_with(|| a.1.cmp(&b.1));
+            match sort {
+                LibrarySort::Oldest => order,
+                LibrarySort::Newest => order.reverse(),
+            }
+        });
+
+        let total = owned.len();
+        let items = owned
+            .into_iter()
+            .skip(skipped)
+            .take(per_page as usize)
+            .map(|(asset_type, asset_id, purchased_at)| {
+                Ok(LibraryItem {
+                    asset_type,
+                    asset_id,
+                    purchased_at,
+                    summary: asset_type.summarize(conn, asset_id, u_id)?,
+                })
+            })
+            .collect::<Result<Vec<LibraryItem>, AppError>>()?;
+
+        Ok(Library {
+            items,
+            page,
+            per_page,
+            total,
+        })
+    }
+}
+
+fn purchases(
+    conn: &mut PgConnection,
+    asset_type: AssetType,
+    u_id: i32,
+) -> Result<Vec<(i32, i64)>, AppError> {
+    match asset_type {
+        AssetType::Book => UserBook::list(conn, u_id),
+        AssetType::Album => UserAlbum::list(conn, u_id),
+        AssetType::MapPack => UserMapPack::list(conn, u_id),
+        AssetType::Map => UserMap::list(conn, u_id),
+        AssetType::Stl => UserStl::list(conn, u_id),
+        AssetType::TokenPack => UserTokenPack::list(conn, u_id),
+        AssetType::Token => UserToken::list(conn, u_id),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::book::{Book, BookCreate};
+    use crate::handlers::connect;
+    use crate::handlers::creator::{CreatorNew, Creators};
+    use crate::handlers::map::{MapCreate, MapPack, MapPackCreate};
+    use crate::handlers::user::{User, UserNew};
+    use crate::types::asset::{Asset, Ownership};
+    use crate::types::price::{Currency, Price};
+    use crate::types::user::DisplayName;
+
+    #[test]
+    fn library_full() {
+        let conn = &mut connect::establish_connection();
+
+        let user = UserNew::create(
+            conn,
+            String::from("library_full"),
+            String::from("library_full@gmail.com"),
+            String::from("logo.svg"),
+        )
+        .unwrap();
+
+        let creator = CreatorNew::create(
+            conn,
+            user.id,
+            Some(String::from("Chris")),
+            Some(String::from("Hughes")),
+            None,
+            None,
+            DisplayName::Name,
+        )
+        .unwrap();
+
+        let mut books = Vec::new();
+        for title in ["Library One", "Library Two"] {
+            let book = BookCreate::new(
+                creator.id,
+                String::from(title),
+                String::from("thumb.jpg"),
+                String::from("What a book!"),
+                String::from("file.pdf"),
+                100,
+                String::from("image.jpg"),
+                false,
+                Price::new(1000, Currency::Usd),
+            )
+            .create(conn)
+            .unwrap();
+            books.push(book);
+        }
+
+        let map_pack = MapPackCreate::new(
+            creator.id,
+            String::from("Library Maps"),
+            String::from("thumb.jpg"),
+            String::from("Lots of great locations"),
+            String::from("library"),
+            false,
+            String::from("image.jpg"),
+            Price::new(500, Currency::Eur),
+        )
+        .create(conn)
+        .unwrap();
+
+        let map = MapCreate::new(
+            creator.id,
+            map_pack.id,
+            String::from("Library Glade"),
+            String::from("thumb.jpg"),
+            String::from("What a map!"),
+            None,
+            None,
+            &map_pack.directory,
+            String::from("image.jpg"),
+            false,
+            None,
+        )
+        .create(conn)
+        .unwrap();
+
+        AssetType::Book.grant(conn, books[0].id, user.id).unwrap();
+        AssetType::Book.grant(conn, books[1].id, user.id).unwrap();
+        AssetType::Map.grant(conn, map.id, user.id).unwrap();
+
+        {
+            use crate::schema::user_books::dsl::*;
+
+            for (book, bought) in books.iter().zip([1_000, 2_000]) {
+                diesel::update(user_books.filter(book_id.eq(book.id)))
+                    .set(purchased_at.eq(bought))
+                    .execute(conn)
+                    .unwrap();
+            }
+        }
+
+        let library = Library::read(conn, user.id, None, LibrarySort::Newest, 1, 2).unwrap();
+
+        assert_eq!(library.total, 3);
+        assert_eq!(library.items.len(), 2);
+        assert_eq!(library.items[0].asset_type, AssetType::Map);
+        assert_eq!(library.items[0].summary.ownership, Ownership::Owned);
+        assert_eq!(
+            library.items[0].summary.pricing.price,
+            Price::new(500, Currency::Eur)
+        );
+
+        let last_page = Library::read(conn, user.id, None, LibrarySort::Newest, 2, 2).unwrap();
+
+        assert_eq!(last_page.items.len(), 1);
+        assert_eq!(last_page.items[0].asset_id, books[0].id);
+
+        let oldest = Library::read(
+            conn,
+            user.id,
+            Some(AssetType::Book),
+            LibrarySort::Oldest,
+            1,
+            10,
+        )
+        .unwrap();
+
+        assert_eq!(oldest.total, 2);
+        assert_eq!(oldest.items[0].asset_id, books[0].id);
+        assert_eq!(oldest.items[0].purchased_at, 1_000);
+
+        let invalid = Library::read(conn, user.id, None, LibrarySort::Newest, 0, 10);
+
+        assert!(matches!(invalid, Err(AppError::Validation(_))));
+
+        let beyond = Library::read(conn, user.id, None, LibrarySort::Newest, i64::MAX, 10);
+
+        assert!(matches!(beyond, Err(AppError::Validation(_))));
+
+        UserMap::destroy(conn, user.id, map.id).unwrap();
+        MapPack::destroy(conn, map_pack.id).unwrap();
+        for book in books {
+            UserBook::destroy(conn, user.id, book.id).unwrap();
+            Book::destroy(conn, book.id).unwrap();
+        }
+        Creators::destroy(conn, creator.id).unwrap();
+        User::destroy(conn, user.id).unwrap();
+    }
+}
@@ -0,0 +1,213 @@
+use super::entitlements::Downloadable;
+use super::storage::check_key;
+use crate::types::error::AppError;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::Serialize;
+use sha2::Sha256;
+use std::env;
+
+// How long an issued link stays valid, in seconds
+const DEFAULT_TTL: i64 = 15 * 60;
+
+#[derive(Serialize, Debug)]
+pub struct SignedLink {
+    pub url: String,
+    // unix seconds
+    pub expires_at: i64,
+}
+
+// Signs download paths so files can only be fetched through a link issued after
+// an entitlement check, and only until the link expires
+pub struct LinkSigner {
+    secret: Vec<u8>,
+    ttl: i64,
+}
+
+impl LinkSigner {
+    pub fn new(secret: Vec<u8>, ttl: i64) -> Self {
+        LinkSigner { secret, ttl }
+    }
+
+    pub fn sign(&self, key: &str, now: i64) -> Result<SignedLink, AppError> {
+        check_key(key)?;
+        Ok(self.link(format!("/files/{}", key), now))
+    }
+
+    // Packs and albums are built into an archive when the link is followed
+    pub fn sign_archive(&self, kind: Downloadable, id: i32, now: i64) -> SignedLink {
+        self.link(format!("/archives/{}/{}", kind.store(), id), now)
+    }
+
+    fn link(&self, path: String, now: i64) -> SignedLink {
+        let expires_at = now + self.ttl;
+        let signature = hex::encode(self.mac(&path, expires_at).finalize().into_bytes());
+
+        SignedLink {
+            url: format!("{}?expires={}&signature={}", path, expires_at, signature),
+            expires_at,
+        }
+    }
+
+    // path is the link without its query
+    pub fn verify(
+        &self,
+        path: &str,
+        expires: i64,
+        signature: &str,
+        now: i64,
+    ) -> Result<(), AppError> {
+        let invalid = || AppError::Forbidden(String::from("download link is not valid"));
+        let signature = hex::decode(signature).map_err(|_| invalid())?;
+
+        // compared in constant time
+        self.mac(path, expires)
+            .verify_slice(&signature)
+            .map_err(|_| invalid())?;
+
+        if expires < now {
+            return Err(AppError::Forbidden(String::from(
+                "download link has expired",
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn mac(&self, path: &str, expires: i64) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("hmac accepts any key length");
+        mac.update(path.as_bytes());
+        mac.update(b"\n");
+        mac.update(expires.to_string().as_bytes());
+        mac
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub enum ByteRange {
+    Full,
+    // start..end, end exclusive
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+// Reads a Range header against a file of the given size; anything other than
+// a single well formed byte range is answered with the whole file
+pub fn byte_range(header: Option<&str>, size: u64) -> ByteRange {
+    let Some(spec) = header.and_then(|header| header.strip_prefix("bytes=")) else {
+        return ByteRange::Full;
+    };
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+
+    match (start.parse::<u64>(), end.parse::<u64>()) {
+        (Ok(start), _) if start >= size => ByteRange::Unsatisfiable,
+        (Ok(start), Ok(end)) if start <= end => ByteRange::Partial(start, size.min(end + 1)),
+        (Ok(start), Err(_)) if end.is_empty() => ByteRange::Partial(start, size),
+        (Err(_), Ok(suffix)) if start.is_empty() => match suffix.min(size) {
+            0 => ByteRange::Unsatisfiable,
+            suffix => ByteRange::Partial(size - suffix, size),
+        },
+        _ => ByteRange::Full,
+    }
+}
+
+// Without DOWNLOAD_SECRET a random secret is used and links do not survive a restart
+pub fn signer_from_env() -> LinkSigner {
+    let secret = match env::var("DOWNLOAD_SECRET") {
+        Ok(secret) if secret.len() >= 32 => secret.into_bytes(),
+        _ => {
+            let mut secret = vec![0; 64];
+            rand::thread_rng().fill_bytes(&mut secret);
+            secret
+        }
+    };
+    let ttl = env::var("DOWNLOAD_TTL")
+        .ok()
+        .and_then(|ttl| ttl.parse().ok())
+        .unwrap_or(DEFAULT_TTL);
+
+    LinkSigner::new(secret, ttl)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signed_links() {
+        let signer = LinkSigner::new(b"a secret that is long enough".to_vec(), 60);
+        let link = signer.sign("books/1/file-abc.pdf", 1_000).unwrap();
+
+        assert_eq!(link.expires_at, 1_060);
+        assert!(link
+            .url
+            .starts_with("/files/books/1/file-abc.pdf?expires=1060&signature="));
+
+        let signature = link.url.rsplit('=').next().unwrap();
+
+        signer
+            .verify("/files/books/1/file-abc.pdf", 1_060, signature, 1_030)
+            .unwrap();
+
+        for (path, expires, signature, now) in [
+            ("/files/books/1/file-abc.pdf", 1_060, signature, 1_061),
+            ("/files/books/2/file-abc.pdf", 1_060, signature, 1_030),
+            ("/files/books/1/file-abc.pdf", 9_999, signature, 1_030),
+            ("/files/books/1/file-abc.pdf", 1_060, "not hex", 1_030),
+            ("/archives/books/1/file-abc.pdf", 1_060, signature, 1_030),
+        ] {
+            assert!(matches!(
+                signer.verify(path, expires, signature, now),
+                Err(AppError::Forbidden(_))
+            ));
+        }
+
+        let other = LinkSigner::new(b"some other secret of enough length".to_vec(), 60);
+
+        assert!(other
+            .verify("/files/books/1/file-abc.pdf", 1_060, signature, 1_030)
+            .is_err());
+
+        let archive = signer.sign_archive(Downloadable::MapPack, 3, 1_000);
+
+        assert!(archive
+            .url
+            .starts_with("/archives/map_pack/3?expires=1060&"));
+    }
+
+    #[test]
+    fn byte_ranges() {
+        assert_eq!(byte_range(None, 100), ByteRange::Full);
+        assert_eq!(
+            byte_range(Some("bytes=0-9"), 100),
+            ByteRange::Partial(0, 10)
+        );
+        assert_eq!(
+            byte_range(Some("bytes=90-"), 100),
+            ByteRange::Partial(90, 100)
+        );
+        assert_eq!(
+            byte_range(Some("bytes=90-500"), 100),
+            ByteRange::Partial(90, 100)
+        );
+        assert_eq!(
+            byte_range(Some("bytes=-10"), 100),
+            ByteRange::Partial(90, 100)
+        );
+        assert_eq!(
+            byte_range(Some("bytes=-500"), 100),
+            ByteRange::Partial(0, 100)
+        );
+        assert_eq!(
+            byte_range(Some("bytes=100-"), 100),
+            ByteRange::Unsatisfiable
+        );
+        assert_eq!(byte_range(Some("bytes=-0"), 100), ByteRange::Unsatisfiable);
+        assert_eq!(byte_range(Some("bytes=0-1,5-9"), 100), ByteRange::Full);
+        assert_eq!(byte_range(Some("bytes=9-0"), 100), ByteRange::Full);
+        assert_eq!(byte_range(Some("items=0-9"), 100), ByteRange::Full);
+    }
+}
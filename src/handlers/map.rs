@@ -1,23 +1,36 @@
 use super::creator::Creator;
+use super::images::map_packs::MapPackImage;
+use super::images::maps::MapImage;
+use super::ownership::map_packs::UserMapPack;
 use super::ownership::maps::UserMap;
+use super::prices::AssetPrice;
 use crate::schema::map_packs;
 use crate::schema::maps;
 use crate::types::asset::{Asset, AssetType, Ownership, Page, Summary};
+use crate::types::error::AppError;
+use crate::types::price::{Price, Pricing};
 use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Queryable, Selectable, AsChangeset)]
+#[derive(Queryable, Selectable, Serialize, Deserialize)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct Map {
     pub id: i32,
     pub creator_id: i32,
     pub map_pack_id: i32,
     pub title: String,
+    #[serde(skip_deserializing)]
     pub thumb: String,
     pub summary: String,
+    #[serde(skip_deserializing)]
     pub file: String,
     pub height: Option<i32>,
     pub width: Option<i32>,
+    #[serde(skip_deserializing)]
     pub main_image: String,
+    pub is_free: bool,
+    pub price: Option<i32>,
+    pub currency: Option<String>,
 }
 
 #[derive(Insertable)]
@@ -32,9 +45,12 @@ pub struct MapCreate {
     pub height: Option<i32>,
     pub width: Option<i32>,
     pub main_image: String,
+    pub is_free: bool,
+    pub price: Option<i32>,
+    pub currency: Option<String>,
 }
 
-#[derive(Queryable, Selectable, AsChangeset, Identifiable)]
+#[derive(Queryable, Selectable, Identifiable, Serialize)]
 #[diesel(table_name = map_packs)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct MapPackQuery {
@@ -46,33 +62,47 @@ pub struct MapPackQuery {
     pub directory: String,
     pub is_free: bool,
     pub main_image: String,
+    pub price: i32,
+    pub currency: String,
 }
 
-#[derive(Insertable)]
+#[derive(Insertable, Deserialize)]
 #[diesel(table_name = map_packs)]
 pub struct MapPackCreate {
     pub creator_id: i32,
     pub title: String,
+    #[serde(skip_deserializing)]
     pub thumb: String,
     pub summary: String,
+    #[serde(skip_deserializing)]
     pub directory: String,
     pub is_free: bool,
+    #[serde(skip_deserializing)]
     pub main_image: String,
+    pub price: i32,
+    pub currency: String,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct MapPack {
     pub id: i32,
     pub creator_id: i32,
     pub title: String,
+    #[serde(skip_deserializing)]
     pub thumb: String,
     pub summary: String,
+    #[serde(skip_deserializing)]
     pub directory: String,
     pub is_free: bool,
+    #[serde(skip_deserializing)]
     pub main_image: String,
+    pub price: i32,
+    pub currency: String,
     pub maps: Vec<Map>,
 }
 
 impl MapPackCreate {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         creator_id: i32,
         title: String,
@@ -81,6 +111,7 @@ impl MapPackCreate {
         directory: String,
         is_free: bool,
         main_image: String,
+        price: Price,
     ) -> Self {
         MapPackCreate {
             creator_id,
@@ -90,15 +121,20 @@ impl MapPackCreate {
             directory,
             is_free,
             main_image,
+            price: price.amount,
+            currency: String::from(price.currency.store()),
         }
     }
 
-    pub fn create(&self, conn: &mut PgConnection) -> MapPackQuery {
-        diesel::insert_into(map_packs::table)
+    pub fn create(&self, conn: &mut PgConnection) -> Result<MapPackQuery, AppError> {
+        Price::stored(self.price, &self.currency)?;
+
+        let map_pack = diesel::insert_into(map_packs::table)
             .values(self)
             .returning(MapPackQuery::as_returning())
-            .get_result(conn)
-            .expect("Error saving Book")
+            .get_result(conn)?;
+
+        Ok(map_pack)
     }
 }
 
@@ -114,6 +150,8 @@ impl MapCreate {
         width: Option<i32>,
         directory: &str,
         main_image: String,
+        is_free: bool,
+        price: Option<Price>,
     ) -> Self {
         let slug = title.to_lowercase().trim().replace(' ', "-");
         let file = format!("{}/{}", directory, slug);
@@ -127,31 +165,36 @@ impl MapCreate {
             width,
             file,
             main_image,
+            is_free,
+            price: price.map(|price| price.amount),
+            currency: price.map(|price| String::from(price.currency.store())),
         }
     }
 
-    pub fn create(&self, conn: &mut PgConnection) -> Map {
-        diesel::insert_into(maps::table)
+    pub fn create(&self, conn: &mut PgConnection) -> Result<Map, AppError> {
+        Price::optional(self.price, &self.currency)?;
+
+        let map = diesel::insert_into(maps::table)
             .values(self)
             .returning(Map::as_returning())
-            .get_result(conn)
-            .expect("Error saving map")
+            .get_result(conn)?;
+
+        Ok(map)
     }
 }
 
 impl Asset for MapPack {
-    fn read(conn: &mut PgConnection, pack_id: i32) -> MapPack {
+    fn read(conn: &mut PgConnection, pack_id: i32) -> Result<MapPack, AppError> {
         use crate::schema::maps::dsl::*;
 
-        let map_pack = get_map_pack(conn, pack_id);
+        let map_pack = get_map_pack(conn, pack_id)?;
 
         let map = maps
             .filter(map_pack_id.eq(pack_id))
             .select(Map::as_select())
-            .get_results(conn)
-            .expect("Error loading posts");
+            .get_results(conn)?;
 
-        MapPack {
+        Ok(MapPack {
             id: map_pack.id,
             creator_id: map_pack.creator_id,
             title: map_pack.title,
@@ -160,121 +203,235 @@ impl Asset for MapPack {
             directory: map_pack.directory,
             is_free: map_pack.is_free,
             main_image: map_pack.main_image,
+            price: map_pack.price,
+            currency: map_pack.currency,
             maps: map,
-        }
+        })
     }
 
-    fn destroy(conn: &mut PgConnection, pack_id: i32) -> usize {
+    fn destroy(conn: &mut PgConnection, pack_id: i32) -> Result<usize, AppError> {
         use crate::schema::maps::dsl::*;
 
-        let changes = diesel::delete(maps.filter(map_pack_id.eq(pack_id)))
-            .execute(conn)
-            .expect("Error deleting posts");
-
-        changes + destroy_map_pack(conn, pack_id)
+        conn.transaction(|conn| {
+            let map_ids = maps
+                .filter(map_pack_id.eq(pack_id))
+                .select(id)
+                .get_results::<i32>(conn)?;
+
+            // buyers keep their copies, so a pack with anything bought is never removed
+            if UserMapPack::is_owned(conn, pack_id)? || UserMap::any_owned(conn, &map_ids)? {
+                return Err(AppError::Conflict(format!(
+                    "map pack {} has been bought",
+                    pack_id
+                )));
+            }
+
+            let mut images = MapPackImage::destroy_all(conn, pack_id)?;
+            let mut prices = AssetPrice::destroy_all(conn, &AssetType::MapPack, pack_id)?;
+            for map_id in map_ids {
+                images += MapImage::destroy_all(conn, map_id)?;
+                prices += AssetPrice::destroy_all(conn, &AssetType::Map, map_id)?;
+            }
+
+            let changes = diesel::delete(maps.filter(map_pack_id.eq(pack_id))).execute(conn)?;
+
+            Ok(images + prices + changes + destroy_map_pack(conn, pack_id)?)
+        })
     }
 
-    fn update(&self, conn: &mut PgConnection) -> usize {
+    fn update(&self, conn: &mut PgConnection) -> Result<usize, AppError> {
         use crate::schema::map_packs::dsl::*;
 
-        let map_pack = MapPackQuery {
-            id: self.id,
-            creator_id: self.creator_id.to_owned(),
-            title: self.title.to_owned(),
-            thumb: self.thumb.to_owned(),
-            summary: self.summary.to_owned(),
-            directory: self.directory.to_owned(),
-            is_free: self.is_free,
-            main_image: self.main_image.to_owned(),
-        };
+        Price::stored(self.price, &self.currency)?;
 
         let changes = diesel::update(map_packs)
-            .filter(id.eq(map_pack.id))
-            .set(map_pack)
-            .execute(conn)
-            .expect("Failed to update user");
-
-        update_maps(conn, &self.maps) + changes
+            .filter(id.eq(self.id))
+            .set((
+                creator_id.eq(self.creator_id),
+                title.eq(&self.title),
+                summary.eq(&self.summary),
+                is_free.eq(self.is_free),
+                price.eq(self.price),
+                currency.eq(&self.currency),
+            ))
+            .execute(conn)?;
+
+        Ok(update_maps(conn, &self.maps)? + changes)
     }
 
-    fn summarize(&self, conn: &mut PgConnection, user_id: i32) -> Summary {
-        let (creator, user) = Creator::creator_with_user(conn, self.creator_id);
+    fn summarize(&self, conn: &mut PgConnection, user_id: i32) -> Result<Summary, AppError> {
+        let (creator, user) = Creator::creator_with_user(conn, self.creator_id)?;
         let asset_type = AssetType::Map;
         let display_name = creator.get_display_name();
-        let ownership = self.check_ownership(conn, user_id);
+        let ownership = self.check_ownership(conn, user_id)?;
+        let pricing = self.pricing(conn)?;
 
-        Summary {
+        Ok(Summary {
+            title: self.title.to_owned(),
             display_name,
             ownership,
             asset_type,
             logo: user.logo,
-        }
+            pricing,
+        })
     }
 
-    fn paginate(&self, conn: &mut PgConnection, user_id: i32) -> Page {
-        let (creator, user) = Creator::creator_with_user(conn, self.creator_id);
+    fn paginate(&self, conn: &mut PgConnection, user_id: i32) -> Result<Page, AppError> {
+        let (creator, user) = Creator::creator_with_user(conn, self.creator_id)?;
         let display_name = creator.get_display_name();
         let asset_type = AssetType::Map;
-        let extra_images = asset_type.images();
-        let ownership = self.check_ownership(conn, user_id);
+        let extra_images = AssetType::MapPack.images(conn, self.id)?;
+        let ownership = self.check_ownership(conn, user_id)?;
+        let pricing = self.pricing(conn)?;
 
-        Page {
+        Ok(Page {
             display_name,
             ownership,
             asset_type,
             logo: user.logo,
             extra_images,
-        }
+            pricing,
+            details: None,
+        })
     }
 
-    fn check_ownership(&self, conn: &mut PgConnection, user_id: i32) -> Ownership {
+    fn check_ownership(
+        &self,
+        conn: &mut PgConnection,
+        user_id: i32,
+    ) -> Result<Ownership, AppError> {
         if self.is_free {
-            Ownership::Free
+            Ok(Ownership::Free)
         } else {
-            UserMap::check_ownership(conn, user_id, self.id)
+            UserMapPack::check_ownership(conn, user_id, self.id)
         }
     }
 }
 
-fn get_map_pack(conn: &mut PgConnection, pack_id: i32) -> MapPackQuery {
+fn get_map_pack(conn: &mut PgConnection, pack_id: i32) -> Result<MapPackQuery, AppError> {
     use crate::schema::map_packs::dsl::*;
 
-    map_packs
+    let map_pack = map_packs
         .filter(id.eq(pack_id))
         .select(MapPackQuery::as_select())
-        .get_result(conn)
-        .expect("Error loading map pack")
+        .get_result(conn)?;
+
+    Ok(map_pack)
 }
 
-fn destroy_map_pack(conn: &mut PgConnection, pack_id: i32) -> usize {
+fn destroy_map_pack(conn: &mut PgConnection, pack_id: i32) -> Result<usize, AppError> {
     use crate::schema::map_packs::dsl::*;
 
-    diesel::delete(map_packs.filter(id.eq(pack_id)))
-        .execute(conn)
-        .expect("Error deleting map_packs")
+    let changes = diesel::delete(map_packs.filter(id.eq(pack_id))).execute(conn)?;
+
+    Ok(changes)
 }
 
-fn update_maps(conn: &mut PgConnection, maps_vec: &Vec<Map>) -> usize {
+fn update_maps(conn: &mut PgConnection, maps_vec: &Vec<Map>) -> Result<usize, AppError> {
     use crate::schema::maps::dsl::*;
 
     let mut changes: usize = 0;
     for map in maps_vec {
+        Price::optional(map.price, &map.currency)?;
         let result = diesel::update(maps)
             .filter(id.eq(map.id))
-            .set(map)
-            .execute(conn)
-            .expect("Failed to update user");
+            .set((
+                creator_id.eq(map.creator_id),
+                map_pack_id.eq(map.map_pack_id),
+                title.eq(&map.title),
+                summary.eq(&map.summary),
+                height.eq(map.height),
+                width.eq(map.width),
+                is_free.eq(map.is_free),
+                price.eq(map.price),
+                currency.eq(&map.currency),
+            ))
+            .execute(conn)?;
         changes += result;
     }
-    changes
+    Ok(changes)
+}
+
+impl Map {
+    pub fn read(conn: &mut PgConnection, m_id: i32) -> Result<Map, AppError> {
+        use crate::schema::maps::dsl::*;
+
+        let map = maps
+            .filter(id.eq(m_id))
+            .select(Map::as_select())
+            .get_result(conn)?;
+
+        Ok(map)
+    }
+
+    pub fn summarize(&self, conn: &mut PgConnection, user_id: i32) -> Result<Summary, AppError> {
+        let (creator, user) = Creator::creator_with_user(conn, self.creator_id)?;
+        let display_name = creator.get_display_name();
+        let ownership = self.check_ownership(conn, user_id)?;
+        // maps without a price of their own are shown at their pack's price
+        let pricing = match self.price {
+            Some(_) => self.pricing(conn)?,
+            None => {
+                let map_pack = get_map_pack(conn, self.map_pack_id)?;
+                let price = Price::stored(map_pack.price, &map_pack.currency)?;
+                AssetPrice::pricing(conn, &AssetType::MapPack, map_pack.id, price)?
+            }
+        };
+
+        Ok(Summary {
+            title: self.title.to_owned(),
+            display_name,
+            ownership,
+            asset_type: AssetType::Map,
+            logo: user.logo,
+            pricing,
+        })
+    }
+
+    // A map is covered by owning it, owning its pack, or either being free
+    pub fn check_ownership(
+        &self,
+        conn: &mut PgConnection,
+        user_id: i32,
+    ) -> Result<Ownership, AppError> {
+        let map_pack = get_map_pack(conn, self.map_pack_id)?;
+
+        if self.is_free || map_pack.is_free {
+            Ok(Ownership::Free)
+        } else if UserMapPack::check_ownership(conn, user_id, map_pack.id)? == Ownership::Owned {
+            Ok(Ownership::Owned)
+        } else {
+            UserMap::check_ownership(conn, user_id, self.id)
+        }
+    }
+
+    // Only maps with a price of their own can be bought outside their pack
+    pub fn pricing(&self, conn: &mut PgConnection) -> Result<Pricing, AppError> {
+        match Price::optional(self.price, &self.currency)? {
+            Some(price) => AssetPrice::pricing(conn, &AssetType::Map, self.id, price),
+            None => Err(AppError::Validation(format!(
+                "map {} is sold through its pack",
+                self.id
+            ))),
+        }
+    }
+}
+
+impl MapPack {
+    pub fn pricing(&self, conn: &mut PgConnection) -> Result<Pricing, AppError> {
+        let price = Price::stored(self.price, &self.currency)?;
+        AssetPrice::pricing(conn, &AssetType::MapPack, self.id, price)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::handlers::cart::{Cart, CartItem, CartOwner};
     use crate::handlers::connect;
     use crate::handlers::creator::{CreatorNew, Creators};
     use crate::handlers::user::{User, UserNew};
+    use crate::types::price::Currency;
     use crate::types::user::DisplayName;
 
     #[test]
@@ -283,10 +440,11 @@ mod tests {
 
         let user = UserNew::create(
             conn,
-            String::from("naokotani"),
-            String::from("nao@gmail.com"),
+            String::from("map_full"),
+            String::from("map_full@gmail.com"),
             String::from("logo.svg"),
-        );
+        )
+        .unwrap();
 
         let creator = CreatorNew::create(
             conn,
@@ -296,7 +454,8 @@ mod tests {
             Some(String::from("naokotani")),
             Some(String::from("Random House")),
             DisplayName::Name,
-        );
+        )
+        .unwrap();
 
         let map_pack = MapPackCreate::new(
             creator.id,
@@ -306,8 +465,10 @@ mod tests {
             String::from("directory"),
             false,
             String::from("image.jpg"),
+            Price::new(1500, Currency::Cad),
         )
-        .create(conn);
+        .create(conn)
+        .unwrap();
 
         vec![MapCreate::new(
             creator.id,
@@ -319,23 +480,227 @@ mod tests {
             Some(450),
             &map_pack.directory,
             String::from("image.jpg"),
+            false,
+            None,
         )
-        .create(conn)];
+        .create(conn)
+        .unwrap()];
 
-        let album_full = MapPack::read(conn, map_pack.id);
+        let album_full = MapPack::read(conn, map_pack.id).unwrap();
 
         assert_eq!(album_full.maps[0].title, "Windy Glade");
 
-        let page = album_full.paginate(conn, user.id);
+        let page = album_full.paginate(conn, user.id).unwrap();
 
         assert_eq!(page.display_name, "Chris Hughes");
         assert_eq!(page.asset_type, AssetType::Map);
 
-        let delete = MapPack::destroy(conn, map_pack.id);
+        let delete = MapPack::destroy(conn, map_pack.id).unwrap();
 
         assert_eq!(delete, 2);
 
-        Creators::destroy(conn, creator.id);
-        User::destroy(conn, user.id);
+        Creators::destroy(conn, creator.id).unwrap();
+        User::destroy(conn, user.id).unwrap();
+    }
+
+    #[test]
+    fn map_pack_ownership() {
+        let conn = &mut connect::establish_connection();
+
+        let user = UserNew::create(
+            conn,
+            String::from("map_pack_ownership"),
+            String::from("map_pack_ownership@gmail.com"),
+            String::from("logo.svg"),
+        )
+        .unwrap();
+
+        let creator = CreatorNew::create(
+            conn,
+            user.id,
+            None,
+            None,
+            Some(String::from("naokotani")),
+            None,
+            DisplayName::Other,
+        )
+        .unwrap();
+
+        let map_pack = MapPackCreate::new(
+            creator.id,
+            String::from("Epic Fights"),
+            String::from("thumb.jpg"),
+            String::from("Lots of great locations"),
+            String::from("directory"),
+            false,
+            String::from("image.jpg"),
+            Price::new(1500, Currency::Cad),
+        )
+        .create(conn)
+        .unwrap();
+
+        let mut maps = Vec::new();
+        for (title, is_free) in [
+            ("Windy Glade", false),
+            ("Misty Moor", false),
+            ("Free Ford", true),
+        ] {
+            let map = MapCreate::new(
+                creator.id,
+                map_pack.id,
+                String::from(title),
+                String::from("thumb.jpg"),
+                String::from("What a fight area!"),
+                None,
+                None,
+                &map_pack.directory,
+                String::from("image.jpg"),
+                is_free,
+                None,
+            )
+            .create(conn)
+            .unwrap();
+            maps.push(map);
+        }
+
+        let map_pack = MapPack::read(conn, map_pack.id).unwrap();
+
+        assert_eq!(
+            map_pack.check_ownership(conn, user.id).unwrap(),
+            Ownership::Unowned
+        );
+        assert_eq!(
+            maps[0].check_ownership(conn, user.id).unwrap(),
+            Ownership::Unowned
+        );
+        assert_eq!(
+            maps[2].check_ownership(conn, user.id).unwrap(),
+            Ownership::Free
+        );
+
+        UserMap::new(user.id, maps[1].id).create(conn).unwrap();
+
+        assert_eq!(
+            maps[0].check_ownership(conn, user.id).unwrap(),
+            Ownership::Unowned
+        );
+        assert_eq!(
+            maps[1].check_ownership(conn, user.id).unwrap(),
+            Ownership::Owned
+        );
+        assert_eq!(
+            map_pack.check_ownership(conn, user.id).unwrap(),
+            Ownership::Unowned
+        );
+
+        UserMapPack::new(user.id, map_pack.id).create(conn).unwrap();
+
+        assert_eq!(
+            map_pack.check_ownership(conn, user.id).unwrap(),
+            Ownership::Owned
+        );
+        assert_eq!(
+            maps[0].check_ownership(conn, user.id).unwrap(),
+            Ownership::Owned
+        );
+
+        UserMapPack::destroy(conn, user.id, map_pack.id).unwrap();
+        UserMap::destroy(conn, user.id, maps[1].id).unwrap();
+        MapPack::destroy(conn, map_pack.id).unwrap();
+        Creators::destroy(conn, creator.id).unwrap();
+        User::destroy(conn, user.id).unwrap();
+    }
+
+    #[test]
+    fn map_sold_alone() {
+        let conn = &mut connect::establish_connection();
+
+        let user = UserNew::create(
+            conn,
+            String::from("single_map_buyer"),
+            String::from("single_map@gmail.com"),
+            String::from("logo.svg"),
+        )
+        .unwrap();
+
+        let creator = CreatorNew::create(
+            conn,
+            user.id,
+            None,
+            None,
+            Some(String::from("naokotani")),
+            None,
+            DisplayName::Other,
+        )
+        .unwrap();
+
+        let map_pack = MapPackCreate::new(
+            creator.id,
+            String::from("Epic Fights"),
+            String::from("thumb.jpg"),
+            String::from("Lots of great locations"),
+            String::from("directory"),
+            false,
+            String::from("image.jpg"),
+            Price::new(1500, Currency::Cad),
+        )
+        .create(conn)
+        .unwrap();
+
+        let mut maps = Vec::new();
+        for (title, price) in [
+            ("Windy Glade", Some(Price::new(300, Currency::Cad))),
+            ("Misty Moor", None),
+        ] {
+            let map = MapCreate::new(
+                creator.id,
+                map_pack.id,
+                String::from(title),
+                String::from("thumb.jpg"),
+                String::from("What a fight area!"),
+                None,
+                None,
+                &map_pack.directory,
+                String::from("image.jpg"),
+                false,
+                price,
+            )
+            .create(conn)
+            .unwrap();
+            maps.push(map);
+        }
+
+        assert_eq!(
+            AssetType::Map.pricing(conn, maps[0].id).unwrap().price,
+            Price::new(300, Currency::Cad)
+        );
+        assert!(matches!(
+            AssetType::Map.pricing(conn, maps[1].id),
+            Err(AppError::Validation(_))
+        ));
+        assert_eq!(
+            AssetType::Map
+                .summarize(conn, maps[1].id, user.id)
+                .unwrap()
+                .pricing
+                .price,
+            Price::new(1500, Currency::Cad)
+        );
+
+        let owner = CartOwner::User(user.id);
+        CartItem::create(conn, &owner, AssetType::Map, maps[0].id).unwrap();
+        let unpriced = CartItem::create(conn, &owner, AssetType::Map, maps[1].id);
+
+        assert!(matches!(unpriced, Err(AppError::Validation(_))));
+
+        let cart = Cart::read(conn, &owner, None).unwrap();
+
+        assert_eq!(cart.items[0].title, "Windy Glade");
+        assert_eq!(cart.totals, vec![Price::new(300, Currency::Cad)]);
+
+        CartItem::destroy_all(conn, &owner).unwrap();
+        MapPack::destroy(conn, map_pack.id).unwrap();
+        Creators::destroy(conn, creator.id).unwrap();
+        User::destroy(conn, user.id).unwrap();
     }
 }
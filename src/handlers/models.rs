@@ -0,0 +1,1087 @@
+use super::storage::StorageBackend;
+use super::uploads::{Incoming, Upload};
+use crate::schema::model_files;
+use crate::types::error::AppError;
+use crate::types::time::now;
+use diesel::prelude::*;
+use roxmltree::{Document, Node};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use zip::ZipArchive;
+
+// The largest 3MF model part that is unpacked for measuring, kept well under
+// the upload limit since the parsed document takes several times as much
+const MAX_MODEL_XML: u64 = 128 * 1024 * 1024;
+// Every format is read against the same cap on triangles and vertices. For
+// 3MF it counts each copy components place; a binary STL at the upload limit
+// holds about 5.2M triangles
+const MAX_PLACED_TRIANGLES: usize = 6_000_000;
+const MAX_PLACED_VERTICES: usize = 18_000_000;
+// Components may nest; anything deeper than this is taken to be a cycle
+const MAX_NESTING: usize = 16;
+
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelFormat {
+    Stl,
+    Obj,
+    #[serde(rename = "3mf")]
+    ThreeMf,
+}
+
+// What a printer needs to know about a mesh. STL and OBJ have no units;
+// millimetres are what slicers assume, so the extents are reported as such.
+#[derive(PartialEq, Debug, Clone, Serialize)]
+pub struct MeshStats {
+    // separately placed objects
+    pub parts: i32,
+    pub triangles: i64,
+    // extents along x, y and z
+    pub width: f64,
+    pub depth: f64,
+    pub height: f64,
+    // every edge is shared by exactly two triangles
+    pub is_watertight: bool,
+    // no edge is shared by more than two triangles, neighbours agree on
+    // orientation and no triangle has collapsed
+    pub is_manifold: bool,
+}
+
+// One file of a 3D print asset; a model can ship several, with and without
+// supports
+#[derive(Queryable, Selectable, Serialize, PartialEq, Debug)]
+#[diesel(table_name = model_files)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ModelFile {
+    pub id: i32,
+    pub stl_id: i32,
+    pub format: String,
+    pub label: String,
+    pub is_supported: bool,
+    pub file_key: String,
+    pub file_size: i64,
+    pub parts: i32,
+    pub triangles: i64,
+    pub width: f64,
+    pub depth: f64,
+    pub height: f64,
+    pub is_watertight: bool,
+    pub is_manifold: bool,
+    // unix seconds
+    pub uploaded_at: i64,
+}
+
+#[derive(Insertable, AsChangeset)]
+#[diesel(table_name = model_files)]
+struct ModelFileNew {
+    stl_id: i32,
+    format: String,
+    label: String,
+    is_supported: bool,
+    file_key: String,
+    file_size: i64,
+    parts: i32,
+    triangles: i64,
+    width: f64,
+    depth: f64,
+    height: f64,
+    is_watertight: bool,
+    is_manifold: bool,
+    uploaded_at: i64,
+}
+
+#[derive(Serialize, PartialEq, Debug)]
+pub struct ModelListing {
+    pub supported: Vec<ModelFile>,
+    pub unsupported: Vec<ModelFile>,
+}
+
+pub type Triangle = [[f32; 3]; 3];
+
+// Every triangle of a model, in millimetres
+pub struct Mesh {
+    pub triangles: Vec<Triangle>,
+    pub parts: i32,
+}
+// a 3MF affine transform, row by row: m00 m01 m02 m10 m11 m12 m20 m21 m22 m30 m31 m32
+type Transform = [f32; 12];
+
+const IDENTITY: Transform = [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0];
+
+impl ModelFormat {
+    pub fn store(&self) -> &str {
+        match self {
+            Self::Stl => "stl",
+            Self::Obj => "obj",
+            Self::ThreeMf => "3mf",
+        }
+    }
+
+    // zip bundles are accepted as an asset's file but are not measured
+    pub fn from_extension(extension: &str) -> Option<ModelFormat> {
+        match extension {
+            "stl" => Some(Self::Stl),
+            "obj" => Some(Self::Obj),
+            "3mf" => Some(Self::ThreeMf),
+            _ => None,
+        }
+    }
+}
+
+impl Mesh {
+    pub fn parse(format: ModelFormat, bytes: &[u8]) -> Result<Mesh, AppError> {
+        let mut budget = Budget::new();
+        let mesh = match format {
+            ModelFormat::Stl => Mesh::parse_stl(bytes, &mut budget)?,
+            ModelFormat::Obj => Mesh::parse_obj(bytes, &mut budget)?,
+            ModelFormat::ThreeMf => Mesh::parse_3mf(bytes, &mut budget)?,
+        };
+
+        if mesh.triangles.is_empty() {
+            return Err(invalid("model has no triangles"));
+        }
+
+        Ok(mesh)
+    }
+
+    // Binary STL is recognised by its length matching the triangle count in
+    // its header, since binary files may also begin with "solid"
+    fn parse_stl(bytes: &[u8], budget: &mut Budget) -> Result<Mesh, AppError> {
+        let triangles = if is_binary_stl(bytes) {
+            let count = (bytes.len() - 84) / 50;
+            budget
+                .spend(count, count * 3)
+                .ok_or_else(|| invalid("STL has too many triangles"))?;
+            binary_triangles(bytes)
+        } else if bytes.trim_ascii_start().starts_with(b"solid") {
+            ascii_triangles(bytes, budget)?
+        } else {
+            return Err(invalid("file is neither ASCII nor binary STL"));
+        };
+
+        Ok(Mesh {
+            triangles,
+            parts: 1,
+        })
+    }
+
+    // Faces may be polygons and are split into fans; each `o` starts a part
+    fn parse_obj(bytes: &[u8], budget: &mut Budget) -> Result<Mesh, AppError> {
+        let text = std::str::from_utf8(bytes).map_err(|_| invalid("OBJ is not valid text"))?;
+        let mut vertices = Vec::new();
+        let mut faces = Vec::new();
+        let mut objects = 0;
+
+        for line in text.lines() {
+            let mut words = line.split_ascii_whitespace();
+            match words.next() {
+                Some("v") => {
+                    let mut vertex = [0.0; 3];
+                    for coordinate in vertex.iter_mut() {
+                        *coordinate = words
+                            .next()
+                            .and_then(|value| value.parse::<f32>().ok())
+                            .ok_or_else(|| invalid("OBJ has a malformed vertex"))?;
+                    }
+                    budget
+                        .spend(0, 1)
+                        .ok_or_else(|| invalid("OBJ has too many vertices"))?;
+                    vertices.push(vertex);
+                }
+                Some("f") => {
+                    // v, v/vt, v//vn or v/vt/vn; negative indices count back
+                    // from the latest vertex
+                    let corners = words
+                        .map(|corner| {
+                            let index = corner
+                                .split('/')
+                                .next()
+                                .and_then(|index| index.parse::<i64>().ok())
+                                .ok_or_else(|| invalid("OBJ has a malformed face"))?;
+                            match index {
+                                0 => Err(invalid("OBJ face indices start at 1")),
+                                index if index < 0 => Ok(vertices.len() as i64 + index),
+                                index => Ok(index - 1),
+                            }
+                        })
+                        .collect::<Result<Vec<i64>, AppError>>()?;
+                    if corners.len() < 3 {
+                        return Err(invalid("OBJ face has fewer than three corners"));
+                    }
+                    // each face becomes a fan of corners - 2 triangles
+                    budget
+                        .spend(corners.len() - 2, 0)
+                        .ok_or_else(|| invalid("OBJ has too many triangles"))?;
+                    faces.push(corners);
+                }
+                Some("o") => objects += 1,
+                _ => {}
+            }
+        }
+
+        let vertex = |index: i64| {
+            usize::try_from(index)
+                .ok()
+                .and_then(|index| vertices.get(index).copied())
+                .ok_or_else(|| invalid("OBJ face refers to a missing vertex"))
+        };
+        let mut triangles = Vec::new();
+        for face in faces {
+            let first = vertex(face[0])?;
+            for pair in face[1..].windows(2) {
+                triangles.push([first, vertex(pair[0])?, vertex(pair[1])?]);
+            }
+        }
+
+        Ok(Mesh {
+            triangles,
+            parts: objects.max(1),
+        })
+    }
+
+    // Reads the package's root model and places every build item, following
+    // components and transforms, in millimetres
+    fn parse_3mf(bytes: &[u8], budget: &mut Budget) -> Result<Mesh, AppError> {
+        let mut package =
+            ZipArchive::new(Cursor::new(bytes)).map_err(|_| invalid("3MF is not a zip package"))?;
+        let root = read_part(&mut package, "_rels/.rels")
+            .ok()
+            .and_then(|rels| root_model(&rels))
+            .unwrap_or_else(|| String::from("3D/3dmodel.model"));
+        let xml = read_part(&mut package, &root)?;
+        let document = Document::parse(&xml).map_err(|_| invalid("3MF model is not valid XML"))?;
+        let model = document.root_element();
+
+        let scale = match model.attribute("unit").unwrap_or("millimeter") {
+            "micron" => 0.001,
+            "millimeter" => 1.0,
+            "centimeter" => 10.0,
+            "inch" => 25.4,
+            "foot" => 304.8,
+            "meter" => 1000.0,
+            _ => return Err(invalid("3MF model has an unknown unit")),
+        };
+        let objects = model
+            .descendants()
+            .filter(|node| node.has_tag_name_local("object"))
+            .filter_map(|node| Some((node.attribute("id")?, node)))
+            .collect::<HashMap<&str, Node>>();
+
+        let mut triangles = Vec::new();
+        let mut parts = 0;
+        for item in model
+            .descendants()
+            .filter(|node| node.has_tag_name_local("item"))
+        {
+            let object = item
+                .attribute("objectid")
+                .ok_or_else(|| invalid("3MF build item names no object"))?;
+            let placement = transform(item)?;
+            place(&objects, object, &[placement], &mut triangles, budget, 0)?;
+            parts += 1;
+        }
+
+        if parts == 0 {
+            return Err(invalid("3MF model has nothing to build"));
+        }
+
+        let triangles = triangles
+            .into_iter()
+            .map(|triangle: Triangle| triangle.map(|vertex| vertex.map(|value| value * scale)))
+            .collect::<Vec<Triangle>>();
+
+        Ok(Mesh { triangles, parts })
+    }
+}
+
+impl MeshStats {
+    pub fn parse(format: ModelFormat, bytes: &[u8]) -> Result<MeshStats, AppError> {
+        MeshStats::measure(&Mesh::parse(format, bytes)?)
+    }
+
+    pub fn measure(mesh: &Mesh) -> Result<MeshStats, AppError> {
+        let Mesh { triangles, parts } = mesh;
+
+        let mut min = [f32::MAX; 3];
+        let mut max = [f32::MIN; 3];
+        let mut indices = HashMap::new();
+        let mut edges = HashMap::new();
+        let mut degenerate = false;
+
+        for triangle in triangles {
+            let mut corners = [0; 3];
+            for (corner, vertex) in corners.iter_mut().zip(triangle) {
+                if vertex.iter().any(|coordinate| !coordinate.is_finite()) {
+                    return Err(invalid("model has a vertex that is not a number"));
+                }
+                for axis in 0..3 {
+                    min[axis] = min[axis].min(vertex[axis]);
+                    max[axis] = max[axis].max(vertex[axis]);
+                }
+                // vertices are welded only where they are bit for bit equal
+                let next = indices.len();
+                *corner = *indices.entry(vertex.map(f32::to_bits)).or_insert(next);
+            }
+
+            if corners[0] == corners[1] || corners[1] == corners[2] || corners[0] == corners[2] {
+                degenerate = true;
+                continue;
+            }
+            for (from, to) in [(0, 1), (1, 2), (2, 0)] {
+                *edges.entry((corners[from], corners[to])).or_insert(0) += 1;
+            }
+        }
+
+        let mut is_watertight = true;
+        let mut is_manifold = !degenerate;
+        for (&(from, to), &count) in &edges {
+            let reverse = edges.get(&(to, from)).copied().unwrap_or(0);
+            if count + reverse != 2 {
+                is_watertight = false;
+            }
+            if count > 1 || count + reverse > 2 {
+                is_manifold = false;
+            }
+        }
+
+        Ok(MeshStats {
+            parts: *parts,
+            triangles: triangles.len() as i64,
+            width: (max[0] - min[0]) as f64,
+            depth: (max[1] - min[1]) as f64,
+            height: (max[2] - min[2]) as f64,
+            is_watertight,
+            is_manifold,
+        })
+    }
+}
+
+impl ModelFile {
+    // Measures a file before anything is kept, so one that does not parse is
+    // refused; zip bundles are kept unmeasured
+    pub fn inspect(incoming: &Incoming) -> Result<Option<(ModelFormat, MeshStats)>, AppError> {
+        match ModelFormat::from_extension(incoming.extension()) {
+            Some(format) => Ok(Some((format, MeshStats::parse(format, &incoming.read()?)?))),
+            None => Ok(None),
+        }
+    }
+
+    // Adds one more file to a model
+    pub fn store(
+        conn: &mut PgConnection,
+        storage: &dyn StorageBackend,
+        s_id: i32,
+        incoming: Incoming,
+        label: String,
+        is_supported: bool,
+    ) -> Result<ModelFile, AppError> {
+        let (format, stats) = ModelFile::inspect(&incoming)?.ok_or_else(|| {
+            AppError::Validation(String::from("model files must be STL, OBJ or 3MF"))
+        })?;
+        let (_, spooled, checksum) = incoming.finish()?;
+        let key = format!("stls/{}/model-{}.{}", s_id, &checksum[..16], format.store());
+        let existing = ModelFile::find(conn, s_id, &key)?;
+
+        storage.put_file(&key, spooled.path())?;
+
+        let stored = ModelFile::record(
+            conn,
+            s_id,
+            format,
+            label,
+            is_supported,
+            &key,
+            spooled.size as i64,
+            &stats,
+        );
+        if stored.is_err() && existing.is_none() {
+            storage.delete(&key)?;
+        }
+
+        stored
+    }
+
+    // The file uploaded through the asset's own `file` slot is one of its
+    // model files too, and takes the place of the one it replaced
+    pub fn replace_main(
+        conn: &mut PgConnection,
+        s_id: i32,
+        previous: Option<String>,
+        upload: &Upload,
+        inspected: Option<(ModelFormat, MeshStats)>,
+    ) -> Result<(), AppError> {
+        use crate::schema::stls;
+
+        if let Some(previous) = previous.filter(|previous| *previous != upload.file_key) {
+            diesel::delete(
+                model_files::table
+                    .filter(model_files::stl_id.eq(s_id))
+                    .filter(model_files::file_key.eq(previous)),
+            )
+            .execute(conn)?;
+        }
+
+        if let Some((format, stats)) = inspected {
+            let title = stls::table
+                .find(s_id)
+                .select(stls::title)
+                .get_result::<String>(conn)?;
+            ModelFile::record(
+                conn,
+                s_id,
+                format,
+                title,
+                false,
+                &upload.file_key,
+                upload.size,
+                &stats,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn record(
+        conn: &mut PgConnection,
+        s_id: i32,
+        format: ModelFormat,
+        label: String,
+        is_supported: bool,
+        key: &str,
+        size: i64,
+        stats: &MeshStats,
+    ) -> Result<ModelFile, AppError> {
+        let row = ModelFileNew {
+            stl_id: s_id,
+            format: String::from(format.store()),
+            label,
+            is_supported,
+            file_key: key.to_owned(),
+            file_size: size,
+            parts: stats.parts,
+            triangles: stats.triangles,
+            width: stats.width,
+            depth: stats.depth,
+            height: stats.height,
+            is_watertight: stats.is_watertight,
+            is_manifold: stats.is_manifold,
+            uploaded_at: now(),
+        };
+
+        let model_file = diesel::insert_into(model_files::table)
+            .values(&row)
+            .on_conflict((model_files::stl_id, model_files::file_key))
+            .do_update()
+            .set(&row)
+            .returning(ModelFile::as_returning())
+            .get_result(conn)?;
+
+        Ok(model_file)
+    }
+
+    fn find(conn: &mut PgConnection, s_id: i32, key: &str) -> Result<Option<ModelFile>, AppError> {
+        use crate::schema::model_files::dsl::*;
+
+        let model_file = model_files
+            .filter(stl_id.eq(s_id))
+            .filter(file_key.eq(key))
+            .select(ModelFile::as_select())
+            .get_result(conn)
+            .optional()?;
+
+        Ok(model_file)
+    }
+
+    pub fn list(conn: &mut PgConnection, s_id: i32) -> Result<Vec<ModelFile>, AppError> {
+        use crate::schema::model_files::dsl::*;
+
+        let files = model_files
+            .filter(stl_id.eq(s_id))
+            .order((label.asc(), id.asc()))
+            .select(ModelFile::as_select())
+            .get_results(conn)?;
+
+        Ok(files)
+    }
+
+    // The stored file goes too, unless the asset's own `file` still points at it
+    pub fn destroy(
+        conn: &mut PgConnection,
+        storage: &dyn StorageBackend,
+        s_id: i32,
+        model_id: i32,
+    ) -> Result<usize, AppError> {
+        use crate::schema::stls;
+
+        let key = diesel::delete(
+            model_files::table
+                .filter(model_files::id.eq(model_id))
+                .filter(model_files::stl_id.eq(s_id)),
+        )
+        .returning(model_files::file_key)
+        .get_result::<String>(conn)
+        .optional()?;
+
+        let Some(key) = key else {
+            return Ok(0);
+        };
+        let main = stls::table
+            .find(s_id)
+            .select(stls::file)
+            .get_result::<String>(conn)?;
+        if main != key {
+            storage.delete(&key)?;
+        }
+
+        Ok(1)
+    }
+
+    pub fn destroy_all(conn: &mut PgConnection, s_id: i32) -> Result<usize, AppError> {
+        use crate::schema::model_files::dsl::*;
+
+        let changes = diesel::delete(model_files.filter(stl_id.eq(s_id))).execute(conn)?;
+
+        Ok(changes)
+    }
+}
+
+impl ModelListing {
+    pub fn read(conn: &mut PgConnection, s_id: i32) -> Result<ModelListing, AppError> {
+        let (supported, unsupported) = ModelFile::list(conn, s_id)?
+            .into_iter()
+            .partition(|model_file| model_file.is_supported);
+
+        Ok(ModelListing {
+            supported,
+            unsupported,
+        })
+    }
+}
+
+fn is_binary_stl(bytes: &[u8]) -> bool {
+    match bytes.get(80..84) {
+        Some(count) => {
+            let count = u32::from_le_bytes([count[0], count[1], count[2], count[3]]) as usize;
+            bytes.len() == 84 + count * 50
+        }
+        None => false,
+    }
+}
+
+// Each record is a normal, three vertices and a two byte attribute
+fn binary_triangles(bytes: &[u8]) -> Vec<Triangle> {
+    bytes[84..]
+        .chunks_exact(50)
+        .map(|record| {
+            let float = |offset: usize| {
+                f32::from_le_bytes([
+                    record[offset],
+                    record[offset + 1],
+                    record[offset + 2],
+                    record[offset + 3],
+                ])
+            };
+            [0, 1, 2].map(|corner| [0, 1, 2].map(|axis| float(12 + corner * 12 + axis * 4)))
+        })
+        .collect()
+}
+
+// Only the vertices matter; normals are recomputed by every slicer anyway
+fn ascii_triangles(bytes: &[u8], budget: &mut Budget) -> Result<Vec<Triangle>, AppError> {
+    let text = std::str::from_utf8(bytes).map_err(|_| invalid("ASCII STL is not valid text"))?;
+    let mut words = text.split_ascii_whitespace();
+    let mut vertices = Vec::new();
+
+    while let Some(word) = words.next() {
+        if word != "vertex" {
+            continue;
+        }
+        let mut vertex = [0.0; 3];
+        for coordinate in vertex.iter_mut() {
+            *coordinate = words
+                .next()
+                .and_then(|value| value.parse::<f32>().ok())
+                .ok_or_else(|| invalid("ASCII STL has a malformed vertex"))?;
+        }
+        // a triangle is counted as its first corner is read
+        budget
+            .spend(usize::from(vertices.len() % 3 == 0), 1)
+            .ok_or_else(|| invalid("STL has too many triangles"))?;
+        vertices.push(vertex);
+    }
+
+    if vertices.len() % 3 != 0 {
+        return Err(invalid("ASCII STL has an incomplete facet"));
+    }
+
+    Ok(vertices
+        .chunks_exact(3)
+        .map(|corners| [corners[0], corners[1], corners[2]])
+        .collect())
+}
+
+// The root model is named by the package relationships
+fn root_model(rels: &str) -> Option<String> {
+    let document = Document::parse(rels).ok()?;
+    let target = document
+        .descendants()
+        .filter(|node| node.has_tag_name_local("Relationship"))
+        .find(|node| {
+            node.attribute("Type")
+                .is_some_and(|kind| kind.ends_with("/3dmodel"))
+        })?
+        .attribute("Target")?;
+
+    Some(target.trim_start_matches('/').to_owned())
+}
+
+fn read_part(package: &mut ZipArchive<Cursor<&[u8]>>, name: &str) -> Result<String, AppError> {
+    let part = package
+        .by_name(name)
+        .map_err(|_| invalid(&format!("3MF package has no {}", name)))?;
+    let mut text = String::new();
+    part.take(MAX_MODEL_XML)
+        .read_to_string(&mut text)
+        .map_err(|_| invalid(&format!("3MF {} could not be read", name)))?;
+
+    Ok(text)
+}
+
+fn transform(node: Node) -> Result<Transform, AppError> {
+    let Some(matrix) = node.attribute("transform") else {
+        return Ok(IDENTITY);
+    };
+    let values = matrix
+        .split_ascii_whitespace()
+        .map(|value| value.parse::<f32>())
+        .collect::<Result<Vec<f32>, _>>()
+        .ok()
+        .and_then(|values| Transform::try_from(values).ok())
+        .ok_or_else(|| invalid("3MF transform is malformed"))?;
+
+    Ok(values)
+}
+
+fn apply(m: &Transform, [x, y, z]: [f32; 3]) -> [f32; 3] {
+    [
+        x * m[0] + y * m[3] + z * m[6] + m[9],
+        x * m[1] + y * m[4] + z * m[7] + m[10],
+        x * m[2] + y * m[5] + z * m[8] + m[11],
+    ]
+}
+
+// What is left of the caps on placed triangles and vertices
+struct Budget {
+    triangles: usize,
+    vertices: usize,
+}
+
+impl Budget {
+    fn new() -> Budget {
+        Budget {
+            triangles: MAX_PLACED_TRIANGLES,
+            vertices: MAX_PLACED_VERTICES,
+        }
+    }
+
+    // None once either cap would be passed
+    fn spend(&mut self, triangles: usize, vertices: usize) -> Option<()> {
+        self.triangles = self.triangles.checked_sub(triangles)?;
+        self.vertices = self.vertices.checked_sub(vertices)?;
+        Some(())
+    }
+}
+
+// transforms run from the innermost component out to the build item. Each
+// mesh is counted against the budget before it is placed
+fn place(
+    objects: &HashMap<&str, Node>,
+    id: &str,
+    transforms: &[Transform],
+    triangles: &mut Vec<Triangle>,
+    budget: &mut Budget,
+    depth: usize,
+) -> Result<(), AppError> {
+    if depth > MAX_NESTING {
+        return Err(invalid("3MF components nest too deeply"));
+    }
+    let object = objects
+        .get(id)
+        .ok_or_else(|| invalid("3MF refers to a missing object"))?;
+
+    for child in object.children().filter(Node::is_element) {
+        if child.has_tag_name_local("mesh") {
+            let vertex_count = child
+                .descendants()
+                .filter(|node| node.has_tag_name_local("vertex"))
+                .count();
+            let triangle_count = child
+                .descendants()
+                .filter(|node| node.has_tag_name_local("triangle"))
+                .count();
+            budget
+                .spend(triangle_count, vertex_count)
+                .ok_or_else(|| invalid("3MF places too many triangles or vertices"))?;
+
+            let points = child
+                .descendants()
+                .filter(|node| node.has_tag_name_local("vertex"))
+                .map(|vertex| {
+                    let mut point = [0.0; 3];
+                    for (coordinate, axis) in point.iter_mut().zip(["x", "y", "z"]) {
+                        *coordinate = vertex
+                            .attribute(axis)
+                            .and_then(|value| value.parse::<f32>().ok())
+                            .ok_or_else(|| invalid("3MF has a malformed vertex"))?;
+                    }
+                    Ok(transforms
+                        .iter()
+                        .fold(point, |point, transform| apply(transform, point)))
+                })
+                .collect::<Result<Vec<[f32; 3]>, AppError>>()?;
+
+            triangles.reserve(triangle_count);
+            for triangle in child
+                .descendants()
+                .filter(|node| node.has_tag_name_local("triangle"))
+            {
+                let mut corners = [[0.0; 3]; 3];
+                for (corner, name) in corners.iter_mut().zip(["v1", "v2", "v3"]) {
+                    *corner = triangle
+                        .attribute(name)
+                        .and_then(|index| index.parse::<usize>().ok())
+                        .and_then(|index| points.get(index).copied())
+                        .ok_or_else(|| invalid("3MF triangle refers to a missing vertex"))?;
+                }
+                triangles.push(corners);
+            }
+        } else if child.has_tag_name_local("components") {
+            for component in child
+                .children()
+                .filter(|node| node.has_tag_name_local("component"))
+            {
+                let inner = component
+                    .attribute("objectid")
+                    .ok_or_else(|| invalid("3MF component names no object"))?;
+                let mut nested = vec![transform(component)?];
+                nested.extend_from_slice(transforms);
+                place(objects, inner, &nested, triangles, budget, depth + 1)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+trait LocalName {
+    fn has_tag_name_local(&self, name: &str) -> bool;
+}
+
+// 3MF extensions add namespaces, so elements are matched on their local name
+impl LocalName for Node<'_, '_> {
+    fn has_tag_name_local(&self, name: &str) -> bool {
+        self.is_element() && self.tag_name().name() == name
+    }
+}
+
+fn invalid(reason: &str) -> AppError {
+    AppError::Validation(format!("not a valid model: {}", reason))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // A 10 x 20 x 30 box, wound outwards; the preview tests render it too
+    pub(crate) fn cuboid() -> Vec<Triangle> {
+        let corner =
+            |x: usize, y: usize, z: usize| [10.0 * x as f32, 20.0 * y as f32, 30.0 * z as f32];
+        let quads = [
+            [(0, 0, 0), (0, 1, 0), (1, 1, 0), (1, 0, 0)],
+            [(0, 0, 1), (1, 0, 1), (1, 1, 1), (0, 1, 1)],
+            [(0, 0, 0), (1, 0, 0), (1, 0, 1), (0, 0, 1)],
+            [(0, 1, 0), (0, 1, 1), (1, 1, 1), (1, 1, 0)],
+            [(0, 0, 0), (0, 0, 1), (0, 1, 1), (0, 1, 0)],
+            [(1, 0, 0), (1, 1, 0), (1, 1, 1), (1, 0, 1)],
+        ];
+
+        quads
+            .iter()
+            .flat_map(|quad| {
+                let [a, b, c, d] = quad.map(|(x, y, z)| corner(x, y, z));
+                [[a, b, c], [a, c, d]]
+            })
+            .collect()
+    }
+
+    fn binary(triangles: &[Triangle]) -> Vec<u8> {
+        let mut bytes = b"solid but actually binary".to_vec();
+        bytes.resize(80, 0);
+        bytes.extend_from_slice(&(triangles.len() as u32).to_le_bytes());
+        for triangle in triangles {
+            bytes.extend_from_slice(&[0; 12]);
+            for value in triangle.iter().flatten() {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            bytes.extend_from_slice(&[0; 2]);
+        }
+        bytes
+    }
+
+    fn ascii(triangles: &[Triangle]) -> Vec<u8> {
+        let mut text = String::from("solid cuboid\n");
+        for triangle in triangles {
+            text.push_str("facet normal 0 0 0\nouter loop\n");
+            for [x, y, z] in triangle {
+                text.push_str(&format!("vertex {} {} {}\n", x, y, z));
+            }
+            text.push_str("endloop\nendfacet\n");
+        }
+        text.push_str("endsolid cuboid\n");
+        text.into_bytes()
+    }
+
+    #[test]
+    fn stl_parsing() {
+        let closed = MeshStats {
+            parts: 1,
+            triangles: 12,
+            width: 10.0,
+            depth: 20.0,
+            height: 30.0,
+            is_watertight: true,
+            is_manifold: true,
+        };
+
+        assert_eq!(
+            MeshStats::parse(ModelFormat::Stl, &binary(&cuboid())).unwrap(),
+            closed
+        );
+        assert_eq!(
+            MeshStats::parse(ModelFormat::Stl, &ascii(&cuboid())).unwrap(),
+            closed
+        );
+
+        let mut open = cuboid();
+        open.pop();
+        let stats = MeshStats::parse(ModelFormat::Stl, &binary(&open)).unwrap();
+
+        assert!(!stats.is_watertight);
+        assert!(stats.is_manifold);
+
+        let mut flipped = cuboid();
+        flipped[0].swap(1, 2);
+        let stats = MeshStats::parse(ModelFormat::Stl, &ascii(&flipped)).unwrap();
+
+        assert!(stats.is_watertight);
+        assert!(!stats.is_manifold);
+
+        // the cap holds for both encodings
+        for bytes in [binary(&cuboid()), ascii(&cuboid())] {
+            let parse_with = |triangles, vertices| {
+                Mesh::parse_stl(
+                    &bytes,
+                    &mut Budget {
+                        triangles,
+                        vertices,
+                    },
+                )
+                .map(|mesh| mesh.triangles.len())
+            };
+
+            assert_eq!(parse_with(12, 36).unwrap(), 12);
+            assert!(matches!(parse_with(11, 36), Err(AppError::Validation(_))));
+            assert!(matches!(parse_with(12, 35), Err(AppError::Validation(_))));
+        }
+
+        let mut truncated = binary(&cuboid());
+        truncated.truncate(200);
+
+        for bytes in [
+            truncated,
+            b"solid empty\nendsolid empty\n".to_vec(),
+            b"solid broken\nfacet normal 0 0 0\nouter loop\nvertex 1 2\n".to_vec(),
+            b"\x89PNG\r\n\x1a\n".to_vec(),
+        ] {
+            assert!(matches!(
+                MeshStats::parse(ModelFormat::Stl, &bytes),
+                Err(AppError::Validation(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn obj_parsing() {
+        // the cuboid as quads, with texture and normal indices
+        let obj = "# box\no box\n\
+            v 0 0 0\nv 10 0 0\nv 10 20 0\nv 0 20 0\n\
+            v 0 0 30\nv 10 0 30\nv 10 20 30\nv 0 20 30\n\
+            vt 0 0\nvn 0 0 1\n\
+            f 1/1/1 4/1/1 3/1/1 2/1/1\nf 5//1 6//1 7//1 8//1\n\
+            f 1 2 6 5\nf 4 8 7 3\nf 1 5 8 4\nf -7 -6 -2 -3\n";
+        let stats = MeshStats::parse(ModelFormat::Obj, obj.as_bytes()).unwrap();
+
+        assert_eq!(
+            stats,
+            MeshStats {
+                parts: 1,
+                triangles: 12,
+                width: 10.0,
+                depth: 20.0,
+                height: 30.0,
+                is_watertight: true,
+                is_manifold: true,
+            }
+        );
+
+        for obj in [
+            "v 0 0 0\nv 1 0 0\nf 1 2 3\n",
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1 2\n",
+            "v 0 0\n",
+            "v 0 0 0\nf 0 1 2\n",
+            "",
+        ] {
+            assert!(matches!(
+                MeshStats::parse(ModelFormat::Obj, obj.as_bytes()),
+                Err(AppError::Validation(_))
+            ));
+        }
+
+        // vertices are counted as they are read and faces by their triangles
+        let parse_with = |triangles, vertices| {
+            Mesh::parse_obj(
+                obj.as_bytes(),
+                &mut Budget {
+                    triangles,
+                    vertices,
+                },
+            )
+            .map(|mesh| mesh.triangles.len())
+        };
+
+        assert_eq!(parse_with(12, 8).unwrap(), 12);
+        assert!(matches!(parse_with(11, 8), Err(AppError::Validation(_))));
+        assert!(matches!(parse_with(12, 7), Err(AppError::Validation(_))));
+    }
+
+    fn package(model: &str, rels: Option<&str>) -> Vec<u8> {
+        use std::io::Write;
+        use zip::write::SimpleFileOptions;
+
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        if let Some(rels) = rels {
+            zip.start_file("_rels/.rels", SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(rels.as_bytes()).unwrap();
+        }
+        zip.start_file("3D/box.model", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(model.as_bytes()).unwrap();
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn three_mf_parsing() {
+        let vertices = cuboid()
+            .iter()
+            .flatten()
+            .map(|[x, y, z]| format!("<vertex x=\"{}\" y=\"{}\" z=\"{}\"/>", x, y, z))
+            .collect::<String>();
+        let triangles = (0..12)
+            .map(|n| {
+                format!(
+                    "<triangle v1=\"{}\" v2=\"{}\" v3=\"{}\"/>",
+                    3 * n,
+                    3 * n + 1,
+                    3 * n + 2
+                )
+            })
+            .collect::<String>();
+        // two boxes side by side, the second placed through a component
+        let model = format!(
+            "<?xml version=\"1.0\"?>\
+            <model unit=\"centimeter\" xmlns=\"http://schemas.microsoft.com/3dmanufacturing/core/2015/02\">\
+            <resources>\
+            <object id=\"1\" type=\"model\"><mesh><vertices>{}</vertices><triangles>{}</triangles></mesh></object>\
+            <object id=\"2\" type=\"model\"><components><component objectid=\"1\" transform=\"1 0 0 0 1 0 0 0 1 5 0 0\"/></components></object>\
+            </resources>\
+            <build><item objectid=\"1\"/><item objectid=\"2\" transform=\"1 0 0 0 1 0 0 0 1 10 0 0\"/></build>\
+            </model>",
+            vertices, triangles
+        );
+        let rels = "<?xml version=\"1.0\"?>\
+            <Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">\
+            <Relationship Target=\"/3D/box.model\" Id=\"rel0\" \
+            Type=\"http://schemas.microsoft.com/3dmanufacturing/2013/01/3dmodel\"/>\
+            </Relationships>";
+        let stats = MeshStats::parse(ModelFormat::ThreeMf, &package(&model, Some(rels))).unwrap();
+
+        assert_eq!(
+            stats,
+            MeshStats {
+                parts: 2,
+                triangles: 24,
+                width: 250.0,
+                depth: 200.0,
+                height: 300.0,
+                is_watertight: true,
+                is_manifold: true,
+            }
+        );
+
+        // without relationships the model is looked for in the usual place
+        let missing = package(&model, None);
+        let looping = package(
+            "<model><resources>\
+            <object id=\"1\"><components><component objectid=\"1\"/></components></object>\
+            </resources><build><item objectid=\"1\"/></build></model>",
+            Some(rels),
+        );
+
+        for bytes in [missing, looping, b"PK\x03\x04 not really".to_vec()] {
+            assert!(matches!(
+                MeshStats::parse(ModelFormat::ThreeMf, &bytes),
+                Err(AppError::Validation(_))
+            ));
+        }
+        assert_eq!(ModelFormat::from_extension("zip"), None);
+    }
+
+    #[test]
+    fn placement_budget() {
+        // one triangle placed ten times over, twice
+        let components = "<component objectid=\"1\"/>".repeat(10);
+        let xml = format!(
+            "<model><resources>\
+            <object id=\"1\"><mesh><vertices>\
+            <vertex x=\"0\" y=\"0\" z=\"0\"/><vertex x=\"1\" y=\"0\" z=\"0\"/>\
+            <vertex x=\"0\" y=\"1\" z=\"0\"/></vertices>\
+            <triangles><triangle v1=\"0\" v2=\"1\" v3=\"2\"/></triangles></mesh></object>\
+            <object id=\"2\"><components>{}</components></object>\
+            <object id=\"3\"><components>{}</components></object>\
+            </resources></model>",
+            components,
+            components.replace("\"1\"", "\"2\"")
+        );
+        let document = Document::parse(&xml).unwrap();
+        let objects = document
+            .descendants()
+            .filter(|node| node.has_tag_name_local("object"))
+            .filter_map(|node| Some((node.attribute("id")?, node)))
+            .collect::<HashMap<&str, Node>>();
+        let place_with = |triangles, vertices| {
+            let mut placed = Vec::new();
+            let mut budget = Budget {
+                triangles,
+                vertices,
+            };
+            place(&objects, "3", &[IDENTITY], &mut placed, &mut budget, 0).map(|_| placed.len())
+        };
+
+        assert_eq!(place_with(100, 300).unwrap(), 100);
+        assert!(matches!(place_with(99, 300), Err(AppError::Validation(_))));
+        assert!(matches!(place_with(100, 299), Err(AppError::Validation(_))));
+    }
+}
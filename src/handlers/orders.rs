@@ -0,0 +1,526 @@
+use super::cart::{Cart, CartItem, CartOwner};
+use super::payments::{Charge, PaymentProvider};
+use crate::schema::{cart_items, order_items, orders};
+use crate::types::asset::{AssetType, Ownership};
+use crate::types::error::AppError;
+use crate::types::order::OrderStatus;
+use crate::types::price::{Currency, Price};
+use crate::types::time::now;
+use diesel::prelude::*;
+use serde::Serialize;
+
+// How long a pending order holds up the next checkout, in seconds; an order
+// still pending after this was cut off mid charge and is not waited on
+const CHECKOUT_TIMEOUT: i64 = 10 * 60;
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = orders)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+struct OrderQuery {
+    id: i32,
+    user_id: i32,
+    status: String,
+    total: i32,
+    currency: String,
+    payment_reference: Option<String>,
+    created_at: i64,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = orders)]
+struct OrderNew {
+    user_id: i32,
+    status: String,
+    total: i32,
+    currency: String,
+    created_at: i64,
+}
+
+#[derive(Queryable, Selectable, Serialize)]
+#[diesel(table_name = order_items)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct OrderItem {
+    pub id: i32,
+    pub order_id: i32,
+    pub asset_type: String,
+    pub asset_id: i32,
+    pub amount: i32,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = order_items)]
+struct OrderItemNew {
+    order_id: i32,
+    asset_type: String,
+    asset_id: i32,
+    amount: i32,
+}
+
+#[derive(Serialize)]
+pub struct Order {
+    pub id: i32,
+    pub user_id: i32,
+    pub status: OrderStatus,
+    pub total: Price,
+    pub payment_reference: Option<String>,
+    // unix seconds
+    pub created_at: i64,
+    pub items: Vec<OrderItem>,
+}
+
+impl Order {
+    // Turns the user's cart into an order and charges it; ownership is only
+    // granted, and the cart only emptied, once the provider accepts the charge.
+    // A charge that cannot be turned into a purchase is refunded.
+    pub fn checkout(
+        conn: &mut PgConnection,
+        u_id: i32,
+        currency: Currency,
+        provider: &dyn PaymentProvider,
+    ) -> Result<Order, AppError> {
+        let owner = CartOwner::User(u_id);
+
+        let (cart, order_id, total) = conn.transaction(|conn| {
+            // a second checkout waits here for the first to record its order,
+            // then finds it pending and stops before charging again
+            cart_items::table
+                .filter(cart_items::user_id.eq(u_id))
+                .select(cart_items::id)
+                .for_update()
+                .load::<i32>(conn)?;
+
+            let in_progress = orders::table
+                .filter(orders::user_id.eq(u_id))
+                .filter(orders::status.eq(OrderStatus::Pending.store()))
+                .filter(orders::created_at.gt(now() - CHECKOUT_TIMEOUT))
+                .select(orders::id)
+                .first::<i32>(conn)
+                .optional()?;
+
+            if let Some(pending) = in_progress {
+                return Err(AppError::Conflict(format!(
+                    "order {} is already being checked out",
+                    pending
+                )));
+            }
+
+            let cart = Cart::read(conn, &owner, Some(currency))?;
+
+            if cart.items.is_empty() {
+                return Err(AppError::Validation(String::from("cart is empty")));
+            }
+
+            if cart
+                .items
+                .iter()
+                .any(|line| line.price.currency != currency)
+            {
+                return Err(AppError::Validation(format!(
+                    "not every item in the cart is sold in {}",
+                    currency.store()
+                )));
+            }
+
+            for line in &cart.items {
+                let summary = line.asset_type.summarize(conn, line.asset_id, u_id)?;
+                if summary.ownership != Ownership::Unowned {
+                    return Err(AppError::Conflict(format!(
+                        "{} {} is already owned",
+                        line.asset_type.store(),
+                        line.asset_id
+                    )));
+                }
+            }
+
+            let total = cart
+                .items
+                .iter()
+                .try_fold(0i32, |sum, line| sum.checked_add(line.price.amount))
+                .ok_or_else(|| AppError::Validation(String::from("order total is too large")))?;
+
+            let order_id = diesel::insert_into(orders::table)
+                .values(OrderNew {
+                    user_id: u_id,
+                    status: String::from(OrderStatus::Pending.store()),
+                    total,
+                    currency: String::from(currency.store()),
+                    created_at: now(),
+                })
+                .returning(orders::id)
+                .get_result::<i32>(conn)?;
+
+            let items: Vec<OrderItemNew> = cart
+                .items
+                .iter()
+                .map(|line| OrderItemNew {
+                    order_id,
+                    asset_type: String::from(line.asset_type.store()),
+                    asset_id: line.asset_id,
+                    amount: line.price.amount,
+                })
+                .collect();
+
+            diesel::insert_into(order_items::table)
+                .values(&items)
+                .execute(conn)?;
+
+            Ok::<(Cart, i32, i32), AppError>((cart, order_id, total))
+        })?;
+
+        let charge = Charge {
+            order_id,
+            user_id: u_id,
+            price: Price::new(total, currency),
+        };
+
+        let receipt = match provider.charge(&charge) {
+            Ok(receipt) => receipt,
+            Err(err) => {
+                set_status(conn, order_id, OrderStatus::Failed, None)?;
+                return Err(err);
+            }
+        };
+
+        let purchased = conn.transaction(|conn| {
+            set_status(
+                conn,
+                order_id,
+                OrderStatus::Paid,
+                Some(receipt.reference.to_owned()),
+            )?;
+
+            for line in &cart.items {
+                line.asset_type.grant(conn, line.asset_id, u_id)?;
+            }
+
+            CartItem::destroy_all(conn, &owner)
+        });
+
+        // the money was taken, so the order keeps the charge's reference either
+        // way; a failed order with a reference is one still to be refunded
+        if let Err(err) = purchased {
+            let status = match provider.refund(&receipt.reference) {
+                Ok(()) => OrderStatus::Refunded,
+                Err(refund_err) => {
+                    tracing::error!(
+                        "order {} was charged as {} but neither completed nor refunded: {}",
+                        order_id,
+                        receipt.reference,
+                        refund_err
+                    );
+                    OrderStatus::Failed
+                }
+            };
+            set_status(conn, order_id, status, Some(receipt.reference))?;
+            return Err(err);
+        }
+
+        Order::read(conn, u_id, order_id)
+    }
+
+    pub fn read(conn: &mut PgConnection, u_id: i32, o_id: i32) -> Result<Order, AppError> {
+        use crate::schema::orders::dsl::*;
+
+        let order = orders
+            .filter(id.eq(o_id))
+            .filter(user_id.eq(u_id))
+            .select(OrderQuery::as_select())
+            .get_result(conn)?;
+
+        Order::new(conn, order)
+    }
+
+    // newest first
+    pub fn list(conn: &mut PgConnection, u_id: i32) -> Result<Vec<Order>, AppError> {
+        use crate::schema::orders::dsl::*;
+
+        let results = orders
+            .filter(user_id.eq(u_id))
+            .order((created_at.desc(), id.desc()))
+            .select(OrderQuery::as_select())
+            .get_results(conn)?;
+
+        results
+            .into_iter()
+            .map(|order| Order::new(conn, order))
+            .collect()
+    }
+
+    // The latest paid order the user bought the asset in, if they bought it
+    pub fn purchase_of(
+        conn: &mut PgConnection,
+        u_id: i32,
+        asset_type: &AssetType,
+        a_id: i32,
+    ) -> Result<Option<i32>, AppError> {
+        let order_id = orders::table
+            .inner_join(order_items::table)
+            .filter(orders::user_id.eq(u_id))
+            .filter(orders::status.eq(OrderStatus::Paid.store()))
+            .filter(order_items::asset_type.eq(asset_type.store()))
+            .filter(order_items::asset_id.eq(a_id))
+            .order((orders::created_at.desc(), orders::id.desc()))
+            .select(orders::id)
+            .first(conn)
+            .optional()?;
+
+        Ok(order_id)
+    }
+
+    // order items go with the order
+    pub fn destroy(conn: &mut PgConnection, u_id: i32, o_id: i32) -> Result<usize, AppError> {
+        use crate::schema::orders::dsl::*;
+
+        let changes =
+            diesel::delete(orders.filter(id.eq(o_id)).filter(user_id.eq(u_id))).execute(conn)?;
+
+        Ok(changes)
+    }
+
+    fn new(conn: &mut PgConnection, order: OrderQuery) -> Result<Order, AppError> {
+        use crate::schema::order_items::dsl::*;
+
+        let items = order_items
+            .filter(order_id.eq(order.id))
+            .order(id)
+            .select(OrderItem::as_select())
+            .get_results(conn)?;
+
+        Ok(Order {
+            id: order.id,
+            user_id: order.user_id,
+            status: OrderStatus::retrieve(&order.status)?,
+            total: Price::stored(order.total, &order.currency)?,
+            payment_reference: order.payment_reference,
+            created_at: order.created_at,
+            items,
+        })
+    }
+}
+
+fn set_status(
+    conn: &mut PgConnection,
+    o_id: i32,
+    new_status: OrderStatus,
+    reference: Option<String>,
+) -> Result<usize, AppError> {
+    use crate::schema::orders::dsl::*;
+
+    let changes = diesel::update(orders.filter(id.eq(o_id)))
+        .set((
+            status.eq(new_status.store()),
+            payment_reference.eq(reference),
+        ))
+        .execute(conn)?;
+
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::book::{Book, BookCreate};
+    use crate::handlers::connect;
+    use crate::handlers::creator::{CreatorNew, Creators};
+    use crate::handlers::ownership::books::UserBook;
+    use crate::handlers::payments::{FakeProvider, Receipt};
+    use crate::handlers::user::{User, UserNew};
+    use crate::types::asset::{Asset, AssetType};
+    use crate::types::user::DisplayName;
+    use std::sync::Mutex;
+
+    // Approves charges, but runs a test's interference while one is in flight
+    struct Meddling<F: Fn() + Send + Sync> {
+        during: F,
+        refunds: Mutex<Vec<String>>,
+    }
+
+    impl<F: Fn() + Send + Sync> PaymentProvider for Meddling<F> {
+        fn charge(&self, charge: &Charge) -> Result<Receipt, AppError> {
+            (self.during)();
+            FakeProvider::approving().charge(charge)
+        }
+
+        fn refund(&self, reference: &str) -> Result<(), AppError> {
+            self.refunds.lock().unwrap().push(String::from(reference));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn checkout_full() {
+        let conn = &mut connect::establish_connection();
+
+        let user = UserNew::create(
+            conn,
+            String::from("checkout_full"),
+            String::from("checkout_full@gmail.com"),
+            String::from("logo.svg"),
+        )
+        .unwrap();
+
+        let creator = CreatorNew::create(
+            conn,
+            user.id,
+            Some(String::from("Chris")),
+            Some(String::from("Hughes")),
+            None,
+            None,
+            DisplayName::Name,
+        )
+        .unwrap();
+
+        let mut books = Vec::new();
+        for (title, amount) in [("Checkout One", 1000), ("Checkout Two", 500)] {
+            let book = BookCreate::new(
+                creator.id,
+                String::from(title),
+                String::from("thumb.jpg"),
+                String::from("What a book!"),
+                String::from("file.pdf"),
+                100,
+                String::from("image.jpg"),
+                false,
+                Price::new(amount, Currency::Usd),
+            )
+            .create(conn)
+            .unwrap();
+            CartItem::create(conn, &CartOwner::User(user.id), AssetType::Book, book.id).unwrap();
+            books.push(book);
+        }
+
+        let foreign = Order::checkout(conn, user.id, Currency::Eur, &FakeProvider::approving());
+
+        assert!(matches!(foreign, Err(AppError::Validation(_))));
+
+        let declined = Order::checkout(conn, user.id, Currency::Usd, &FakeProvider::declining());
+
+        assert!(matches!(declined, Err(AppError::Payment(_))));
+
+        let failed = Order::list(conn, user.id).unwrap();
+
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].status, OrderStatus::Failed);
+        assert_eq!(
+            books[0].check_ownership(conn, user.id).unwrap(),
+            Ownership::Unowned
+        );
+
+        let order =
+            Order::checkout(conn, user.id, Currency::Usd, &FakeProvider::approving()).unwrap();
+
+        assert_eq!(order.status, OrderStatus::Paid);
+        assert_eq!(order.total, Price::new(1500, Currency::Usd));
+        assert_eq!(order.items.len(), 2);
+        assert!(order.payment_reference.is_some());
+
+        for book in &books {
+            assert_eq!(
+                book.check_ownership(conn, user.id).unwrap(),
+                Ownership::Owned
+            );
+        }
+
+        let cart = Cart::read(conn, &CartOwner::User(user.id), None).unwrap();
+
+        assert!(cart.items.is_empty());
+
+        let empty = Order::checkout(conn, user.id, Currency::Usd, &FakeProvider::approving());
+
+        assert!(matches!(empty, Err(AppError::Validation(_))));
+
+        let orders = Order::list(conn, user.id).unwrap();
+
+        assert_eq!(orders.len(), 2);
+        assert_eq!(orders[0].id, order.id);
+
+        for order in orders {
+            Order::destroy(conn, user.id, order.id).unwrap();
+        }
+        for book in books {
+            UserBook::destroy(conn, user.id, book.id).unwrap();
+            Book::destroy(conn, book.id).unwrap();
+        }
+        Creators::destroy(conn, creator.id).unwrap();
+        User::destroy(conn, user.id).unwrap();
+    }
+
+    #[test]
+    fn checkout_refunds_what_it_cannot_grant() {
+        let conn = &mut connect::establish_connection();
+
+        let user = UserNew::create(
+            conn,
+            String::from("refunded_reader"),
+            String::from("refunded@gmail.com"),
+            String::from("logo.svg"),
+        )
+        .unwrap();
+
+        let creator = CreatorNew::create(
+            conn,
+            user.id,
+            Some(String::from("Chris")),
+            Some(String::from("Hughes")),
+            None,
+            None,
+            DisplayName::Name,
+        )
+        .unwrap();
+
+        let book = BookCreate::new(
+            creator.id,
+            String::from("Checkout Race"),
+            String::from("thumb.jpg"),
+            String::from("What a book!"),
+            String::from("file.pdf"),
+            100,
+            String::from("image.jpg"),
+            false,
+            Price::new(1000, Currency::Usd),
+        )
+        .create(conn)
+        .unwrap();
+        CartItem::create(conn, &CartOwner::User(user.id), AssetType::Book, book.id).unwrap();
+
+        // while the charge is out, a second checkout is turned away and the
+        // book is granted some other way, so the first cannot grant it
+        let second = Mutex::new(None);
+        let provider = Meddling {
+            during: || {
+                let other = &mut connect::establish_connection();
+                let again =
+                    Order::checkout(other, user.id, Currency::Usd, &FakeProvider::approving());
+                *second.lock().unwrap() = Some(matches!(again, Err(AppError::Conflict(_))));
+                UserBook::new(user.id, book.id).create(other).unwrap();
+            },
+            refunds: Mutex::new(Vec::new()),
+        };
+
+        let checkout = Order::checkout(conn, user.id, Currency::Usd, &provider);
+
+        assert!(checkout.is_err());
+        assert_eq!(*second.lock().unwrap(), Some(true));
+
+        let orders = Order::list(conn, user.id).unwrap();
+        let refunds = provider.refunds.lock().unwrap();
+
+        assert_eq!(orders.len(), 1);
+        assert_eq!(orders[0].status, OrderStatus::Refunded);
+        assert_eq!(*refunds, vec![orders[0].payment_reference.clone().unwrap()]);
+        assert_eq!(
+            Cart::read(conn, &CartOwner::User(user.id), None)
+                .unwrap()
+                .items
+                .len(),
+            1
+        );
+
+        CartItem::destroy_all(conn, &CartOwner::User(user.id)).unwrap();
+        Order::destroy(conn, user.id, orders[0].id).unwrap();
+        UserBook::destroy(conn, user.id, book.id).unwrap();
+        Book::destroy(conn, book.id).unwrap();
+        Creators::destroy(conn, creator.id).unwrap();
+        User::destroy(conn, user.id).unwrap();
+    }
+}
@@ -1,5 +1,6 @@
 use crate::schema::user_albums;
 use crate::types::asset::Ownership;
+use crate::types::error::AppError;
 use diesel::prelude::*;
 
 #[derive(Queryable, Selectable, Insertable)]
@@ -13,25 +14,64 @@ impl UserAlbum {
         UserAlbum { user_id, album_id }
     }
 
-    pub fn create(&self, conn: &mut PgConnection) -> usize {
-        diesel::insert_into(user_albums::table)
+    pub fn create(&self, conn: &mut PgConnection) -> Result<usize, AppError> {
+        let changes = diesel::insert_into(user_albums::table)
             .values(self)
-            .execute(conn)
-            .expect("Error saving Album")
+            .execute(conn)?;
+
+        Ok(changes)
     }
 
-    pub fn check_ownership(conn: &mut PgConnection, u_id: i32, b_id: i32) -> Ownership {
+    pub fn destroy(conn: &mut PgConnection, u_id: i32, a_id: i32) -> Result<usize, AppError> {
+        use crate::schema::user_albums::dsl::*;
+
+        let changes = diesel::delete(
+            user_albums
+                .filter(user_id.eq(u_id))
+                .filter(album_id.eq(a_id)),
+        )
+        .execute(conn)?;
+
+        Ok(changes)
+    }
+
+    // ids the user has bought, with when they bought them
+    pub fn list(conn: &mut PgConnection, u_id: i32) -> Result<Vec<(i32, i64)>, AppError> {
+        use crate::schema::user_albums::dsl::*;
+
+        let owned = user_albums
+            .filter(user_id.eq(u_id))
+            .select((album_id, purchased_at))
+            .get_results(conn)?;
+
+        Ok(owned)
+    }
+
+    pub fn check_ownership(
+        conn: &mut PgConnection,
+        u_id: i32,
+        b_id: i32,
+    ) -> Result<Ownership, AppError> {
         use crate::schema::user_albums::dsl::*;
 
         let result = user_albums
             .filter(album_id.eq(b_id))
             .filter(user_id.eq(u_id))
-            .execute(conn)
-            .expect("Error loading posts");
+            .execute(conn)?;
 
         match result {
-            1 => Ownership::Owned,
-            _ => Ownership::Unowned,
+            1 => Ok(Ownership::Owned),
+            _ => Ok(Ownership::Unowned),
         }
     }
+
+    // whether anyone at all has bought the album
+    pub fn is_owned(conn: &mut PgConnection, a_id: i32) -> Result<bool, AppError> {
+        use crate::schema::user_albums::dsl::*;
+
+        let owned = diesel::select(diesel::dsl::exists(user_albums.filter(album_id.eq(a_id))))
+            .get_result(conn)?;
+
+        Ok(owned)
+    }
 }
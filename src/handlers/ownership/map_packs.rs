@@ -0,0 +1,82 @@
+use crate::schema::user_map_packs;
+use crate::types::asset::Ownership;
+use crate::types::error::AppError;
+use diesel::prelude::*;
+
+#[derive(Queryable, Selectable, Insertable)]
+pub struct UserMapPack {
+    user_id: i32,
+    map_pack_id: i32,
+}
+
+impl UserMapPack {
+    pub fn new(user_id: i32, map_pack_id: i32) -> Self {
+        UserMapPack {
+            user_id,
+            map_pack_id,
+        }
+    }
+
+    pub fn create(&self, conn: &mut PgConnection) -> Result<usize, AppError> {
+        let changes = diesel::insert_into(user_map_packs::table)
+            .values(self)
+            .execute(conn)?;
+
+        Ok(changes)
+    }
+
+    pub fn destroy(conn: &mut PgConnection, u_id: i32, p_id: i32) -> Result<usize, AppError> {
+        use crate::schema::user_map_packs::dsl::*;
+
+        let changes = diesel::delete(
+            user_map_packs
+                .filter(user_id.eq(u_id))
+                .filter(map_pack_id.eq(p_id)),
+        )
+        .execute(conn)?;
+
+        Ok(changes)
+    }
+
+    // ids the user has bought, with when they bought them
+    pub fn list(conn: &mut PgConnection, u_id: i32) -> Result<Vec<(i32, i64)>, AppError> {
+        use crate::schema::user_map_packs::dsl::*;
+
+        let owned = user_map_packs
+            .filter(user_id.eq(u_id))
+            .select((map_pack_id, purchased_at))
+            .get_results(conn)?;
+
+        Ok(owned)
+    }
+
+    pub fn check_ownership(
+        conn: &mut PgConnection,
+        u_id: i32,
+        p_id: i32,
+    ) -> Result<Ownership, AppError> {
+        use crate::schema::user_map_packs::dsl::*;
+
+        let result = user_map_packs
+            .filter(map_pack_id.eq(p_id))
+            .filter(user_id.eq(u_id))
+            .execute(conn)?;
+
+        match result {
+            1 => Ok(Ownership::Owned),
+            _ => Ok(Ownership::Unowned),
+        }
+    }
+
+    // whether anyone at all has bought the map pack
+    pub fn is_owned(conn: &mut PgConnection, p_id: i32) -> Result<bool, AppError> {
+        use crate::schema::user_map_packs::dsl::*;
+
+        let owned = diesel::select(diesel::dsl::exists(
+            user_map_packs.filter(map_pack_id.eq(p_id)),
+        ))
+        .get_result(conn)?;
+
+        Ok(owned)
+    }
+}
@@ -1,5 +1,6 @@
 use crate::schema::user_stls;
 use crate::types::asset::Ownership;
+use crate::types::error::AppError;
 use diesel::prelude::*;
 
 #[derive(Queryable, Selectable, Insertable)]
@@ -13,25 +14,51 @@ impl UserStl {
         UserStl { user_id, stl_id }
     }
 
-    pub fn create(&self, conn: &mut PgConnection) -> usize {
-        diesel::insert_into(user_stls::table)
+    pub fn create(&self, conn: &mut PgConnection) -> Result<usize, AppError> {
+        let changes = diesel::insert_into(user_stls::table)
             .values(self)
-            .execute(conn)
-            .expect("Error saving stl ownership")
+            .execute(conn)?;
+
+        Ok(changes)
+    }
+
+    // ids the user has bought, with when they bought them
+    pub fn list(conn: &mut PgConnection, u_id: i32) -> Result<Vec<(i32, i64)>, AppError> {
+        use crate::schema::user_stls::dsl::*;
+
+        let owned = user_stls
+            .filter(user_id.eq(u_id))
+            .select((stl_id, purchased_at))
+            .get_results(conn)?;
+
+        Ok(owned)
     }
 
-    pub fn check_ownership(conn: &mut PgConnection, u_id: i32, b_id: i32) -> Ownership {
+    pub fn check_ownership(
+        conn: &mut PgConnection,
+        u_id: i32,
+        b_id: i32,
+    ) -> Result<Ownership, AppError> {
         use crate::schema::user_stls::dsl::*;
 
         let result = user_stls
             .filter(stl_id.eq(b_id))
             .filter(user_id.eq(u_id))
-            .execute(conn)
-            .expect("Error loading stl ownership");
+            .execute(conn)?;
 
         match result {
-            1 => Ownership::Owned,
-            _ => Ownership::Unowned,
+            1 => Ok(Ownership::Owned),
+            _ => Ok(Ownership::Unowned),
         }
     }
+
+    // whether anyone at all has bought the stl
+    pub fn is_owned(conn: &mut PgConnection, s_id: i32) -> Result<bool, AppError> {
+        use crate::schema::user_stls::dsl::*;
+
+        let owned = diesel::select(diesel::dsl::exists(user_stls.filter(stl_id.eq(s_id))))
+            .get_result(conn)?;
+
+        Ok(owned)
+    }
 }
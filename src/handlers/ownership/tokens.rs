@@ -1,5 +1,6 @@
 use crate::schema::user_tokens;
 use crate::types::asset::Ownership;
+use crate::types::error::AppError;
 use diesel::prelude::*;
 
 #[derive(Queryable, Selectable, Insertable)]
@@ -13,25 +14,66 @@ impl UserToken {
         UserToken { user_id, token_id }
     }
 
-    pub fn create(&self, conn: &mut PgConnection) -> usize {
-        diesel::insert_into(user_tokens::table)
+    pub fn create(&self, conn: &mut PgConnection) -> Result<usize, AppError> {
+        let changes = diesel::insert_into(user_tokens::table)
             .values(self)
-            .execute(conn)
-            .expect("Error saving stl ownership")
+            .execute(conn)?;
+
+        Ok(changes)
     }
 
-    pub fn check_ownership(conn: &mut PgConnection, u_id: i32, b_id: i32) -> Ownership {
+    pub fn destroy(conn: &mut PgConnection, u_id: i32, t_id: i32) -> Result<usize, AppError> {
+        use crate::schema::user_tokens::dsl::*;
+
+        let changes = diesel::delete(
+            user_tokens
+                .filter(user_id.eq(u_id))
+                .filter(token_id.eq(t_id)),
+        )
+        .execute(conn)?;
+
+        Ok(changes)
+    }
+
+    // ids the user has bought, with when they bought them
+    pub fn list(conn: &mut PgConnection, u_id: i32) -> Result<Vec<(i32, i64)>, AppError> {
+        use crate::schema::user_tokens::dsl::*;
+
+        let owned = user_tokens
+            .filter(user_id.eq(u_id))
+            .select((token_id, purchased_at))
+            .get_results(conn)?;
+
+        Ok(owned)
+    }
+
+    pub fn check_ownership(
+        conn: &mut PgConnection,
+        u_id: i32,
+        b_id: i32,
+    ) -> Result<Ownership, AppError> {
         use crate::schema::user_tokens::dsl::*;
 
         let result = user_tokens
             .filter(token_id.eq(b_id))
             .filter(user_id.eq(u_id))
-            .execute(conn)
-            .expect("Error loading stl ownership");
+            .execute(conn)?;
 
         match result {
-            1 => Ownership::Owned,
-            _ => Ownership::Unowned,
+            1 => Ok(Ownership::Owned),
+            _ => Ok(Ownership::Unowned),
         }
     }
+
+    // whether anyone at all has bought one of the tokens
+    pub fn any_owned(conn: &mut PgConnection, ids: &[i32]) -> Result<bool, AppError> {
+        use crate::schema::user_tokens::dsl::*;
+
+        let owned = diesel::select(diesel::dsl::exists(
+            user_tokens.filter(token_id.eq_any(ids)),
+        ))
+        .get_result(conn)?;
+
+        Ok(owned)
+    }
 }
@@ -0,0 +1,101 @@
+use crate::types::error::AppError;
+use crate::types::price::Price;
+use rand::distributions::{Alphanumeric, DistString};
+use std::env;
+use std::sync::Arc;
+
+pub struct Charge {
+    pub order_id: i32,
+    pub user_id: i32,
+    pub price: Price,
+}
+
+pub struct Receipt {
+    pub reference: String,
+}
+
+// A declined or failed charge is reported as AppError::Payment
+pub trait PaymentProvider: Send + Sync {
+    fn charge(&self, charge: &Charge) -> Result<Receipt, AppError>;
+    // Gives back the whole of a charge, by the reference on its receipt
+    fn refund(&self, reference: &str) -> Result<(), AppError>;
+}
+
+// Settles every charge locally so the purchase path runs without an external service
+pub struct FakeProvider {
+    decline: bool,
+}
+
+impl FakeProvider {
+    pub fn approving() -> Self {
+        FakeProvider { decline: false }
+    }
+
+    pub fn declining() -> Self {
+        FakeProvider { decline: true }
+    }
+}
+
+impl PaymentProvider for FakeProvider {
+    fn charge(&self, charge: &Charge) -> Result<Receipt, AppError> {
+        if self.decline {
+            return Err(AppError::Payment(format!(
+                "card declined for order {}",
+                charge.order_id
+            )));
+        }
+
+        let token = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+
+        Ok(Receipt {
+            reference: format!("fake_{}_{}", charge.order_id, token),
+        })
+    }
+
+    fn refund(&self, reference: &str) -> Result<(), AppError> {
+        match reference.starts_with("fake_") {
+            true => Ok(()),
+            false => Err(AppError::Payment(format!(
+                "no charge {} to refund",
+                reference
+            ))),
+        }
+    }
+}
+
+// PAYMENT_PROVIDER selects the provider and must be set. "fake" approves
+// every charge, so it has to be asked for by name.
+pub fn provider_from_env() -> Result<Arc<dyn PaymentProvider>, AppError> {
+    match env::var("PAYMENT_PROVIDER").as_deref() {
+        Ok("fake") => Ok(Arc::new(FakeProvider::approving())),
+        Ok(other) => Err(AppError::Validation(format!(
+            "unknown payment provider: {}",
+            other
+        ))),
+        Err(_) => Err(AppError::Validation(String::from(
+            "PAYMENT_PROVIDER is not set",
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn provider_must_be_named() {
+        env::remove_var("PAYMENT_PROVIDER");
+
+        assert!(matches!(provider_from_env(), Err(AppError::Validation(_))));
+
+        env::set_var("PAYMENT_PROVIDER", "stripe");
+
+        assert!(matches!(provider_from_env(), Err(AppError::Validation(_))));
+
+        env::set_var("PAYMENT_PROVIDER", "fake");
+
+        assert!(provider_from_env().is_ok());
+
+        env::remove_var("PAYMENT_PROVIDER");
+    }
+}
@@ -0,0 +1,310 @@
+use super::images::stls::StlImage;
+use super::models::{Mesh, ModelFile, ModelFormat, Triangle};
+use super::storage::StorageBackend;
+use super::thumbnails::{encode, shrink, ThumbFormat, THUMB_SIZES};
+use super::uploads::{FileSlot, Upload, UploadTarget};
+use crate::schema::stls;
+use crate::types::asset::GalleryImage;
+use crate::types::error::AppError;
+use diesel::prelude::*;
+use image::{DynamicImage, Rgba, RgbaImage};
+use sha2::{Digest, Sha256};
+
+// Side of the square previews; they are drawn at twice this and scaled down
+// to smooth the edges
+pub const PREVIEW_SIZE: u32 = 512;
+const BACKGROUND: Rgba<u8> = Rgba([240, 240, 240, 255]);
+const SURFACE: [f32; 3] = [120.0, 150.0, 200.0];
+// the model fills this much of the frame
+const MARGIN: f32 = 0.9;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum PreviewAngle {
+    Iso,
+    Front,
+    Side,
+}
+
+// the first is used as the page's main image
+const ANGLES: [PreviewAngle; 3] = [PreviewAngle::Iso, PreviewAngle::Front, PreviewAngle::Side];
+
+type Vector = [f32; 3];
+
+impl PreviewAngle {
+    pub fn store(&self) -> &str {
+        match self {
+            Self::Iso => "iso",
+            Self::Front => "front",
+            Self::Side => "side",
+        }
+    }
+
+    // Where the camera sits as azimuth and elevation in degrees; z is up as
+    // on a print bed and the front faces -y
+    fn camera(&self) -> (f32, f32) {
+        match self {
+            Self::Iso => (45.0, 30.0),
+            Self::Front => (0.0, 0.0),
+            Self::Side => (90.0, 0.0),
+        }
+    }
+}
+
+pub struct Preview;
+
+impl Preview {
+    // Renders the asset's main model from each angle into the gallery. Unless
+    // the creator has uploaded a main image of their own, the first preview
+    // also becomes the main image and thumbnail.
+    pub fn generate(
+        conn: &mut PgConnection,
+        storage: &dyn StorageBackend,
+        s_id: i32,
+    ) -> Result<Vec<GalleryImage>, AppError> {
+        let main = match Upload::read(conn, UploadTarget::Stl, s_id, FileSlot::File) {
+            Ok(upload) => Some(upload.file_key),
+            Err(AppError::NotFound(_)) => None,
+            Err(err) => return Err(err),
+        };
+        let models = ModelFile::list(conn, s_id)?;
+        let source = models
+            .iter()
+            .find(|model_file| Some(&model_file.file_key) == main.as_ref())
+            .or(models.first())
+            .ok_or_else(|| AppError::Validation(format!("stl {} has no model to render", s_id)))?;
+        let format = ModelFormat::from_extension(&source.format)
+            .ok_or_else(|| AppError::Validation(format!("{} cannot be rendered", source.format)))?;
+        let mesh = Mesh::parse(format, &storage.get(&source.file_key)?)?;
+
+        // keys follow the model file so an unchanged model renders to the same keys
+        let digest = hex::encode(Sha256::digest(source.file_key.as_bytes()));
+        let key = |name: &str| format!("stls/{}/preview-{}-{}.png", s_id, name, &digest[..16]);
+
+        let mut previews = Vec::new();
+        for angle in ANGLES {
+            let image = DynamicImage::ImageRgba8(render(&mesh.triangles, angle, PREVIEW_SIZE));
+            let preview = key(angle.store());
+            storage.put(&preview, &encode(&image, ThumbFormat::Png)?)?;
+            if previews.is_empty() {
+                let thumb = shrink(&image, THUMB_SIZES[0]);
+                storage.put(&key("thumb"), &encode(&thumb, ThumbFormat::Png)?)?;
+            }
+            previews.push(preview);
+        }
+
+        let main_image = previews[0].to_owned();
+        let thumb = key("thumb");
+        let own = format!("stls/{}/preview-", s_id);
+        let (images, mut unused) = conn.transaction(|conn| {
+            let uploaded = match Upload::read(conn, UploadTarget::Stl, s_id, FileSlot::MainImage) {
+                Ok(_) => true,
+                Err(AppError::NotFound(_)) => false,
+                Err(err) => return Err(err),
+            };
+            let mut unused = Vec::new();
+            if !uploaded {
+                let previous = stls::table
+                    .find(s_id)
+                    .select(stls::thumb)
+                    .get_result::<String>(conn)?;
+                diesel::update(stls::table.find(s_id))
+                    .set((stls::main_image.eq(&main_image), stls::thumb.eq(&thumb)))
+                    .execute(conn)?;
+                unused.push(previous);
+            }
+
+            let (images, previous) = StlImage::replace_previews(conn, s_id, previews.to_owned())?;
+            unused.extend(previous);
+
+            Ok::<(Vec<GalleryImage>, Vec<String>), AppError>((images, unused))
+        })?;
+
+        // only renders of an earlier model are removed; hand written and
+        // uploaded files are left alone
+        unused.retain(|previous| {
+            previous.starts_with(&own) && !previews.contains(previous) && *previous != thumb
+        });
+        unused.sort_unstable();
+        unused.dedup();
+        for previous in unused {
+            storage.delete(&previous)?;
+        }
+
+        Ok(images)
+    }
+}
+
+// A flat shaded orthographic view of the triangles fitted into a size by size
+// square, with a depth buffer for hidden surfaces
+pub fn render(triangles: &[Triangle], angle: PreviewAngle, size: u32) -> RgbaImage {
+    let canvas = size * 2;
+    let (azimuth, elevation) = angle.camera();
+    let (azimuth, elevation) = (azimuth.to_radians(), elevation.to_radians());
+    let eye = [
+        elevation.cos() * azimuth.sin(),
+        -elevation.cos() * azimuth.cos(),
+        elevation.sin(),
+    ];
+    let forward = eye.map(|value| -value);
+    let right = normalize(cross(forward, [0.0, 0.0, 1.0]));
+    let up = cross(right, forward);
+    // lit from over the camera's left shoulder
+    let light = normalize(add(eye, add(scale(up, 0.6), scale(right, -0.4))));
+
+    let project = |point: Vector| [dot(point, right), dot(point, up), dot(point, forward)];
+    let mut min = [f32::MAX; 2];
+    let mut max = [f32::MIN; 2];
+    for point in triangles.iter().flatten() {
+        let [x, y, _] = project(*point);
+        min = [min[0].min(x), min[1].min(y)];
+        max = [max[0].max(x), max[1].max(y)];
+    }
+    let extent = (max[0] - min[0]).max(max[1] - min[1]);
+    let zoom = match extent > 0.0 {
+        true => canvas as f32 * MARGIN / extent,
+        false => 1.0,
+    };
+    let middle = [(min[0] + max[0]) / 2.0, (min[1] + max[1]) / 2.0];
+    let half = canvas as f32 / 2.0;
+    let to_screen = |point: Vector| {
+        let [x, y, depth] = project(point);
+        [
+            half + (x - middle[0]) * zoom,
+            half - (y - middle[1]) * zoom,
+            depth,
+        ]
+    };
+
+    let mut image = RgbaImage::from_pixel(canvas, canvas, BACKGROUND);
+    let mut depths = vec![f32::INFINITY; (canvas * canvas) as usize];
+    for triangle in triangles {
+        let normal = cross(sub(triangle[1], triangle[0]), sub(triangle[2], triangle[0]));
+        if dot(normal, normal) == 0.0 {
+            continue;
+        }
+        // faces are shaded from whichever side is seen, so badly wound
+        // models still look solid
+        let mut normal = normalize(normal);
+        if dot(normal, forward) > 0.0 {
+            normal = scale(normal, -1.0);
+        }
+        let shade = 0.3 + 0.7 * dot(normal, light).max(0.0);
+        let color = Rgba([
+            (SURFACE[0] * shade) as u8,
+            (SURFACE[1] * shade) as u8,
+            (SURFACE[2] * shade) as u8,
+            255,
+        ]);
+
+        let corners = triangle.map(to_screen);
+        let area = edge(corners[0], corners[1], corners[2]);
+        if area.abs() < f32::EPSILON {
+            continue;
+        }
+        let low = |axis: usize| corners.iter().map(|c| c[axis]).fold(f32::MAX, f32::min);
+        let high = |axis: usize| corners.iter().map(|c| c[axis]).fold(f32::MIN, f32::max);
+        let columns = (low(0).floor().max(0.0) as u32)..(high(0).ceil().min(canvas as f32) as u32);
+        let rows = (low(1).floor().max(0.0) as u32)..(high(1).ceil().min(canvas as f32) as u32);
+
+        for row in rows {
+            for column in columns.clone() {
+                let point = [column as f32 + 0.5, row as f32 + 0.5, 0.0];
+                let first = edge(corners[1], corners[2], point) / area;
+                let second = edge(corners[2], corners[0], point) / area;
+                let third = 1.0 - first - second;
+                if first < 0.0 || second < 0.0 || third < 0.0 {
+                    continue;
+                }
+
+                let depth = first * corners[0][2] + second * corners[1][2] + third * corners[2][2];
+                let index = (row * canvas + column) as usize;
+                if depth < depths[index] {
+                    depths[index] = depth;
+                    image.put_pixel(column, row, color);
+                }
+            }
+        }
+    }
+
+    image::imageops::resize(&image, size, size, image::imageops::FilterType::Triangle)
+}
+
+// twice the signed area of abp, from screen x and y
+fn edge(a: Vector, b: Vector, p: Vector) -> f32 {
+    (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0])
+}
+
+fn add(a: Vector, b: Vector) -> Vector {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn sub(a: Vector, b: Vector) -> Vector {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn scale(a: Vector, factor: f32) -> Vector {
+    a.map(|value| value * factor)
+}
+
+fn dot(a: Vector, b: Vector) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: Vector, b: Vector) -> Vector {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn normalize(a: Vector) -> Vector {
+    scale(a, 1.0 / dot(a, a).sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::models::tests::cuboid;
+    use std::collections::HashSet;
+
+    // the columns and rows the model covers
+    fn covered(image: &RgbaImage) -> (u32, u32) {
+        let columns = (0..image.width())
+            .filter(|&x| (0..image.height()).any(|y| *image.get_pixel(x, y) != BACKGROUND))
+            .count() as u32;
+        let rows = (0..image.height())
+            .filter(|&y| (0..image.width()).any(|x| *image.get_pixel(x, y) != BACKGROUND))
+            .count() as u32;
+        (columns, rows)
+    }
+
+    #[test]
+    fn preview_rendering() {
+        let front = render(&cuboid(), PreviewAngle::Front, 100);
+
+        assert_eq!(front.dimensions(), (100, 100));
+        assert_eq!(*front.get_pixel(0, 0), BACKGROUND);
+        assert_ne!(*front.get_pixel(50, 50), BACKGROUND);
+
+        // the box is 30 tall and 10 wide from the front, 20 wide from the side
+        let (columns, rows) = covered(&front);
+
+        assert!((89..=92).contains(&rows));
+        assert!((29..=32).contains(&columns));
+
+        let (columns, _) = covered(&render(&cuboid(), PreviewAngle::Side, 100));
+
+        assert!((59..=62).contains(&columns));
+
+        // three faces are in view, each lit differently
+        let iso = render(&cuboid(), PreviewAngle::Iso, 100);
+        let shades = [(50, 12), (40, 50), (60, 50)]
+            .iter()
+            .map(|&(x, y)| *iso.get_pixel(x, y))
+            .collect::<HashSet<Rgba<u8>>>();
+
+        assert_eq!(shades.len(), 3);
+        assert!(!shades.contains(&BACKGROUND));
+    }
+}
@@ -0,0 +1,118 @@
+use crate::schema::asset_prices;
+use crate::types::asset::AssetType;
+use crate::types::error::AppError;
+use crate::types::price::{Currency, Price, Pricing};
+use diesel::prelude::*;
+
+#[derive(Insertable, AsChangeset)]
+#[diesel(table_name = asset_prices)]
+pub struct AssetPrice {
+    asset_type: String,
+    asset_id: i32,
+    currency: String,
+    amount: i32,
+}
+
+// Per-currency overrides of an asset's own price
+impl AssetPrice {
+    pub fn set(
+        conn: &mut PgConnection,
+        kind: &AssetType,
+        a_id: i32,
+        price: Price,
+    ) -> Result<Price, AppError> {
+        use crate::schema::asset_prices::dsl::*;
+
+        if price.amount < 0 {
+            return Err(AppError::Validation(format!(
+                "price cannot be negative: {}",
+                price.amount
+            )));
+        }
+
+        let row = AssetPrice {
+            asset_type: String::from(kind.store()),
+            asset_id: a_id,
+            currency: String::from(price.currency.store()),
+            amount: price.amount,
+        };
+
+        diesel::insert_into(asset_prices)
+            .values(&row)
+            .on_conflict((asset_type, asset_id, currency))
+            .do_update()
+            .set(amount.eq(price.amount))
+            .execute(conn)?;
+
+        Ok(price)
+    }
+
+    pub fn list(
+        conn: &mut PgConnection,
+        kind: &AssetType,
+        a_id: i32,
+    ) -> Result<Vec<Price>, AppError> {
+        use crate::schema::asset_prices::dsl::*;
+
+        let rows = asset_prices
+            .filter(asset_type.eq(kind.store()))
+            .filter(asset_id.eq(a_id))
+            .order(currency)
+            .select((amount, currency))
+            .get_results::<(i32, String)>(conn)?;
+
+        rows.iter()
+            .map(|(stored, code)| Price::stored(*stored, code))
+            .collect()
+    }
+
+    pub fn pricing(
+        conn: &mut PgConnection,
+        kind: &AssetType,
+        a_id: i32,
+        price: Price,
+    ) -> Result<Pricing, AppError> {
+        let overrides = AssetPrice::list(conn, kind, a_id)?
+            .into_iter()
+            .filter(|other| other.currency != price.currency)
+            .collect();
+
+        Ok(Pricing { price, overrides })
+    }
+
+    pub fn destroy(
+        conn: &mut PgConnection,
+        kind: &AssetType,
+        a_id: i32,
+        code: Currency,
+    ) -> Result<usize, AppError> {
+        use crate::schema::asset_prices::dsl::*;
+
+        let changes = diesel::delete(
+            asset_prices
+                .filter(asset_type.eq(kind.store()))
+                .filter(asset_id.eq(a_id))
+                .filter(currency.eq(code.store())),
+        )
+        .execute(conn)?;
+
+        Ok(changes)
+    }
+
+    pub fn destroy_all(
+        conn: &mut PgConnection,
+        kind: &AssetType,
+        a_id: i32,
+    ) -> Result<usize, AppError> {
+        use crate::schema::asset_prices::dsl::*;
+
+        let changes = diesel::delete(
+            asset_prices
+                .filter(asset_type.eq(kind.store()))
+                .filter(asset_id.eq(a_id)),
+        )
+        .execute(conn)?;
+
+        Ok(changes)
+    }
+}
@@ -0,0 +1,1191 @@
+use super::documents::descendant_font;
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayImage, ImageFormat, RgbImage, RgbaImage};
+use lopdf::content::{Content, Operation};
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+use std::collections::HashMap;
+use std::rc::Rc;
+use tiny_skia::{
+    Color, FillRule, FilterQuality, LineCap, LineJoin, Mask, Paint, PathBuilder, Pixmap,
+    PixmapPaint, Rect, Stroke, Transform,
+};
+
+// Drawing stops after this many operators, nested forms included
+const MAX_OPERATIONS: usize = 100_000;
+// How deeply forms may draw other forms
+const MAX_FORM_DEPTH: usize = 8;
+// The most a page's content, images and fonts may inflate to altogether
+const MAX_INFLATED: usize = 128 * 1024 * 1024;
+
+// Draws a page onto white with its longer side `size` pixels long. Paths,
+// clipping, images and text set in embedded TrueType or OpenType fonts are
+// drawn; shadings, patterns, inline images and text in Type 1 or unembedded
+// fonts are left out, so a page that relies on them comes out incomplete.
+pub fn render(document: &Document, page: ObjectId, size: u32) -> Option<RgbaImage> {
+    let dictionary = document.get_dictionary(page).ok()?;
+    let [x0, y0, x1, y1] = page_box(document, dictionary)?;
+    let scale = size as f32 / (x1 - x0).max(y1 - y0);
+    let (width, height) = ((x1 - x0) * scale, (y1 - y0) * scale);
+
+    // PDF's y axis points up and /Rotate turns the page clockwise
+    let rotate = inherited(document, dictionary, b"Rotate")
+        .and_then(|rotate| rotate.as_i64().ok())
+        .unwrap_or(0)
+        .rem_euclid(360);
+    let (turn, width, height) = match rotate {
+        90 => (
+            Transform::from_row(0.0, 1.0, -1.0, 0.0, height, 0.0),
+            height,
+            width,
+        ),
+        180 => (
+            Transform::from_row(-1.0, 0.0, 0.0, -1.0, width, height),
+            width,
+            height,
+        ),
+        270 => (
+            Transform::from_row(0.0, -1.0, 1.0, 0.0, 0.0, width),
+            height,
+            width,
+        ),
+        _ => (Transform::identity(), width, height),
+    };
+    let device =
+        Transform::from_row(scale, 0.0, 0.0, -scale, -x0 * scale, y1 * scale).post_concat(turn);
+
+    let mut pixmap = Pixmap::new(
+        (width.round() as u32).max(1),
+        (height.round() as u32).max(1),
+    )?;
+    pixmap.fill(Color::WHITE);
+
+    let content = document
+        .get_page_content_with_limit(page, MAX_INFLATED)
+        .ok()?;
+    let operations = Content::decode(&content).ok()?.operations;
+    let (direct, inherited) = document.get_page_resources(page).ok()?;
+    let resources = direct
+        .into_iter()
+        .chain(
+            inherited
+                .into_iter()
+                .filter_map(|id| document.get_dictionary(id).ok()),
+        )
+        .collect::<Vec<_>>();
+
+    let mut painter = Painter {
+        document,
+        pixmap,
+        fonts: HashMap::new(),
+        operations: MAX_OPERATIONS,
+        inflated: MAX_INFLATED.saturating_sub(content.len()),
+    };
+    painter.run(&operations, &resources, State::new(device), 0);
+
+    // the page is opaque, so its premultiplied pixels are already plain RGBA
+    let (width, height) = (painter.pixmap.width(), painter.pixmap.height());
+    RgbaImage::from_raw(width, height, painter.pixmap.take())
+}
+
+// The graphics state that q and Q save and restore
+#[derive(Clone)]
+struct State {
+    ctm: Transform,
+    clip: Option<Rc<Mask>>,
+    fill: Color,
+    stroke: Color,
+    // Separation and DeviceN colours give the amount of ink
+    fill_tint: bool,
+    stroke_tint: bool,
+    fill_alpha: f32,
+    stroke_alpha: f32,
+    line: Stroke,
+    font: Option<Rc<Font>>,
+    font_size: f32,
+    char_spacing: f32,
+    word_spacing: f32,
+    horizontal_scaling: f32,
+    leading: f32,
+    rise: f32,
+    render_mode: i64,
+}
+
+impl State {
+    fn new(ctm: Transform) -> State {
+        State {
+            ctm,
+            clip: None,
+            fill: Color::BLACK,
+            stroke: Color::BLACK,
+            fill_tint: false,
+            stroke_tint: false,
+            fill_alpha: 1.0,
+            stroke_alpha: 1.0,
+            line: Stroke::default(),
+            font: None,
+            font_size: 0.0,
+            char_spacing: 0.0,
+            word_spacing: 0.0,
+            horizontal_scaling: 1.0,
+            leading: 0.0,
+            rise: 0.0,
+            render_mode: 0,
+        }
+    }
+
+    fn clip(&mut self, path: &tiny_skia::Path, rule: FillRule, width: u32, height: u32) {
+        match &mut self.clip {
+            Some(mask) => Rc::make_mut(mask).intersect_path(path, rule, true, self.ctm),
+            None => {
+                if let Some(mut mask) = Mask::new(width, height) {
+                    mask.fill_path(path, rule, true, self.ctm);
+                    self.clip = Some(Rc::new(mask));
+                }
+            }
+        }
+    }
+}
+
+// An embedded font as far as drawing it goes
+struct Font {
+    // empty when there is nothing to draw with
+    data: Vec<u8>,
+    // composite fonts use two byte codes
+    composite: bool,
+    // in thousandths of the font size
+    widths: HashMap<u32, f32>,
+    default_width: f32,
+    // simple fonts map each code to a glyph, composite ones through CIDToGIDMap
+    glyphs: Vec<Option<u16>>,
+    cid_to_gid: Option<Vec<u8>>,
+}
+
+impl Font {
+    fn glyph(&self, code: u32) -> Option<u16> {
+        match (self.composite, &self.cid_to_gid) {
+            (false, _) => self.glyphs.get(code as usize).copied().flatten(),
+            (true, Some(map)) => map
+                .get(code as usize * 2..code as usize * 2 + 2)
+                .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]])),
+            (true, None) => u16::try_from(code).ok(),
+        }
+    }
+}
+
+struct Painter<'a> {
+    document: &'a Document,
+    pixmap: Pixmap,
+    fonts: HashMap<ObjectId, Rc<Font>>,
+    // what is left of MAX_OPERATIONS and MAX_INFLATED
+    operations: usize,
+    inflated: usize,
+}
+
+impl<'a> Painter<'a> {
+    fn run(
+        &mut self,
+        operations: &[Operation],
+        resources: &[&'a Dictionary],
+        mut state: State,
+        depth: usize,
+    ) {
+        let mut saved = Vec::new();
+        let mut path = PathBuilder::new();
+        let mut current = (0.0, 0.0);
+        let mut clip = None;
+        let mut text = Transform::identity();
+        let mut line = Transform::identity();
+
+        for operation in operations {
+            if self.operations == 0 {
+                return;
+            }
+            self.operations -= 1;
+            let numbers = operation
+                .operands
+                .iter()
+                .filter_map(|operand| operand.as_float().ok())
+                .collect::<Vec<_>>();
+            let name = operation
+                .operands
+                .first()
+                .and_then(|operand| operand.as_name().ok());
+
+            match (operation.operator.as_str(), numbers.as_slice()) {
+                ("q", _) => saved.push(state.clone()),
+                ("Q", _) => {
+                    if let Some(previous) = saved.pop() {
+                        state = previous;
+                    }
+                }
+                ("cm", &[a, b, c, d, e, f]) => {
+                    state.ctm = state.ctm.pre_concat(Transform::from_row(a, b, c, d, e, f))
+                }
+                ("w", &[width]) => state.line.width = width.abs(),
+                ("J", &[cap]) => {
+                    state.line.line_cap = match cap as i64 {
+                        1 => LineCap::Round,
+                        2 => LineCap::Square,
+                        _ => LineCap::Butt,
+                    }
+                }
+                ("j", &[join]) => {
+                    state.line.line_join = match join as i64 {
+                        1 => LineJoin::Round,
+                        2 => LineJoin::Bevel,
+                        _ => LineJoin::Miter,
+                    }
+                }
+                ("M", &[limit]) => state.line.miter_limit = limit,
+                ("gs", _) => {
+                    if let Some(graphics) = name
+                        .and_then(|name| self.resource(resources, b"ExtGState", name))
+                        .and_then(|(_, graphics)| graphics.as_dict().ok())
+                    {
+                        let number = |key: &[u8]| graphics.get(key).and_then(Object::as_float).ok();
+                        if let Some(alpha) = number(b"ca") {
+                            state.fill_alpha = alpha.clamp(0.0, 1.0);
+                        }
+                        if let Some(alpha) = number(b"CA") {
+                            state.stroke_alpha = alpha.clamp(0.0, 1.0);
+                        }
+                        if let Some(width) = number(b"LW") {
+                            state.line.width = width.abs();
+                        }
+                    }
+                }
+
+                ("g" | "rg" | "k", _) => {
+                    if let Some(color) = colour(&numbers, false) {
+                        state.fill = color;
+                        state.fill_tint = false;
+                    }
+                }
+                ("G" | "RG" | "K", _) => {
+                    if let Some(color) = colour(&numbers, false) {
+                        state.stroke = color;
+                        state.stroke_tint = false;
+                    }
+                }
+                ("cs", _) => {
+                    state.fill = Color::BLACK;
+                    state.fill_tint = self.is_tint(resources, name);
+                }
+                ("CS", _) => {
+                    state.stroke = Color::BLACK;
+                    state.stroke_tint = self.is_tint(resources, name);
+                }
+                ("sc" | "scn", _) => {
+                    if let Some(color) = colour(&numbers, state.fill_tint) {
+                        state.fill = color;
+                    }
+                }
+                ("SC" | "SCN", _) => {
+                    if let Some(color) = colour(&numbers, state.stroke_tint) {
+                        state.stroke = color;
+                    }
+                }
+
+                ("m", &[x, y]) => {
+                    path.move_to(x, y);
+                    current = (x, y);
+                }
+                ("l", &[x, y]) => {
+                    path.line_to(x, y);
+                    current = (x, y);
+                }
+                ("c", &[x1, y1, x2, y2, x, y]) => {
+                    path.cubic_to(x1, y1, x2, y2, x, y);
+                    current = (x, y);
+                }
+                ("v", &[x2, y2, x, y]) => {
+                    path.cubic_to(current.0, current.1, x2, y2, x, y);
+                    current = (x, y);
+                }
+                ("y", &[x1, y1, x, y]) => {
+                    path.cubic_to(x1, y1, x, y, x, y);
+                    current = (x, y);
+                }
+                ("h", _) => path.close(),
+                ("re", &[x, y, width, height]) => {
+                    path.move_to(x, y);
+                    path.line_to(x + width, y);
+                    path.line_to(x + width, y + height);
+                    path.line_to(x, y + height);
+                    path.close();
+                    current = (x, y);
+                }
+                ("W", _) => clip = Some(FillRule::Winding),
+                ("W*", _) => clip = Some(FillRule::EvenOdd),
+                (operator @ ("n" | "f" | "F" | "f*" | "S" | "s" | "B" | "B*" | "b" | "b*"), _) => {
+                    if matches!(operator, "s" | "b" | "b*") {
+                        path.close();
+                    }
+                    let Some(built) = std::mem::take(&mut path).finish() else {
+                        clip = None;
+                        continue;
+                    };
+                    let rule = match operator.ends_with('*') {
+                        true => FillRule::EvenOdd,
+                        false => FillRule::Winding,
+                    };
+                    if matches!(operator, "f" | "F" | "f*" | "B" | "B*" | "b" | "b*") {
+                        self.pixmap.fill_path(
+                            &built,
+                            &paint(state.fill, state.fill_alpha),
+                            rule,
+                            state.ctm,
+                            state.clip.as_deref(),
+                        );
+                    }
+                    if matches!(operator, "S" | "s" | "B" | "B*" | "b" | "b*") {
+                        self.pixmap.stroke_path(
+                            &built,
+                            &paint(state.stroke, state.stroke_alpha),
+                            &state.line,
+                            state.ctm,
+                            state.clip.as_deref(),
+                        );
+                    }
+                    if let Some(rule) = clip.take() {
+                        let (width, height) = (self.pixmap.width(), self.pixmap.height());
+                        state.clip(&built, rule, width, height);
+                    }
+                }
+
+                ("Do", _) => {
+                    let Some((_, object)) =
+                        name.and_then(|name| self.resource(resources, b"XObject", name))
+                    else {
+                        continue;
+                    };
+                    let Ok(stream) = object.as_stream() else {
+                        continue;
+                    };
+                    match stream.dict.get(b"Subtype").and_then(Object::as_name) {
+                        Ok(b"Image") => self.image(stream, &state),
+                        Ok(b"Form") if depth < MAX_FORM_DEPTH => {
+                            self.form(stream, resources, &state, depth)
+                        }
+                        _ => {}
+                    }
+                }
+
+                ("BT", _) => {
+                    text = Transform::identity();
+                    line = text;
+                }
+                ("Tf", &[size]) => {
+                    state.font = name.and_then(|name| self.font(resources, name));
+                    state.font_size = size;
+                }
+                ("Tc", &[spacing]) => state.char_spacing = spacing,
+                ("Tw", &[spacing]) => state.word_spacing = spacing,
+                ("Tz", &[scaling]) => state.horizontal_scaling = scaling / 100.0,
+                ("TL", &[leading]) => state.leading = leading,
+                ("Ts", &[rise]) => state.rise = rise,
+                ("Tr", &[mode]) => state.render_mode = mode as i64,
+                ("Td", &[x, y]) => {
+                    line = line.pre_translate(x, y);
+                    text = line;
+                }
+                ("TD", &[x, y]) => {
+                    state.leading = -y;
+                    line = line.pre_translate(x, y);
+                    text = line;
+                }
+                ("Tm", &[a, b, c, d, e, f]) => {
+                    line = Transform::from_row(a, b, c, d, e, f);
+                    text = line;
+                }
+                ("T*", _) => {
+                    line = line.pre_translate(0.0, -state.leading);
+                    text = line;
+                }
+                (operator @ ("Tj" | "'" | "\"" | "TJ"), _) => {
+                    if operator == "\"" {
+                        if let [word, char, ..] = numbers[..] {
+                            state.word_spacing = word;
+                            state.char_spacing = char;
+                        }
+                    }
+                    if matches!(operator, "'" | "\"") {
+                        line = line.pre_translate(0.0, -state.leading);
+                        text = line;
+                    }
+                    let shown = match operator {
+                        "TJ" => operation
+                            .operands
+                            .first()
+                            .and_then(|operand| operand.as_array().ok())
+                            .map(|parts| parts.as_slice())
+                            .unwrap_or_default(),
+                        _ => operation
+                            .operands
+                            .last()
+                            .map(std::slice::from_ref)
+                            .unwrap_or_default(),
+                    };
+                    for part in shown {
+                        match part {
+                            Object::String(bytes, _) => self.show(bytes, &state, &mut text),
+                            _ => {
+                                if let Ok(adjust) = part.as_float() {
+                                    let shift = -adjust / 1000.0
+                                        * state.font_size
+                                        * state.horizontal_scaling;
+                                    text = text.pre_translate(shift, 0.0);
+                                }
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Draws a string's glyphs and moves the text matrix past them
+    fn show(&mut self, bytes: &[u8], state: &State, text: &mut Transform) {
+        let Some(font) = state.font.clone() else {
+            return;
+        };
+        let face = ttf_parser::Face::parse(&font.data, 0).ok();
+        let codes: Vec<u32> = match font.composite {
+            true => bytes
+                .chunks(2)
+                .map(|pair| pair.iter().fold(0, |code, byte| code << 8 | *byte as u32))
+                .collect(),
+            false => bytes.iter().map(|byte| *byte as u32).collect(),
+        };
+        let size = Transform::from_row(
+            state.font_size * state.horizontal_scaling,
+            0.0,
+            0.0,
+            state.font_size,
+            0.0,
+            state.rise,
+        );
+
+        for code in codes {
+            if let Some((face, glyph)) = face.as_ref().zip(font.glyph(code)) {
+                let units = 1.0 / face.units_per_em() as f32;
+                let mut outline = Outline(PathBuilder::new());
+                let drawn = face
+                    .outline_glyph(ttf_parser::GlyphId(glyph), &mut outline)
+                    .and_then(|_| outline.0.finish());
+                if let Some(glyph) = drawn {
+                    let transform = state
+                        .ctm
+                        .pre_concat(*text)
+                        .pre_concat(size)
+                        .pre_concat(Transform::from_scale(units, units));
+                    // modes 4 to 7 add to the clip as well, which is left out
+                    let mode = state.render_mode % 4;
+                    if matches!(mode, 0 | 2) {
+                        self.pixmap.fill_path(
+                            &glyph,
+                            &paint(state.fill, state.fill_alpha),
+                            FillRule::Winding,
+                            transform,
+                            state.clip.as_deref(),
+                        );
+                    }
+                    if matches!(mode, 1 | 2) {
+                        // the line width is in text space rather than glyph units
+                        let mut line = state.line.clone();
+                        line.width /= units * state.font_size.abs().max(f32::EPSILON);
+                        self.pixmap.stroke_path(
+                            &glyph,
+                            &paint(state.stroke, state.stroke_alpha),
+                            &line,
+                            transform,
+                            state.clip.as_deref(),
+                        );
+                    }
+                }
+            }
+
+            let width = font
+                .widths
+                .get(&code)
+                .copied()
+                .unwrap_or(font.default_width);
+            let spacing = match !font.composite && code == 32 {
+                true => state.char_spacing + state.word_spacing,
+                false => state.char_spacing,
+            };
+            let advance = (width / 1000.0 * state.font_size + spacing) * state.horizontal_scaling;
+            *text = text.pre_translate(advance, 0.0);
+        }
+    }
+
+    // Images are drawn into the unit square of user space
+    fn image(&mut self, stream: &Stream, state: &State) {
+        let Some(mut image) = self.decode(stream, state.fill) else {
+            return;
+        };
+        // a soft mask is a grey image that gives the transparency
+        if let Some(mask) = stream
+            .dict
+            .get(b"SMask")
+            .ok()
+            .and_then(|mask| self.document.dereference(mask).ok())
+            .and_then(|(_, mask)| mask.as_stream().ok())
+        {
+            if let Some(alpha) = self.decode(mask, Color::BLACK) {
+                let alpha = image::imageops::resize(
+                    &alpha,
+                    image.width(),
+                    image.height(),
+                    FilterType::Triangle,
+                );
+                for (pixel, alpha) in image.pixels_mut().zip(alpha.pixels()) {
+                    pixel[3] = alpha[0];
+                }
+            }
+        }
+        // shrinking on the way in keeps large photographs from aliasing
+        let ctm = state.ctm;
+        let wanted = (
+            ctm.sx.hypot(ctm.ky).ceil().max(1.0) as u32,
+            ctm.kx.hypot(ctm.sy).ceil().max(1.0) as u32,
+        );
+        let image = match image.width() > wanted.0 * 2 || image.height() > wanted.1 * 2 {
+            true => DynamicImage::ImageRgba8(image).resize_exact(
+                wanted.0,
+                wanted.1,
+                FilterType::Triangle,
+            ),
+            false => DynamicImage::ImageRgba8(image),
+        };
+        let (width, height) = (image.width(), image.height());
+        let mut pixels = image.into_rgba8().into_raw();
+        for pixel in pixels.chunks_exact_mut(4) {
+            let alpha = pixel[3] as u16;
+            for channel in &mut pixel[..3] {
+                *channel = (*channel as u16 * alpha / 255) as u8;
+            }
+        }
+        let Some(pixmap) = tiny_skia::IntSize::from_wh(width, height)
+            .and_then(|size| Pixmap::from_vec(pixels, size))
+        else {
+            return;
+        };
+
+        let transform = ctm.pre_concat(Transform::from_row(
+            1.0 / width as f32,
+            0.0,
+            0.0,
+            -1.0 / height as f32,
+            0.0,
+            1.0,
+        ));
+        self.pixmap.draw_pixmap(
+            0,
+            0,
+            pixmap.as_ref(),
+            &PixmapPaint {
+                opacity: state.fill_alpha,
+                quality: FilterQuality::Bilinear,
+                ..PixmapPaint::default()
+            },
+            transform,
+            state.clip.as_deref(),
+        );
+    }
+
+    // JPEG images are decoded as they are; flate and uncompressed images only
+    // in 8 bit grey, RGB or CMYK, which covers what layout programs write.
+    // Image masks are painted in the fill colour.
+    fn decode(&mut self, stream: &Stream, fill: Color) -> Option<RgbaImage> {
+        let size = |key: &[u8]| {
+            stream
+                .dict
+                .get(key)
+                .and_then(Object::as_i64)
+                .ok()
+                .and_then(|size| u32::try_from(size).ok())
+                .filter(|size| *size > 0)
+        };
+        let (width, height) = (size(b"Width")?, size(b"Height")?);
+        let area = width as usize * height as usize;
+        self.inflated = self.inflated.checked_sub(area.checked_mul(4)?)?;
+
+        if matches!(
+            stream.dict.get(b"ImageMask").and_then(Object::as_bool),
+            Ok(true)
+        ) {
+            // set bits leave the page alone unless /Decode swaps them
+            let painted = match self.array(&stream.dict, b"Decode").first() {
+                Some(first) => first.as_float().unwrap_or(0.0) == 1.0,
+                None => false,
+            };
+            let bits = self.inflate(stream)?;
+            let row = (width as usize).div_ceil(8);
+            let color = fill.to_color_u8();
+            return Some(RgbaImage::from_fn(width, height, |x, y| {
+                let byte = bits
+                    .get(y as usize * row + x as usize / 8)
+                    .copied()
+                    .unwrap_or(0xff);
+                let set = byte >> (7 - x % 8) & 1 == 1;
+                match set == painted {
+                    true => image::Rgba([color.red(), color.green(), color.blue(), 255]),
+                    false => image::Rgba([0, 0, 0, 0]),
+                }
+            }));
+        }
+
+        let filters = stream.filters().unwrap_or_default();
+        Some(if filters == [b"DCTDecode"] {
+            image::load_from_memory_with_format(&stream.content, ImageFormat::Jpeg)
+                .ok()?
+                .into_rgba8()
+        } else {
+            if !matches!(
+                stream
+                    .dict
+                    .get(b"BitsPerComponent")
+                    .and_then(Object::as_i64),
+                Ok(8)
+            ) {
+                return None;
+            }
+            let pixels = self.inflate(stream)?;
+            let components = self.components(stream.dict.get(b"ColorSpace").ok()?)?;
+            let pixels = pixels.get(..area * components)?;
+            match components {
+                1 => DynamicImage::ImageLuma8(GrayImage::from_raw(width, height, pixels.to_vec())?)
+                    .into_rgba8(),
+                3 => DynamicImage::ImageRgb8(RgbImage::from_raw(width, height, pixels.to_vec())?)
+                    .into_rgba8(),
+                _ => DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+                    let at = (y as usize * width as usize + x as usize) * 4;
+                    let [c, m, y, k] = [0, 1, 2, 3].map(|i| pixels[at + i] as u16);
+                    let ink = |c: u16| ((255 - c) * (255 - k) / 255) as u8;
+                    image::Rgb([ink(c), ink(m), ink(y)])
+                }))
+                .into_rgba8(),
+            }
+        })
+    }
+
+    fn form(
+        &mut self,
+        stream: &'a Stream,
+        resources: &[&'a Dictionary],
+        state: &State,
+        depth: usize,
+    ) {
+        let mut state = state.clone();
+        if let [a, b, c, d, e, f] = self.numbers(&stream.dict, b"Matrix")[..] {
+            state.ctm = state.ctm.pre_concat(Transform::from_row(a, b, c, d, e, f));
+        }
+        if let [x0, y0, x1, y1] = self.numbers(&stream.dict, b"BBox")[..] {
+            if let Some(rect) = Rect::from_ltrb(x0.min(x1), y0.min(y1), x0.max(x1), y0.max(y1)) {
+                let (width, height) = (self.pixmap.width(), self.pixmap.height());
+                state.clip(
+                    &PathBuilder::from_rect(rect),
+                    FillRule::Winding,
+                    width,
+                    height,
+                );
+            }
+        }
+
+        let own = stream
+            .dict
+            .get(b"Resources")
+            .ok()
+            .and_then(|own| self.document.dereference(own).ok())
+            .and_then(|(_, own)| own.as_dict().ok());
+        let resources = match own {
+            Some(own) => vec![own],
+            None => resources.to_vec(),
+        };
+        let Some(content) = self.inflate(stream) else {
+            return;
+        };
+        if let Ok(content) = Content::decode(&content) {
+            self.run(&content.operations, &resources, state, depth + 1);
+        }
+    }
+
+    fn font(&mut self, resources: &[&'a Dictionary], name: &[u8]) -> Option<Rc<Font>> {
+        let (id, font) = self.resource(resources, b"Font", name)?;
+        if let Some(loaded) = id.and_then(|id| self.fonts.get(&id)) {
+            return Some(loaded.clone());
+        }
+        let font = Rc::new(self.load_font(font.as_dict().ok()?));
+        if let Some(id) = id {
+            self.fonts.insert(id, font.clone());
+        }
+        Some(font)
+    }
+
+    fn load_font(&mut self, font: &'a Dictionary) -> Font {
+        let composite = matches!(font.get(b"Subtype").and_then(Object::as_name), Ok(b"Type0"));
+        let descendant = descendant_font(self.document, font);
+        let data = descendant
+            .and_then(|descendant| descendant.get(b"FontDescriptor").ok())
+            .and_then(|descriptor| self.document.dereference(descriptor).ok())
+            .and_then(|(_, descriptor)| descriptor.as_dict().ok())
+            .and_then(|descriptor| {
+                // FontFile3 also holds bare CFF, which ttf-parser cannot read
+                [b"FontFile2".as_slice(), b"FontFile3"]
+                    .into_iter()
+                    .find_map(|key| descriptor.get(key).ok())
+            })
+            .and_then(|file| self.document.dereference(file).ok())
+            .and_then(|(_, file)| file.as_stream().ok())
+            .and_then(|file| self.inflate(file))
+            .filter(|data| ttf_parser::Face::parse(data, 0).is_ok())
+            .unwrap_or_default();
+        let face = ttf_parser::Face::parse(&data, 0).ok();
+
+        let mut widths = HashMap::new();
+        let mut glyphs = Vec::new();
+        let mut cid_to_gid = None;
+        let mut default_width = 0.0;
+        if composite {
+            default_width = descendant
+                .and_then(|descendant| descendant.get(b"DW").and_then(Object::as_float).ok())
+                .unwrap_or(1000.0);
+            // W lists either "first [w w ...]" or "first last w"
+            let list = descendant
+                .map(|descendant| self.array(descendant, b"W"))
+                .unwrap_or_default();
+            let mut entries = list.iter();
+            while let Some(first) = entries.next().and_then(|first| first.as_i64().ok()) {
+                match entries.next() {
+                    Some(Object::Array(each)) => {
+                        for (offset, width) in each.iter().enumerate() {
+                            if let Ok(width) = width.as_float() {
+                                widths.insert(first as u32 + offset as u32, width);
+                            }
+                        }
+                    }
+                    Some(last) => {
+                        let (Ok(last), Some(Ok(width))) =
+                            (last.as_i64(), entries.next().map(|width| width.as_float()))
+                        else {
+                            break;
+                        };
+                        for cid in first..=last.min(first + 0xffff) {
+                            widths.insert(cid as u32, width);
+                        }
+                    }
+                    None => break,
+                }
+            }
+            cid_to_gid = descendant
+                .and_then(|descendant| descendant.get(b"CIDToGIDMap").ok())
+                .and_then(|map| self.document.dereference(map).ok())
+                .and_then(|(_, map)| map.as_stream().ok())
+                .and_then(|map| self.inflate(map));
+        } else {
+            let first = font.get(b"FirstChar").and_then(Object::as_i64).unwrap_or(0);
+            for (offset, width) in self.array(font, b"Widths").iter().enumerate() {
+                if let Ok(width) = width.as_float() {
+                    widths.insert((first + offset as i64) as u32, width);
+                }
+            }
+            if let Some(face) = &face {
+                let encoding = font
+                    .get_font_encoding_with_limit(self.document, self.inflated)
+                    .ok();
+                let cmaps = face
+                    .tables()
+                    .cmap
+                    .map(|cmap| cmap.subtables.into_iter().collect::<Vec<_>>())
+                    .unwrap_or_default();
+                for code in 0..=255u32 {
+                    // through the character the encoding names, else the
+                    // symbol and Mac tables that take codes as they are
+                    let glyph = encoding
+                        .as_ref()
+                        .and_then(|encoding| encoding.bytes_to_string(&[code as u8]).ok())
+                        .and_then(|text| text.chars().next())
+                        .and_then(|char| face.glyph_index(char))
+                        .or_else(|| {
+                            cmaps.iter().find_map(|cmap| {
+                                match (cmap.platform_id, cmap.encoding_id) {
+                                    (ttf_parser::PlatformId::Windows, 0) => cmap
+                                        .glyph_index(0xf000 | code)
+                                        .or_else(|| cmap.glyph_index(code)),
+                                    (ttf_parser::PlatformId::Macintosh, 0) => {
+                                        cmap.glyph_index(code)
+                                    }
+                                    _ => None,
+                                }
+                            })
+                        });
+                    glyphs.push(glyph.map(|glyph| glyph.0));
+                    if let (Some(glyph), false) = (glyph, widths.contains_key(&code)) {
+                        let advance = face.glyph_hor_advance(glyph).unwrap_or(0) as f32;
+                        widths.insert(code, advance * 1000.0 / face.units_per_em() as f32);
+                    }
+                }
+            }
+        }
+
+        Font {
+            data,
+            composite,
+            widths,
+            default_width,
+            glyphs,
+            cid_to_gid,
+        }
+    }
+
+    // Looks a name up under a category of the resources, nearest first
+    fn resource(
+        &self,
+        resources: &[&'a Dictionary],
+        category: &[u8],
+        name: &[u8],
+    ) -> Option<(Option<ObjectId>, &'a Object)> {
+        resources.iter().find_map(|resources| {
+            let (_, entries) = self
+                .document
+                .dereference(resources.get(category).ok()?)
+                .ok()?;
+            let entry = entries.as_dict().ok()?.get(name).ok()?;
+            self.document.dereference(entry).ok()
+        })
+    }
+
+    fn is_tint(&self, resources: &[&'a Dictionary], name: Option<&[u8]>) -> bool {
+        name.and_then(|name| self.resource(resources, b"ColorSpace", name))
+            .and_then(|(_, space)| space.as_array().ok())
+            .and_then(|space| space.first())
+            .and_then(|family| family.as_name().ok())
+            .is_some_and(|family| family == b"Separation" || family == b"DeviceN")
+    }
+
+    // Colour components per pixel, from a colour space's name or an ICC
+    // profile's /N
+    fn components(&self, space: &Object) -> Option<usize> {
+        let (_, space) = self.document.dereference(space).ok()?;
+        match space {
+            Object::Name(name) => match name.as_slice() {
+                b"DeviceGray" | b"CalGray" => Some(1),
+                b"DeviceRGB" | b"CalRGB" => Some(3),
+                b"DeviceCMYK" => Some(4),
+                _ => None,
+            },
+            Object::Array(space)
+                if space.first().and_then(|f| f.as_name().ok()) == Some(b"ICCBased") =>
+            {
+                let (_, profile) = self.document.dereference(space.get(1)?).ok()?;
+                let count = profile
+                    .as_stream()
+                    .ok()?
+                    .dict
+                    .get(b"N")
+                    .and_then(Object::as_i64)
+                    .ok()?;
+                Some(count as usize).filter(|count| matches!(count, 1 | 3 | 4))
+            }
+            Object::Array(space) => match space.first().and_then(|f| f.as_name().ok()) {
+                Some(b"CalGray") => Some(1),
+                Some(b"CalRGB") => Some(3),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn array(&self, dictionary: &'a Dictionary, key: &[u8]) -> Vec<&'a Object> {
+        dictionary
+            .get(key)
+            .ok()
+            .and_then(|array| self.document.dereference(array).ok())
+            .and_then(|(_, array)| array.as_array().ok())
+            .map(|array| {
+                array
+                    .iter()
+                    .filter_map(|item| self.document.dereference(item).ok().map(|(_, item)| item))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn numbers(&self, dictionary: &'a Dictionary, key: &[u8]) -> Vec<f32> {
+        self.array(dictionary, key)
+            .into_iter()
+            .filter_map(|number| number.as_float().ok())
+            .collect()
+    }
+
+    fn inflate(&mut self, stream: &Stream) -> Option<Vec<u8>> {
+        let data = stream.decompressed_content_with_limit(self.inflated).ok()?;
+        self.inflated -= data.len();
+        Some(data)
+    }
+}
+
+// Glyph outlines drawn as paths
+struct Outline(PathBuilder);
+
+impl ttf_parser::OutlineBuilder for Outline {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.0.move_to(x, y);
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.0.line_to(x, y);
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        self.0.quad_to(x1, y1, x, y);
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        self.0.cubic_to(x1, y1, x2, y2, x, y);
+    }
+
+    fn close(&mut self) {
+        self.0.close();
+    }
+}
+
+// Grey, RGB or CMYK by how many components there are; a tint is the amount
+// of a single ink
+fn colour(components: &[f32], tint: bool) -> Option<Color> {
+    let [r, g, b] = match *components {
+        [amount] if tint => [1.0 - amount; 3],
+        [grey] => [grey; 3],
+        [r, g, b] => [r, g, b],
+        [c, m, y, k] => [c, m, y].map(|ink| (1.0 - ink) * (1.0 - k)),
+        _ => return None,
+    };
+    Color::from_rgba(r.clamp(0.0, 1.0), g.clamp(0.0, 1.0), b.clamp(0.0, 1.0), 1.0)
+}
+
+fn paint(color: Color, alpha: f32) -> Paint<'static> {
+    let mut color = color;
+    color.apply_opacity(alpha);
+    let mut paint = Paint::default();
+    paint.set_color(color);
+    paint
+}
+
+// The visible area, CropBox before MediaBox, as [left, bottom, right, top]
+fn page_box(document: &Document, page: &Dictionary) -> Option<[f32; 4]> {
+    let area =
+        inherited(document, page, b"CropBox").or_else(|| inherited(document, page, b"MediaBox"))?;
+    let numbers = area
+        .as_array()
+        .ok()?
+        .iter()
+        .filter_map(|number| document.dereference(number).ok()?.1.as_float().ok())
+        .collect::<Vec<_>>();
+    let [x0, y0, x1, y1] = numbers[..] else {
+        return None;
+    };
+    let area = [x0.min(x1), y0.min(y1), x0.max(x1), y0.max(y1)];
+    (area[2] - area[0] >= 1.0 && area[3] - area[1] >= 1.0).then_some(area)
+}
+
+// Pages take some attributes from the page tree above them
+fn inherited<'a>(document: &'a Document, page: &'a Dictionary, key: &[u8]) -> Option<&'a Object> {
+    let mut node = page;
+    for _ in 0..32 {
+        if let Ok(value) = node.get(key) {
+            return document.dereference(value).ok().map(|(_, value)| value);
+        }
+        node = document
+            .get_dictionary(node.get(b"Parent").and_then(Object::as_reference).ok()?)
+            .ok()?;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::dictionary;
+
+    // A TrueType font whose only glyph is a full em square, reached from "A"
+    // through a Mac cmap
+    fn square_font() -> Vec<u8> {
+        let be16 = |values: &[i16]| {
+            values
+                .iter()
+                .flat_map(|v| v.to_be_bytes())
+                .collect::<Vec<_>>()
+        };
+        let mut head = vec![
+            0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0x5f, 0x0f, 0x3c, 0xf5, 0, 0,
+        ];
+        head.extend(be16(&[1000]));
+        head.extend([0; 16]);
+        head.extend(be16(&[0, 0, 1000, 1000, 0, 8, 2, 1, 0]));
+        let mut hhea = vec![0, 1, 0, 0];
+        hhea.extend([0; 32]);
+        let maxp = [0, 0, 0x50, 0, 0, 2].to_vec();
+        let mut cmap = be16(&[0, 1, 1, 0, 0, 12, 0, 262, 0]);
+        let mut codes = [0u8; 256];
+        codes[b'A' as usize] = 1;
+        cmap.extend(codes);
+        let mut glyf = be16(&[1, 0, 0, 1000, 1000, 3, 0]);
+        glyf.extend([1, 1, 1, 1]);
+        glyf.extend(be16(&[0, 1000, 0, -1000, 0, 0, 1000, 0]));
+        let loca = [0u32, 0, glyf.len() as u32]
+            .iter()
+            .flat_map(|offset| offset.to_be_bytes())
+            .collect::<Vec<_>>();
+
+        let tables = [
+            (b"cmap", cmap),
+            (b"glyf", glyf),
+            (b"head", head),
+            (b"hhea", hhea),
+            (b"loca", loca),
+            (b"maxp", maxp),
+        ];
+        let mut font = vec![0, 1, 0, 0];
+        font.extend(be16(&[tables.len() as i16, 0, 0, 0]));
+        let mut offset = 12 + tables.len() * 16;
+        let mut data = Vec::new();
+        for (tag, table) in &tables {
+            font.extend(*tag);
+            font.extend([0; 4]);
+            font.extend((offset as u32).to_be_bytes());
+            font.extend((table.len() as u32).to_be_bytes());
+            offset += table.len().next_multiple_of(4);
+            data.extend(table);
+            data.resize(data.len().next_multiple_of(4), 0);
+        }
+        font.extend(data);
+        font
+    }
+
+    // Adds a page drawing the content, 200 wide and 100 high
+    fn one_page(
+        document: &mut Document,
+        content: &str,
+        resources: Dictionary,
+        rotate: i64,
+    ) -> ObjectId {
+        let pages = document.new_object_id();
+        let content = document.add_object(Stream::new(dictionary! {}, content.as_bytes().to_vec()));
+        let page = document.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages,
+            "Contents" => content,
+            "Resources" => resources,
+            "Rotate" => rotate,
+        });
+        document.objects.insert(
+            pages,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![page.into()],
+                "Count" => 1,
+                "MediaBox" => vec![0.into(), 0.into(), 200.into(), 100.into()],
+            }),
+        );
+        page
+    }
+
+    #[test]
+    fn drawing() {
+        let mut document = Document::with_version("1.5");
+        let checks = document.add_object(Stream::new(
+            dictionary! {
+                "Subtype" => "Image",
+                "Width" => 2,
+                "Height" => 2,
+                "ColorSpace" => "DeviceGray",
+                "BitsPerComponent" => 8,
+            },
+            vec![0, 255, 255, 0],
+        ));
+        let file = document.add_object(Stream::new(dictionary! {}, square_font()));
+        let descriptor = document.add_object(dictionary! {
+            "Type" => "FontDescriptor",
+            "FontName" => "Square",
+            "FontFile2" => file,
+        });
+        let font = document.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "TrueType",
+            "BaseFont" => "Square",
+            "FirstChar" => 65,
+            "Widths" => vec![1000.into()],
+            "FontDescriptor" => descriptor,
+        });
+        let page = one_page(
+            &mut document,
+            "1 0 0 rg 10 10 30 30 re f \
+             q 60 10 20 20 re W n 0 0 1 rg 50 0 100 100 re f Q \
+             q 40 0 0 40 100 50 cm /Checks Do Q \
+             0 g BT /F1 20 Tf 150 20 Td (AA) Tj ET",
+            dictionary! {
+                "XObject" => dictionary! { "Checks" => checks },
+                "Font" => dictionary! { "F1" => font },
+            },
+            0,
+        );
+
+        let image = render(&document, page, 200).unwrap();
+
+        assert_eq!(image.dimensions(), (200, 100));
+        let colour = |x, y| image.get_pixel(x, y).0;
+        assert_eq!(colour(5, 5), [255, 255, 255, 255]);
+        // the red square, y counted down from the top
+        assert_eq!(colour(25, 75), [255, 0, 0, 255]);
+        // the blue fill only shows inside its clip
+        assert_eq!(colour(70, 80), [0, 0, 255, 255]);
+        assert_eq!(colour(90, 80), [255, 255, 255, 255]);
+        // the image's first row is its top
+        assert!(colour(105, 15)[0] < 64);
+        assert!(colour(135, 15)[0] > 192);
+        assert!(colour(105, 45)[0] > 192);
+        // each glyph is a 20 point square, the second one advanced past the first
+        assert_eq!(colour(160, 70), [0, 0, 0, 255]);
+        assert_eq!(colour(180, 70), [0, 0, 0, 255]);
+        assert_eq!(colour(195, 70), [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn rotation_and_nesting() {
+        let mut document = Document::with_version("1.5");
+        // a form that draws itself stops at the depth limit
+        let form = document.new_object_id();
+        document.objects.insert(
+            form,
+            Object::Stream(Stream::new(
+                dictionary! {
+                    "Subtype" => "Form",
+                    "BBox" => vec![0.into(), 0.into(), 200.into(), 100.into()],
+                    "Resources" => dictionary! {
+                        "XObject" => dictionary! { "Loop" => form },
+                    },
+                },
+                b"/Loop Do".to_vec(),
+            )),
+        );
+        let page = one_page(
+            &mut document,
+            "1 0 0 rg 10 10 30 30 re f /Loop Do",
+            dictionary! { "XObject" => dictionary! { "Loop" => form } },
+            90,
+        );
+
+        let image = render(&document, page, 200).unwrap();
+
+        // turned clockwise, the bottom left corner of the page is at the top left
+        assert_eq!(image.dimensions(), (100, 200));
+        assert_eq!(image.get_pixel(25, 25).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(75, 175).0, [255, 255, 255, 255]);
+
+        // without a page box there is nothing to draw on
+        document.get_dictionary_mut(page).unwrap().remove(b"Parent");
+        assert!(render(&document, page, 200).is_none());
+    }
+}
@@ -0,0 +1,194 @@
+use crate::handlers::connect::DbPool;
+use crate::schema::sessions;
+use crate::types::error::AppError;
+use crate::types::time::now;
+use actix_session::storage::{LoadError, SaveError, SessionKey, SessionStore, UpdateError};
+use actix_web::cookie::time::Duration;
+use actix_web::web;
+use diesel::prelude::*;
+use rand::distributions::{Alphanumeric, DistString};
+use std::collections::HashMap;
+
+type SessionState = HashMap<String, String>;
+
+#[derive(Insertable, AsChangeset)]
+#[diesel(table_name = sessions)]
+struct SessionRow {
+    session_key: String,
+    state: String,
+    expires_at: i64,
+}
+
+// Session state lives in the sessions table; the cookie only carries the key
+#[derive(Clone)]
+pub struct PgSessionStore {
+    pool: DbPool,
+}
+
+impl PgSessionStore {
+    pub fn new(pool: DbPool) -> Self {
+        PgSessionStore { pool }
+    }
+
+    pub fn purge_expired(conn: &mut PgConnection) -> Result<usize, AppError> {
+        use crate::schema::sessions::dsl::*;
+
+        let changes = diesel::delete(sessions.filter(expires_at.le(now()))).execute(conn)?;
+
+        Ok(changes)
+    }
+
+    async fn query<T, F>(&self, query: F) -> Result<T, anyhow::Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut PgConnection) -> QueryResult<T> + Send + 'static,
+    {
+        let pool = self.pool.clone();
+
+        web::block(move || {
+            let conn = &mut pool.get()?;
+            Ok(query(conn)?)
+        })
+        .await?
+    }
+
+    async fn store(
+        &self,
+        key: String,
+        state: SessionState,
+        ttl: &Duration,
+    ) -> Result<String, anyhow::Error> {
+        let row = SessionRow {
+            session_key: key.clone(),
+            state: serde_json::to_string(&state)?,
+            expires_at: now() + ttl.whole_seconds(),
+        };
+
+        self.query(move |conn| {
+            diesel::insert_into(sessions::table)
+                .values(&row)
+                .on_conflict(sessions::session_key)
+                .do_update()
+                .set(&row)
+                .execute(conn)
+        })
+        .await?;
+
+        Ok(key)
+    }
+}
+
+impl SessionStore for PgSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let key = session_key.as_ref().to_owned();
+
+        let state = self
+            .query(move |conn| {
+                sessions::table
+                    .filter(sessions::session_key.eq(key))
+                    .filter(sessions::expires_at.gt(now()))
+                    .select(sessions::state)
+                    .get_result::<String>(conn)
+                    .optional()
+            })
+            .await
+            .map_err(LoadError::Other)?;
+
+        state
+            .map(|state| serde_json::from_str(&state))
+            .transpose()
+            .map_err(|err| LoadError::Deserialization(err.into()))
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let key = Alphanumeric.sample_string(&mut rand::thread_rng(), 64);
+        let key = self
+            .store(key, session_state, ttl)
+            .await
+            .map_err(SaveError::Other)?;
+
+        SessionKey::try_from(key).map_err(|err| SaveError::Other(err.into()))
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let key = self
+            .store(session_key.into(), session_state, ttl)
+            .await
+            .map_err(UpdateError::Other)?;
+
+        SessionKey::try_from(key).map_err(|err| UpdateError::Other(err.into()))
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: &Duration) -> anyhow::Result<()> {
+        let key = session_key.as_ref().to_owned();
+        let expires = now() + ttl.whole_seconds();
+
+        self.query(move |conn| {
+            diesel::update(sessions::table.filter(sessions::session_key.eq(key)))
+                .set(sessions::expires_at.eq(expires))
+                .execute(conn)
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> anyhow::Result<()> {
+        let key = session_key.as_ref().to_owned();
+
+        self.query(move |conn| {
+            diesel::delete(sessions::table.filter(sessions::session_key.eq(key))).execute(conn)
+        })
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::connect;
+
+    #[test]
+    fn purge_expired_keeps_live_sessions() {
+        let conn = &mut connect::establish_connection();
+
+        for (key, expires_at) in [
+            ("purge_expired_old", now() - 1),
+            ("purge_expired_live", now() + 60),
+        ] {
+            diesel::insert_into(sessions::table)
+                .values(SessionRow {
+                    session_key: String::from(key),
+                    state: String::from("{}"),
+                    expires_at,
+                })
+                .execute(conn)
+                .unwrap();
+        }
+
+        assert!(PgSessionStore::purge_expired(conn).unwrap() >= 1);
+
+        let left = sessions::table
+            .filter(sessions::session_key.like("purge_expired_%"))
+            .select(sessions::session_key)
+            .get_results::<String>(conn)
+            .unwrap();
+
+        assert_eq!(left, vec![String::from("purge_expired_live")]);
+
+        diesel::delete(sessions::table.filter(sessions::session_key.eq("purge_expired_live")))
+            .execute(conn)
+            .unwrap();
+    }
+}
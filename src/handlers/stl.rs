@@ -1,35 +1,53 @@
 use super::creator::Creator;
+use super::images::stls::StlImage;
+use super::models::{ModelFile, ModelListing};
 use super::ownership::stls::UserStl;
+use super::prices::AssetPrice;
 use crate::schema::stls;
-use crate::types::asset::{Asset, AssetType, Ownership, Page, Summary};
+use crate::types::asset::{Asset, AssetType, Details, Ownership, Page, Summary};
+use crate::types::error::AppError;
+use crate::types::price::{Price, Pricing};
 use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Queryable, Selectable, AsChangeset)]
+// The file and image columns are only ever written by uploads.
+#[derive(Queryable, Selectable, Serialize, Deserialize)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct Stl {
     pub id: i32,
     pub creator_id: i32,
     pub title: String,
+    #[serde(skip_deserializing)]
     pub thumb: String,
     pub summary: String,
+    #[serde(skip_deserializing)]
     pub file: String,
     pub is_free: bool,
+    #[serde(skip_deserializing)]
     pub main_image: String,
+    pub price: i32,
+    pub currency: String,
 }
 
-#[derive(Insertable)]
+#[derive(Insertable, Deserialize)]
 #[diesel(table_name = stls)]
 pub struct StlCreate {
     pub creator_id: i32,
     pub title: String,
+    #[serde(skip_deserializing)]
     pub thumb: String,
     pub summary: String,
+    #[serde(skip_deserializing)]
     pub file: String,
     pub is_free: bool,
+    #[serde(skip_deserializing)]
     pub main_image: String,
+    pub price: i32,
+    pub currency: String,
 }
 
 impl StlCreate {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         creator_id: i32,
         title: String,
@@ -38,6 +56,7 @@ impl StlCreate {
         file: String,
         main_image: String,
         is_free: bool,
+        price: Price,
     ) -> Self {
         StlCreate {
             creator_id,
@@ -47,91 +66,144 @@ impl StlCreate {
             file,
             main_image,
             is_free,
+            price: price.amount,
+            currency: String::from(price.currency.store()),
         }
     }
 
-    pub fn create(&self, conn: &mut PgConnection) -> Stl {
-        diesel::insert_into(stls::table)
+    pub fn create(&self, conn: &mut PgConnection) -> Result<Stl, AppError> {
+        Price::stored(self.price, &self.currency)?;
+
+        let stl = diesel::insert_into(stls::table)
             .values(self)
             .returning(Stl::as_returning())
-            .get_result(conn)
-            .expect("Error saving Stl")
+            .get_result(conn)?;
+
+        Ok(stl)
     }
 }
 
 impl Asset for Stl {
-    fn read(conn: &mut PgConnection, stl_id: i32) -> Self {
+    fn read(conn: &mut PgConnection, stl_id: i32) -> Result<Self, AppError> {
         use crate::schema::stls::dsl::*;
 
-        stls.filter(id.eq(stl_id))
+        let stl = stls
+            .filter(id.eq(stl_id))
             .select(Stl::as_select())
-            .get_result(conn)
-            .expect("Error loading posts")
+            .get_result(conn)?;
+
+        Ok(stl)
     }
 
-    fn destroy(conn: &mut PgConnection, stl_id: i32) -> usize {
+    fn destroy(conn: &mut PgConnection, stl_id: i32) -> Result<usize, AppError> {
         use crate::schema::stls::dsl::*;
 
-        diesel::delete(stls.filter(id.eq(stl_id)))
-            .execute(conn)
-            .expect("Error deleting posts")
+        conn.transaction(|conn| {
+            // buyers keep their copies, so a bought stl is never removed
+            if UserStl::is_owned(conn, stl_id)? {
+                return Err(AppError::Conflict(format!(
+                    "stl {} has been bought",
+                    stl_id
+                )));
+            }
+
+            let images = StlImage::destroy_all(conn, stl_id)?;
+            let prices = AssetPrice::destroy_all(conn, &AssetType::Stl, stl_id)?;
+            let models = ModelFile::destroy_all(conn, stl_id)?;
+            let changes = diesel::delete(stls.filter(id.eq(stl_id))).execute(conn)?;
+
+            Ok(images + prices + models + changes)
+        })
     }
 
-    fn update(&self, conn: &mut PgConnection) -> usize {
+    fn update(&self, conn: &mut PgConnection) -> Result<usize, AppError> {
         use crate::schema::stls::dsl::*;
 
-        diesel::update(stls)
+        Price::stored(self.price, &self.currency)?;
+
+        let changes = diesel::update(stls)
             .filter(id.eq(self.id))
-            .set(self)
-            .execute(conn)
-            .expect("Failed to update user")
+            .set((
+                creator_id.eq(self.creator_id),
+                title.eq(&self.title),
+                summary.eq(&self.summary),
+                is_free.eq(self.is_free),
+                price.eq(self.price),
+                currency.eq(&self.currency),
+            ))
+            .execute(conn)?;
+
+        Ok(changes)
     }
 
-    fn summarize(&self, conn: &mut PgConnection, user_id: i32) -> Summary {
-        let (creator, user) = Creator::creator_with_user(conn, self.creator_id);
+    fn summarize(&self, conn: &mut PgConnection, user_id: i32) -> Result<Summary, AppError> {
+        let (creator, user) = Creator::creator_with_user(conn, self.creator_id)?;
         let asset_type = AssetType::Stl;
         let display_name = creator.get_display_name();
-        let ownership = self.check_ownership(conn, user_id);
+        let ownership = self.check_ownership(conn, user_id)?;
+        let pricing = self.pricing(conn)?;
 
-        Summary {
+        Ok(Summary {
+            title: self.title.to_owned(),
             display_name,
             ownership,
             asset_type,
             logo: user.logo,
-        }
+            pricing,
+        })
     }
 
-    fn paginate(&self, conn: &mut PgConnection, user_id: i32) -> Page {
-        let (creator, user) = Creator::creator_with_user(conn, self.creator_id);
+    fn paginate(&self, conn: &mut PgConnection, user_id: i32) -> Result<Page, AppError> {
+        let (creator, user) = Creator::creator_with_user(conn, self.creator_id)?;
         let display_name = creator.get_display_name();
         let asset_type = AssetType::Stl;
-        let extra_images = Vec::new();
-        let ownership = self.check_ownership(conn, user_id);
-
-        Page {
+        let extra_images = asset_type.images(conn, self.id)?;
+        let ownership = self.check_ownership(conn, user_id)?;
+        let pricing = self.pricing(conn)?;
+        let models = ModelListing::read(conn, self.id)?;
+        let details = Some(models)
+            .filter(|models| !models.supported.is_empty() || !models.unsupported.is_empty())
+            .map(Details::Model);
+
+        Ok(Page {
             display_name,
             ownership,
             asset_type,
             logo: user.logo,
             extra_images,
-        }
+            pricing,
+            details,
+        })
     }
 
-    fn check_ownership(&self, conn: &mut PgConnection, user_id: i32) -> Ownership {
+    fn check_ownership(
+        &self,
+        conn: &mut PgConnection,
+        user_id: i32,
+    ) -> Result<Ownership, AppError> {
         if self.is_free {
-            Ownership::Free
+            Ok(Ownership::Free)
         } else {
             UserStl::check_ownership(conn, user_id, self.id)
         }
     }
 }
 
+impl Stl {
+    pub fn pricing(&self, conn: &mut PgConnection) -> Result<Pricing, AppError> {
+        let price = Price::stored(self.price, &self.currency)?;
+        AssetPrice::pricing(conn, &AssetType::Stl, self.id, price)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::handlers::connect;
     use crate::handlers::creator::{CreatorNew, Creators};
+    use crate::handlers::models::{MeshStats, ModelFormat};
     use crate::handlers::user::{User, UserNew};
+    use crate::types::price::Currency;
     use crate::types::user::DisplayName;
 
     #[test]
@@ -140,12 +212,13 @@ mod tests {
 
         let user = UserNew::create(
             conn,
-            String::from("naokotani"),
-            String::from("nao@gmail.com"),
+            String::from("stl_full"),
+            String::from("stl_full@gmail.com"),
             String::from("logo.svg"),
-        );
+        )
+        .unwrap();
 
-        assert_eq!(user.username, "naokotani");
+        assert_eq!(user.username, "stl_full");
 
         let creator = CreatorNew::create(
             conn,
@@ -155,7 +228,8 @@ mod tests {
             Some(String::from("naokotani")),
             Some(String::from("Random House")),
             DisplayName::Name,
-        );
+        )
+        .unwrap();
 
         let mut stl = StlCreate::new(
             creator.id,
@@ -165,43 +239,103 @@ mod tests {
             String::from("file.pdf"),
             String::from("image.jpg"),
             false,
+            Price::new(499, Currency::Eur),
         )
-        .create(conn);
+        .create(conn)
+        .unwrap();
 
         assert_eq!(stl.title, "Dungeons and Dragons");
         assert_eq!(stl.thumb, "thumb.jpg");
         assert_eq!(stl.summary, "What a stl!");
         assert_eq!(stl.file, "file.pdf");
         assert_eq!(stl.main_image, "image.jpg");
-        assert_eq!(stl.is_free, false);
+        assert!(!stl.is_free);
 
-        let summary = stl.summarize(conn, user.id);
+        let summary = stl.summarize(conn, user.id).unwrap();
 
         assert_eq!(summary.display_name, "Chris Hughes");
         assert_eq!(summary.logo, "logo.svg");
 
-        let page = stl.paginate(conn, user.id);
+        let page = stl.paginate(conn, user.id).unwrap();
 
         assert_eq!(page.display_name, "Chris Hughes");
         assert_eq!(page.logo, "logo.svg");
         assert_eq!(page.ownership, Ownership::Unowned);
+        assert_eq!(page.details, None);
+
+        let stats = MeshStats {
+            parts: 1,
+            triangles: 12,
+            width: 10.0,
+            depth: 20.0,
+            height: 30.5,
+            is_watertight: true,
+            is_manifold: true,
+        };
+        let key = format!("stls/{}/model-aa.stl", stl.id);
+        ModelFile::record(
+            conn,
+            stl.id,
+            ModelFormat::Stl,
+            String::from("Bare"),
+            false,
+            &key,
+            684,
+            &stats,
+        )
+        .unwrap();
+        let unsupported = ModelFile::record(
+            conn,
+            stl.id,
+            ModelFormat::Stl,
+            String::from("Bare"),
+            false,
+            &key,
+            700,
+            &stats,
+        )
+        .unwrap();
+
+        assert_eq!(unsupported.file_size, 700);
+
+        let supported = ModelFile::record(
+            conn,
+            stl.id,
+            ModelFormat::ThreeMf,
+            String::from("With supports"),
+            true,
+            &format!("stls/{}/model-bb.3mf", stl.id),
+            900,
+            &MeshStats { parts: 2, ..stats },
+        )
+        .unwrap();
+
+        let page = stl.paginate(conn, user.id).unwrap();
+
+        assert_eq!(
+            page.details,
+            Some(Details::Model(ModelListing {
+                supported: vec![supported],
+                unsupported: vec![unsupported],
+            }))
+        );
 
         stl.title = String::from("For Whom the Bell Tolls");
 
-        let update = stl.update(conn);
+        let update = stl.update(conn).unwrap();
 
         assert_eq!(update, 1);
 
-        let stl = Stl::read(conn, stl.id);
+        let stl = Stl::read(conn, stl.id).unwrap();
 
         assert_eq!(stl.title, "For Whom the Bell Tolls");
         assert_eq!(stl.summary, "What a stl!");
 
-        let delete = Stl::destroy(conn, stl.id);
+        let delete = Stl::destroy(conn, stl.id).unwrap();
 
-        assert_eq!(delete, 1);
+        assert_eq!(delete, 3);
 
-        Creators::destroy(conn, creator.id);
-        User::destroy(conn, user.id);
+        Creators::destroy(conn, creator.id).unwrap();
+        User::destroy(conn, user.id).unwrap();
     }
 }
@@ -0,0 +1,511 @@
+use crate::types::error::AppError;
+use crate::types::time::now;
+use hmac::{Hmac, Mac};
+use rand::distributions::{Alphanumeric, DistString};
+use reqwest::blocking::{Body, Client};
+use reqwest::{Method, StatusCode};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+// Keys are the strings stored on asset rows, e.g. "directory/windy-glade".
+// A missing key is reported as AppError::NotFound
+pub trait StorageBackend: Send + Sync {
+    fn put(&self, key: &str, bytes: &[u8]) -> Result<(), AppError>;
+    // stores the file at path without reading it into memory all at once
+    fn put_file(&self, key: &str, path: &Path) -> Result<(), AppError>;
+    fn get(&self, key: &str) -> Result<Vec<u8>, AppError>;
+    // bytes start..end of the file, so large files can be served in pieces
+    fn get_range(&self, key: &str, start: u64, end: u64) -> Result<Vec<u8>, AppError>;
+    fn size(&self, key: &str) -> Result<u64, AppError>;
+    fn exists(&self, key: &str) -> Result<bool, AppError>;
+    // deleting a key that is not stored is not an error
+    fn delete(&self, key: &str) -> Result<(), AppError>;
+}
+
+// Keys are relative paths, so they can never climb out of the storage root
+pub fn check_key(key: &str) -> Result<(), AppError> {
+    let invalid = key.is_empty()
+        || key.starts_with('/')
+        || key.contains('\\')
+        || key
+            .split('/')
+            .any(|segment| segment.is_empty() || segment == "." || segment == "..");
+
+    if invalid {
+        Err(AppError::Validation(format!("invalid file key: {}", key)))
+    } else {
+        Ok(())
+    }
+}
+
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        LocalStorage { root: root.into() }
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, AppError> {
+        check_key(key)?;
+        Ok(self.root.join(key))
+    }
+}
+
+impl StorageBackend for LocalStorage {
+    // Written to a temporary file first so readers never see half a file. Each
+    // write has its own, so two writes to one key cannot mix their bytes
+    fn put(&self, key: &str, bytes: &[u8]) -> Result<(), AppError> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let partial = partial(&path);
+        fs::write(&partial, bytes)
+            .and_then(|_| fs::rename(&partial, &path))
+            .inspect_err(|_| {
+                let _ = fs::remove_file(&partial);
+            })?;
+
+        Ok(())
+    }
+
+    fn put_file(&self, key: &str, source: &Path) -> Result<(), AppError> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let partial = partial(&path);
+        fs::copy(source, &partial)
+            .and_then(|_| fs::rename(&partial, &path))
+            .inspect_err(|_| {
+                let _ = fs::remove_file(&partial);
+            })?;
+
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>, AppError> {
+        let path = self.path(key)?;
+
+        fs::read(path).map_err(|err| not_found(err, key))
+    }
+
+    fn get_range(&self, key: &str, start: u64, end: u64) -> Result<Vec<u8>, AppError> {
+        let mut file = File::open(self.path(key)?).map_err(|err| not_found(err, key))?;
+        let mut bytes = Vec::new();
+
+        file.seek(SeekFrom::Start(start))?;
+        file.take(end.saturating_sub(start))
+            .read_to_end(&mut bytes)?;
+
+        Ok(bytes)
+    }
+
+    fn size(&self, key: &str) -> Result<u64, AppError> {
+        let metadata = fs::metadata(self.path(key)?).map_err(|err| not_found(err, key))?;
+        Ok(metadata.len())
+    }
+
+    fn exists(&self, key: &str) -> Result<bool, AppError> {
+        Ok(self.path(key)?.is_file())
+    }
+
+    fn delete(&self, key: &str) -> Result<(), AppError> {
+        match fs::remove_file(self.path(key)?) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(AppError::from(err)),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Talks to any S3-compatible service using path-style URLs and SigV4 signing
+pub struct S3Storage {
+    endpoint: String,
+    bucket: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    // built on first use, from inside a blocking thread
+    client: OnceLock<Client>,
+}
+
+impl S3Storage {
+    pub fn new(
+        endpoint: String,
+        bucket: String,
+        region: String,
+        access_key: String,
+        secret_key: String,
+    ) -> Self {
+        S3Storage {
+            endpoint: endpoint.trim_end_matches('/').to_owned(),
+            bucket,
+            region,
+            access_key,
+            secret_key,
+            client: OnceLock::new(),
+        }
+    }
+
+    fn send(
+        &self,
+        method: Method,
+        key: &str,
+        body: Vec<u8>,
+        range: Option<(u64, u64)>,
+    ) -> Result<reqwest::blocking::Response, AppError> {
+        let payload_hash = hex::encode(Sha256::digest(&body));
+        self.send_body(method, key, Body::from(body), payload_hash, range)
+    }
+
+    fn send_body(
+        &self,
+        method: Method,
+        key: &str,
+        body: Body,
+        payload_hash: String,
+        range: Option<(u64, u64)>,
+    ) -> Result<reqwest::blocking::Response, AppError> {
+        check_key(key)?;
+
+        let path = format!("/{}/{}", uri_encode(&self.bucket), encode_key(key));
+        let url = reqwest::Url::parse(&format!("{}{}", self.endpoint, path))
+            .map_err(|err| AppError::Storage(err.to_string()))?;
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => String::from(url.host_str().unwrap_or_default()),
+        };
+
+        let amz_date = amz_date(now());
+        let authorization = self.authorization(&method, &path, &host, &amz_date, &payload_hash);
+
+        let client = self.client.get_or_init(Client::new);
+        let mut request = client
+            .request(method, url)
+            .header("host", host)
+            .header("x-amz-date", amz_date)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("authorization", authorization);
+        // Range is left out of the signature, which SigV4 allows
+        if let Some((start, end)) = range {
+            request = request.header("range", format!("bytes={}-{}", start, end - 1));
+        }
+
+        request
+            .body(body)
+            .send()
+            .map_err(|err| AppError::Storage(err.to_string()))
+    }
+
+    fn authorization(
+        &self,
+        method: &Method,
+        path: &str,
+        host: &str,
+        amz_date: &str,
+        payload_hash: &str,
+    ) -> String {
+        let date = &amz_date[..8];
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+
+        let canonical_request = format!(
+            "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method.as_str(),
+            path,
+            host,
+            payload_hash,
+            amz_date,
+            signed_headers,
+            payload_hash
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let key = [date, &self.region, "s3", "aws4_request"].iter().fold(
+            format!("AWS4{}", self.secret_key).into_bytes(),
+            |key, part| hmac(&key, part.as_bytes()),
+        );
+        let signature = hex::encode(hmac(&key, string_to_sign.as_bytes()));
+
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key, scope, signed_headers, signature
+        )
+    }
+}
+
+impl StorageBackend for S3Storage {
+    fn put(&self, key: &str, bytes: &[u8]) -> Result<(), AppError> {
+        let response = self.send(Method::PUT, key, bytes.to_vec(), None)?;
+        check_status(response.status(), key)
+    }
+
+    // The file is read twice, once for the signature's payload hash and once
+    // as it is sent
+    fn put_file(&self, key: &str, path: &Path) -> Result<(), AppError> {
+        let mut file = File::open(path)?;
+        let mut hasher = Sha256::new();
+        let size = io::copy(&mut file, &mut hasher)?;
+        file.rewind()?;
+
+        let body = Body::sized(file, size);
+        let response =
+            self.send_body(Method::PUT, key, body, hex::encode(hasher.finalize()), None)?;
+        check_status(response.status(), key)
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>, AppError> {
+        let response = self.send(Method::GET, key, Vec::new(), None)?;
+        check_status(response.status(), key)?;
+
+        let bytes = response
+            .bytes()
+            .map_err(|err| AppError::Storage(err.to_string()))?;
+
+        Ok(bytes.to_vec())
+    }
+
+    fn get_range(&self, key: &str, start: u64, end: u64) -> Result<Vec<u8>, AppError> {
+        if start >= end {
+            return Ok(Vec::new());
+        }
+
+        let response = self.send(Method::GET, key, Vec::new(), Some((start, end)))?;
+        check_status(response.status(), key)?;
+
+        let bytes = response
+            .bytes()
+            .map_err(|err| AppError::Storage(err.to_string()))?;
+
+        Ok(bytes.to_vec())
+    }
+
+    fn size(&self, key: &str) -> Result<u64, AppError> {
+        let response = self.send(Method::HEAD, key, Vec::new(), None)?;
+        check_status(response.status(), key)?;
+
+        response
+            .headers()
+            .get("content-length")
+            .and_then(|length| length.to_str().ok())
+            .and_then(|length| length.parse().ok())
+            .ok_or_else(|| AppError::Storage(format!("no size reported for {}", key)))
+    }
+
+    fn exists(&self, key: &str) -> Result<bool, AppError> {
+        let response = self.send(Method::HEAD, key, Vec::new(), None)?;
+
+        match check_status(response.status(), key) {
+            Ok(()) => Ok(true),
+            Err(AppError::NotFound(_)) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn delete(&self, key: &str) -> Result<(), AppError> {
+        let response = self.send(Method::DELETE, key, Vec::new(), None)?;
+
+        match check_status(response.status(), key) {
+            Err(AppError::NotFound(_)) => Ok(()),
+            result => result,
+        }
+    }
+}
+
+fn not_found(err: std::io::Error, key: &str) -> AppError {
+    match err.kind() {
+        std::io::ErrorKind::NotFound => AppError::NotFound(format!("file {}", key)),
+        _ => AppError::from(err),
+    }
+}
+
+// A temporary name beside the final file, unique to one write
+fn partial(path: &Path) -> PathBuf {
+    path.with_file_name(format!(
+        "{}.{}.part",
+        path.file_name().unwrap_or_default().to_string_lossy(),
+        Alphanumeric.sample_string(&mut rand::thread_rng(), 16)
+    ))
+}
+
+fn check_status(status: StatusCode, key: &str) -> Result<(), AppError> {
+    if status.is_success() {
+        Ok(())
+    } else if status == StatusCode::NOT_FOUND {
+        Err(AppError::NotFound(format!("file {}", key)))
+    } else {
+        Err(AppError::Storage(format!("{} for {}", status, key)))
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn uri_encode(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                String::from(byte as char)
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn encode_key(key: &str) -> String {
+    key.split('/').map(uri_encode).collect::<Vec<_>>().join("/")
+}
+
+// unix seconds as YYYYMMDD'T'HHMMSS'Z'
+fn amz_date(secs: i64) -> String {
+    let days = secs.div_euclid(86_400);
+    let rem = secs.rem_euclid(86_400);
+
+    // civil date from days since 1970-01-01
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
+
+// STORAGE_BACKEND selects the backend; "local" is the default and keeps files
+// under STORAGE_ROOT, "s3" reads the S3_* variables
+pub fn storage_from_env() -> Result<Arc<dyn StorageBackend>, AppError> {
+    let var = |name: &str| {
+        env::var(name).map_err(|_| AppError::Validation(format!("{} must be set", name)))
+    };
+
+    match env::var("STORAGE_BACKEND").as_deref() {
+        Ok("local") | Err(_) => Ok(Arc::new(LocalStorage::new(
+            env::var("STORAGE_ROOT").unwrap_or_else(|_| String::from("storage")),
+        ))),
+        Ok("s3") => Ok(Arc::new(S3Storage::new(
+            var("S3_ENDPOINT")?,
+            var("S3_BUCKET")?,
+            env::var("S3_REGION").unwrap_or_else(|_| String::from("us-east-1")),
+            var("S3_ACCESS_KEY")?,
+            var("S3_SECRET_KEY")?,
+        ))),
+        Ok(other) => Err(AppError::Validation(format!(
+            "unknown storage backend: {}",
+            other
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_storage() {
+        let root = env::temp_dir().join(format!("alembic-storage-{}", std::process::id()));
+        let storage = LocalStorage::new(&root);
+
+        let source = root.join("source");
+        storage.put("source", b"spooled").unwrap();
+        storage.put_file("directory/spooled", &source).unwrap();
+
+        assert_eq!(storage.get("directory/spooled").unwrap(), b"spooled");
+
+        storage.put("directory/windy-glade", b"token").unwrap();
+
+        assert!(storage.exists("directory/windy-glade").unwrap());
+        assert_eq!(storage.get("directory/windy-glade").unwrap(), b"token");
+        assert_eq!(storage.size("directory/windy-glade").unwrap(), 5);
+        assert_eq!(
+            storage.get_range("directory/windy-glade", 1, 3).unwrap(),
+            b"ok"
+        );
+
+        storage.delete("directory/windy-glade").unwrap();
+        storage.delete("directory/windy-glade").unwrap();
+
+        assert!(!storage.exists("directory/windy-glade").unwrap());
+        assert!(matches!(
+            storage.get("directory/windy-glade"),
+            Err(AppError::NotFound(_))
+        ));
+
+        for key in [
+            "",
+            "/etc/passwd",
+            "directory/../../secret",
+            "directory//file",
+        ] {
+            assert!(matches!(
+                storage.put(key, b"token"),
+                Err(AppError::Validation(_))
+            ));
+        }
+
+        // concurrent writes to one key each land whole and leave nothing behind
+        std::thread::scope(|scope| {
+            for fill in [b'a', b'b', b'c', b'd'] {
+                let storage = &storage;
+                scope.spawn(move || {
+                    for _ in 0..20 {
+                        storage.put("racing/key", &[fill; 4096]).unwrap();
+                    }
+                });
+            }
+        });
+        let raced = storage.get("racing/key").unwrap();
+
+        assert!(raced.iter().all(|byte| *byte == raced[0]));
+        assert_eq!(fs::read_dir(root.join("racing")).unwrap().count(), 1);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn s3_signing() {
+        assert_eq!(amz_date(0), "19700101T000000Z");
+        assert_eq!(amz_date(1_369_353_600), "20130524T000000Z");
+        assert_eq!(amz_date(1_709_251_199), "20240229T235959Z");
+        assert_eq!(encode_key("maps/windy glade+1"), "maps/windy%20glade%2B1");
+
+        // the worked example from the SigV4 documentation
+        let key = ["20130524", "us-east-1", "s3", "aws4_request"].iter().fold(
+            b"AWS4wJalrXUtnFEMI/K7MDENG/bPxRfiCYEXAMPLEKEY".to_vec(),
+            |key, part| hmac(&key, part.as_bytes()),
+        );
+
+        assert_eq!(
+            hex::encode(key),
+            "dbb893acc010964918f1fd433add87c70e8b0db6be30c1fbeafefa5ec6ba8378"
+        );
+    }
+}
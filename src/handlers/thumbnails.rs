@@ -0,0 +1,250 @@
+use super::storage::StorageBackend;
+use super::uploads::{Upload, UploadTarget};
+use crate::schema::thumbnails;
+use crate::types::error::AppError;
+use crate::types::time::now;
+use diesel::prelude::*;
+use diesel::sql_types::{Integer, Text};
+use image::{DynamicImage, ImageFormat};
+use serde::Serialize;
+use std::io::Cursor;
+
+// Longest side of each generated thumbnail; the first is written to `thumb`
+pub const THUMB_SIZES: [u32; 2] = [256, 512];
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ThumbFormat {
+    Png,
+    Webp,
+}
+
+#[derive(Queryable, Selectable, Serialize, Debug)]
+#[diesel(table_name = thumbnails)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Thumbnail {
+    pub id: i32,
+    pub asset_type: String,
+    pub asset_id: i32,
+    pub size: i32,
+    pub format: String,
+    pub file_key: String,
+    pub width: i32,
+    pub height: i32,
+    // unix seconds
+    pub created_at: i64,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = thumbnails)]
+struct ThumbnailNew {
+    asset_type: String,
+    asset_id: i32,
+    size: i32,
+    format: String,
+    file_key: String,
+    width: i32,
+    height: i32,
+    created_at: i64,
+}
+
+const FORMATS: [ThumbFormat; 2] = [ThumbFormat::Png, ThumbFormat::Webp];
+
+impl ThumbFormat {
+    pub fn store(&self) -> &str {
+        match self {
+            Self::Png => "png",
+            Self::Webp => "webp",
+        }
+    }
+
+    fn image_format(&self) -> ImageFormat {
+        match self {
+            Self::Png => ImageFormat::Png,
+            Self::Webp => ImageFormat::WebP,
+        }
+    }
+}
+
+impl Thumbnail {
+    // Renders every size and format from the row's current source image and
+    // points `thumb` at the smallest PNG. Maps also get their dimensions from
+    // the image. Nothing changes if the source was replaced in the meantime.
+    pub fn generate(
+        conn: &mut PgConnection,
+        storage: &dyn StorageBackend,
+        target: UploadTarget,
+        a_id: i32,
+    ) -> Result<Vec<Thumbnail>, AppError> {
+        let slot = target.thumb_source().ok_or_else(|| {
+            AppError::Validation(format!("{} does not have a thumbnail", target.store()))
+        })?;
+        let source = Upload::read(conn, target, a_id, slot)?;
+        let image = image::load_from_memory(&storage.get(&source.file_key)?).map_err(|err| {
+            AppError::Validation(format!("{} could not be decoded: {}", source.file_key, err))
+        })?;
+
+        let mut rendered = Vec::new();
+        for size in THUMB_SIZES {
+            let thumb = shrink(&image, size);
+            for format in FORMATS {
+                let key = format!(
+                    "{}/{}/thumb-{}-{}.{}",
+                    target.table(),
+                    a_id,
+                    size,
+                    &source.sha256[..16],
+                    format.store()
+                );
+                storage.put(&key, &encode(&thumb, format)?)?;
+                rendered.push(ThumbnailNew {
+                    asset_type: String::from(target.store()),
+                    asset_id: a_id,
+                    size: size as i32,
+                    format: String::from(format.store()),
+                    file_key: key,
+                    width: thumb.width() as i32,
+                    height: thumb.height() as i32,
+                    created_at: now(),
+                });
+            }
+        }
+
+        let dimensions = match target {
+            UploadTarget::Map => Some((image.width() as i32, image.height() as i32)),
+            _ => None,
+        };
+
+        // the source is still current while its upload is
+        let current =
+            "EXISTS (SELECT 1 FROM uploads WHERE uploads.id = $1 AND uploads.file_key = $2)";
+        let thumb = &rendered[0].file_key;
+        let stored = conn.transaction(|conn| {
+            let changes = match dimensions {
+                Some((width, height)) => diesel::sql_query(format!(
+                    "UPDATE {} SET thumb = $3, width = $4, height = $5 WHERE id = $6 AND {}",
+                    target.table(),
+                    current
+                ))
+                .bind::<Integer, _>(source.id)
+                .bind::<Text, _>(&source.file_key)
+                .bind::<Text, _>(thumb)
+                .bind::<Integer, _>(width)
+                .bind::<Integer, _>(height)
+                .bind::<Integer, _>(a_id)
+                .execute(conn)?,
+                None => diesel::sql_query(format!(
+                    "UPDATE {} SET thumb = $3 WHERE id = $4 AND {}",
+                    target.table(),
+                    current
+                ))
+                .bind::<Integer, _>(source.id)
+                .bind::<Text, _>(&source.file_key)
+                .bind::<Text, _>(thumb)
+                .bind::<Integer, _>(a_id)
+                .execute(conn)?,
+            };
+
+            if changes == 0 {
+                return Ok(None);
+            }
+
+            let previous = diesel::delete(
+                thumbnails::table
+                    .filter(thumbnails::asset_type.eq(target.store()))
+                    .filter(thumbnails::asset_id.eq(a_id)),
+            )
+            .returning(thumbnails::file_key)
+            .get_results::<String>(conn)?;
+
+            let thumbnails = diesel::insert_into(thumbnails::table)
+                .values(&rendered)
+                .returning(Thumbnail::as_returning())
+                .get_results(conn)?;
+
+            Ok::<Option<(Vec<String>, Vec<Thumbnail>)>, AppError>(Some((previous, thumbnails)))
+        })?;
+
+        let keys = rendered
+            .into_iter()
+            .map(|thumbnail| thumbnail.file_key)
+            .collect::<Vec<String>>();
+
+        // a superseded source leaves its own renders unused; otherwise it is
+        // the thumbnails they replace that go
+        let (unused, thumbnails) = match stored {
+            Some((previous, thumbnails)) => (
+                previous
+                    .into_iter()
+                    .filter(|key| !keys.contains(key))
+                    .collect(),
+                thumbnails,
+            ),
+            None => (keys, Vec::new()),
+        };
+        for key in unused {
+            storage.delete(&key)?;
+        }
+
+        Ok(thumbnails)
+    }
+
+    pub fn list(
+        conn: &mut PgConnection,
+        target: UploadTarget,
+        a_id: i32,
+    ) -> Result<Vec<Thumbnail>, AppError> {
+        use crate::schema::thumbnails::dsl::*;
+
+        let thumbs = thumbnails
+            .filter(asset_type.eq(target.store()))
+            .filter(asset_id.eq(a_id))
+            .order((size.asc(), format.asc()))
+            .select(Thumbnail::as_select())
+            .get_results(conn)?;
+
+        Ok(thumbs)
+    }
+}
+
+// Fits the image inside a size by size square; smaller images are kept as they are
+pub fn shrink(image: &DynamicImage, size: u32) -> DynamicImage {
+    if image.width() <= size && image.height() <= size {
+        image.clone()
+    } else {
+        image.thumbnail(size, size)
+    }
+}
+
+pub fn encode(image: &DynamicImage, format: ThumbFormat) -> Result<Vec<u8>, AppError> {
+    let mut bytes = Cursor::new(Vec::new());
+    image
+        .write_to(&mut bytes, format.image_format())
+        .map_err(|err| AppError::Storage(format!("thumbnail could not be encoded: {}", err)))?;
+    Ok(bytes.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, RgbaImage};
+
+    #[test]
+    fn thumbnail_sizes() {
+        let wide = DynamicImage::ImageRgba8(RgbaImage::new(1000, 500));
+        let small = DynamicImage::ImageRgba8(RgbaImage::new(100, 40));
+
+        assert_eq!(shrink(&wide, 256).dimensions(), (256, 128));
+        assert_eq!(shrink(&wide, 512).dimensions(), (512, 256));
+        assert_eq!(shrink(&small, 256).dimensions(), (100, 40));
+
+        let png = encode(&shrink(&wide, 256), ThumbFormat::Png).unwrap();
+        let webp = encode(&shrink(&wide, 256), ThumbFormat::Webp).unwrap();
+
+        assert_eq!(image::guess_format(&png).unwrap(), ImageFormat::Png);
+        assert_eq!(image::guess_format(&webp).unwrap(), ImageFormat::WebP);
+        assert_eq!(
+            image::load_from_memory(&webp).unwrap().dimensions(),
+            (256, 128)
+        );
+    }
+}
@@ -1,11 +1,17 @@
 use super::creator::Creator;
+use super::images::token_packs::TokenPackImage;
+use super::ownership::token_packs::UserTokenPack;
 use super::ownership::tokens::UserToken;
+use super::prices::AssetPrice;
 use crate::schema::token_packs;
 use crate::schema::tokens;
 use crate::types::asset::{Asset, AssetType, Ownership, Page, Summary};
+use crate::types::error::AppError;
+use crate::types::price::{Price, Pricing};
 use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Queryable, Selectable, AsChangeset)]
+#[derive(Queryable, Selectable, Serialize, Deserialize)]
 #[diesel(table_name = tokens)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct Token {
@@ -13,24 +19,35 @@ pub struct Token {
     pub creator_id: i32,
     pub token_pack_id: i32,
     pub title: String,
+    #[serde(skip_deserializing)]
     pub thumb: String,
     pub summary: String,
+    #[serde(skip_deserializing)]
     pub file: String,
     pub height: Option<i32>,
     pub width: Option<i32>,
     pub is_free: bool,
+    #[serde(skip_deserializing)]
     pub main_image: String,
+    pub price: Option<i32>,
+    pub currency: Option<String>,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct TokenPack {
     pub id: i32,
     pub creator_id: i32,
     pub title: String,
+    #[serde(skip_deserializing)]
     pub thumb: String,
     pub summary: String,
+    #[serde(skip_deserializing)]
     pub directory: String,
     pub is_free: bool,
+    #[serde(skip_deserializing)]
     pub main_image: String,
+    pub price: i32,
+    pub currency: String,
     pub tokens: Vec<Token>,
 }
 
@@ -47,9 +64,11 @@ pub struct TokenCreate {
     pub width: Option<i32>,
     pub is_free: bool,
     pub main_image: String,
+    pub price: Option<i32>,
+    pub currency: Option<String>,
 }
 
-#[derive(Queryable, Selectable, AsChangeset)]
+#[derive(Queryable, Selectable, Serialize)]
 #[diesel(table_name = token_packs)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct TokenPackQuery {
@@ -61,21 +80,29 @@ pub struct TokenPackQuery {
     pub directory: String,
     pub is_free: bool,
     pub main_image: String,
+    pub price: i32,
+    pub currency: String,
 }
 
-#[derive(Insertable)]
+#[derive(Insertable, Deserialize)]
 #[diesel(table_name = token_packs)]
 pub struct TokenPackCreate {
     pub creator_id: i32,
     pub title: String,
+    #[serde(skip_deserializing)]
     pub thumb: String,
     pub summary: String,
+    #[serde(skip_deserializing)]
     pub directory: String,
     pub is_free: bool,
+    #[serde(skip_deserializing)]
     pub main_image: String,
+    pub price: i32,
+    pub currency: String,
 }
 
 impl TokenPackCreate {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         creator_id: i32,
         title: String,
@@ -84,6 +111,7 @@ impl TokenPackCreate {
         directory: String,
         is_free: bool,
         main_image: String,
+        price: Price,
     ) -> Self {
         TokenPackCreate {
             creator_id,
@@ -93,15 +121,20 @@ impl TokenPackCreate {
             directory,
             is_free,
             main_image,
+            price: price.amount,
+            currency: String::from(price.currency.store()),
         }
     }
 
-    pub fn create(&self, conn: &mut PgConnection) -> TokenPackQuery {
-        diesel::insert_into(token_packs::table)
+    pub fn create(&self, conn: &mut PgConnection) -> Result<TokenPackQuery, AppError> {
+        Price::stored(self.price, &self.currency)?;
+
+        let token_pack = diesel::insert_into(token_packs::table)
             .values(self)
             .returning(TokenPackQuery::as_returning())
-            .get_result(conn)
-            .expect("Error saving Token Pack")
+            .get_result(conn)?;
+
+        Ok(token_pack)
     }
 }
 
@@ -118,6 +151,7 @@ impl TokenCreate {
         directory: &str,
         main_image: String,
         is_free: bool,
+        price: Option<Price>,
     ) -> Self {
         let slug = title.to_lowercase().trim().replace(' ', "-");
         let file = format!("{}/{}", directory, slug);
@@ -132,31 +166,35 @@ impl TokenCreate {
             file,
             main_image,
             is_free,
+            price: price.map(|price| price.amount),
+            currency: price.map(|price| String::from(price.currency.store())),
         }
     }
 
-    pub fn create(&self, conn: &mut PgConnection) -> Token {
-        diesel::insert_into(tokens::table)
+    pub fn create(&self, conn: &mut PgConnection) -> Result<Token, AppError> {
+        Price::optional(self.price, &self.currency)?;
+
+        let token = diesel::insert_into(tokens::table)
             .values(self)
             .returning(Token::as_returning())
-            .get_result(conn)
-            .expect("Error saving token")
+            .get_result(conn)?;
+
+        Ok(token)
     }
 }
 
 impl Asset for TokenPack {
-    fn read(conn: &mut PgConnection, pack_id: i32) -> TokenPack {
+    fn read(conn: &mut PgConnection, pack_id: i32) -> Result<TokenPack, AppError> {
         use crate::schema::tokens::dsl::*;
 
-        let token_pack = get_token_pack(conn, pack_id);
+        let token_pack = get_token_pack(conn, pack_id)?;
 
         let token = tokens
             .filter(token_pack_id.eq(pack_id))
             .select(Token::as_select())
-            .get_results(conn)
-            .expect("Error loading posts");
+            .get_results(conn)?;
 
-        TokenPack {
+        Ok(TokenPack {
             id: token_pack.id,
             creator_id: token_pack.creator_id,
             title: token_pack.title,
@@ -165,113 +203,224 @@ impl Asset for TokenPack {
             directory: token_pack.directory,
             is_free: token_pack.is_free,
             main_image: token_pack.main_image,
+            price: token_pack.price,
+            currency: token_pack.currency,
             tokens: token,
-        }
+        })
     }
 
-    fn destroy(conn: &mut PgConnection, pack_id: i32) -> usize {
+    fn destroy(conn: &mut PgConnection, pack_id: i32) -> Result<usize, AppError> {
         use crate::schema::tokens::dsl::*;
 
-        let changes = diesel::delete(tokens.filter(token_pack_id.eq(pack_id)))
-            .execute(conn)
-            .expect("Error deleting posts");
-
-        changes + destroy_token_pack(conn, pack_id)
+        conn.transaction(|conn| {
+            let token_ids = tokens
+                .filter(token_pack_id.eq(pack_id))
+                .select(id)
+                .get_results::<i32>(conn)?;
+
+            // buyers keep their copies, so a pack with anything bought is never removed
+            if UserTokenPack::is_owned(conn, pack_id)? || UserToken::any_owned(conn, &token_ids)? {
+                return Err(AppError::Conflict(format!(
+                    "token pack {} has been bought",
+                    pack_id
+                )));
+            }
+
+            let images = TokenPackImage::destroy_all(conn, pack_id)?;
+            let mut prices = AssetPrice::destroy_all(conn, &AssetType::TokenPack, pack_id)?;
+            for token in token_ids {
+                prices += AssetPrice::destroy_all(conn, &AssetType::Token, token)?;
+            }
+            let changes = diesel::delete(tokens.filter(token_pack_id.eq(pack_id))).execute(conn)?;
+
+            Ok(images + prices + changes + destroy_token_pack(conn, pack_id)?)
+        })
     }
 
-    fn update(&self, conn: &mut PgConnection) -> usize {
+    fn update(&self, conn: &mut PgConnection) -> Result<usize, AppError> {
         use crate::schema::token_packs::dsl::*;
 
-        let token_pack = TokenPackQuery {
-            id: self.id,
-            creator_id: self.creator_id.to_owned(),
-            title: self.title.to_owned(),
-            thumb: self.thumb.to_owned(),
-            summary: self.summary.to_owned(),
-            directory: self.directory.to_owned(),
-            is_free: self.is_free,
-            main_image: self.main_image.to_owned(),
-        };
+        Price::stored(self.price, &self.currency)?;
 
         let changes = diesel::update(token_packs)
-            .filter(id.eq(token_pack.id))
-            .set(token_pack)
-            .execute(conn)
-            .expect("Failed to update user");
-
-        update_tokens(conn, &self.tokens) + changes
+            .filter(id.eq(self.id))
+            .set((
+                creator_id.eq(self.creator_id),
+                title.eq(&self.title),
+                summary.eq(&self.summary),
+                is_free.eq(self.is_free),
+                price.eq(self.price),
+                currency.eq(&self.currency),
+            ))
+            .execute(conn)?;
+
+        Ok(update_tokens(conn, &self.tokens)? + changes)
     }
 
-    fn summarize(&self, conn: &mut PgConnection, user_id: i32) -> Summary {
-        let (creator, user) = Creator::creator_with_user(conn, self.creator_id);
+    fn summarize(&self, conn: &mut PgConnection, user_id: i32) -> Result<Summary, AppError> {
+        let (creator, user) = Creator::creator_with_user(conn, self.creator_id)?;
         let asset_type = AssetType::Token;
         let display_name = creator.get_display_name();
-        let ownership = self.check_ownership(conn, user_id);
+        let ownership = self.check_ownership(conn, user_id)?;
+        let pricing = self.pricing(conn)?;
 
-        Summary {
+        Ok(Summary {
+            title: self.title.to_owned(),
             display_name,
             ownership,
             asset_type,
             logo: user.logo,
-        }
+            pricing,
+        })
     }
 
-    fn paginate(&self, conn: &mut PgConnection, user_id: i32) -> Page {
-        let (creator, user) = Creator::creator_with_user(conn, self.creator_id);
+    fn paginate(&self, conn: &mut PgConnection, user_id: i32) -> Result<Page, AppError> {
+        let (creator, user) = Creator::creator_with_user(conn, self.creator_id)?;
         let display_name = creator.get_display_name();
         let asset_type = AssetType::Token;
-        let extra_images = asset_type.images();
-        let ownership = self.check_ownership(conn, user_id);
+        let extra_images = AssetType::TokenPack.images(conn, self.id)?;
+        let ownership = self.check_ownership(conn, user_id)?;
+        let pricing = self.pricing(conn)?;
 
-        Page {
+        Ok(Page {
             display_name,
             ownership,
             asset_type,
             logo: user.logo,
             extra_images,
-        }
+            pricing,
+            details: None,
+        })
     }
 
-    fn check_ownership(&self, conn: &mut PgConnection, user_id: i32) -> Ownership {
+    fn check_ownership(
+        &self,
+        conn: &mut PgConnection,
+        user_id: i32,
+    ) -> Result<Ownership, AppError> {
         if self.is_free {
-            Ownership::Free
+            Ok(Ownership::Free)
         } else {
-            UserToken::check_ownership(conn, user_id, self.id)
+            UserTokenPack::check_ownership(conn, user_id, self.id)
         }
     }
 }
 
-fn get_token_pack(conn: &mut PgConnection, pack_id: i32) -> TokenPackQuery {
+fn get_token_pack(conn: &mut PgConnection, pack_id: i32) -> Result<TokenPackQuery, AppError> {
     use crate::schema::token_packs::dsl::*;
 
-    token_packs
+    let token_pack = token_packs
         .filter(id.eq(pack_id))
         .select(TokenPackQuery::as_select())
-        .get_result(conn)
-        .expect("Error loading token pack")
+        .get_result(conn)?;
+
+    Ok(token_pack)
 }
 
-fn destroy_token_pack(conn: &mut PgConnection, pack_id: i32) -> usize {
+fn destroy_token_pack(conn: &mut PgConnection, pack_id: i32) -> Result<usize, AppError> {
     use crate::schema::token_packs::dsl::*;
 
-    diesel::delete(token_packs.filter(id.eq(pack_id)))
-        .execute(conn)
-        .expect("Error deleting token_packs")
+    let changes = diesel::delete(token_packs.filter(id.eq(pack_id))).execute(conn)?;
+
+    Ok(changes)
 }
 
-fn update_tokens(conn: &mut PgConnection, tokens_vec: &Vec<Token>) -> usize {
+fn update_tokens(conn: &mut PgConnection, tokens_vec: &Vec<Token>) -> Result<usize, AppError> {
     use crate::schema::tokens::dsl::*;
 
     let mut changes: usize = 0;
     for token in tokens_vec {
+        Price::optional(token.price, &token.currency)?;
         let result = diesel::update(tokens)
             .filter(id.eq(token.id))
-            .set(token)
-            .execute(conn)
-            .expect("Failed to update user");
+            .set((
+                creator_id.eq(token.creator_id),
+                token_pack_id.eq(token.token_pack_id),
+                title.eq(&token.title),
+                summary.eq(&token.summary),
+                height.eq(token.height),
+                width.eq(token.width),
+                is_free.eq(token.is_free),
+                price.eq(token.price),
+                currency.eq(&token.currency),
+            ))
+            .execute(conn)?;
         changes += result;
     }
-    changes
+    Ok(changes)
+}
+
+impl Token {
+    pub fn read(conn: &mut PgConnection, t_id: i32) -> Result<Token, AppError> {
+        use crate::schema::tokens::dsl::*;
+
+        let token = tokens
+            .filter(id.eq(t_id))
+            .select(Token::as_select())
+            .get_result(conn)?;
+
+        Ok(token)
+    }
+
+    pub fn summarize(&self, conn: &mut PgConnection, user_id: i32) -> Result<Summary, AppError> {
+        let (creator, user) = Creator::creator_with_user(conn, self.creator_id)?;
+        let display_name = creator.get_display_name();
+        let ownership = self.check_ownership(conn, user_id)?;
+        // tokens without a price of their own are shown at their pack's price
+        let pricing = match self.price {
+            Some(_) => self.pricing(conn)?,
+            None => {
+                let token_pack = get_token_pack(conn, self.token_pack_id)?;
+                let price = Price::stored(token_pack.price, &token_pack.currency)?;
+                AssetPrice::pricing(conn, &AssetType::TokenPack, token_pack.id, price)?
+            }
+        };
+
+        Ok(Summary {
+            title: self.title.to_owned(),
+            display_name,
+            ownership,
+            asset_type: AssetType::Token,
+            logo: user.logo,
+            pricing,
+        })
+    }
+
+    // A token is covered by owning it, owning its pack, or either being free
+    pub fn check_ownership(
+        &self,
+        conn: &mut PgConnection,
+        user_id: i32,
+    ) -> Result<Ownership, AppError> {
+        let token_pack = get_token_pack(conn, self.token_pack_id)?;
+
+        if self.is_free || token_pack.is_free {
+            Ok(Ownership::Free)
+        } else if UserTokenPack::check_ownership(conn, user_id, token_pack.id)? == Ownership::Owned
+        {
+            Ok(Ownership::Owned)
+        } else {
+            UserToken::check_ownership(conn, user_id, self.id)
+        }
+    }
+
+    // Only tokens with a price of their own can be bought outside their pack
+    pub fn pricing(&self, conn: &mut PgConnection) -> Result<Pricing, AppError> {
+        match Price::optional(self.price, &self.currency)? {
+            Some(price) => AssetPrice::pricing(conn, &AssetType::Token, self.id, price),
+            None => Err(AppError::Validation(format!(
+                "token {} is sold through its pack",
+                self.id
+            ))),
+        }
+    }
+}
+
+impl TokenPack {
+    pub fn pricing(&self, conn: &mut PgConnection) -> Result<Pricing, AppError> {
+        let price = Price::stored(self.price, &self.currency)?;
+        AssetPrice::pricing(conn, &AssetType::TokenPack, self.id, price)
+    }
 }
 
 #[cfg(test)]
@@ -280,6 +429,7 @@ mod tests {
     use crate::handlers::connect;
     use crate::handlers::creator::{CreatorNew, Creators};
     use crate::handlers::user::{User, UserNew};
+    use crate::types::price::Currency;
     use crate::types::user::DisplayName;
 
     // #[test]
@@ -344,10 +494,11 @@ mod tests {
 
         let user = UserNew::create(
             conn,
-            String::from("naokotani"),
-            String::from("nao@gmail.com"),
+            String::from("token_full"),
+            String::from("token_full@gmail.com"),
             String::from("logo.svg"),
-        );
+        )
+        .unwrap();
 
         let creator = CreatorNew::create(
             conn,
@@ -357,7 +508,8 @@ mod tests {
             Some(String::from("naokotani")),
             Some(String::from("Random House")),
             DisplayName::Name,
-        );
+        )
+        .unwrap();
 
         let token_pack = TokenPackCreate::new(
             creator.id,
@@ -367,8 +519,10 @@ mod tests {
             String::from("directory"),
             false,
             String::from("image.jpg"),
+            Price::new(750, Currency::Gbp),
         )
-        .create(conn);
+        .create(conn)
+        .unwrap();
 
         vec![TokenCreate::new(
             creator.id,
@@ -381,19 +535,21 @@ mod tests {
             &token_pack.directory,
             String::from("image.jpg"),
             false,
+            None,
         )
-        .create(conn)];
+        .create(conn)
+        .unwrap()];
 
-        let mut token_pack = TokenPack::read(conn, token_pack.id);
+        let mut token_pack = TokenPack::read(conn, token_pack.id).unwrap();
 
         assert_eq!(token_pack.tokens[0].title, "Windy Glade");
 
-        let page = token_pack.paginate(conn, user.id);
+        let page = token_pack.paginate(conn, user.id).unwrap();
 
         assert_eq!(page.display_name, "Chris Hughes");
         assert_eq!(page.asset_type, AssetType::Token);
 
-        let summary = token_pack.summarize(conn, user.id);
+        let summary = token_pack.summarize(conn, user.id).unwrap();
 
         assert_eq!(summary.display_name, "Chris Hughes");
         assert_eq!(summary.asset_type, AssetType::Token);
@@ -406,25 +562,26 @@ mod tests {
             Some(creator.other_name),
             Some(String::from("Random House")),
             DisplayName::OtherPublisher,
-        );
+        )
+        .unwrap();
 
         assert_eq!(update_names, 1);
 
         token_pack.is_free = true;
-        token_pack.update(conn);
+        token_pack.update(conn).unwrap();
 
-        let token_pack = TokenPack::read(conn, token_pack.id);
-        let summary = token_pack.summarize(conn, user.id);
+        let token_pack = TokenPack::read(conn, token_pack.id).unwrap();
+        let summary = token_pack.summarize(conn, user.id).unwrap();
 
         assert_eq!(summary.display_name, "naokotani publisher: Random House");
 
         assert_eq!(summary.ownership, Ownership::Free);
 
-        let delete = TokenPack::destroy(conn, token_pack.id);
+        let delete = TokenPack::destroy(conn, token_pack.id).unwrap();
 
         assert_eq!(delete, 2);
 
-        Creators::destroy(conn, creator.id);
-        User::destroy(conn, user.id);
+        Creators::destroy(conn, creator.id).unwrap();
+        User::destroy(conn, user.id).unwrap();
     }
 }
@@ -0,0 +1,742 @@
+use super::formats::BookFormat;
+use super::storage::StorageBackend;
+use crate::schema::uploads;
+use crate::types::error::AppError;
+use crate::types::time::now;
+use diesel::prelude::*;
+use diesel::sql_types::{Integer, Text};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const MIB: usize = 1024 * 1024;
+
+// Enough of a file to check its signature
+const HEAD: usize = 64;
+
+// The rows creators upload files for
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum UploadTarget {
+    Book,
+    Stl,
+    Track,
+    Map,
+    Token,
+}
+
+// The column on the row the uploaded file's key is written to
+#[derive(PartialEq, Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileSlot {
+    File,
+    Thumb,
+    MainImage,
+}
+
+// The file names thumbnails, cover and gallery images and previews are stored under
+const PUBLIC_IMAGES: [&str; 5] = ["thumb-", "main_image-", "cover-", "gallery-", "preview-"];
+
+pub struct Format {
+    pub content_type: &'static str,
+    pub extension: &'static str,
+    magic: fn(&[u8]) -> bool,
+}
+
+pub struct UploadRule {
+    pub max_size: usize,
+    pub formats: &'static [Format],
+}
+
+const IMAGES: [Format; 3] = [
+    Format {
+        content_type: "image/png",
+        extension: "png",
+        magic: |head| head.starts_with(b"\x89PNG\r\n\x1a\n"),
+    },
+    Format {
+        content_type: "image/jpeg",
+        extension: "jpg",
+        magic: |head| head.starts_with(&[0xff, 0xd8, 0xff]),
+    },
+    Format {
+        content_type: "image/webp",
+        extension: "webp",
+        magic: |head| head.starts_with(b"RIFF") && head.get(8..12) == Some(b"WEBP"),
+    },
+];
+
+const DOCUMENTS: [Format; 1] = [Format {
+    content_type: "application/pdf",
+    extension: "pdf",
+    magic: |head| head.starts_with(b"%PDF-"),
+}];
+
+// an EPUB is a zip package too; its mimetype entry is checked when it is parsed
+const EBOOKS: [Format; 1] = [Format {
+    content_type: "application/epub+zip",
+    extension: "epub",
+    magic: |head| head.starts_with(b"PK\x03\x04"),
+}];
+
+// binary STL has no signature, so any bytes are accepted as one; the models
+// themselves are checked when they are parsed
+const MODELS: [Format; 9] = [
+    Format {
+        content_type: "model/stl",
+        extension: "stl",
+        magic: |_| true,
+    },
+    Format {
+        content_type: "application/sla",
+        extension: "stl",
+        magic: |_| true,
+    },
+    Format {
+        content_type: "application/vnd.ms-pki.stl",
+        extension: "stl",
+        magic: |_| true,
+    },
+    Format {
+        content_type: "application/zip",
+        extension: "zip",
+        magic: |head| head.starts_with(b"PK\x03\x04"),
+    },
+    Format {
+        content_type: "application/x-zip-compressed",
+        extension: "zip",
+        magic: |head| head.starts_with(b"PK\x03\x04"),
+    },
+    Format {
+        content_type: "model/obj",
+        extension: "obj",
+        magic: is_text,
+    },
+    Format {
+        content_type: "text/plain",
+        extension: "obj",
+        magic: is_text,
+    },
+    // 3MF is a zip package
+    Format {
+        content_type: "model/3mf",
+        extension: "3mf",
+        magic: |head| head.starts_with(b"PK\x03\x04"),
+    },
+    Format {
+        content_type: "application/vnd.ms-package.3dmanufacturing-3dmodel+xml",
+        extension: "3mf",
+        magic: |head| head.starts_with(b"PK\x03\x04"),
+    },
+];
+
+const AUDIO: [Format; 6] = [
+    Format {
+        content_type: "audio/mpeg",
+        extension: "mp3",
+        magic: |head| {
+            head.starts_with(b"ID3")
+                || (head.len() > 1 && head[0] == 0xff && head[1] & 0xe0 == 0xe0)
+        },
+    },
+    Format {
+        content_type: "audio/ogg",
+        extension: "ogg",
+        magic: |head| head.starts_with(b"OggS"),
+    },
+    Format {
+        content_type: "audio/flac",
+        extension: "flac",
+        magic: |head| head.starts_with(b"fLaC"),
+    },
+    Format {
+        content_type: "audio/x-flac",
+        extension: "flac",
+        magic: |head| head.starts_with(b"fLaC"),
+    },
+    Format {
+        content_type: "audio/wav",
+        extension: "wav",
+        magic: |head| head.starts_with(b"RIFF") && head.get(8..12) == Some(b"WAVE"),
+    },
+    Format {
+        content_type: "audio/x-wav",
+        extension: "wav",
+        magic: |head| head.starts_with(b"RIFF") && head.get(8..12) == Some(b"WAVE"),
+    },
+];
+
+#[derive(Queryable, Selectable, Serialize)]
+#[diesel(table_name = uploads)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Upload {
+    pub id: i32,
+    pub asset_type: String,
+    pub asset_id: i32,
+    pub slot: String,
+    pub file_key: String,
+    pub content_type: String,
+    pub size: i64,
+    pub sha256: String,
+    // unix seconds
+    pub uploaded_at: i64,
+}
+
+#[derive(Insertable, AsChangeset)]
+#[diesel(table_name = uploads)]
+struct UploadNew {
+    asset_type: String,
+    asset_id: i32,
+    slot: String,
+    file_key: String,
+    content_type: String,
+    size: i64,
+    sha256: String,
+    uploaded_at: i64,
+}
+
+// A file as it arrives, checked against its rule chunk by chunk so an
+// oversized upload is refused before it is read in full. It is hashed and
+// spooled to a temporary file as it comes in rather than held in memory
+pub struct Incoming {
+    rule: UploadRule,
+    format: &'static Format,
+    // the first bytes, which the format is checked against
+    head: Vec<u8>,
+    spool: Spooled,
+    hasher: Sha256,
+}
+
+// An upload's temporary file, removed once it is dropped
+pub struct Spooled {
+    path: PathBuf,
+    file: File,
+    pub size: u64,
+}
+
+impl UploadTarget {
+    pub fn store(&self) -> &str {
+        match self {
+            Self::Book => "book",
+            Self::Stl => "stl",
+            Self::Track => "track",
+            Self::Map => "map",
+            Self::Token => "token",
+        }
+    }
+
+    pub fn table(&self) -> &str {
+        match self {
+            Self::Book => "books",
+            Self::Stl => "stls",
+            Self::Track => "tracks",
+            Self::Map => "maps",
+            Self::Token => "tokens",
+        }
+    }
+
+    // The image a row's thumbnails are made from; tracks have no thumbnail
+    pub fn thumb_source(&self) -> Option<FileSlot> {
+        match self {
+            Self::Map | Self::Token => Some(FileSlot::File),
+            Self::Book | Self::Stl => Some(FileSlot::MainImage),
+            Self::Track => None,
+        }
+    }
+
+    pub fn rule(&self, slot: FileSlot) -> Result<UploadRule, AppError> {
+        match (self, slot) {
+            (Self::Track, FileSlot::Thumb) => Err(AppError::Validation(String::from(
+                "tracks do not have a thumbnail",
+            ))),
+            (Self::Book, FileSlot::File) => Ok(UploadRule {
+                max_size: 100 * MIB,
+                formats: &DOCUMENTS,
+            }),
+            (Self::Stl, FileSlot::File) => Ok(UploadRule {
+                max_size: 250 * MIB,
+                formats: &MODELS,
+            }),
+            (Self::Track, FileSlot::File) => Ok(UploadRule {
+                max_size: 100 * MIB,
+                formats: &AUDIO,
+            }),
+            _ => Ok(UploadRule::image()),
+        }
+    }
+}
+
+impl UploadRule {
+    pub fn image() -> UploadRule {
+        UploadRule {
+            max_size: 25 * MIB,
+            formats: &IMAGES,
+        }
+    }
+
+    pub fn book(format: BookFormat) -> UploadRule {
+        match format {
+            BookFormat::Pdf | BookFormat::PrintPdf => UploadRule {
+                max_size: 100 * MIB,
+                formats: &DOCUMENTS,
+            },
+            BookFormat::Epub => UploadRule {
+                max_size: 100 * MIB,
+                formats: &EBOOKS,
+            },
+        }
+    }
+}
+
+impl FileSlot {
+    pub fn store(&self) -> &str {
+        match self {
+            Self::File => "file",
+            Self::Thumb => "thumb",
+            Self::MainImage => "main_image",
+        }
+    }
+}
+
+impl Incoming {
+    pub fn new(rule: UploadRule, content_type: &str) -> Result<Self, AppError> {
+        let format = rule
+            .formats
+            .iter()
+            .find(|format| format.content_type == content_type)
+            .ok_or_else(|| {
+                AppError::Validation(format!("{} files are not accepted here", content_type))
+            })?;
+
+        Ok(Incoming {
+            rule,
+            format,
+            head: Vec::with_capacity(HEAD),
+            spool: Spooled::new()?,
+            hasher: Sha256::new(),
+        })
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) -> Result<(), AppError> {
+        if self.spool.size as usize + chunk.len() > self.rule.max_size {
+            return Err(AppError::Validation(format!(
+                "file is larger than {} MiB",
+                self.rule.max_size / MIB
+            )));
+        }
+
+        self.hasher.update(chunk);
+        let wanted = HEAD.saturating_sub(self.head.len()).min(chunk.len());
+        self.head.extend_from_slice(&chunk[..wanted]);
+        self.spool.file.write_all(chunk)?;
+        self.spool.size += chunk.len() as u64;
+
+        Ok(())
+    }
+
+    pub fn extension(&self) -> &str {
+        self.format.extension
+    }
+
+    // Checks the complete file against its format, handing back the spooled
+    // file and its sha256
+    pub fn finish(self) -> Result<(&'static Format, Spooled, String), AppError> {
+        if self.spool.size == 0 {
+            return Err(AppError::Validation(String::from("file is empty")));
+        }
+        if !(self.format.magic)(&self.head) {
+            return Err(AppError::Validation(format!(
+                "file is not a valid {}",
+                self.format.content_type
+            )));
+        }
+
+        Ok((self.format, self.spool, hex::encode(self.hasher.finalize())))
+    }
+
+    // Reads the whole file back, for the parsers that need all of it at once
+    pub fn read(&self) -> Result<Vec<u8>, AppError> {
+        Ok(fs::read(&self.spool.path)?)
+    }
+}
+
+impl Spooled {
+    fn new() -> Result<Self, AppError> {
+        let path = env::temp_dir().join(format!("alembic-upload-{}", rand::random::<u64>()));
+        let file = File::create_new(&path)?;
+
+        Ok(Spooled {
+            path,
+            file,
+            size: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Spooled {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// Allows for the head ending part way through a character
+fn is_text(head: &[u8]) -> bool {
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(err) => err.error_len().is_none(),
+    }
+}
+
+// The content type of a stored file, going by the extension uploads are given
+pub fn content_type(key: &str) -> &'static str {
+    let extension = key.rsplit_once('.').map_or("", |(_, extension)| extension);
+
+    IMAGES
+        .iter()
+        .chain(DOCUMENTS.iter())
+        .chain(EBOOKS.iter())
+        .chain(MODELS.iter())
+        .chain(AUDIO.iter())
+        .find(|format| format.extension == extension)
+        .map_or("application/octet-stream", |format| format.content_type)
+}
+
+// Whether a stored file is one of the catalog's own images, which anyone may
+// fetch. Bought files are only ever handed out behind a signed link
+pub fn is_public_image(key: &str) -> bool {
+    let name = key.rsplit('/').next().unwrap_or(key);
+
+    PUBLIC_IMAGES.iter().any(|prefix| name.starts_with(prefix))
+        && IMAGES
+            .iter()
+            .any(|format| format.content_type == content_type(key))
+}
+
+// Stores a gallery image under a key derived from its checksum, handing back
+// the key. Gallery images are rows of their own rather than an upload slot
+pub fn store_gallery_image(
+    storage: &dyn StorageBackend,
+    table: &str,
+    a_id: i32,
+    incoming: Incoming,
+) -> Result<String, AppError> {
+    let (format, spooled, checksum) = incoming.finish()?;
+    let key = format!(
+        "{}/{}/gallery-{}.{}",
+        table,
+        a_id,
+        &checksum[..16],
+        format.extension
+    );
+    storage.put_file(&key, spooled.path())?;
+
+    Ok(key)
+}
+
+impl Upload {
+    // Stores the file under a key derived from its checksum, points the row's
+    // column at it and removes the file it replaces
+    pub fn store(
+        conn: &mut PgConnection,
+        storage: &dyn StorageBackend,
+        target: UploadTarget,
+        a_id: i32,
+        file_slot: FileSlot,
+        incoming: Incoming,
+    ) -> Result<Upload, AppError> {
+        Upload::store_with(conn, storage, target, a_id, file_slot, incoming, |_, _| {
+            Ok(())
+        })
+    }
+
+    // As store, with record run in the same transaction as the upload row so
+    // whatever it saves about the file commits or fails along with it. On
+    // failure nothing is kept, the new file included
+    pub fn store_with<F>(
+        conn: &mut PgConnection,
+        storage: &dyn StorageBackend,
+        target: UploadTarget,
+        a_id: i32,
+        file_slot: FileSlot,
+        incoming: Incoming,
+        record: F,
+    ) -> Result<Upload, AppError>
+    where
+        F: FnOnce(&mut PgConnection, &Upload) -> Result<(), AppError>,
+    {
+        let (format, spooled, checksum) = incoming.finish()?;
+        let key = format!(
+            "{}/{}/{}-{}.{}",
+            target.table(),
+            a_id,
+            file_slot.store(),
+            &checksum[..16],
+            format.extension
+        );
+        let previous = Upload::read(conn, target, a_id, file_slot).ok();
+
+        storage.put_file(&key, spooled.path())?;
+
+        let row = UploadNew {
+            asset_type: String::from(target.store()),
+            asset_id: a_id,
+            slot: String::from(file_slot.store()),
+            file_key: key.to_owned(),
+            content_type: String::from(format.content_type),
+            size: spooled.size as i64,
+            sha256: checksum,
+            uploaded_at: now(),
+        };
+
+        let stored = conn.transaction(|conn| {
+            let changes = diesel::sql_query(format!(
+                "UPDATE {} SET {} = $1 WHERE id = $2",
+                target.table(),
+                file_slot.store()
+            ))
+            .bind::<Text, _>(&key)
+            .bind::<Integer, _>(a_id)
+            .execute(conn)?;
+
+            if changes == 0 {
+                return Err(AppError::NotFound(format!("{} {}", target.store(), a_id)));
+            }
+
+            let upload = diesel::insert_into(uploads::table)
+                .values(&row)
+                .on_conflict((uploads::asset_type, uploads::asset_id, uploads::slot))
+                .do_update()
+                .set(&row)
+                .returning(Upload::as_returning())
+                .get_result(conn)?;
+            record(conn, &upload)?;
+
+            Ok(upload)
+        });
+
+        // an identical re-upload lands on the key it already had, which is kept
+        let previous_key = previous.map(|previous| previous.file_key);
+        let unused = match &stored {
+            Ok(_) => previous_key.filter(|previous_key| *previous_key != key),
+            Err(_) => Some(key.to_owned()).filter(|_| previous_key.as_deref() != Some(&key)),
+        };
+        if let Some(unused) = unused {
+            storage.delete(&unused)?;
+        }
+
+        stored
+    }
+
+    pub fn read(
+        conn: &mut PgConnection,
+        target: UploadTarget,
+        a_id: i32,
+        file_slot: FileSlot,
+    ) -> Result<Upload, AppError> {
+        use crate::schema::uploads::dsl::*;
+
+        let upload = uploads
+            .filter(asset_type.eq(target.store()))
+            .filter(asset_id.eq(a_id))
+            .filter(slot.eq(file_slot.store()))
+            .select(Upload::as_select())
+            .get_result(conn)?;
+
+        Ok(upload)
+    }
+
+    // The key the slot's file was stored under. Files are always found
+    // through here rather than through the row's own column, which is only
+    // a copy of it
+    pub fn key(
+        conn: &mut PgConnection,
+        target: UploadTarget,
+        a_id: i32,
+        file_slot: FileSlot,
+    ) -> Result<String, AppError> {
+        match Upload::read(conn, target, a_id, file_slot) {
+            Ok(upload) => Ok(upload.file_key),
+            Err(AppError::NotFound(_)) => Err(AppError::NotFound(format!(
+                "{} {} has no {}",
+                target.store(),
+                a_id,
+                file_slot.store()
+            ))),
+            Err(err) => Err(err),
+        }
+    }
+
+    // The keys of the files in the slot of each of the rows that has one
+    pub fn keys(
+        conn: &mut PgConnection,
+        target: UploadTarget,
+        a_ids: &[i32],
+        file_slot: FileSlot,
+    ) -> Result<HashMap<i32, String>, AppError> {
+        use crate::schema::uploads::dsl::*;
+
+        let keys = uploads
+            .filter(asset_type.eq(target.store()))
+            .filter(asset_id.eq_any(a_ids))
+            .filter(slot.eq(file_slot.store()))
+            .select((asset_id, file_key))
+            .get_results::<(i32, String)>(conn)?;
+
+        Ok(keys.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::book::{Book, BookCreate};
+    use crate::handlers::connect;
+    use crate::handlers::creator::{CreatorNew, Creators};
+    use crate::handlers::storage::LocalStorage;
+    use crate::handlers::user::{User, UserNew};
+    use crate::types::asset::Asset;
+    use crate::types::price::{Currency, Price};
+    use crate::types::user::DisplayName;
+
+    #[test]
+    fn upload_rules() {
+        let rule = UploadTarget::Book.rule(FileSlot::File).unwrap();
+
+        assert!(matches!(
+            Incoming::new(rule, "image/png"),
+            Err(AppError::Validation(_))
+        ));
+        assert!(matches!(
+            UploadTarget::Track.rule(FileSlot::Thumb),
+            Err(AppError::Validation(_))
+        ));
+
+        let rule = UploadRule {
+            max_size: 8,
+            formats: &IMAGES,
+        };
+        let mut incoming = Incoming::new(rule, "image/png").unwrap();
+
+        incoming.push(b"\x89PNG").unwrap();
+        incoming.push(b"\r\n\x1a\n").unwrap();
+
+        assert!(matches!(incoming.push(b"!"), Err(AppError::Validation(_))));
+        assert!((incoming.format.magic)(&incoming.head));
+        assert!(!(IMAGES[1].magic)(&incoming.head));
+        assert_eq!(incoming.read().unwrap(), b"\x89PNG\r\n\x1a\n");
+
+        let (_, spooled, checksum) = incoming.finish().unwrap();
+        let path = spooled.path().to_owned();
+
+        assert_eq!(fs::read(&path).unwrap(), b"\x89PNG\r\n\x1a\n");
+        assert_eq!(spooled.size, 8);
+        assert_eq!(checksum, hex::encode(Sha256::digest(b"\x89PNG\r\n\x1a\n")));
+
+        drop(spooled);
+        assert!(!path.exists());
+        assert!(is_text("détail".as_bytes().split_at(2).0));
+    }
+
+    #[test]
+    fn failed_records_keep_nothing() {
+        let conn = &mut connect::establish_connection();
+        let root = env::temp_dir().join(format!("alembic-uploads-{}", std::process::id()));
+        let storage = LocalStorage::new(&root);
+
+        let user = UserNew::create(
+            conn,
+            String::from("failed_records"),
+            String::from("failed_records@gmail.com"),
+            String::from("logo.svg"),
+        )
+        .unwrap();
+        let creator = CreatorNew::create(
+            conn,
+            user.id,
+            None,
+            None,
+            Some(String::from("failed_records")),
+            None,
+            DisplayName::Other,
+        )
+        .unwrap();
+        let book = BookCreate::new(
+            creator.id,
+            String::from("Half Written"),
+            String::new(),
+            String::from("Never finished"),
+            String::new(),
+            1,
+            String::new(),
+            true,
+            Price::new(0, Currency::Usd),
+        )
+        .create(conn)
+        .unwrap();
+        let pdf = |body: &[u8]| {
+            let rule = UploadTarget::Book.rule(FileSlot::File).unwrap();
+            let mut incoming = Incoming::new(rule, "application/pdf").unwrap();
+            incoming.push(b"%PDF-1.4\n").unwrap();
+            incoming.push(body).unwrap();
+            incoming
+        };
+
+        let first = Upload::store(
+            conn,
+            &storage,
+            UploadTarget::Book,
+            book.id,
+            FileSlot::File,
+            pdf(b"first"),
+        )
+        .unwrap();
+        let failed = Upload::store_with(
+            conn,
+            &storage,
+            UploadTarget::Book,
+            book.id,
+            FileSlot::File,
+            pdf(b"second"),
+            |_, _| Err(AppError::Validation(String::from("metadata refused"))),
+        );
+
+        assert!(matches!(failed, Err(AppError::Validation(_))));
+        assert_eq!(
+            Upload::key(conn, UploadTarget::Book, book.id, FileSlot::File).unwrap(),
+            first.file_key
+        );
+        assert_eq!(Book::read(conn, book.id).unwrap().file, first.file_key);
+        assert!(storage.exists(&first.file_key).unwrap());
+        assert_eq!(
+            fs::read_dir(root.join("books").join(book.id.to_string()))
+                .unwrap()
+                .count(),
+            1
+        );
+
+        diesel::delete(uploads::table.filter(uploads::id.eq(first.id)))
+            .execute(conn)
+            .unwrap();
+        Book::destroy(conn, book.id).unwrap();
+        Creators::destroy(conn, creator.id).unwrap();
+        User::destroy(conn, user.id).unwrap();
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn public_images() {
+        assert!(is_public_image("maps/1/thumb-256-0123456789abcdef.webp"));
+        assert!(is_public_image("books/1/gallery-0123456789abcdef.jpg"));
+        assert!(is_public_image("stls/1/preview-front-0123456789abcdef.png"));
+        assert!(!is_public_image("maps/1/file-0123456789abcdef.png"));
+        assert!(!is_public_image("books/1/preview-0123456789abcdef.pdf"));
+        assert!(!is_public_image("books/thumb-1/file-0123456789abcdef.png"));
+    }
+}
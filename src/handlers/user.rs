@@ -1,7 +1,10 @@
 use crate::schema::users;
+use crate::types::error::AppError;
+use crate::types::user::Role;
 use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Queryable, Selectable, Identifiable, AsChangeset)]
+#[derive(Queryable, Selectable, Identifiable, AsChangeset, Serialize, Deserialize)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct User {
     pub id: i32,
@@ -19,48 +22,81 @@ pub struct UserNew {
 }
 
 impl UserNew {
-    pub fn create(conn: &mut PgConnection, username: String, email: String, logo: String) -> User {
+    pub fn create(
+        conn: &mut PgConnection,
+        username: String,
+        email: String,
+        logo: String,
+    ) -> Result<User, AppError> {
         let user_new = UserNew {
             username,
             email,
             logo,
         };
 
-        diesel::insert_into(users::table)
+        let user = diesel::insert_into(users::table)
             .values(&user_new)
             .returning(User::as_returning())
-            .get_result(conn)
-            .expect("Error saving new post")
+            .get_result(conn)?;
+
+        Ok(user)
     }
 }
 
 impl User {
-    pub fn read(conn: &mut PgConnection, user_id: i32) -> Self {
+    pub fn read(conn: &mut PgConnection, user_id: i32) -> Result<Self, AppError> {
         use crate::schema::users::dsl::*;
 
-        users
+        let user = users
             .filter(id.eq(user_id))
             .select(User::as_select())
-            .get_result(conn)
-            .expect("Error loading posts")
+            .get_result(conn)?;
+
+        Ok(user)
     }
 
-    pub fn update(conn: &mut PgConnection, user: User) -> usize {
+    pub fn update(conn: &mut PgConnection, user: User) -> Result<usize, AppError> {
         use crate::schema::users::dsl::*;
 
-        diesel::update(users)
+        let changes = diesel::update(users)
             .filter(id.eq(user.id))
             .set(&user)
-            .execute(conn)
-            .expect("Failed to update user")
+            .execute(conn)?;
+
+        Ok(changes)
     }
 
-    pub fn destroy(conn: &mut PgConnection, user_id: i32) -> usize {
+    pub fn role(conn: &mut PgConnection, user_id: i32) -> Result<Role, AppError> {
         use crate::schema::users::dsl::*;
 
-        diesel::delete(users.filter(id.eq(user_id)))
-            .execute(conn)
-            .expect("Error deleting posts")
+        let stored = users
+            .filter(id.eq(user_id))
+            .select(role)
+            .get_result::<String>(conn)?;
+
+        Role::retrieve(&stored)
+    }
+
+    pub fn set_role(
+        conn: &mut PgConnection,
+        user_id: i32,
+        new_role: Role,
+    ) -> Result<usize, AppError> {
+        use crate::schema::users::dsl::*;
+
+        let changes = diesel::update(users.filter(id.eq(user_id)))
+            .set(role.eq(new_role.store()))
+            .execute(conn)?;
+
+        Ok(changes)
+    }
+
+    pub fn destroy(conn: &mut PgConnection, user_id: i32) -> Result<usize, AppError> {
+        use crate::schema::users::dsl::*;
+
+        let changes = diesel::delete(users.filter(id.eq(user_id))).execute(conn)?;
+
+        Ok(changes)
     }
 }
 
@@ -75,19 +111,20 @@ mod tests {
 
         let user = UserNew::create(
             conn,
-            String::from("naokotani"),
-            String::from("nao@gmail.com"),
+            String::from("user_full"),
+            String::from("user_full@gmail.com"),
             String::from("logo.svg"),
-        );
+        )
+        .unwrap();
 
-        assert_eq!(user.username, "naokotani");
-        assert_eq!(user.email, "nao@gmail.com");
+        assert_eq!(user.username, "user_full");
+        assert_eq!(user.email, "user_full@gmail.com");
         assert_eq!(user.logo, "logo.svg");
 
-        let user = User::read(conn, user.id);
+        let user = User::read(conn, user.id).unwrap();
 
-        assert_eq!(user.username, "naokotani");
-        assert_eq!(user.email, "nao@gmail.com");
+        assert_eq!(user.username, "user_full");
+        assert_eq!(user.email, "user_full@gmail.com");
         assert_eq!(user.logo, "logo.svg");
 
         let update = User::update(
@@ -98,17 +135,18 @@ mod tests {
                 email: String::from("bill@hotmail.com"),
                 logo: String::from("slick.svg"),
             },
-        );
+        )
+        .unwrap();
 
         assert_eq!(update, 1);
 
-        let user = User::read(conn, user.id);
+        let user = User::read(conn, user.id).unwrap();
 
         assert_eq!(user.username, "bob");
         assert_eq!(user.email, "bill@hotmail.com");
         assert_eq!(user.logo, "slick.svg");
 
-        let delete = User::destroy(conn, user.id);
+        let delete = User::destroy(conn, user.id).unwrap();
 
         assert_eq!(delete, 1);
     }
@@ -0,0 +1,366 @@
+use super::formats::{BookFile, BookFormat};
+use super::orders::Order;
+use super::storage::StorageBackend;
+use crate::schema::{users, watermarks};
+use crate::types::asset::AssetType;
+use crate::types::error::AppError;
+use crate::types::time::now;
+use diesel::prelude::*;
+use lopdf::content::{Content, Operation};
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream};
+use sha2::{Digest, Sha256};
+
+// The stamp's font resource; named so it cannot clash with the page's own
+const FONT: &str = "AlembicLicense";
+const FONT_SIZE: i64 = 8;
+// distance of the stamp from the bottom left corner of the page, in points
+const OFFSET: (f32, f32) = (36.0, 18.0);
+
+// A book as stamped for one customer, kept until the book's file changes
+#[derive(Queryable, Selectable, PartialEq, Debug)]
+#[diesel(table_name = watermarks)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Watermark {
+    pub id: i32,
+    pub user_id: i32,
+    pub book_id: i32,
+    pub source_key: String,
+    pub file_key: String,
+    // unix seconds
+    pub created_at: i64,
+    pub format: String,
+}
+
+#[derive(Insertable, AsChangeset)]
+#[diesel(table_name = watermarks)]
+struct WatermarkNew {
+    user_id: i32,
+    book_id: i32,
+    source_key: String,
+    file_key: String,
+    created_at: i64,
+    format: String,
+}
+
+impl Watermark {
+    // Returns the customer's stamped copy of one of the book's PDFs, stamping
+    // one when there is none yet or the book has a new file since
+    pub fn prepare(
+        conn: &mut PgConnection,
+        storage: &dyn StorageBackend,
+        b_id: i32,
+        u_id: i32,
+        format: BookFormat,
+    ) -> Result<Watermark, AppError> {
+        // the book's PDF is recorded as a format of its own when it is uploaded
+        let source = match format {
+            BookFormat::Pdf | BookFormat::PrintPdf => BookFile::read(conn, b_id, format)?.file_key,
+            BookFormat::Epub => {
+                return Err(AppError::Validation(String::from("only PDFs are stamped")))
+            }
+        };
+        let (username, email) = users::table
+            .find(u_id)
+            .select((users::username, users::email))
+            .get_result::<(String, String)>(conn)?;
+        let order = Order::purchase_of(conn, u_id, &AssetType::Book, b_id)?;
+        let notice = license(&username, &email, order);
+
+        let digest = hex::encode(Sha256::digest(format!("{}\n{}", source, notice)));
+        let key = format!("watermarks/books/{}/{}-{}.pdf", b_id, u_id, &digest[..16]);
+        let existing = watermarks::table
+            .filter(watermarks::user_id.eq(u_id))
+            .filter(watermarks::book_id.eq(b_id))
+            .filter(watermarks::format.eq(format.store()))
+            .select(Watermark::as_select())
+            .get_result(conn)
+            .optional()?;
+
+        let previous = match existing {
+            Some(existing) if existing.file_key == key => return Ok(existing),
+            existing => existing.map(|existing| existing.file_key),
+        };
+
+        storage.put(&key, &stamp(&storage.get(&source)?, &notice)?)?;
+
+        let row = WatermarkNew {
+            user_id: u_id,
+            book_id: b_id,
+            source_key: source,
+            file_key: key.to_owned(),
+            created_at: now(),
+            format: String::from(format.store()),
+        };
+        let watermark = diesel::insert_into(watermarks::table)
+            .values(&row)
+            .on_conflict((watermarks::user_id, watermarks::book_id, watermarks::format))
+            .do_update()
+            .set(&row)
+            .returning(Watermark::as_returning())
+            .get_result(conn)?;
+
+        // the copy of an earlier edition, or made before the user's details changed
+        if let Some(previous) = previous {
+            storage.delete(&previous)?;
+        }
+
+        Ok(watermark)
+    }
+
+    // Removes every customer's stamped copy of the book along with its file
+    // The stamped files are left in place and their keys returned, so the
+    // caller can delete them once its transaction has committed
+    pub fn destroy_all(conn: &mut PgConnection, b_id: i32) -> Result<Vec<String>, AppError> {
+        use crate::schema::watermarks::dsl::*;
+
+        let keys = diesel::delete(watermarks.filter(book_id.eq(b_id)))
+            .returning(file_key)
+            .get_results::<String>(conn)?;
+
+        Ok(keys)
+    }
+}
+
+pub fn license(username: &str, email: &str, order: Option<i32>) -> String {
+    match order {
+        Some(order) => format!("Licensed to {} <{}>, order #{}", username, email, order),
+        None => format!("Licensed to {} <{}>", username, email),
+    }
+}
+
+// Writes the notice in small grey type in the footer of every page. The
+// page's own drawing is wrapped in q/Q so whatever state it leaves behind
+// cannot move or hide the stamp.
+pub fn stamp(bytes: &[u8], notice: &str) -> Result<Vec<u8>, AppError> {
+    let mut document = Document::load_mem(bytes)
+        .map_err(|err| AppError::Validation(format!("book could not be stamped: {}", err)))?;
+
+    let font = document.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+        "Encoding" => "WinAnsiEncoding",
+    });
+    let save = document.add_object(Stream::new(dictionary! {}, b"q\n".to_vec()));
+
+    for page in document.get_pages().into_values() {
+        let resources = page_resources(&document, page);
+        let mut fonts = match resources.get(b"Font") {
+            Ok(fonts) => document
+                .dereference(fonts)
+                .ok()
+                .and_then(|(_, fonts)| fonts.as_dict().ok())
+                .cloned()
+                .unwrap_or_default(),
+            Err(_) => Dictionary::new(),
+        };
+        fonts.set(FONT, font);
+        let mut resources = resources;
+        resources.set("Font", fonts);
+
+        let (left, bottom) = inherited(&document, page, b"CropBox")
+            .or_else(|| inherited(&document, page, b"MediaBox"))
+            .and_then(|corner| corner_of(&document, &corner))
+            .unwrap_or((0.0, 0.0));
+        let content = Content {
+            operations: vec![
+                Operation::new("Q", vec![]),
+                Operation::new("q", vec![]),
+                Operation::new("BT", vec![]),
+                Operation::new("g", vec![0.45.into()]),
+                Operation::new("Tf", vec![FONT.into(), FONT_SIZE.into()]),
+                Operation::new(
+                    "Td",
+                    vec![(left + OFFSET.0).into(), (bottom + OFFSET.1).into()],
+                ),
+                Operation::new("Tj", vec![Object::string_literal(win_ansi(notice))]),
+                Operation::new("ET", vec![]),
+                Operation::new("Q", vec![]),
+            ],
+        };
+        let encoded = content
+            .encode()
+            .map_err(|err| AppError::Storage(format!("stamp could not be encoded: {}", err)))?;
+        let stamp = document.add_object(Stream::new(dictionary! {}, encoded));
+
+        let mut contents = vec![Object::Reference(save)];
+        contents.extend(
+            document
+                .get_page_contents(page)
+                .into_iter()
+                .map(Object::Reference),
+        );
+        contents.push(Object::Reference(stamp));
+
+        let page = document
+            .get_dictionary_mut(page)
+            .map_err(|err| AppError::Storage(err.to_string()))?;
+        page.set("Resources", resources);
+        page.set("Contents", contents);
+    }
+
+    let mut stamped = Vec::new();
+    document
+        .save_to(&mut stamped)
+        .map_err(|err| AppError::Storage(format!("stamped book could not be written: {}", err)))?;
+
+    Ok(stamped)
+}
+
+// A copy of the page's resources, which pages may inherit from the page tree
+fn page_resources(document: &Document, page: ObjectId) -> Dictionary {
+    inherited(document, page, b"Resources")
+        .and_then(|resources| {
+            document
+                .dereference(&resources)
+                .ok()
+                .and_then(|(_, resources)| resources.as_dict().ok())
+                .cloned()
+        })
+        .unwrap_or_default()
+}
+
+fn inherited(document: &Document, page: ObjectId, key: &[u8]) -> Option<Object> {
+    let mut node = document.get_dictionary(page).ok();
+    // a malformed tree may loop back on itself
+    for _ in 0..64 {
+        let current = node?;
+        if let Ok(value) = current.get(key) {
+            return Some(value.to_owned());
+        }
+        node = current
+            .get(b"Parent")
+            .and_then(Object::as_reference)
+            .and_then(|parent| document.get_dictionary(parent))
+            .ok();
+    }
+    None
+}
+
+fn corner_of(document: &Document, rectangle: &Object) -> Option<(f32, f32)> {
+    let (_, rectangle) = document.dereference(rectangle).ok()?;
+    let values = rectangle
+        .as_array()
+        .ok()?
+        .iter()
+        .map(|value| value.as_float().ok())
+        .collect::<Option<Vec<f32>>>()?;
+
+    match values.as_slice() {
+        [x1, y1, x2, y2] => Some((x1.min(*x2), y1.min(*y2))),
+        _ => None,
+    }
+}
+
+// Helvetica is written in WinAnsiEncoding, which is Latin-1 for the
+// characters that matter here
+fn win_ansi(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| match u8::try_from(c as u32) {
+            Ok(byte) if byte >= 0x20 && byte != 0x7f => byte,
+            _ => b'?',
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two pages sharing inherited resources, one with a font of its own
+    fn book() -> Vec<u8> {
+        let mut document = Document::with_version("1.5");
+        let pages = document.new_object_id();
+        let times = document.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Times-Roman",
+        });
+        let text = Content {
+            operations: vec![
+                Operation::new("BT", vec![]),
+                Operation::new("Tf", vec!["F1".into(), 12.into()]),
+                Operation::new("Td", vec![72.into(), 720.into()]),
+                Operation::new("Tj", vec![Object::string_literal("Chapter One")]),
+                Operation::new("ET", vec![]),
+                // left unbalanced on purpose
+                Operation::new("q", vec![]),
+                Operation::new(
+                    "cm",
+                    vec![0.into(), 0.into(), 0.into(), 0.into(), 0.into(), 0.into()],
+                ),
+            ],
+        };
+        let content = document.add_object(Stream::new(dictionary! {}, text.encode().unwrap()));
+        let first = document.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages,
+            "Contents" => content,
+        });
+        let second = document.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages,
+            "MediaBox" => vec![10.into(), 20.into(), 300.into(), 400.into()],
+            "Resources" => dictionary! { "Font" => dictionary! { "F2" => times } },
+        });
+        document.objects.insert(
+            pages,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![first.into(), second.into()],
+                "Count" => 2,
+                "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+                "Resources" => dictionary! { "Font" => dictionary! { "F1" => times } },
+            }),
+        );
+        let catalog = document.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages });
+        document.trailer.set("Root", catalog);
+
+        let mut bytes = Vec::new();
+        document.save_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn stamped_pages() {
+        let notice = license("naokotani", "nao@gmail.com", Some(42));
+
+        assert_eq!(notice, "Licensed to naokotani <nao@gmail.com>, order #42");
+        assert_eq!(
+            license("naokotani", "nao@gmail.com", None),
+            "Licensed to naokotani <nao@gmail.com>"
+        );
+
+        let stamped = Document::load_mem(&stamp(&book(), &notice).unwrap()).unwrap();
+        let pages = stamped.get_pages();
+
+        assert_eq!(pages.len(), 2);
+
+        for (number, page) in pages {
+            let content = String::from_utf8_lossy(&stamped.get_page_content(page)).into_owned();
+            let fonts = stamped.get_page_fonts(page).unwrap();
+
+            assert!(content.starts_with("q\n"));
+            assert!(content.contains("(Licensed to naokotani <nao@gmail.com>, order #42) Tj"));
+            assert!(fonts.contains_key(FONT.as_bytes()));
+
+            match number {
+                1 => {
+                    assert!(content.contains("Chapter One"));
+                    assert!(content.contains("36 18 Td"));
+                    assert!(fonts.contains_key(b"F1".as_slice()));
+                }
+                _ => {
+                    assert!(content.contains("46 38 Td"));
+                    assert!(fonts.contains_key(b"F2".as_slice()));
+                }
+            }
+        }
+
+        assert_eq!(win_ansi("Zoë 日本"), b"Zo\xeb ??");
+        assert!(matches!(
+            stamp(b"%PDF-1.7 not really", &notice),
+            Err(AppError::Validation(_))
+        ));
+    }
+}
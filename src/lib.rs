@@ -1,15 +1,154 @@
+pub mod types {
+    pub mod asset;
+    pub mod error;
+    pub mod order;
+    pub mod price;
+    pub mod time;
+    pub mod user;
+}
+
+pub mod handlers {
+    pub mod album;
+    pub mod archives;
+    pub mod audio;
+    pub mod auth;
+    pub mod book;
+    pub mod cart;
+    pub mod connect;
+    pub mod creator;
+    pub mod documents;
+    pub mod entitlements;
+    pub mod excerpts;
+    pub mod formats;
+    pub mod images {
+        pub mod albums;
+        pub mod books;
+        pub mod map_packs;
+        pub mod maps;
+        pub mod stls;
+        pub mod token_packs;
+    }
+    pub mod library;
+    pub mod links;
+    pub mod map;
+    pub mod models;
+    pub mod orders;
+    pub mod payments;
+    pub mod previews;
+    pub mod prices;
+    pub mod rendering;
+    pub mod sessions;
+    pub mod stl;
+    pub mod storage;
+    pub mod thumbnails;
+    pub mod tokens;
+    pub mod uploads;
+    pub mod user;
+    pub mod watermarks;
+    pub mod ownership {
+        pub mod albums;
+        pub mod books;
+        pub mod map_packs;
+        pub mod maps;
+        pub mod stls;
+        pub mod token_packs;
+        pub mod tokens;
+    }
+}
+
+pub mod middleware {
+    pub mod authorization;
+}
+
+pub mod routes {
+    pub mod album;
+    pub mod auth;
+    pub mod book;
+    pub mod cart;
+    pub mod creator;
+    pub mod downloads;
+    pub mod images;
+    pub mod library;
+    pub mod map;
+    pub mod orders;
+    pub mod pages;
+    pub mod prices;
+    pub mod stl;
+    pub mod tokens;
+    pub mod uploads;
+}
+
+mod schema;
+
+use actix_session::SessionMiddleware;
+use actix_web::cookie::Key;
 use actix_web::dev::Server;
-use actix_web::{web, App, HttpResponse, HttpServer, Responder};
+use actix_web::{web, App, HttpResponse, HttpServer};
+use handlers::archives::ArchiveCache;
+use handlers::connect::{self, DbPool};
+use handlers::links;
+use handlers::payments::{self, PaymentProvider};
+use handlers::sessions::PgSessionStore;
+use handlers::storage::{self, StorageBackend};
+use middleware::authorization::Authorization;
+use std::env;
 use std::net::TcpListener;
 
-async fn health_check() -> HttpResponse {
-    HttpResponse::Ok().finish()
+async fn health_check(pool: web::Data<DbPool>) -> HttpResponse {
+    match web::block(move || connect::is_ready(&pool)).await {
+        Ok(true) => HttpResponse::Ok().finish(),
+        _ => HttpResponse::ServiceUnavailable().finish(),
+    }
+}
+
+// Without SESSION_SECRET a random key is used and sessions do not survive a restart
+fn session_key() -> Key {
+    match env::var("SESSION_SECRET") {
+        Ok(secret) if secret.len() >= 64 => Key::from(secret.as_bytes()),
+        _ => Key::generate(),
+    }
 }
 
-pub fn run(listener: TcpListener) -> Result<Server, std::io::Error> {
-    let server = HttpServer::new(|| App::new().route("/health_check", web::get().to(health_check)))
-        .listen(listener)?
-        .run();
+pub fn run(listener: TcpListener, pool: DbPool) -> Result<Server, std::io::Error> {
+    let key = session_key();
+    let store = PgSessionStore::new(pool.clone());
+    let provider = payments::provider_from_env()
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+    let provider: web::Data<dyn PaymentProvider> = web::Data::from(provider);
+    let storage = storage::storage_from_env()
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+    let storage: web::Data<dyn StorageBackend> = web::Data::from(storage);
+    let signer = web::Data::new(links::signer_from_env());
+    let archive_cache = web::Data::new(ArchiveCache::from_env());
+    let pool = web::Data::new(pool);
+    let server = HttpServer::new(move || {
+        App::new()
+            .wrap(Authorization)
+            .wrap(SessionMiddleware::new(store.clone(), key.clone()))
+            .app_data(pool.clone())
+            .app_data(provider.clone())
+            .app_data(storage.clone())
+            .app_data(signer.clone())
+            .app_data(archive_cache.clone())
+            .route("/health_check", web::get().to(health_check))
+            .configure(routes::auth::config)
+            .configure(routes::book::config)
+            .configure(routes::cart::config)
+            .configure(routes::creator::config)
+            .configure(routes::album::config)
+            .configure(routes::map::config)
+            .configure(routes::orders::config)
+            .configure(routes::stl::config)
+            .configure(routes::tokens::config)
+            .configure(routes::images::config)
+            .configure(routes::pages::config)
+            .configure(routes::prices::config)
+            .configure(routes::downloads::config)
+            .configure(routes::library::config)
+            .configure(routes::uploads::config)
+    })
+    .listen(listener)?
+    .run();
 
     Ok(server)
 }
@@ -1,29 +1,4 @@
-#![allow(dead_code)]
-mod types {
-    pub mod asset;
-    pub mod user;
-}
-
-mod handlers {
-    pub mod album;
-    pub mod book;
-    pub mod connect;
-    pub mod creator;
-    pub mod map;
-    pub mod stl;
-    pub mod tokens;
-    pub mod user;
-    pub mod ownership {
-        pub mod albums;
-        pub mod books;
-        pub mod maps;
-        pub mod stls;
-        pub mod tokens;
-    }
-}
-
-mod schema;
-
+use alembic_head::handlers::connect;
 use alembic_head::run;
 use std::net::TcpListener;
 
@@ -31,5 +6,6 @@ use std::net::TcpListener;
 async fn main() -> std::io::Result<()> {
     let listener = TcpListener::bind("127.0.0.1:8080").expect("Failed to bind port 8080");
     let _port = listener.local_addr().unwrap().port();
-    run(listener)?.await
+    let pool = connect::establish_pool();
+    run(listener, pool)?.await
 }
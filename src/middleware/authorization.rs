@@ -0,0 +1,234 @@
+use crate::handlers::connect::DbPool;
+use crate::handlers::user::User;
+use crate::schema::{albums, books, creators, map_packs, maps, stls, token_packs, tokens, tracks};
+use crate::types::error::AppError;
+use crate::types::user::Role;
+use actix_session::SessionExt;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::Method;
+use actix_web::HttpMessage;
+use actix_web::{web, Error};
+use diesel::prelude::*;
+use serde::Deserialize;
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+
+// Collections whose writes are restricted to the owning creator or an admin
+const GUARDED: [&str; 9] = [
+    "books",
+    "albums",
+    "maps",
+    "map_packs",
+    "stls",
+    "token_packs",
+    "tokens",
+    "tracks",
+    "creators",
+];
+
+#[derive(Deserialize)]
+struct Owner {
+    creator_id: Option<i32>,
+}
+
+// The creator that owns the thing a write request touches
+enum Target {
+    Existing(i32),
+    New(Option<i32>),
+}
+
+pub struct Authorization;
+
+impl<S, B> Transform<S, ServiceRequest> for Authorization
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AuthorizationMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AuthorizationMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct AuthorizationMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for AuthorizationMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            authorize(&mut req).await?;
+            service.call(req).await
+        })
+    }
+}
+
+async fn authorize(req: &mut ServiceRequest) -> Result<(), Error> {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return Ok(());
+    }
+
+    let segments: Vec<String> = req
+        .path()
+        .trim_matches('/')
+        .split('/')
+        .map(String::from)
+        .collect();
+
+    let collection = segments[0].clone();
+    if !GUARDED.contains(&collection.as_str()) {
+        return Ok(());
+    }
+
+    let user_id = req
+        .get_session()
+        .get::<i32>("user_id")?
+        .ok_or_else(|| AppError::Unauthorized(String::from("login required")))?;
+
+    // The body is put back untouched so the handler can still extract it;
+    // uploads are left alone so they can stream past unbuffered
+    let named = if is_json(req) {
+        let body = req.extract::<web::Bytes>().await?;
+        let named = serde_json::from_slice::<Owner>(&body)
+            .ok()
+            .and_then(|owner| owner.creator_id);
+        req.set_payload(bytes_to_payload(body));
+        named
+    } else {
+        None
+    };
+
+    let target = match segments.get(1) {
+        Some(id) => Target::Existing(
+            id.parse()
+                .map_err(|_| AppError::NotFound(format!("{} {}", collection, id)))?,
+        ),
+        None => Target::New(named),
+    };
+
+    let pool = req
+        .app_data::<web::Data<DbPool>>()
+        .cloned()
+        .ok_or_else(|| AppError::Validation(String::from("database pool missing")))?;
+
+    web::block(move || {
+        let conn = &mut pool.get()?;
+        check(conn, user_id, &collection, target, named)
+    })
+    .await??;
+
+    Ok(())
+}
+
+// The same test web::Json applies, so any body a handler would read as JSON
+// is checked for the creator it names
+fn is_json(req: &ServiceRequest) -> bool {
+    match req.mime_type() {
+        Ok(Some(mime)) => mime.subtype() == "json" || mime.suffix().is_some_and(|s| s == "json"),
+        _ => false,
+    }
+}
+
+fn check(
+    conn: &mut PgConnection,
+    user_id: i32,
+    collection: &str,
+    target: Target,
+    named: Option<i32>,
+) -> Result<(), AppError> {
+    let role = User::role(conn, user_id)?;
+    let forbidden = || AppError::Forbidden(format!("{} belongs to another creator", collection));
+
+    match (role, target) {
+        (Role::Admin, _) => Ok(()),
+        // anyone signed in may open their own creator profile
+        (_, Target::New(_)) if collection == "creators" => Ok(()),
+        (Role::Customer, _) => Err(AppError::Forbidden(String::from(
+            "customers have read-only access to the catalog",
+        ))),
+        (Role::Creator, Target::New(owner)) => match owner {
+            Some(owner) if owner == user_id => Ok(()),
+            _ => Err(forbidden()),
+        },
+        (Role::Creator, Target::Existing(id)) => {
+            let owner = owner_of(conn, collection, id)?;
+            let reassigned = named.is_some_and(|named| named != user_id);
+
+            if owner == user_id && !reassigned {
+                Ok(())
+            } else {
+                Err(forbidden())
+            }
+        }
+    }
+}
+
+fn owner_of(conn: &mut PgConnection, collection: &str, asset_id: i32) -> Result<i32, AppError> {
+    let owner = match collection {
+        "books" => books::table
+            .find(asset_id)
+            .select(books::creator_id)
+            .get_result(conn),
+        "albums" => albums::table
+            .find(asset_id)
+            .select(albums::creator_id)
+            .get_result(conn),
+        "maps" => maps::table
+            .find(asset_id)
+            .select(maps::creator_id)
+            .get_result(conn),
+        "map_packs" => map_packs::table
+            .find(asset_id)
+            .select(map_packs::creator_id)
+            .get_result(conn),
+        "stls" => stls::table
+            .find(asset_id)
+            .select(stls::creator_id)
+            .get_result(conn),
+        "token_packs" => token_packs::table
+            .find(asset_id)
+            .select(token_packs::creator_id)
+            .get_result(conn),
+        "tokens" => tokens::table
+            .find(asset_id)
+            .select(tokens::creator_id)
+            .get_result(conn),
+        "tracks" => tracks::table
+            .find(asset_id)
+            .select(tracks::creator_id)
+            .get_result(conn),
+        "creators" => creators::table
+            .find(asset_id)
+            .select(creators::id)
+            .get_result(conn),
+        _ => return Err(AppError::NotFound(String::from(collection))),
+    };
+
+    owner.map_err(AppError::from)
+}
+
+fn bytes_to_payload(body: web::Bytes) -> actix_web::dev::Payload {
+    let (_, mut payload) = actix_http::h1::Payload::create(true);
+    payload.unread_data(body);
+    payload.into()
+}
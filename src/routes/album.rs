@@ -0,0 +1,115 @@
+use crate::handlers::album::{Album, AlbumCreate, TrackCreate};
+use crate::handlers::connect::DbPool;
+use crate::types::asset::Asset;
+use crate::types::error::AppError;
+use actix_web::{web, Error, HttpResponse};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct TrackForm {
+    pub title: String,
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/albums").route(web::post().to(create_album)))
+        .service(
+            web::resource("/albums/{id}")
+                .route(web::get().to(get_album))
+                .route(web::put().to(update_album))
+                .route(web::delete().to(delete_album)),
+        )
+        .service(web::resource("/albums/{id}/tracks").route(web::post().to(create_track)));
+}
+
+async fn get_album(pool: web::Data<DbPool>, path: web::Path<i32>) -> Result<HttpResponse, Error> {
+    let album_id = path.into_inner();
+
+    let album = web::block(move || {
+        let conn = &mut pool.get()?;
+        Album::read(conn, album_id)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(album))
+}
+
+async fn create_album(
+    pool: web::Data<DbPool>,
+    form: web::Json<AlbumCreate>,
+) -> Result<HttpResponse, Error> {
+    // The folder name is derived rather than taken from the body.
+    let mut form = form.into_inner();
+    form.directory = form.title.to_lowercase().trim().replace(' ', "-");
+
+    let album = web::block(move || {
+        let conn = &mut pool.get()?;
+        form.create(conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Created().json(album))
+}
+
+async fn create_track(
+    pool: web::Data<DbPool>,
+    path: web::Path<i32>,
+    form: web::Json<TrackForm>,
+) -> Result<HttpResponse, Error> {
+    let album_id = path.into_inner();
+    let form = form.into_inner();
+
+    let track = web::block(move || {
+        let conn = &mut pool.get()?;
+        let album = Album::read(conn, album_id)?;
+
+        TrackCreate::new(
+            album.creator_id,
+            album.id,
+            form.title,
+            &album.directory,
+            String::new(),
+        )
+        .create(conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Created().json(track))
+}
+
+async fn update_album(
+    pool: web::Data<DbPool>,
+    path: web::Path<i32>,
+    form: web::Json<Album>,
+) -> Result<HttpResponse, Error> {
+    let mut album = form.into_inner();
+    album.id = path.into_inner();
+
+    let album = web::block(move || {
+        let conn = &mut pool.get()?;
+        match album.update(conn)? {
+            0 => Err(AppError::NotFound(format!("album {}", album.id))),
+            _ => Album::read(conn, album.id),
+        }
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(album))
+}
+
+async fn delete_album(
+    pool: web::Data<DbPool>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, Error> {
+    let album_id = path.into_inner();
+
+    web::block(move || {
+        let conn = &mut pool.get()?;
+        match Album::destroy(conn, album_id)? {
+            0 => Err(AppError::NotFound(format!("album {}", album_id))),
+            _ => Ok(()),
+        }
+    })
+    .await??;
+
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,164 @@
+use crate::handlers::auth::{self, Registration};
+use crate::handlers::cart::CartItem;
+use crate::handlers::connect::DbPool;
+use crate::handlers::sessions::PgSessionStore;
+use crate::handlers::user::User;
+use crate::routes::cart::CART_KEY;
+use crate::types::error::AppError;
+use actix_session::{Session, SessionExt};
+use actix_web::dev::Payload;
+use actix_web::{web, Error, FromRequest, HttpRequest, HttpResponse};
+use diesel::PgConnection;
+use serde::Deserialize;
+use std::future::{ready, Ready};
+
+pub const USER_ID_KEY: &str = "user_id";
+
+#[derive(Deserialize)]
+pub struct RegisterForm {
+    pub username: String,
+    pub email: String,
+    pub password: String,
+    pub logo: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct LoginForm {
+    pub username: String,
+    pub password: String,
+}
+
+// The user id stored in the session at login; anonymous requests are rejected with 401
+pub struct AuthenticatedUser {
+    pub id: i32,
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let user_id = req.get_session().get::<i32>(USER_ID_KEY);
+
+        ready(match user_id {
+            Ok(Some(id)) => Ok(AuthenticatedUser { id }),
+            _ => Err(AppError::Unauthorized(String::from("login required")).into()),
+        })
+    }
+}
+
+// Whoever is looking at a public page; guests are id 0, which owns nothing
+pub struct Viewer {
+    pub id: i32,
+}
+
+impl FromRequest for Viewer {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let user_id = req.get_session().get::<i32>(USER_ID_KEY);
+
+        ready(match user_id {
+            Ok(id) => Ok(Viewer {
+                id: id.unwrap_or(0),
+            }),
+            Err(err) => Err(err.into()),
+        })
+    }
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/register", web::post().to(register))
+        .route("/login", web::post().to(login))
+        .route("/logout", web::post().to(logout))
+        .route("/me", web::get().to(me));
+}
+
+async fn register(
+    pool: web::Data<DbPool>,
+    session: Session,
+    form: web::Json<RegisterForm>,
+) -> Result<HttpResponse, Error> {
+    let form = form.into_inner();
+    let guest_cart = session.get::<String>(CART_KEY)?;
+
+    let user = web::block(move || {
+        let conn = &mut pool.get()?;
+        let user = Registration::new(
+            form.username,
+            form.email,
+            form.logo.unwrap_or_default(),
+            &form.password,
+        )?
+        .create(conn)?;
+
+        claim_cart(conn, guest_cart, &user)?;
+        Ok::<User, AppError>(user)
+    })
+    .await??;
+
+    start_session(&session, &user)?;
+
+    Ok(HttpResponse::Created().json(user))
+}
+
+async fn login(
+    pool: web::Data<DbPool>,
+    session: Session,
+    form: web::Json<LoginForm>,
+) -> Result<HttpResponse, Error> {
+    let form = form.into_inner();
+    let guest_cart = session.get::<String>(CART_KEY)?;
+
+    let user = web::block(move || {
+        let conn = &mut pool.get()?;
+        let user = auth::authenticate(conn, &form.username, &form.password)?;
+
+        // logins are frequent enough to keep the sessions table trimmed
+        PgSessionStore::purge_expired(conn)?;
+        claim_cart(conn, guest_cart, &user)?;
+        Ok::<User, AppError>(user)
+    })
+    .await??;
+
+    start_session(&session, &user)?;
+
+    Ok(HttpResponse::Ok().json(user))
+}
+
+async fn logout(session: Session) -> HttpResponse {
+    session.purge();
+    HttpResponse::NoContent().finish()
+}
+
+async fn me(pool: web::Data<DbPool>, user: AuthenticatedUser) -> Result<HttpResponse, Error> {
+    let user = web::block(move || {
+        let conn = &mut pool.get()?;
+        User::read(conn, user.id)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(user))
+}
+
+// Anything put in the cart before signing in carries over to the account
+fn claim_cart(
+    conn: &mut PgConnection,
+    guest_cart: Option<String>,
+    user: &User,
+) -> Result<(), AppError> {
+    if let Some(key) = guest_cart {
+        CartItem::claim(conn, &key, user.id)?;
+    }
+
+    Ok(())
+}
+
+// A fresh session key on login prevents session fixation
+fn start_session(session: &Session, user: &User) -> Result<(), Error> {
+    session.renew();
+    session.remove(CART_KEY);
+    session.insert(USER_ID_KEY, user.id)?;
+    Ok(())
+}
@@ -0,0 +1,176 @@
+use crate::handlers::book::{Book, BookCreate};
+use crate::handlers::connect::DbPool;
+use crate::handlers::excerpts::{BookPreview, PreviewRange};
+use crate::handlers::storage::StorageBackend;
+use crate::handlers::watermarks::Watermark;
+use crate::routes::downloads::serve;
+use crate::types::asset::Asset;
+use crate::types::error::AppError;
+use actix_web::http::header::{
+    ContentDisposition, DispositionParam, DispositionType, CONTENT_DISPOSITION,
+};
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use diesel::Connection;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/books").route(web::post().to(create_book)))
+        .service(
+            web::resource("/books/{id}")
+                .route(web::get().to(get_book))
+                .route(web::put().to(update_book))
+                .route(web::delete().to(delete_book)),
+        )
+        .service(
+            web::resource("/books/{id}/preview")
+                .route(web::get().to(get_preview))
+                .route(web::put().to(set_preview))
+                .route(web::delete().to(delete_preview)),
+        );
+}
+
+async fn get_book(pool: web::Data<DbPool>, path: web::Path<i32>) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+
+    let book = web::block(move || {
+        let conn = &mut pool.get()?;
+        Book::read(conn, book_id)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(book))
+}
+
+async fn create_book(
+    pool: web::Data<DbPool>,
+    form: web::Json<BookCreate>,
+) -> Result<HttpResponse, Error> {
+    let book = web::block(move || {
+        let conn = &mut pool.get()?;
+        form.create(conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Created().json(book))
+}
+
+async fn update_book(
+    pool: web::Data<DbPool>,
+    path: web::Path<i32>,
+    form: web::Json<Book>,
+) -> Result<HttpResponse, Error> {
+    let mut book = form.into_inner();
+    book.id = path.into_inner();
+
+    let book = web::block(move || {
+        let conn = &mut pool.get()?;
+        match book.update(conn)? {
+            0 => Err(AppError::NotFound(format!("book {}", book.id))),
+            _ => Book::read(conn, book.id),
+        }
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(book))
+}
+
+// Stamped copies go in the same transaction as the book, and their files
+// only once it has committed
+async fn delete_book(
+    pool: web::Data<DbPool>,
+    storage: web::Data<dyn StorageBackend>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+
+    web::block(move || {
+        let conn = &mut pool.get()?;
+        let keys = conn.transaction(|conn| {
+            let keys = Watermark::destroy_all(conn, book_id)?;
+            match Book::destroy(conn, book_id)? {
+                0 => Err(AppError::NotFound(format!("book {}", book_id))),
+                _ => Ok(keys),
+            }
+        })?;
+        for key in &keys {
+            storage.delete(key)?;
+        }
+
+        Ok::<_, AppError>(())
+    })
+    .await??;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Anyone may read the preview, so it is served without a signed link and
+// shown in the browser rather than downloaded
+async fn get_preview(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    storage: web::Data<dyn StorageBackend>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+
+    let backend = storage.clone();
+    let preview = web::block(move || {
+        let conn = &mut pool.get()?;
+        BookPreview::prepare(conn, backend.get_ref(), book_id)
+    })
+    .await??;
+
+    let filename = format!("book-{}-preview.pdf", book_id);
+    let mut response = serve(&req, storage, preview.file_key, filename.to_owned()).await?;
+    let inline = ContentDisposition {
+        disposition: DispositionType::Inline,
+        parameters: vec![DispositionParam::Filename(filename)],
+    };
+    response.headers_mut().insert(
+        CONTENT_DISPOSITION,
+        inline
+            .to_string()
+            .parse()
+            .map_err(|_| AppError::Storage(String::from("invalid preview filename")))?,
+    );
+
+    Ok(response)
+}
+
+async fn set_preview(
+    pool: web::Data<DbPool>,
+    storage: web::Data<dyn StorageBackend>,
+    path: web::Path<i32>,
+    form: web::Json<PreviewRange>,
+) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+
+    let preview = web::block(move || {
+        let conn = &mut pool.get()?;
+        BookPreview::set(conn, storage.get_ref(), book_id, form.into_inner())
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(preview))
+}
+
+async fn delete_preview(
+    pool: web::Data<DbPool>,
+    storage: web::Data<dyn StorageBackend>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+
+    web::block(move || {
+        let conn = &mut pool.get()?;
+        match BookPreview::destroy(conn, storage.get_ref(), book_id)? {
+            0 => Err(AppError::NotFound(format!(
+                "book {} has no preview",
+                book_id
+            ))),
+            _ => Ok(()),
+        }
+    })
+    .await??;
+
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,100 @@
+use crate::handlers::cart::{Cart, CartItem, CartOwner};
+use crate::handlers::connect::DbPool;
+use crate::routes::auth::USER_ID_KEY;
+use crate::types::asset::AssetType;
+use crate::types::error::AppError;
+use crate::types::price::Currency;
+use actix_session::Session;
+use actix_web::{web, Error, HttpResponse};
+use rand::distributions::{Alphanumeric, DistString};
+use serde::Deserialize;
+
+pub const CART_KEY: &str = "cart_key";
+
+#[derive(Deserialize)]
+pub struct CartItemForm {
+    pub asset_type: AssetType,
+    pub asset_id: i32,
+}
+
+#[derive(Deserialize)]
+pub struct CartQuery {
+    pub currency: Option<Currency>,
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/cart", web::get().to(get_cart))
+        .route("/cart/items", web::post().to(add_item))
+        .route("/cart/items/{id}", web::delete().to(remove_item));
+}
+
+// Guests get a random cart key in their session the first time they need one
+pub fn cart_owner(session: &Session) -> Result<CartOwner, Error> {
+    if let Some(user_id) = session.get::<i32>(USER_ID_KEY)? {
+        return Ok(CartOwner::User(user_id));
+    }
+
+    match session.get::<String>(CART_KEY)? {
+        Some(key) => Ok(CartOwner::Guest(key)),
+        None => {
+            let key = Alphanumeric.sample_string(&mut rand::thread_rng(), 64);
+            session.insert(CART_KEY, &key)?;
+            Ok(CartOwner::Guest(key))
+        }
+    }
+}
+
+async fn get_cart(
+    pool: web::Data<DbPool>,
+    session: Session,
+    query: web::Query<CartQuery>,
+) -> Result<HttpResponse, Error> {
+    let owner = cart_owner(&session)?;
+    let currency = query.into_inner().currency;
+
+    let cart = web::block(move || {
+        let conn = &mut pool.get()?;
+        Cart::read(conn, &owner, currency)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(cart))
+}
+
+async fn add_item(
+    pool: web::Data<DbPool>,
+    session: Session,
+    form: web::Json<CartItemForm>,
+) -> Result<HttpResponse, Error> {
+    let owner = cart_owner(&session)?;
+    let form = form.into_inner();
+
+    let cart = web::block(move || {
+        let conn = &mut pool.get()?;
+        CartItem::create(conn, &owner, form.asset_type, form.asset_id)?;
+        Cart::read(conn, &owner, None)
+    })
+    .await??;
+
+    Ok(HttpResponse::Created().json(cart))
+}
+
+async fn remove_item(
+    pool: web::Data<DbPool>,
+    session: Session,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, Error> {
+    let owner = cart_owner(&session)?;
+    let item_id = path.into_inner();
+
+    web::block(move || {
+        let conn = &mut pool.get()?;
+        match CartItem::destroy(conn, &owner, item_id)? {
+            0 => Err(AppError::NotFound(format!("cart item {}", item_id))),
+            _ => Ok(()),
+        }
+    })
+    .await??;
+
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,111 @@
+use crate::handlers::connect::DbPool;
+use crate::handlers::creator::{CreatorNew, Creators};
+use crate::routes::auth::AuthenticatedUser;
+use crate::types::error::AppError;
+use crate::types::user::DisplayName;
+use actix_web::{web, Error, HttpResponse};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct CreatorForm {
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub other_name: Option<String>,
+    pub publisher: Option<String>,
+    pub default_name: DisplayName,
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/creators").route(web::post().to(create_creator)))
+        .service(
+            web::resource("/creators/{id}")
+                .route(web::get().to(get_creator))
+                .route(web::put().to(update_creator))
+                .route(web::delete().to(delete_creator)),
+        );
+}
+
+async fn get_creator(pool: web::Data<DbPool>, path: web::Path<i32>) -> Result<HttpResponse, Error> {
+    let creator_id = path.into_inner();
+
+    let creator = web::block(move || {
+        let conn = &mut pool.get()?;
+        Creators::read(conn, creator_id)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(creator))
+}
+
+// The creator profile always belongs to the signed in user
+async fn create_creator(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    form: web::Json<CreatorForm>,
+) -> Result<HttpResponse, Error> {
+    let form = form.into_inner();
+
+    let creator = web::block(move || {
+        let conn = &mut pool.get()?;
+        CreatorNew::create(
+            conn,
+            user.id,
+            form.first_name,
+            form.last_name,
+            form.other_name,
+            form.publisher,
+            form.default_name,
+        )
+    })
+    .await??;
+
+    Ok(HttpResponse::Created().json(creator))
+}
+
+async fn update_creator(
+    pool: web::Data<DbPool>,
+    path: web::Path<i32>,
+    form: web::Json<CreatorForm>,
+) -> Result<HttpResponse, Error> {
+    let creator_id = path.into_inner();
+    let form = form.into_inner();
+
+    let creator = web::block(move || {
+        let conn = &mut pool.get()?;
+        let changes = Creators::update_names(
+            conn,
+            creator_id,
+            form.first_name,
+            form.last_name,
+            form.other_name,
+            form.publisher,
+            form.default_name,
+        )?;
+
+        match changes {
+            0 => Err(AppError::NotFound(format!("creator {}", creator_id))),
+            _ => Creators::read(conn, creator_id),
+        }
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(creator))
+}
+
+async fn delete_creator(
+    pool: web::Data<DbPool>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, Error> {
+    let creator_id = path.into_inner();
+
+    web::block(move || {
+        let conn = &mut pool.get()?;
+        match Creators::destroy(conn, creator_id)? {
+            0 => Err(AppError::NotFound(format!("creator {}", creator_id))),
+            _ => Ok(()),
+        }
+    })
+    .await??;
+
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,277 @@
+use crate::handlers::archives::{Archive, ArchiveCache};
+use crate::handlers::connect::DbPool;
+use crate::handlers::entitlements::{Downloadable, Entitlement};
+use crate::handlers::formats::{BookFile, BookFormat};
+use crate::handlers::links::{byte_range, ByteRange, LinkSigner};
+use crate::handlers::models::ModelFile;
+use crate::handlers::storage::StorageBackend;
+use crate::handlers::uploads::{content_type, is_public_image};
+use crate::handlers::watermarks::Watermark;
+use crate::routes::auth::AuthenticatedUser;
+use crate::types::error::AppError;
+use crate::types::time::now;
+use actix_web::body::SizedStream;
+use actix_web::http::header::HeaderValue;
+use actix_web::http::header::{
+    ContentDisposition, DispositionParam, DispositionType, ACCEPT_RANGES, CACHE_CONTROL,
+    CONTENT_DISPOSITION, CONTENT_RANGE, RANGE,
+};
+use actix_web::web::Bytes;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use futures_util::Stream;
+use serde::Deserialize;
+use std::io;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+// Stored files are sent this much at a time
+const CHUNK: u64 = 1024 * 1024;
+
+#[derive(Deserialize)]
+pub struct DownloadQuery {
+    pub format: Option<BookFormat>,
+}
+
+#[derive(Deserialize)]
+pub struct LinkQuery {
+    pub expires: i64,
+    pub signature: String,
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/downloads/{kind}/{id}", web::get().to(get_download))
+        .route("/files/{key:.*}", web::get().to(get_file))
+        .route("/archives/{kind}/{id}", web::get().to(get_archive))
+        .route("/images/{key:.*}", web::get().to(get_image));
+}
+
+// Checks the user may have the item, then hands out a short lived link to it
+async fn get_download(
+    pool: web::Data<DbPool>,
+    storage: web::Data<dyn StorageBackend>,
+    signer: web::Data<LinkSigner>,
+    user: AuthenticatedUser,
+    path: web::Path<(Downloadable, i32)>,
+    query: web::Query<DownloadQuery>,
+) -> Result<HttpResponse, Error> {
+    let (kind, item_id) = path.into_inner();
+    let format = match (kind, query.format) {
+        (Downloadable::Book, format) => format.unwrap_or(BookFormat::Pdf),
+        (_, None) => BookFormat::Pdf,
+        (_, Some(_)) => {
+            return Err(AppError::Validation(String::from("only books come in formats")).into())
+        }
+    };
+
+    let (entitlement, has_models) = web::block(move || {
+        let conn = &mut pool.get()?;
+        let mut entitlement = Entitlement::require(conn, kind, item_id, user.id)?;
+        // a model with more than one file is downloaded as all of them
+        let has_models = kind == Downloadable::Stl && ModelFile::list(conn, item_id)?.len() > 1;
+        // PDFs go out stamped with who bought them, EPUBs as they were uploaded
+        if kind == Downloadable::Book {
+            entitlement.file = Some(match format {
+                BookFormat::Epub => BookFile::read(conn, item_id, format)?.file_key,
+                _ => Watermark::prepare(conn, &**storage, item_id, user.id, format)?.file_key,
+            });
+        }
+        Ok::<(Entitlement, bool), AppError>((entitlement, has_models))
+    })
+    .await??;
+
+    let link = match kind {
+        Downloadable::Album | Downloadable::MapPack | Downloadable::TokenPack => {
+            signer.sign_archive(kind, item_id, now())
+        }
+        Downloadable::Stl if has_models => signer.sign_archive(kind, item_id, now()),
+        _ => {
+            let file = entitlement.file.ok_or_else(|| {
+                AppError::NotFound(format!("{} {} has no file", kind.store(), item_id))
+            })?;
+            signer.sign(&file, now())?
+        }
+    };
+
+    Ok(HttpResponse::Ok().json(link))
+}
+
+async fn get_file(
+    req: HttpRequest,
+    storage: web::Data<dyn StorageBackend>,
+    signer: web::Data<LinkSigner>,
+    path: web::Path<String>,
+    query: web::Query<LinkQuery>,
+) -> Result<HttpResponse, Error> {
+    let key = path.into_inner();
+    signer.verify(
+        &format!("/files/{}", key),
+        query.expires,
+        &query.signature,
+        now(),
+    )?;
+
+    let filename = key.rsplit('/').next().unwrap_or(&key).to_owned();
+
+    serve(&req, storage, key, filename).await
+}
+
+// Thumbnails, gallery images and previews need no link, and are shown in the
+// browser rather than downloaded
+async fn get_image(
+    req: HttpRequest,
+    storage: web::Data<dyn StorageBackend>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    let key = path.into_inner();
+    if !is_public_image(&key) {
+        return Err(AppError::NotFound(format!("image {}", key)).into());
+    }
+
+    let filename = key.rsplit('/').next().unwrap_or(&key).to_owned();
+    let mut response = serve(&req, storage, key, filename.to_owned()).await?;
+    let inline = ContentDisposition {
+        disposition: DispositionType::Inline,
+        parameters: vec![DispositionParam::Filename(filename)],
+    };
+    let headers = response.headers_mut();
+    headers.insert(
+        CONTENT_DISPOSITION,
+        inline
+            .to_string()
+            .parse()
+            .map_err(|_| AppError::Storage(String::from("invalid image filename")))?,
+    );
+    // keys carry the image's checksum, so a key never changes what it points at
+    headers.insert(
+        CACHE_CONTROL,
+        HeaderValue::from_static("public, max-age=31536000, immutable"),
+    );
+
+    Ok(response)
+}
+
+async fn get_archive(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    storage: web::Data<dyn StorageBackend>,
+    signer: web::Data<LinkSigner>,
+    cache: web::Data<ArchiveCache>,
+    path: web::Path<(Downloadable, i32)>,
+    query: web::Query<LinkQuery>,
+) -> Result<HttpResponse, Error> {
+    let (kind, item_id) = path.into_inner();
+    signer.verify(
+        &format!("/archives/{}/{}", kind.store(), item_id),
+        query.expires,
+        &query.signature,
+        now(),
+    )?;
+
+    let cache = **cache;
+    let backend = storage.clone();
+    let (archive, cached) = web::block(move || {
+        let conn = &mut pool.get()?;
+        let archive = Archive::read(conn, kind, item_id)?;
+        let cached = cache.enabled && archive.is_cached(&**backend)?;
+        Ok::<(Archive, bool), AppError>((archive, cached))
+    })
+    .await??;
+
+    if cached {
+        let key = archive.cache_key();
+        return serve(&req, storage, key, archive.filename).await;
+    }
+
+    // built as it is sent, so its length and ranges are not known up front
+    Ok(HttpResponse::Ok()
+        .content_type("application/zip")
+        .insert_header(attachment(archive.filename.to_owned()))
+        .streaming(archive.stream(storage.into_inner(), cache)))
+}
+
+// Sends a stored file, honouring a single byte range. The file is read a
+// chunk at a time as it is sent rather than all at once
+pub async fn serve(
+    req: &HttpRequest,
+    storage: web::Data<dyn StorageBackend>,
+    key: String,
+    filename: String,
+) -> Result<HttpResponse, Error> {
+    let range = req
+        .headers()
+        .get(RANGE)
+        .and_then(|range| range.to_str().ok())
+        .map(String::from);
+    let file = key.to_owned();
+    let backend = storage.clone();
+
+    let (size, range) = web::block(move || {
+        let size = backend.size(&file)?;
+        Ok::<(u64, ByteRange), AppError>((size, byte_range(range.as_deref(), size)))
+    })
+    .await??;
+
+    let (mut response, start, end) = match range {
+        ByteRange::Full => (HttpResponse::Ok(), 0, size),
+        ByteRange::Partial(start, end) => {
+            let mut partial = HttpResponse::PartialContent();
+            partial.insert_header((
+                CONTENT_RANGE,
+                format!("bytes {}-{}/{}", start, end - 1, size),
+            ));
+            (partial, start, end)
+        }
+        ByteRange::Unsatisfiable => {
+            return Ok(HttpResponse::RangeNotSatisfiable()
+                .insert_header((CONTENT_RANGE, format!("bytes */{}", size)))
+                .finish())
+        }
+    };
+
+    Ok(response
+        .content_type(content_type(&key))
+        .insert_header((ACCEPT_RANGES, "bytes"))
+        .insert_header(attachment(filename))
+        .body(SizedStream::new(
+            end - start,
+            chunks(storage.into_inner(), key, start, end),
+        )))
+}
+
+// Reads bytes start..end of a stored file on a blocking thread, handing them
+// over a chunk at a time
+fn chunks(
+    storage: Arc<dyn StorageBackend>,
+    key: String,
+    start: u64,
+    end: u64,
+) -> impl Stream<Item = Result<Bytes, io::Error>> {
+    let (sender, mut receiver) = mpsc::channel(4);
+
+    actix_web::rt::task::spawn_blocking(move || {
+        let mut offset = start;
+        while offset < end {
+            let next = end.min(offset + CHUNK);
+            let chunk = storage
+                .get_range(&key, offset, next)
+                .map(Bytes::from)
+                .map_err(|err| io::Error::other(err.to_string()));
+            let failed = chunk.is_err();
+
+            // the receiver is gone once the client disconnects
+            if sender.blocking_send(chunk).is_err() || failed {
+                return;
+            }
+            offset = next;
+        }
+    });
+
+    futures_util::stream::poll_fn(move |cx| receiver.poll_recv(cx))
+}
+
+fn attachment(filename: String) -> ContentDisposition {
+    ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![DispositionParam::Filename(filename)],
+    }
+}
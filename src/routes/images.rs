@@ -0,0 +1,138 @@
+use crate::handlers::connect::DbPool;
+use crate::handlers::storage::StorageBackend;
+use crate::handlers::uploads::{store_gallery_image, UploadRule};
+use crate::routes::uploads::read_file;
+use crate::types::asset::AssetType;
+use crate::types::error::AppError;
+use actix_multipart::Multipart;
+use actix_web::{web, Error, HttpResponse};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct ImageForm {
+    pub file: String,
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/{collection}/{id}/images")
+            .route(web::get().to(list_images))
+            .route(web::post().to(add_image))
+            .route(web::put().to(reorder_images)),
+    )
+    .service(web::resource("/{collection}/{id}/images/upload").route(web::post().to(upload_image)))
+    .service(
+        web::resource("/{collection}/{id}/images/{image_id}").route(web::delete().to(remove_image)),
+    );
+}
+
+// Galleries hang off the same collection paths as the assets themselves
+fn gallery_type(collection: &str) -> Result<AssetType, AppError> {
+    match collection {
+        "books" => Ok(AssetType::Book),
+        "albums" => Ok(AssetType::Album),
+        "maps" => Ok(AssetType::Map),
+        "map_packs" => Ok(AssetType::MapPack),
+        "stls" => Ok(AssetType::Stl),
+        "token_packs" => Ok(AssetType::TokenPack),
+        _ => Err(AppError::NotFound(format!("no gallery for {}", collection))),
+    }
+}
+
+async fn list_images(
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, i32)>,
+) -> Result<HttpResponse, Error> {
+    let (collection, asset_id) = path.into_inner();
+    let asset_type = gallery_type(&collection)?;
+
+    let images = web::block(move || {
+        let conn = &mut pool.get()?;
+        asset_type.gallery(conn, asset_id)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(images))
+}
+
+async fn add_image(
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, i32)>,
+    form: web::Json<ImageForm>,
+) -> Result<HttpResponse, Error> {
+    let (collection, asset_id) = path.into_inner();
+    let asset_type = gallery_type(&collection)?;
+    let form = form.into_inner();
+
+    let image = web::block(move || {
+        let conn = &mut pool.get()?;
+        asset_type.add_image(conn, asset_id, form.file)
+    })
+    .await??;
+
+    Ok(HttpResponse::Created().json(image))
+}
+
+// Expects a multipart body whose first part is the image. It is stored where
+// the public image route serves it from and added to the end of the gallery
+async fn upload_image(
+    pool: web::Data<DbPool>,
+    storage: web::Data<dyn StorageBackend>,
+    path: web::Path<(String, i32)>,
+    mut payload: Multipart,
+) -> Result<HttpResponse, Error> {
+    let (collection, asset_id) = path.into_inner();
+    let asset_type = gallery_type(&collection)?;
+    let (incoming, _) = read_file(&mut payload, UploadRule::image()).await?;
+
+    let image = web::block(move || {
+        let conn = &mut pool.get()?;
+        let key = store_gallery_image(storage.get_ref(), &collection, asset_id, incoming)?;
+        asset_type
+            .add_image(conn, asset_id, key.to_owned())
+            .or_else(|err| {
+                storage.delete(&key)?;
+                Err(err)
+            })
+    })
+    .await??;
+
+    Ok(HttpResponse::Created().json(image))
+}
+
+async fn reorder_images(
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, i32)>,
+    order: web::Json<Vec<i32>>,
+) -> Result<HttpResponse, Error> {
+    let (collection, asset_id) = path.into_inner();
+    let asset_type = gallery_type(&collection)?;
+
+    let images = web::block(move || {
+        let conn = &mut pool.get()?;
+        asset_type.reorder_images(conn, asset_id, &order)?;
+        asset_type.gallery(conn, asset_id)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(images))
+}
+
+async fn remove_image(
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, i32, i32)>,
+) -> Result<HttpResponse, Error> {
+    let (collection, asset_id, image_id) = path.into_inner();
+    let asset_type = gallery_type(&collection)?;
+
+    web::block(move || {
+        let conn = &mut pool.get()?;
+        match asset_type.remove_image(conn, asset_id, image_id)? {
+            0 => Err(AppError::NotFound(format!("image {}", image_id))),
+            _ => Ok(()),
+        }
+    })
+    .await??;
+
+    Ok(HttpResponse::NoContent().finish())
+}
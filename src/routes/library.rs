@@ -0,0 +1,44 @@
+use crate::handlers::connect::DbPool;
+use crate::handlers::library::{Library, LibrarySort};
+use crate::routes::auth::AuthenticatedUser;
+use crate::types::asset::AssetType;
+use actix_web::{web, Error, HttpResponse};
+use serde::Deserialize;
+
+const DEFAULT_PER_PAGE: i64 = 20;
+
+#[derive(Deserialize)]
+pub struct LibraryQuery {
+    pub asset_type: Option<AssetType>,
+    #[serde(default)]
+    pub sort: LibrarySort,
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/library", web::get().to(get_library));
+}
+
+async fn get_library(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    query: web::Query<LibraryQuery>,
+) -> Result<HttpResponse, Error> {
+    let query = query.into_inner();
+
+    let library = web::block(move || {
+        let conn = &mut pool.get()?;
+        Library::read(
+            conn,
+            user.id,
+            query.asset_type,
+            query.sort,
+            query.page.unwrap_or(1),
+            query.per_page.unwrap_or(DEFAULT_PER_PAGE),
+        )
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(library))
+}
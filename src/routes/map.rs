@@ -0,0 +1,130 @@
+use crate::handlers::connect::DbPool;
+use crate::handlers::map::{MapCreate, MapPack, MapPackCreate};
+use crate::types::asset::Asset;
+use crate::types::error::AppError;
+use crate::types::price::Price;
+use actix_web::{web, Error, HttpResponse};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct MapForm {
+    pub title: String,
+    pub summary: String,
+    pub height: Option<i32>,
+    pub width: Option<i32>,
+    pub is_free: bool,
+    pub price: Option<Price>,
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/map_packs").route(web::post().to(create_map_pack)))
+        .service(
+            web::resource("/map_packs/{id}")
+                .route(web::get().to(get_map_pack))
+                .route(web::put().to(update_map_pack))
+                .route(web::delete().to(delete_map_pack)),
+        )
+        .service(web::resource("/map_packs/{id}/maps").route(web::post().to(create_map)));
+}
+
+async fn get_map_pack(
+    pool: web::Data<DbPool>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, Error> {
+    let pack_id = path.into_inner();
+
+    let map_pack = web::block(move || {
+        let conn = &mut pool.get()?;
+        MapPack::read(conn, pack_id)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(map_pack))
+}
+
+async fn create_map_pack(
+    pool: web::Data<DbPool>,
+    form: web::Json<MapPackCreate>,
+) -> Result<HttpResponse, Error> {
+    // The folder name is derived rather than taken from the body.
+    let mut form = form.into_inner();
+    form.directory = form.title.to_lowercase().trim().replace(' ', "-");
+
+    let map_pack = web::block(move || {
+        let conn = &mut pool.get()?;
+        form.create(conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Created().json(map_pack))
+}
+
+async fn create_map(
+    pool: web::Data<DbPool>,
+    path: web::Path<i32>,
+    form: web::Json<MapForm>,
+) -> Result<HttpResponse, Error> {
+    let pack_id = path.into_inner();
+    let form = form.into_inner();
+
+    let map = web::block(move || {
+        let conn = &mut pool.get()?;
+        let map_pack = MapPack::read(conn, pack_id)?;
+
+        MapCreate::new(
+            map_pack.creator_id,
+            map_pack.id,
+            form.title,
+            String::new(),
+            form.summary,
+            form.height,
+            form.width,
+            &map_pack.directory,
+            String::new(),
+            form.is_free,
+            form.price,
+        )
+        .create(conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Created().json(map))
+}
+
+async fn update_map_pack(
+    pool: web::Data<DbPool>,
+    path: web::Path<i32>,
+    form: web::Json<MapPack>,
+) -> Result<HttpResponse, Error> {
+    let mut map_pack = form.into_inner();
+    map_pack.id = path.into_inner();
+
+    let map_pack = web::block(move || {
+        let conn = &mut pool.get()?;
+        match map_pack.update(conn)? {
+            0 => Err(AppError::NotFound(format!("map pack {}", map_pack.id))),
+            _ => MapPack::read(conn, map_pack.id),
+        }
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(map_pack))
+}
+
+async fn delete_map_pack(
+    pool: web::Data<DbPool>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, Error> {
+    let pack_id = path.into_inner();
+
+    web::block(move || {
+        let conn = &mut pool.get()?;
+        match MapPack::destroy(conn, pack_id)? {
+            0 => Err(AppError::NotFound(format!("map pack {}", pack_id))),
+            _ => Ok(()),
+        }
+    })
+    .await??;
+
+    Ok(HttpResponse::NoContent().finish())
+}
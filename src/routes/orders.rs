@@ -0,0 +1,65 @@
+use crate::handlers::connect::DbPool;
+use crate::handlers::orders::Order;
+use crate::handlers::payments::PaymentProvider;
+use crate::routes::auth::AuthenticatedUser;
+use crate::types::price::Currency;
+use actix_web::{web, Error, HttpResponse};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct CheckoutForm {
+    pub currency: Currency,
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/checkout", web::post().to(checkout))
+        .route("/orders", web::get().to(list_orders))
+        .route("/orders/{id}", web::get().to(get_order));
+}
+
+async fn checkout(
+    pool: web::Data<DbPool>,
+    provider: web::Data<dyn PaymentProvider>,
+    user: AuthenticatedUser,
+    form: web::Json<CheckoutForm>,
+) -> Result<HttpResponse, Error> {
+    let currency = form.into_inner().currency;
+
+    let order = web::block(move || {
+        let conn = &mut pool.get()?;
+        Order::checkout(conn, user.id, currency, provider.get_ref())
+    })
+    .await??;
+
+    Ok(HttpResponse::Created().json(order))
+}
+
+async fn list_orders(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let orders = web::block(move || {
+        let conn = &mut pool.get()?;
+        Order::list(conn, user.id)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(orders))
+}
+
+// Orders of other users are reported as missing
+async fn get_order(
+    pool: web::Data<DbPool>,
+    user: AuthenticatedUser,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, Error> {
+    let order_id = path.into_inner();
+
+    let order = web::block(move || {
+        let conn = &mut pool.get()?;
+        Order::read(conn, user.id, order_id)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(order))
+}
@@ -0,0 +1,40 @@
+use crate::handlers::connect::DbPool;
+use crate::routes::auth::Viewer;
+use crate::types::asset::AssetType;
+use crate::types::error::AppError;
+use actix_web::{web, Error, HttpResponse};
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/{collection}/{id}/page", web::get().to(get_page));
+}
+
+// Single maps and tokens are shown on their pack's page
+fn page_type(collection: &str) -> Result<AssetType, AppError> {
+    match collection {
+        "books" => Ok(AssetType::Book),
+        "albums" => Ok(AssetType::Album),
+        "map_packs" => Ok(AssetType::MapPack),
+        "stls" => Ok(AssetType::Stl),
+        "token_packs" => Ok(AssetType::TokenPack),
+        _ => Err(AppError::NotFound(format!("no pages for {}", collection))),
+    }
+}
+
+// The product page: gallery, pricing, ownership for whoever is looking, and
+// whatever was worked out from the asset's files
+async fn get_page(
+    pool: web::Data<DbPool>,
+    viewer: Viewer,
+    path: web::Path<(String, i32)>,
+) -> Result<HttpResponse, Error> {
+    let (collection, asset_id) = path.into_inner();
+    let asset_type = page_type(&collection)?;
+
+    let page = web::block(move || {
+        let conn = &mut pool.get()?;
+        asset_type.paginate(conn, asset_id, viewer.id)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(page))
+}
@@ -0,0 +1,95 @@
+use crate::handlers::connect::DbPool;
+use crate::handlers::prices::AssetPrice;
+use crate::types::asset::AssetType;
+use crate::types::error::AppError;
+use crate::types::price::{Currency, Price};
+use actix_web::{web, Error, HttpResponse};
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/{collection}/{id}/prices")
+            .route(web::get().to(get_pricing))
+            .route(web::put().to(set_price)),
+    )
+    .service(
+        web::resource("/{collection}/{id}/prices/{currency}").route(web::delete().to(remove_price)),
+    );
+}
+
+// Only assets that are sold on their own carry a price
+fn priced_type(collection: &str) -> Result<AssetType, AppError> {
+    match collection {
+        "books" => Ok(AssetType::Book),
+        "albums" => Ok(AssetType::Album),
+        "maps" => Ok(AssetType::Map),
+        "map_packs" => Ok(AssetType::MapPack),
+        "stls" => Ok(AssetType::Stl),
+        "token_packs" => Ok(AssetType::TokenPack),
+        "tokens" => Ok(AssetType::Token),
+        _ => Err(AppError::NotFound(format!("no prices for {}", collection))),
+    }
+}
+
+async fn get_pricing(
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, i32)>,
+) -> Result<HttpResponse, Error> {
+    let (collection, asset_id) = path.into_inner();
+    let asset_type = priced_type(&collection)?;
+
+    let pricing = web::block(move || {
+        let conn = &mut pool.get()?;
+        asset_type.pricing(conn, asset_id)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(pricing))
+}
+
+async fn set_price(
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, i32)>,
+    form: web::Json<Price>,
+) -> Result<HttpResponse, Error> {
+    let (collection, asset_id) = path.into_inner();
+    let asset_type = priced_type(&collection)?;
+    let price = form.into_inner();
+
+    let pricing = web::block(move || {
+        let conn = &mut pool.get()?;
+        let current = asset_type.pricing(conn, asset_id)?;
+
+        if current.price.currency == price.currency {
+            return Err(AppError::Validation(format!(
+                "{} is the asset's own currency, update its price instead",
+                price.currency.store()
+            )));
+        }
+
+        AssetPrice::set(conn, &asset_type, asset_id, price)?;
+        asset_type.pricing(conn, asset_id)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(pricing))
+}
+
+async fn remove_price(
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, i32, String)>,
+) -> Result<HttpResponse, Error> {
+    let (collection, asset_id, code) = path.into_inner();
+    let asset_type = priced_type(&collection)?;
+    let currency = Currency::retrieve(&code)?;
+
+    web::block(move || {
+        let conn = &mut pool.get()?;
+        match AssetPrice::destroy(conn, &asset_type, asset_id, currency)? {
+            0 => Err(AppError::NotFound(format!("{} price", code))),
+            _ => Ok(()),
+        }
+    })
+    .await??;
+
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,75 @@
+use crate::handlers::connect::DbPool;
+use crate::handlers::stl::{Stl, StlCreate};
+use crate::types::asset::Asset;
+use crate::types::error::AppError;
+use actix_web::{web, Error, HttpResponse};
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/stls").route(web::post().to(create_stl)))
+        .service(
+            web::resource("/stls/{id}")
+                .route(web::get().to(get_stl))
+                .route(web::put().to(update_stl))
+                .route(web::delete().to(delete_stl)),
+        );
+}
+
+async fn get_stl(pool: web::Data<DbPool>, path: web::Path<i32>) -> Result<HttpResponse, Error> {
+    let stl_id = path.into_inner();
+
+    let stl = web::block(move || {
+        let conn = &mut pool.get()?;
+        Stl::read(conn, stl_id)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(stl))
+}
+
+async fn create_stl(
+    pool: web::Data<DbPool>,
+    form: web::Json<StlCreate>,
+) -> Result<HttpResponse, Error> {
+    let stl = web::block(move || {
+        let conn = &mut pool.get()?;
+        form.create(conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Created().json(stl))
+}
+
+async fn update_stl(
+    pool: web::Data<DbPool>,
+    path: web::Path<i32>,
+    form: web::Json<Stl>,
+) -> Result<HttpResponse, Error> {
+    let mut stl = form.into_inner();
+    stl.id = path.into_inner();
+
+    let stl = web::block(move || {
+        let conn = &mut pool.get()?;
+        match stl.update(conn)? {
+            0 => Err(AppError::NotFound(format!("stl {}", stl.id))),
+            _ => Stl::read(conn, stl.id),
+        }
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(stl))
+}
+
+async fn delete_stl(pool: web::Data<DbPool>, path: web::Path<i32>) -> Result<HttpResponse, Error> {
+    let stl_id = path.into_inner();
+
+    web::block(move || {
+        let conn = &mut pool.get()?;
+        match Stl::destroy(conn, stl_id)? {
+            0 => Err(AppError::NotFound(format!("stl {}", stl_id))),
+            _ => Ok(()),
+        }
+    })
+    .await??;
+
+    Ok(HttpResponse::NoContent().finish())
+}
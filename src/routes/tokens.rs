@@ -0,0 +1,130 @@
+use crate::handlers::connect::DbPool;
+use crate::handlers::tokens::{TokenCreate, TokenPack, TokenPackCreate};
+use crate::types::asset::Asset;
+use crate::types::error::AppError;
+use crate::types::price::Price;
+use actix_web::{web, Error, HttpResponse};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct TokenForm {
+    pub title: String,
+    pub summary: String,
+    pub height: Option<i32>,
+    pub width: Option<i32>,
+    pub is_free: bool,
+    pub price: Option<Price>,
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/token_packs").route(web::post().to(create_token_pack)))
+        .service(
+            web::resource("/token_packs/{id}")
+                .route(web::get().to(get_token_pack))
+                .route(web::put().to(update_token_pack))
+                .route(web::delete().to(delete_token_pack)),
+        )
+        .service(web::resource("/token_packs/{id}/tokens").route(web::post().to(create_token)));
+}
+
+async fn get_token_pack(
+    pool: web::Data<DbPool>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, Error> {
+    let pack_id = path.into_inner();
+
+    let token_pack = web::block(move || {
+        let conn = &mut pool.get()?;
+        TokenPack::read(conn, pack_id)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(token_pack))
+}
+
+async fn create_token_pack(
+    pool: web::Data<DbPool>,
+    form: web::Json<TokenPackCreate>,
+) -> Result<HttpResponse, Error> {
+    // The folder name is derived rather than taken from the body.
+    let mut form = form.into_inner();
+    form.directory = form.title.to_lowercase().trim().replace(' ', "-");
+
+    let token_pack = web::block(move || {
+        let conn = &mut pool.get()?;
+        form.create(conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Created().json(token_pack))
+}
+
+async fn create_token(
+    pool: web::Data<DbPool>,
+    path: web::Path<i32>,
+    form: web::Json<TokenForm>,
+) -> Result<HttpResponse, Error> {
+    let pack_id = path.into_inner();
+    let form = form.into_inner();
+
+    let token = web::block(move || {
+        let conn = &mut pool.get()?;
+        let token_pack = TokenPack::read(conn, pack_id)?;
+
+        TokenCreate::new(
+            token_pack.creator_id,
+            token_pack.id,
+            form.title,
+            String::new(),
+            form.summary,
+            form.height,
+            form.width,
+            &token_pack.directory,
+            String::new(),
+            form.is_free,
+            form.price,
+        )
+        .create(conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Created().json(token))
+}
+
+async fn update_token_pack(
+    pool: web::Data<DbPool>,
+    path: web::Path<i32>,
+    form: web::Json<TokenPack>,
+) -> Result<HttpResponse, Error> {
+    let mut token_pack = form.into_inner();
+    token_pack.id = path.into_inner();
+
+    let token_pack = web::block(move || {
+        let conn = &mut pool.get()?;
+        match token_pack.update(conn)? {
+            0 => Err(AppError::NotFound(format!("token pack {}", token_pack.id))),
+            _ => TokenPack::read(conn, token_pack.id),
+        }
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(token_pack))
+}
+
+async fn delete_token_pack(
+    pool: web::Data<DbPool>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, Error> {
+    let pack_id = path.into_inner();
+
+    web::block(move || {
+        let conn = &mut pool.get()?;
+        match TokenPack::destroy(conn, pack_id)? {
+            0 => Err(AppError::NotFound(format!("token pack {}", pack_id))),
+            _ => Ok(()),
+        }
+    })
+    .await??;
+
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,359 @@
+use crate::handlers::audio::{AudioFormat, AudioInfo, TrackMetadata};
+use crate::handlers::connect::DbPool;
+use crate::handlers::documents::{BookMetadata, PdfInfo};
+use crate::handlers::formats::{BookFile, BookFormat, FormatDetails};
+use crate::handlers::models::{ModelFile, ModelListing};
+use crate::handlers::previews::Preview;
+use crate::handlers::storage::StorageBackend;
+use crate::handlers::thumbnails::Thumbnail;
+use crate::handlers::uploads::{FileSlot, Incoming, Upload, UploadRule, UploadTarget};
+use crate::types::error::AppError;
+use actix_multipart::Multipart;
+use actix_web::{web, Error, HttpResponse};
+use diesel::PgConnection;
+use futures_util::TryStreamExt;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct ModelQuery {
+    pub supported: Option<bool>,
+    pub label: Option<String>,
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route(
+        "/{collection}/{id}/files/{slot}",
+        web::put().to(upload_file),
+    )
+    .route(
+        "/{collection}/{id}/thumbnails",
+        web::get().to(get_thumbnails),
+    )
+    .route("/books/{id}/formats", web::get().to(get_formats))
+    .route("/books/{id}/formats/{format}", web::put().to(upload_format))
+    .route(
+        "/books/{id}/formats/{format}",
+        web::delete().to(delete_format),
+    )
+    .route("/stls/{id}/models", web::get().to(get_models))
+    .route("/stls/{id}/models", web::post().to(add_model))
+    .route(
+        "/stls/{id}/models/{model_id}",
+        web::delete().to(delete_model),
+    );
+}
+
+fn upload_target(collection: &str) -> Result<UploadTarget, AppError> {
+    match collection {
+        "books" => Ok(UploadTarget::Book),
+        "stls" => Ok(UploadTarget::Stl),
+        "tracks" => Ok(UploadTarget::Track),
+        "maps" => Ok(UploadTarget::Map),
+        "tokens" => Ok(UploadTarget::Token),
+        _ => Err(AppError::NotFound(format!("no uploads for {}", collection))),
+    }
+}
+
+// Expects a multipart body whose first part is the file
+async fn upload_file(
+    pool: web::Data<DbPool>,
+    storage: web::Data<dyn StorageBackend>,
+    path: web::Path<(String, i32, FileSlot)>,
+    mut payload: Multipart,
+) -> Result<HttpResponse, Error> {
+    let (collection, asset_id, slot) = path.into_inner();
+    let target = upload_target(&collection)?;
+    let (incoming, _) = read_file(&mut payload, target.rule(slot)?).await?;
+
+    let backend = storage.clone();
+    let db = pool.clone();
+    let upload = web::block(move || {
+        let conn = &mut db.get()?;
+        // files are read first so one that does not parse is refused
+        match (target, slot) {
+            (UploadTarget::Stl, FileSlot::File) => {
+                let inspected = ModelFile::inspect(&incoming)?;
+                let previous = Upload::read(conn, target, asset_id, slot)
+                    .ok()
+                    .map(|previous| previous.file_key);
+                let upload =
+                    Upload::store(conn, backend.get_ref(), target, asset_id, slot, incoming)?;
+                ModelFile::replace_main(conn, asset_id, previous, &upload, inspected)?;
+                Ok(upload)
+            }
+            (UploadTarget::Book, FileSlot::File) => {
+                store_book_pdf(conn, backend.get_ref(), asset_id, incoming)
+            }
+            (UploadTarget::Track, FileSlot::File) => {
+                store_track_audio(conn, backend.get_ref(), asset_id, incoming)
+            }
+            _ => Upload::store(conn, backend.get_ref(), target, asset_id, slot, incoming),
+        }
+    })
+    .await??;
+
+    // thumbnails are rendered after the response has gone out
+    if target.thumb_source() == Some(slot) {
+        actix_web::rt::task::spawn_blocking(move || {
+            let generated = pool.get().map_err(AppError::from).and_then(|mut conn| {
+                Thumbnail::generate(&mut conn, storage.get_ref(), target, asset_id)
+            });
+
+            if let Err(err) = generated {
+                tracing::warn!(
+                    "thumbnails for {} {} failed: {}",
+                    target.store(),
+                    asset_id,
+                    err
+                );
+            }
+        });
+    } else if (target, slot) == (UploadTarget::Stl, FileSlot::File) {
+        render_previews(pool, storage, asset_id);
+    }
+
+    Ok(HttpResponse::Ok().json(upload))
+}
+
+// Expects a multipart body whose first part is the file. The PDF is the
+// book's own file and is stored as such
+async fn upload_format(
+    pool: web::Data<DbPool>,
+    storage: web::Data<dyn StorageBackend>,
+    path: web::Path<(i32, BookFormat)>,
+    mut payload: Multipart,
+) -> Result<HttpResponse, Error> {
+    let (book_id, format) = path.into_inner();
+    let (incoming, _) = read_file(&mut payload, UploadRule::book(format)).await?;
+
+    let backend = storage.clone();
+    let details = web::block(move || {
+        let conn = &mut pool.get()?;
+        if format == BookFormat::Pdf {
+            store_book_pdf(conn, backend.get_ref(), book_id, incoming)?;
+            return BookFile::read(conn, book_id, format).map(|file| FormatDetails {
+                file,
+                chapters: Vec::new(),
+            });
+        }
+        BookFile::store(conn, backend.get_ref(), book_id, format, incoming)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(details))
+}
+
+async fn get_formats(pool: web::Data<DbPool>, path: web::Path<i32>) -> Result<HttpResponse, Error> {
+    let book_id = path.into_inner();
+
+    let formats = web::block(move || {
+        let conn = &mut pool.get()?;
+        FormatDetails::list(conn, book_id)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(formats))
+}
+
+async fn delete_format(
+    pool: web::Data<DbPool>,
+    storage: web::Data<dyn StorageBackend>,
+    path: web::Path<(i32, BookFormat)>,
+) -> Result<HttpResponse, Error> {
+    let (book_id, format) = path.into_inner();
+
+    web::block(move || {
+        let conn = &mut pool.get()?;
+        match BookFile::destroy(conn, storage.get_ref(), book_id, format)? {
+            0 => Err(AppError::NotFound(format!(
+                "book {} has no {}",
+                book_id,
+                format.store()
+            ))),
+            _ => Ok(()),
+        }
+    })
+    .await??;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Expects a multipart body whose first part is the file; the label defaults
+// to the file's name
+async fn add_model(
+    pool: web::Data<DbPool>,
+    storage: web::Data<dyn StorageBackend>,
+    path: web::Path<i32>,
+    query: web::Query<ModelQuery>,
+    mut payload: Multipart,
+) -> Result<HttpResponse, Error> {
+    let stl_id = path.into_inner();
+    let (incoming, filename) =
+        read_file(&mut payload, UploadTarget::Stl.rule(FileSlot::File)?).await?;
+    let ModelQuery { supported, label } = query.into_inner();
+    let label = label
+        .or(filename)
+        .filter(|label| !label.trim().is_empty())
+        .ok_or_else(|| AppError::Validation(String::from("model file has no label")))?;
+
+    let backend = storage.clone();
+    let db = pool.clone();
+    let model_file = web::block(move || {
+        let conn = &mut db.get()?;
+        ModelFile::store(
+            conn,
+            backend.get_ref(),
+            stl_id,
+            incoming,
+            label,
+            supported.unwrap_or(false),
+        )
+    })
+    .await??;
+
+    render_previews(pool, storage, stl_id);
+
+    Ok(HttpResponse::Ok().json(model_file))
+}
+
+async fn get_models(pool: web::Data<DbPool>, path: web::Path<i32>) -> Result<HttpResponse, Error> {
+    let stl_id = path.into_inner();
+
+    let models = web::block(move || {
+        let conn = &mut pool.get()?;
+        ModelListing::read(conn, stl_id)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(models))
+}
+
+async fn delete_model(
+    pool: web::Data<DbPool>,
+    storage: web::Data<dyn StorageBackend>,
+    path: web::Path<(i32, i32)>,
+) -> Result<HttpResponse, Error> {
+    let (stl_id, model_id) = path.into_inner();
+
+    web::block(move || {
+        let conn = &mut pool.get()?;
+        match ModelFile::destroy(conn, storage.get_ref(), stl_id, model_id)? {
+            0 => Err(AppError::NotFound(format!("model file {}", model_id))),
+            _ => Ok(()),
+        }
+    })
+    .await??;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn get_thumbnails(
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, i32)>,
+) -> Result<HttpResponse, Error> {
+    let (collection, asset_id) = path.into_inner();
+    let target = upload_target(&collection)?;
+
+    let thumbnails = web::block(move || {
+        let conn = &mut pool.get()?;
+        Thumbnail::list(conn, target, asset_id)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(thumbnails))
+}
+
+// The PDF is read first so one that does not parse is refused. The upload,
+// its format row and its metadata are written in one transaction; the cover
+// only follows once that has committed
+fn store_book_pdf(
+    conn: &mut PgConnection,
+    storage: &dyn StorageBackend,
+    book_id: i32,
+    incoming: Incoming,
+) -> Result<Upload, AppError> {
+    let info = PdfInfo::parse(&incoming.read()?)?;
+    let upload = Upload::store_with(
+        conn,
+        storage,
+        UploadTarget::Book,
+        book_id,
+        FileSlot::File,
+        incoming,
+        |conn, upload| {
+            BookFile::replace_main(conn, book_id, upload)?;
+            BookMetadata::save(conn, book_id, &info, upload.size)?;
+            Ok(())
+        },
+    )?;
+    BookMetadata::use_cover(conn, storage, book_id, &info, &upload.sha256)?;
+
+    Ok(upload)
+}
+
+// The audio is probed first so a file with no playable stream is refused. The
+// upload and its metadata are written in one transaction
+fn store_track_audio(
+    conn: &mut PgConnection,
+    storage: &dyn StorageBackend,
+    track_id: i32,
+    incoming: Incoming,
+) -> Result<Upload, AppError> {
+    let format = AudioFormat::from_extension(incoming.extension()).ok_or_else(|| {
+        AppError::Validation(format!("{} is not an audio format", incoming.extension()))
+    })?;
+    let info = AudioInfo::parse(format, &incoming.read()?)?;
+
+    Upload::store_with(
+        conn,
+        storage,
+        UploadTarget::Track,
+        track_id,
+        FileSlot::File,
+        incoming,
+        |conn, upload| {
+            TrackMetadata::save(conn, track_id, &info, upload.size)?;
+            Ok(())
+        },
+    )
+}
+
+// Previews are rendered after the response has gone out
+fn render_previews(pool: web::Data<DbPool>, storage: web::Data<dyn StorageBackend>, stl_id: i32) {
+    actix_web::rt::task::spawn_blocking(move || {
+        let rendered = pool
+            .get()
+            .map_err(AppError::from)
+            .and_then(|mut conn| Preview::generate(&mut conn, storage.get_ref(), stl_id));
+
+        if let Err(err) = rendered {
+            tracing::warn!("previews for stl {} failed: {}", stl_id, err);
+        }
+    });
+}
+
+// Reads the first part of a multipart body, along with its file name
+pub async fn read_file(
+    payload: &mut Multipart,
+    rule: UploadRule,
+) -> Result<(Incoming, Option<String>), Error> {
+    let mut field = payload
+        .try_next()
+        .await?
+        .ok_or_else(|| AppError::Validation(String::from("upload has no file")))?;
+    let content_type = field
+        .content_type()
+        .map(|mime| mime.essence_str().to_owned())
+        .ok_or_else(|| AppError::Validation(String::from("file has no content type")))?;
+    let filename = field
+        .content_disposition()
+        .and_then(|disposition| disposition.get_filename())
+        .map(str::to_owned);
+
+    let mut incoming = Incoming::new(rule, &content_type)?;
+    while let Some(chunk) = field.try_next().await? {
+        incoming.push(&chunk)?;
+    }
+
+    Ok((incoming, filename))
+}
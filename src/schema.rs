@@ -6,6 +6,7 @@ diesel::table! {
         album_id -> Int4,
         #[max_length = 50]
         file -> Varchar,
+        position -> Int4,
     }
 }
 
@@ -24,6 +25,72 @@ diesel::table! {
         #[max_length = 50]
         main_image -> Varchar,
         is_free -> Bool,
+        price -> Int4,
+        #[max_length = 3]
+        currency -> Varchar,
+    }
+}
+
+diesel::table! {
+    asset_prices (asset_type, asset_id, currency) {
+        #[max_length = 20]
+        asset_type -> Varchar,
+        asset_id -> Int4,
+        #[max_length = 3]
+        currency -> Varchar,
+        amount -> Int4,
+    }
+}
+
+diesel::table! {
+    book_chapters (id) {
+        id -> Int4,
+        book_file_id -> Int4,
+        position -> Int4,
+        level -> Int4,
+        #[max_length = 280]
+        title -> Varchar,
+    }
+}
+
+diesel::table! {
+    book_contents (id) {
+        id -> Int4,
+        book_id -> Int4,
+        position -> Int4,
+        level -> Int4,
+        #[max_length = 280]
+        title -> Varchar,
+        page -> Int4,
+    }
+}
+
+diesel::table! {
+    book_files (id) {
+        id -> Int4,
+        book_id -> Int4,
+        #[max_length = 20]
+        format -> Varchar,
+        #[max_length = 100]
+        file_key -> Varchar,
+        file_size -> Int8,
+        #[max_length = 280]
+        title -> Nullable<Varchar>,
+        #[max_length = 280]
+        author -> Nullable<Varchar>,
+        uploaded_at -> Int8,
+    }
+}
+
+diesel::table! {
+    book_fonts (id) {
+        id -> Int4,
+        book_id -> Int4,
+        #[max_length = 100]
+        name -> Varchar,
+        #[max_length = 20]
+        subtype -> Varchar,
+        is_embedded -> Bool,
     }
 }
 
@@ -33,6 +100,33 @@ diesel::table! {
         book_id -> Int4,
         #[max_length = 50]
         file -> Varchar,
+        position -> Int4,
+    }
+}
+
+diesel::table! {
+    book_metadata (id) {
+        id -> Int4,
+        book_id -> Int4,
+        page_count -> Int4,
+        is_encrypted -> Bool,
+        fonts_embedded -> Bool,
+        file_size -> Int8,
+        analyzed_at -> Int8,
+    }
+}
+
+diesel::table! {
+    book_previews (id) {
+        id -> Int4,
+        book_id -> Int4,
+        first_page -> Int4,
+        last_page -> Int4,
+        #[max_length = 100]
+        source_key -> Varchar,
+        #[max_length = 100]
+        file_key -> Varchar,
+        created_at -> Int8,
     }
 }
 
@@ -42,16 +136,31 @@ diesel::table! {
         creator_id -> Int4,
         #[max_length = 50]
         title -> Varchar,
-        #[max_length = 35]
+        #[max_length = 100]
         thumb -> Varchar,
         #[max_length = 280]
         summary -> Varchar,
-        #[max_length = 50]
+        #[max_length = 100]
         file -> Varchar,
         pages -> Int4,
-        #[max_length = 50]
+        #[max_length = 100]
         main_image -> Varchar,
         is_free -> Bool,
+        price -> Int4,
+        #[max_length = 3]
+        currency -> Varchar,
+    }
+}
+
+diesel::table! {
+    cart_items (id) {
+        id -> Int4,
+        user_id -> Nullable<Int4>,
+        #[max_length = 64]
+        guest_key -> Nullable<Varchar>,
+        #[max_length = 20]
+        asset_type -> Varchar,
+        asset_id -> Int4,
     }
 }
 
@@ -77,6 +186,7 @@ diesel::table! {
         map_id -> Int4,
         #[max_length = 50]
         file -> Varchar,
+        position -> Int4,
     }
 }
 
@@ -86,6 +196,7 @@ diesel::table! {
         map_pack_id -> Int4,
         #[max_length = 50]
         file -> Varchar,
+        position -> Int4,
     }
 }
 
@@ -104,6 +215,9 @@ diesel::table! {
         #[max_length = 50]
         main_image -> Varchar,
         is_free -> Bool,
+        price -> Int4,
+        #[max_length = 3]
+        currency -> Varchar,
     }
 }
 
@@ -114,16 +228,78 @@ diesel::table! {
         map_pack_id -> Int4,
         #[max_length = 50]
         title -> Varchar,
-        #[max_length = 35]
+        #[max_length = 100]
         thumb -> Varchar,
         #[max_length = 280]
         summary -> Varchar,
         height -> Nullable<Int4>,
         width -> Nullable<Int4>,
-        #[max_length = 50]
+        #[max_length = 100]
         file -> Varchar,
-        #[max_length = 50]
+        #[max_length = 100]
         main_image -> Varchar,
+        is_free -> Bool,
+        price -> Nullable<Int4>,
+        #[max_length = 3]
+        currency -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    model_files (id) {
+        id -> Int4,
+        stl_id -> Int4,
+        #[max_length = 10]
+        format -> Varchar,
+        #[max_length = 100]
+        label -> Varchar,
+        is_supported -> Bool,
+        #[max_length = 100]
+        file_key -> Varchar,
+        file_size -> Int8,
+        parts -> Int4,
+        triangles -> Int8,
+        width -> Float8,
+        depth -> Float8,
+        height -> Float8,
+        is_watertight -> Bool,
+        is_manifold -> Bool,
+        uploaded_at -> Int8,
+    }
+}
+
+diesel::table! {
+    order_items (id) {
+        id -> Int4,
+        order_id -> Int4,
+        #[max_length = 20]
+        asset_type -> Varchar,
+        asset_id -> Int4,
+        amount -> Int4,
+    }
+}
+
+diesel::table! {
+    orders (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 20]
+        status -> Varchar,
+        total -> Int4,
+        #[max_length = 3]
+        currency -> Varchar,
+        #[max_length = 100]
+        payment_reference -> Nullable<Varchar>,
+        created_at -> Int8,
+    }
+}
+
+diesel::table! {
+    sessions (session_key) {
+        #[max_length = 64]
+        session_key -> Varchar,
+        state -> Text,
+        expires_at -> Int8,
     }
 }
 
@@ -131,8 +307,10 @@ diesel::table! {
     stl_images (id) {
         id -> Int4,
         stl_id -> Int4,
-        #[max_length = 50]
+        #[max_length = 100]
         file -> Varchar,
+        position -> Int4,
+        is_preview -> Bool,
     }
 }
 
@@ -142,15 +320,18 @@ diesel::table! {
         creator_id -> Int4,
         #[max_length = 50]
         title -> Varchar,
-        #[max_length = 35]
+        #[max_length = 100]
         thumb -> Varchar,
         #[max_length = 280]
         summary -> Varchar,
-        #[max_length = 50]
+        #[max_length = 100]
         file -> Varchar,
-        #[max_length = 50]
+        #[max_length = 100]
         main_image -> Varchar,
         is_free -> Bool,
+        price -> Int4,
+        #[max_length = 3]
+        currency -> Varchar,
     }
 }
 
@@ -160,6 +341,24 @@ diesel::table! {
         token_pack_id -> Int4,
         #[max_length = 50]
         file -> Varchar,
+        position -> Int4,
+    }
+}
+
+diesel::table! {
+    thumbnails (id) {
+        id -> Int4,
+        #[max_length = 20]
+        asset_type -> Varchar,
+        asset_id -> Int4,
+        size -> Int4,
+        #[max_length = 10]
+        format -> Varchar,
+        #[max_length = 100]
+        file_key -> Varchar,
+        width -> Int4,
+        height -> Int4,
+        created_at -> Int8,
     }
 }
 
@@ -178,6 +377,9 @@ diesel::table! {
         #[max_length = 50]
         main_image -> Varchar,
         is_free -> Bool,
+        price -> Int4,
+        #[max_length = 3]
+        currency -> Varchar,
     }
 }
 
@@ -188,17 +390,46 @@ diesel::table! {
         token_pack_id -> Int4,
         #[max_length = 50]
         title -> Varchar,
-        #[max_length = 35]
+        #[max_length = 100]
         thumb -> Varchar,
         #[max_length = 280]
         summary -> Varchar,
         height -> Nullable<Int4>,
         width -> Nullable<Int4>,
-        #[max_length = 50]
+        #[max_length = 100]
         file -> Varchar,
-        #[max_length = 50]
+        #[max_length = 100]
         main_image -> Varchar,
         is_free -> Bool,
+        price -> Nullable<Int4>,
+        #[max_length = 3]
+        currency -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    track_metadata (id) {
+        id -> Int4,
+        track_id -> Int4,
+        #[max_length = 10]
+        format -> Varchar,
+        duration_ms -> Int8,
+        bitrate -> Int4,
+        sample_rate -> Int4,
+        channels -> Int4,
+        file_size -> Int8,
+        #[max_length = 280]
+        title -> Nullable<Varchar>,
+        #[max_length = 280]
+        artist -> Nullable<Varchar>,
+        #[max_length = 280]
+        album -> Nullable<Varchar>,
+        #[max_length = 100]
+        genre -> Nullable<Varchar>,
+        #[max_length = 30]
+        date -> Nullable<Varchar>,
+        track_number -> Nullable<Int4>,
+        analyzed_at -> Int8,
     }
 }
 
@@ -209,17 +440,37 @@ diesel::table! {
         album_id -> Int4,
         #[max_length = 50]
         title -> Varchar,
-        #[max_length = 50]
+        #[max_length = 100]
         file -> Varchar,
-        #[max_length = 50]
+        #[max_length = 100]
         main_image -> Nullable<Varchar>,
     }
 }
 
+diesel::table! {
+    uploads (id) {
+        id -> Int4,
+        #[max_length = 20]
+        asset_type -> Varchar,
+        asset_id -> Int4,
+        #[max_length = 20]
+        slot -> Varchar,
+        #[max_length = 100]
+        file_key -> Varchar,
+        #[max_length = 100]
+        content_type -> Varchar,
+        size -> Int8,
+        #[max_length = 64]
+        sha256 -> Varchar,
+        uploaded_at -> Int8,
+    }
+}
+
 diesel::table! {
     user_albums (user_id, album_id) {
         user_id -> Int4,
         album_id -> Int4,
+        purchased_at -> Int8,
     }
 }
 
@@ -227,6 +478,15 @@ diesel::table! {
     user_books (user_id, book_id) {
         user_id -> Int4,
         book_id -> Int4,
+        purchased_at -> Int8,
+    }
+}
+
+diesel::table! {
+    user_map_packs (user_id, map_pack_id) {
+        user_id -> Int4,
+        map_pack_id -> Int4,
+        purchased_at -> Int8,
     }
 }
 
@@ -234,6 +494,7 @@ diesel::table! {
     user_maps (user_id, map_id) {
         user_id -> Int4,
         map_id -> Int4,
+        purchased_at -> Int8,
     }
 }
 
@@ -241,6 +502,7 @@ diesel::table! {
     user_stls (user_id, stl_id) {
         user_id -> Int4,
         stl_id -> Int4,
+        purchased_at -> Int8,
     }
 }
 
@@ -248,6 +510,7 @@ diesel::table! {
     user_token_packs (user_id, token_pack_id) {
         user_id -> Int4,
         token_pack_id -> Int4,
+        purchased_at -> Int8,
     }
 }
 
@@ -255,6 +518,7 @@ diesel::table! {
     user_tokens (user_id, token_id) {
         user_id -> Int4,
         token_id -> Int4,
+        purchased_at -> Int8,
     }
 }
 
@@ -267,31 +531,63 @@ diesel::table! {
         email -> Varchar,
         #[max_length = 50]
         logo -> Varchar,
+        #[max_length = 255]
+        password_hash -> Nullable<Varchar>,
+        #[max_length = 20]
+        role -> Varchar,
+    }
+}
+
+diesel::table! {
+    watermarks (id) {
+        id -> Int4,
+        user_id -> Int4,
+        book_id -> Int4,
+        #[max_length = 100]
+        source_key -> Varchar,
+        #[max_length = 100]
+        file_key -> Varchar,
+        created_at -> Int8,
+        #[max_length = 20]
+        format -> Varchar,
     }
 }
 
 diesel::joinable!(album_images -> albums (album_id));
 diesel::joinable!(albums -> creators (creator_id));
+diesel::joinable!(book_chapters -> book_files (book_file_id));
+diesel::joinable!(book_contents -> books (book_id));
+diesel::joinable!(book_files -> books (book_id));
+diesel::joinable!(book_fonts -> books (book_id));
 diesel::joinable!(book_images -> books (book_id));
+diesel::joinable!(book_metadata -> books (book_id));
+diesel::joinable!(book_previews -> books (book_id));
 diesel::joinable!(books -> creators (creator_id));
+diesel::joinable!(cart_items -> users (user_id));
 diesel::joinable!(creators -> users (id));
 diesel::joinable!(map_images -> maps (map_id));
 diesel::joinable!(map_pack_images -> map_packs (map_pack_id));
 diesel::joinable!(map_packs -> creators (creator_id));
 diesel::joinable!(maps -> creators (creator_id));
 diesel::joinable!(maps -> map_packs (map_pack_id));
+diesel::joinable!(model_files -> stls (stl_id));
+diesel::joinable!(order_items -> orders (order_id));
+diesel::joinable!(orders -> users (user_id));
 diesel::joinable!(stl_images -> stls (stl_id));
 diesel::joinable!(stls -> creators (creator_id));
 diesel::joinable!(token_pack_images -> token_packs (token_pack_id));
 diesel::joinable!(token_packs -> creators (creator_id));
 diesel::joinable!(tokens -> creators (creator_id));
 diesel::joinable!(tokens -> token_packs (token_pack_id));
+diesel::joinable!(track_metadata -> tracks (track_id));
 diesel::joinable!(tracks -> albums (album_id));
 diesel::joinable!(tracks -> creators (creator_id));
 diesel::joinable!(user_albums -> albums (album_id));
 diesel::joinable!(user_albums -> users (user_id));
 diesel::joinable!(user_books -> books (book_id));
 diesel::joinable!(user_books -> users (user_id));
+diesel::joinable!(user_map_packs -> map_packs (map_pack_id));
+diesel::joinable!(user_map_packs -> users (user_id));
 diesel::joinable!(user_maps -> maps (map_id));
 diesel::joinable!(user_maps -> users (user_id));
 diesel::joinable!(user_stls -> stls (stl_id));
@@ -300,28 +596,47 @@ diesel::joinable!(user_token_packs -> token_packs (token_pack_id));
 diesel::joinable!(user_token_packs -> users (user_id));
 diesel::joinable!(user_tokens -> tokens (token_id));
 diesel::joinable!(user_tokens -> users (user_id));
+diesel::joinable!(watermarks -> books (book_id));
+diesel::joinable!(watermarks -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     album_images,
     albums,
+    asset_prices,
+    book_chapters,
+    book_contents,
+    book_files,
+    book_fonts,
     book_images,
+    book_metadata,
+    book_previews,
     books,
+    cart_items,
     creators,
     map_images,
     map_pack_images,
     map_packs,
     maps,
+    model_files,
+    order_items,
+    orders,
+    sessions,
     stl_images,
     stls,
+    thumbnails,
     token_pack_images,
     token_packs,
     tokens,
+    track_metadata,
     tracks,
+    uploads,
     user_albums,
     user_books,
+    user_map_packs,
     user_maps,
     user_stls,
     user_token_packs,
     user_tokens,
     users,
+    watermarks,
 );
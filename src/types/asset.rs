@@ -1,29 +1,78 @@
-use diesel::prelude::PgConnection;
+use super::error::AppError;
+use super::price::Pricing;
+use crate::handlers::album::Album;
+use crate::handlers::audio::AlbumDetails;
+use crate::handlers::book::Book;
+use crate::handlers::documents::BookDetails;
+use crate::handlers::images::albums::AlbumImage;
+use crate::handlers::images::books::BookImage;
+use crate::handlers::images::map_packs::MapPackImage;
+use crate::handlers::images::maps::MapImage;
+use crate::handlers::images::stls::StlImage;
+use crate::handlers::images::token_packs::TokenPackImage;
+use crate::handlers::map::{Map, MapPack};
+use crate::handlers::models::ModelListing;
+use crate::handlers::ownership::albums::UserAlbum;
+use crate::handlers::ownership::books::UserBook;
+use crate::handlers::ownership::map_packs::UserMapPack;
+use crate::handlers::ownership::maps::UserMap;
+use crate::handlers::ownership::stls::UserStl;
+use crate::handlers::ownership::token_packs::UserTokenPack;
+use crate::handlers::ownership::tokens::UserToken;
+use crate::handlers::stl::Stl;
+use crate::handlers::tokens::{Token, TokenPack};
+use diesel::prelude::{PgConnection, Queryable};
+use serde::{Deserialize, Serialize};
 
-pub trait Asset {
-    fn read(conn: &mut PgConnection, id: i32) -> Self;
-    fn destroy(conn: &mut PgConnection, id: i32) -> usize;
-    fn update(&self, conn: &mut PgConnection) -> usize;
+pub trait Asset: Sized {
+    fn read(conn: &mut PgConnection, id: i32) -> Result<Self, AppError>;
+    fn destroy(conn: &mut PgConnection, id: i32) -> Result<usize, AppError>;
+    fn update(&self, conn: &mut PgConnection) -> Result<usize, AppError>;
     //user id refers to the user viewing the content, not the owner
-    fn summarize(&self, conn: &mut PgConnection, user_id: i32) -> Summary;
+    fn summarize(&self, conn: &mut PgConnection, user_id: i32) -> Result<Summary, AppError>;
     //user id refers to the user viewing the content, not the owner
-    fn paginate(&self, conn: &mut PgConnection, user_id: i32) -> Page;
-    fn check_ownership(&self, conn: &mut PgConnection, user_id: i32) -> Ownership;
+    fn paginate(&self, conn: &mut PgConnection, user_id: i32) -> Result<Page, AppError>;
+    fn check_ownership(&self, conn: &mut PgConnection, user_id: i32)
+        -> Result<Ownership, AppError>;
 }
 
+#[derive(Serialize)]
 pub struct Summary {
+    pub title: String,
+    // the creator's name, as they chose to show it
     pub display_name: String,
     pub ownership: Ownership,
     pub asset_type: AssetType,
     pub logo: String,
+    pub pricing: Pricing,
 }
 
+#[derive(Serialize)]
 pub struct Page {
     pub display_name: String,
     pub ownership: Ownership,
     pub asset_type: AssetType,
     pub logo: String,
     pub extra_images: Vec<String>,
+    pub pricing: Pricing,
+    pub details: Option<Details>,
+}
+
+// What has been worked out from the files behind a page
+#[derive(Serialize, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Details {
+    Album(AlbumDetails),
+    Book(BookDetails),
+    Model(ModelListing),
+}
+
+#[derive(Queryable, Serialize, PartialEq, Debug)]
+pub struct GalleryImage {
+    pub id: i32,
+    pub asset_id: i32,
+    pub file: String,
+    pub position: i32,
 }
 
 #[derive(PartialEq, Debug)]
@@ -32,14 +81,16 @@ pub struct Dimensions {
     pub height: i32,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Ownership {
     Owned,
     Free,
     Unowned,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum AssetType {
     Book,
     Album,
@@ -51,16 +102,16 @@ pub enum AssetType {
 }
 
 impl AssetType {
-    pub fn retrieve(str: &str) -> Self {
+    pub fn retrieve(str: &str) -> Result<Self, AppError> {
         match str {
-            "book" => Self::Book,
-            "album" => Self::Album,
-            "map" => Self::Map,
-            "map_pack" => Self::MapPack,
-            "stl" => Self::Stl,
-            "token_pack" => Self::TokenPack,
-            "token" => Self::Token,
-            _ => panic!("invalid ownership"),
+            "book" => Ok(Self::Book),
+            "album" => Ok(Self::Album),
+            "map" => Ok(Self::Map),
+            "map_pack" => Ok(Self::MapPack),
+            "stl" => Ok(Self::Stl),
+            "token_pack" => Ok(Self::TokenPack),
+            "token" => Ok(Self::Token),
+            _ => Err(AppError::Validation(format!("invalid asset type: {}", str))),
         }
     }
 
@@ -76,26 +127,169 @@ impl AssetType {
         }
     }
 
-    pub fn images(&self) -> Vec<String> {
+    pub fn images(&self, conn: &mut PgConnection, asset_id: i32) -> Result<Vec<String>, AppError> {
+        match self {
+            Self::Book => BookImage::files(conn, asset_id),
+            Self::Album => AlbumImage::files(conn, asset_id),
+            Self::Map => MapImage::files(conn, asset_id),
+            Self::MapPack => MapPackImage::files(conn, asset_id),
+            Self::Stl => StlImage::files(conn, asset_id),
+            Self::TokenPack => TokenPackImage::files(conn, asset_id),
+            // individual tokens are shown through their pack's gallery
+            Self::Token => Ok(Vec::new()),
+        }
+    }
+
+    pub fn gallery(
+        &self,
+        conn: &mut PgConnection,
+        asset_id: i32,
+    ) -> Result<Vec<GalleryImage>, AppError> {
+        match self {
+            Self::Book => BookImage::list(conn, asset_id),
+            Self::Album => AlbumImage::list(conn, asset_id),
+            Self::Map => MapImage::list(conn, asset_id),
+            Self::MapPack => MapPackImage::list(conn, asset_id),
+            Self::Stl => StlImage::list(conn, asset_id),
+            Self::TokenPack => TokenPackImage::list(conn, asset_id),
+            Self::Token => Err(self.no_gallery()),
+        }
+    }
+
+    pub fn add_image(
+        &self,
+        conn: &mut PgConnection,
+        asset_id: i32,
+        file: String,
+    ) -> Result<GalleryImage, AppError> {
+        match self {
+            Self::Book => BookImage::create(conn, asset_id, file),
+            Self::Album => AlbumImage::create(conn, asset_id, file),
+            Self::Map => MapImage::create(conn, asset_id, file),
+            Self::MapPack => MapPackImage::create(conn, asset_id, file),
+            Self::Stl => StlImage::create(conn, asset_id, file),
+            Self::TokenPack => TokenPackImage::create(conn, asset_id, file),
+            Self::Token => Err(self.no_gallery()),
+        }
+    }
+
+    pub fn reorder_images(
+        &self,
+        conn: &mut PgConnection,
+        asset_id: i32,
+        order: &[i32],
+    ) -> Result<usize, AppError> {
+        match self {
+            Self::Book => BookImage::reorder(conn, asset_id, order),
+            Self::Album => AlbumImage::reorder(conn, asset_id, order),
+            Self::Map => MapImage::reorder(conn, asset_id, order),
+            Self::MapPack => MapPackImage::reorder(conn, asset_id, order),
+            Self::Stl => StlImage::reorder(conn, asset_id, order),
+            Self::TokenPack => TokenPackImage::reorder(conn, asset_id, order),
+            Self::Token => Err(self.no_gallery()),
+        }
+    }
+
+    pub fn remove_image(
+        &self,
+        conn: &mut PgConnection,
+        asset_id: i32,
+        image_id: i32,
+    ) -> Result<usize, AppError> {
         match self {
-            Self::Book => Vec::new(),
-            Self::Album => Vec::new(),
-            Self::Map => Vec::new(),
-            Self::MapPack => Vec::new(),
-            Self::Stl => Vec::new(),
-            Self::TokenPack => Vec::new(),
-            Self::Token => Vec::new(),
+            Self::Book => BookImage::destroy(conn, asset_id, image_id),
+            Self::Album => AlbumImage::destroy(conn, asset_id, image_id),
+            Self::Map => MapImage::destroy(conn, asset_id, image_id),
+            Self::MapPack => MapPackImage::destroy(conn, asset_id, image_id),
+            Self::Stl => StlImage::destroy(conn, asset_id, image_id),
+            Self::TokenPack => TokenPackImage::destroy(conn, asset_id, image_id),
+            Self::Token => Err(self.no_gallery()),
         }
     }
+
+    //user id refers to the user viewing the content, not the owner
+    pub fn summarize(
+        &self,
+        conn: &mut PgConnection,
+        asset_id: i32,
+        user_id: i32,
+    ) -> Result<Summary, AppError> {
+        match self {
+            Self::Book => Book::read(conn, asset_id)?.summarize(conn, user_id),
+            Self::Album => Album::read(conn, asset_id)?.summarize(conn, user_id),
+            Self::MapPack => MapPack::read(conn, asset_id)?.summarize(conn, user_id),
+            Self::Stl => Stl::read(conn, asset_id)?.summarize(conn, user_id),
+            Self::TokenPack => TokenPack::read(conn, asset_id)?.summarize(conn, user_id),
+            Self::Token => Token::read(conn, asset_id)?.summarize(conn, user_id),
+            Self::Map => Map::read(conn, asset_id)?.summarize(conn, user_id),
+        }
+    }
+
+    //user id refers to the user viewing the content, not the owner
+    pub fn paginate(
+        &self,
+        conn: &mut PgConnection,
+        asset_id: i32,
+        user_id: i32,
+    ) -> Result<Page, AppError> {
+        match self {
+            Self::Book => Book::read(conn, asset_id)?.paginate(conn, user_id),
+            Self::Album => Album::read(conn, asset_id)?.paginate(conn, user_id),
+            Self::MapPack => MapPack::read(conn, asset_id)?.paginate(conn, user_id),
+            Self::Stl => Stl::read(conn, asset_id)?.paginate(conn, user_id),
+            Self::TokenPack => TokenPack::read(conn, asset_id)?.paginate(conn, user_id),
+            Self::Map | Self::Token => Err(self.no_page()),
+        }
+    }
+
+    pub fn pricing(&self, conn: &mut PgConnection, asset_id: i32) -> Result<Pricing, AppError> {
+        match self {
+            Self::Book => Book::read(conn, asset_id)?.pricing(conn),
+            Self::Album => Album::read(conn, asset_id)?.pricing(conn),
+            Self::MapPack => MapPack::read(conn, asset_id)?.pricing(conn),
+            Self::Stl => Stl::read(conn, asset_id)?.pricing(conn),
+            Self::TokenPack => TokenPack::read(conn, asset_id)?.pricing(conn),
+            Self::Token => Token::read(conn, asset_id)?.pricing(conn),
+            Self::Map => Map::read(conn, asset_id)?.pricing(conn),
+        }
+    }
+
+    // Records a purchase; a pack covers every map or token in it, and single
+    // maps and tokens can still be granted on their own
+    pub fn grant(
+        &self,
+        conn: &mut PgConnection,
+        asset_id: i32,
+        user_id: i32,
+    ) -> Result<usize, AppError> {
+        match self {
+            Self::Book => UserBook::new(user_id, asset_id).create(conn),
+            Self::Album => UserAlbum::new(user_id, asset_id).create(conn),
+            Self::Stl => UserStl::new(user_id, asset_id).create(conn),
+            Self::TokenPack => UserTokenPack::new(user_id, asset_id).create(conn),
+            Self::MapPack => UserMapPack::new(user_id, asset_id).create(conn),
+            Self::Map => UserMap::new(user_id, asset_id).create(conn),
+            Self::Token => UserToken::new(user_id, asset_id).create(conn),
+        }
+    }
+
+    // single maps and tokens are shown on their pack's page
+    fn no_page(&self) -> AppError {
+        AppError::Validation(format!("{} has no page of its own", self.store()))
+    }
+
+    fn no_gallery(&self) -> AppError {
+        AppError::Validation(format!("{} has no image gallery", self.store()))
+    }
 }
 
 impl Ownership {
-    pub fn retrieve(str: &str) -> Self {
+    pub fn retrieve(str: &str) -> Result<Self, AppError> {
         match str {
-            "owned" => Self::Owned,
-            "unowned" => Self::Unowned,
-            "free" => Self::Free,
-            _ => panic!("invalid ownership"),
+            "owned" => Ok(Self::Owned),
+            "unowned" => Ok(Self::Unowned),
+            "free" => Ok(Self::Free),
+            _ => Err(AppError::Validation(format!("invalid ownership: {}", str))),
         }
     }
 
@@ -0,0 +1,110 @@
+use actix_web::http::StatusCode;
+use actix_web::ResponseError;
+use diesel::r2d2::PoolError;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use std::fmt;
+
+#[derive(Debug)]
+pub enum AppError {
+    NotFound(String),
+    Unauthorized(String),
+    Forbidden(String),
+    Conflict(String),
+    Validation(String),
+    Payment(String),
+    Storage(String),
+    Database(DieselError),
+    Pool(PoolError),
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound(msg) => write!(f, "not found: {}", msg),
+            Self::Unauthorized(msg) => write!(f, "unauthorized: {}", msg),
+            Self::Forbidden(msg) => write!(f, "forbidden: {}", msg),
+            Self::Conflict(msg) => write!(f, "conflict: {}", msg),
+            Self::Validation(msg) => write!(f, "invalid input: {}", msg),
+            Self::Payment(msg) => write!(f, "payment failed: {}", msg),
+            Self::Storage(msg) => write!(f, "storage error: {}", msg),
+            Self::Database(err) => write!(f, "database error: {}", err),
+            Self::Pool(err) => write!(f, "database unavailable: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for AppError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Database(err) => Some(err),
+            Self::Pool(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<DieselError> for AppError {
+    fn from(err: DieselError) -> Self {
+        match err {
+            DieselError::NotFound => Self::NotFound(String::from("record does not exist")),
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, info) => {
+                Self::Conflict(info.message().to_string())
+            }
+            DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, info) => {
+                Self::Conflict(info.message().to_string())
+            }
+            err => Self::Database(err),
+        }
+    }
+}
+
+impl From<PoolError> for AppError {
+    fn from(err: PoolError) -> Self {
+        Self::Pool(err)
+    }
+}
+
+impl From<std::io::Error> for AppError {
+    fn from(err: std::io::Error) -> Self {
+        match err.kind() {
+            std::io::ErrorKind::NotFound => Self::NotFound(String::from("file does not exist")),
+            _ => Self::Storage(err.to_string()),
+        }
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::Validation(_) => StatusCode::BAD_REQUEST,
+            Self::Payment(_) => StatusCode::PAYMENT_REQUIRED,
+            Self::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Pool(_) => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_status() {
+        let not_found = AppError::from(DieselError::NotFound);
+
+        assert_eq!(not_found.status_code(), StatusCode::NOT_FOUND);
+
+        let validation = AppError::Validation(String::from("bad ownership"));
+
+        assert_eq!(validation.status_code(), StatusCode::BAD_REQUEST);
+
+        let database = AppError::from(DieselError::RollbackTransaction);
+
+        assert_eq!(database.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
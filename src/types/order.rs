@@ -0,0 +1,36 @@
+use super::error::AppError;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStatus {
+    Pending,
+    Paid,
+    Failed,
+    // charged, then refunded because the purchase could not be completed
+    Refunded,
+}
+
+impl OrderStatus {
+    pub fn store(&self) -> &str {
+        match self {
+            Self::Pending => "pending",
+            Self::Paid => "paid",
+            Self::Failed => "failed",
+            Self::Refunded => "refunded",
+        }
+    }
+
+    pub fn retrieve(str: &str) -> Result<Self, AppError> {
+        match str {
+            "pending" => Ok(Self::Pending),
+            "paid" => Ok(Self::Paid),
+            "failed" => Ok(Self::Failed),
+            "refunded" => Ok(Self::Refunded),
+            _ => Err(AppError::Validation(format!(
+                "invalid order status: {}",
+                str
+            ))),
+        }
+    }
+}
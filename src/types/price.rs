@@ -0,0 +1,137 @@
+use super::error::AppError;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Currency {
+    Usd,
+    Eur,
+    Gbp,
+    Cad,
+    Aud,
+    Jpy,
+}
+
+impl Currency {
+    pub fn store(&self) -> &str {
+        match self {
+            Self::Usd => "USD",
+            Self::Eur => "EUR",
+            Self::Gbp => "GBP",
+            Self::Cad => "CAD",
+            Self::Aud => "AUD",
+            Self::Jpy => "JPY",
+        }
+    }
+
+    pub fn retrieve(str: &str) -> Result<Self, AppError> {
+        match str {
+            "USD" => Ok(Self::Usd),
+            "EUR" => Ok(Self::Eur),
+            "GBP" => Ok(Self::Gbp),
+            "CAD" => Ok(Self::Cad),
+            "AUD" => Ok(Self::Aud),
+            "JPY" => Ok(Self::Jpy),
+            _ => Err(AppError::Validation(format!(
+                "unsupported currency: {}",
+                str
+            ))),
+        }
+    }
+}
+
+// amount is in the currency's minor unit, e.g. cents for USD and yen for JPY
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct Price {
+    pub amount: i32,
+    pub currency: Currency,
+}
+
+impl Price {
+    pub fn new(amount: i32, currency: Currency) -> Self {
+        Price { amount, currency }
+    }
+
+    // Builds a price from the columns stored on an asset row
+    pub fn stored(amount: i32, currency: &str) -> Result<Self, AppError> {
+        if amount < 0 {
+            return Err(AppError::Validation(format!(
+                "price cannot be negative: {}",
+                amount
+            )));
+        }
+
+        Ok(Price::new(amount, Currency::retrieve(currency)?))
+    }
+
+    // Maps and tokens may go without a price of their own; both halves of it
+    // are set together or not at all
+    pub fn optional(
+        amount: Option<i32>,
+        currency: &Option<String>,
+    ) -> Result<Option<Self>, AppError> {
+        match (amount, currency) {
+            (Some(amount), Some(currency)) => Ok(Some(Price::stored(amount, currency)?)),
+            (None, None) => Ok(None),
+            _ => Err(AppError::Validation(String::from(
+                "price and currency must be set together",
+            ))),
+        }
+    }
+}
+
+// The asset's own price plus any amounts set for specific currencies
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Pricing {
+    pub price: Price,
+    pub overrides: Vec<Price>,
+}
+
+impl Pricing {
+    pub fn in_currency(&self, currency: Currency) -> Option<Price> {
+        self.overrides
+            .iter()
+            .chain(std::iter::once(&self.price))
+            .find(|price| price.currency == currency)
+            .copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pricing_in_currency() {
+        let pricing = Pricing {
+            price: Price::new(1999, Currency::Usd),
+            overrides: vec![
+                Price::new(1799, Currency::Eur),
+                Price::new(2000, Currency::Jpy),
+            ],
+        };
+
+        assert_eq!(
+            pricing.in_currency(Currency::Usd),
+            Some(Price::new(1999, Currency::Usd))
+        );
+        assert_eq!(
+            pricing.in_currency(Currency::Eur),
+            Some(Price::new(1799, Currency::Eur))
+        );
+        assert_eq!(pricing.in_currency(Currency::Gbp), None);
+
+        assert!(Price::stored(-1, "USD").is_err());
+        assert!(Price::stored(100, "usd").is_err());
+        assert_eq!(
+            Price::stored(100, "GBP").unwrap(),
+            Price::new(100, Currency::Gbp)
+        );
+        assert_eq!(Price::optional(None, &None).unwrap(), None);
+        assert!(Price::optional(Some(100), &None).is_err());
+        assert_eq!(
+            Price::optional(Some(100), &Some(String::from("CAD"))).unwrap(),
+            Some(Price::new(100, Currency::Cad))
+        );
+    }
+}
@@ -1,4 +1,8 @@
-#[derive(Debug, PartialEq)]
+use super::error::AppError;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum DisplayName {
     Name,
     Other,
@@ -16,13 +20,43 @@ impl DisplayName {
         }
     }
 
-    pub fn retreieve(str: &str) -> Self {
+    pub fn retreieve(str: &str) -> Result<Self, AppError> {
+        match str {
+            "name" => Ok(Self::Name),
+            "other" => Ok(Self::Other),
+            "name_publisher" => Ok(Self::NamePublisher),
+            "other_publisher" => Ok(Self::OtherPublisher),
+            _ => Err(AppError::Validation(format!(
+                "invalid display name: {}",
+                str
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Customer,
+    Creator,
+    Admin,
+}
+
+impl Role {
+    pub fn store(&self) -> &str {
+        match self {
+            Self::Customer => "customer",
+            Self::Creator => "creator",
+            Self::Admin => "admin",
+        }
+    }
+
+    pub fn retrieve(str: &str) -> Result<Self, AppError> {
         match str {
-            "name" => Self::Name,
-            "other" => Self::Other,
-            "name_publisher" => Self::NamePublisher,
-            "other_publisher" => Self::OtherPublisher,
-            _ => panic!("Invalid DisplayName string"),
+            "customer" => Ok(Self::Customer),
+            "creator" => Ok(Self::Creator),
+            "admin" => Ok(Self::Admin),
+            _ => Err(AppError::Validation(format!("invalid role: {}", str))),
         }
     }
 }
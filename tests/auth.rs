@@ -0,0 +1,116 @@
+use alembic_head::handlers::connect;
+use alembic_head::handlers::user::User;
+use std::env;
+use std::net::TcpListener;
+
+#[tokio::test]
+async fn session_lifecycle_works() {
+    let address = spawn_app();
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .expect("Failed to build client");
+
+    let response = client
+        .get(format!("{}/me", &address))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = client
+        .post(format!("{}/register", &address))
+        .header("Content-Type", "application/json")
+        .body(
+            r#"{"username": "session_lifecycle", "email": "session@gmail.com",
+                "password": "correct horse battery"}"#,
+        )
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 201);
+
+    let user: User = client
+        .get(format!("{}/me", &address))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse user");
+
+    assert_eq!(user.username, "session_lifecycle");
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/register", &address))
+        .header("Content-Type", "application/json")
+        .body(
+            r#"{"username": "session_lifecycle", "email": "other_session@gmail.com",
+                "password": "correct horse battery"}"#,
+        )
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = client
+        .post(format!("{}/logout", &address))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 204);
+
+    let response = client
+        .get(format!("{}/me", &address))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = client
+        .post(format!("{}/login", &address))
+        .header("Content-Type", "application/json")
+        .body(r#"{"username": "session_lifecycle", "password": "wrong password"}"#)
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = client
+        .post(format!("{}/login", &address))
+        .header("Content-Type", "application/json")
+        .body(r#"{"username": "session_lifecycle", "password": "correct horse battery"}"#)
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert!(response.status().is_success());
+
+    let response = client
+        .get(format!("{}/me", &address))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert!(response.status().is_success());
+
+    let conn = &mut connect::establish_connection();
+    User::destroy(conn, user.id).unwrap();
+}
+
+fn spawn_app() -> String {
+    env::set_var("PAYMENT_PROVIDER", "fake");
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
+    let port = listener.local_addr().unwrap().port();
+    let server =
+        alembic_head::run(listener, connect::establish_pool()).expect("Failed to bind address");
+
+    tokio::spawn(server);
+    format!("http://127.0.0.1:{}", port)
+}
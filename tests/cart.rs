@@ -0,0 +1,130 @@
+use alembic_head::handlers::book::{Book, BookCreate};
+use alembic_head::handlers::connect;
+use alembic_head::handlers::creator::{CreatorNew, Creators};
+use alembic_head::handlers::user::{User, UserNew};
+use alembic_head::types::asset::Asset;
+use alembic_head::types::price::{Currency, Price};
+use alembic_head::types::user::DisplayName;
+use serde_json::Value;
+use std::env;
+use std::net::TcpListener;
+
+#[tokio::test]
+async fn guest_cart_carries_over_to_account() {
+    let address = spawn_app();
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .expect("Failed to build client");
+    let conn = &mut connect::establish_connection();
+
+    let user = UserNew::create(
+        conn,
+        String::from("cart_creator"),
+        String::from("cart_creator@gmail.com"),
+        String::from("logo.svg"),
+    )
+    .unwrap();
+
+    let creator = CreatorNew::create(
+        conn,
+        user.id,
+        None,
+        None,
+        Some(String::from("naokotani")),
+        None,
+        DisplayName::Other,
+    )
+    .unwrap();
+
+    let book = BookCreate::new(
+        creator.id,
+        String::from("Dungeons and Dragons"),
+        String::from("thumb.jpg"),
+        String::from("What a book!"),
+        String::from("file.pdf"),
+        385,
+        String::from("image.jpg"),
+        false,
+        Price::new(1999, Currency::Usd),
+    )
+    .create(conn)
+    .unwrap();
+
+    let response = client
+        .post(format!("{}/cart/items", &address))
+        .header("Content-Type", "application/json")
+        .body(format!(
+            r#"{{"asset_type": "book", "asset_id": {}}}"#,
+            book.id
+        ))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 201);
+
+    let response = client
+        .post(format!("{}/register", &address))
+        .header("Content-Type", "application/json")
+        .body(
+            r#"{"username": "cart_customer", "email": "cart_customer@gmail.com",
+                "password": "correct horse battery"}"#,
+        )
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 201);
+
+    let customer: User = response.json().await.expect("Failed to parse user");
+
+    let cart: Value = client
+        .get(format!("{}/cart?currency=USD", &address))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse cart");
+
+    assert_eq!(cart["items"][0]["asset_id"], book.id);
+    assert_eq!(cart["items"][0]["asset_type"], "book");
+    assert_eq!(cart["items"][0]["title"], "Dungeons and Dragons");
+    assert_eq!(cart["totals"][0]["amount"], 1999);
+    assert_eq!(cart["totals"][0]["currency"], "USD");
+
+    let item_id = cart["items"][0]["id"].as_i64().unwrap();
+
+    let response = client
+        .delete(format!("{}/cart/items/{}", &address, item_id))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 204);
+
+    let response = client
+        .delete(format!("{}/cart/items/{}", &address, item_id))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 404);
+
+    Book::destroy(conn, book.id).unwrap();
+    Creators::destroy(conn, creator.id).unwrap();
+    User::destroy(conn, user.id).unwrap();
+    User::destroy(conn, customer.id).unwrap();
+}
+
+fn spawn_app() -> String {
+    env::set_var("PAYMENT_PROVIDER", "fake");
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
+    let port = listener.local_addr().unwrap().port();
+    let server =
+        alembic_head::run(listener, connect::establish_pool()).expect("Failed to bind address");
+
+    tokio::spawn(server);
+    format!("http://127.0.0.1:{}", port)
+}
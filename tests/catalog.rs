@@ -0,0 +1,371 @@
+use alembic_head::handlers::book::Book;
+use alembic_head::handlers::connect;
+use alembic_head::handlers::creator::Creators;
+use alembic_head::handlers::user::User;
+use serde::Deserialize;
+use std::env;
+use std::net::TcpListener;
+
+#[derive(Deserialize)]
+struct Created {
+    id: i32,
+}
+
+#[tokio::test]
+async fn book_crud_works() {
+    let address = spawn_app();
+    let client = cookie_client();
+    let conn = &mut connect::establish_connection();
+
+    let creator = sign_in_creator(&client, &address, "book_crud").await;
+
+    let body = format!(
+        r#"{{"creator_id": {}, "title": "Dungeons and Dragons", "thumb": "thumb.jpg",
+            "summary": "What a book!", "file": "file.pdf", "pages": 385,
+            "main_image": "image.jpg", "is_free": false, "price": 1999, "currency": "USD"}}"#,
+        creator.id
+    );
+
+    let response = client
+        .post(format!("{}/books", &address))
+        .header("Content-Type", "application/json")
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 201);
+
+    let mut book: Book = response.json().await.expect("Failed to parse book");
+
+    assert_eq!(book.title, "Dungeons and Dragons");
+
+    book.title = String::from("For Whom the Bell Tolls");
+
+    let response = client
+        .put(format!("{}/books/{}", &address, book.id))
+        .json(&book)
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert!(response.status().is_success());
+
+    let book: Book = client
+        .get(format!("{}/books/{}", &address, book.id))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse book");
+
+    assert_eq!(book.title, "For Whom the Bell Tolls");
+    assert_eq!(book.pages, 385);
+    assert_eq!(book.price, 1999);
+
+    let response = client
+        .put(format!("{}/books/{}/prices", &address, book.id))
+        .header("Content-Type", "application/json")
+        .body(r#"{"amount": 1799, "currency": "EUR"}"#)
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert!(response.status().is_success());
+
+    let pricing: serde_json::Value = client
+        .get(format!("{}/books/{}/prices", &address, book.id))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse pricing");
+
+    assert_eq!(pricing["price"]["amount"], 1999);
+    assert_eq!(pricing["price"]["currency"], "USD");
+    assert_eq!(pricing["overrides"][0]["amount"], 1799);
+    assert_eq!(pricing["overrides"][0]["currency"], "EUR");
+
+    let response = client
+        .put(format!("{}/books/{}/prices", &address, book.id))
+        .header("Content-Type", "application/json")
+        .body(r#"{"amount": 1500, "currency": "USD"}"#)
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = client
+        .post(format!("{}/books/{}/images", &address, book.id))
+        .header("Content-Type", "application/json")
+        .body(r#"{"file": "books/gallery/cover.jpg"}"#)
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 201);
+
+    // pages are public, and guests own nothing
+    let page: serde_json::Value = reqwest::Client::new()
+        .get(format!("{}/books/{}/page", &address, book.id))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse page");
+
+    assert_eq!(page["display_name"], "Galator");
+    assert_eq!(page["asset_type"], "book");
+    assert_eq!(page["ownership"], "unowned");
+    assert_eq!(page["extra_images"][0], "books/gallery/cover.jpg");
+    assert_eq!(page["pricing"]["overrides"][0]["amount"], 1799);
+
+    let response = client
+        .get(format!("{}/tokens/{}/page", &address, book.id))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 404);
+
+    let response = client
+        .delete(format!("{}/books/{}", &address, book.id))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 204);
+
+    Creators::destroy(conn, creator.id).unwrap();
+    User::destroy(conn, creator.id).unwrap();
+}
+
+#[tokio::test]
+async fn map_pack_with_maps_works() {
+    let address = spawn_app();
+    let client = cookie_client();
+    let conn = &mut connect::establish_connection();
+
+    let creator = sign_in_creator(&client, &address, "map_pack_with_maps").await;
+
+    let body = format!(
+        r#"{{"creator_id": {}, "title": "Epic Fights", "thumb": "thumb.jpg",
+            "summary": "Lots of great locations", "directory": "directory",
+            "is_free": false, "main_image": "image.jpg", "price": 2500, "currency": "CAD"}}"#,
+        creator.id
+    );
+
+    let response = client
+        .post(format!("{}/map_packs", &address))
+        .header("Content-Type", "application/json")
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 201);
+
+    let map_pack: Created = response.json().await.expect("Failed to parse map pack");
+
+    let response = client
+        .post(format!("{}/map_packs/{}/maps", &address, map_pack.id))
+        .header("Content-Type", "application/json")
+        .body(
+            r#"{"title": "Windy Glade", "thumb": "thumb.jpg", "summary": "What a fight area!",
+                "height": 450, "width": 450, "main_image": "image.jpg", "is_free": false}"#,
+        )
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 201);
+
+    let stored: serde_json::Value = client
+        .get(format!("{}/map_packs/{}", &address, map_pack.id))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse map pack");
+
+    assert_eq!(stored["maps"][0]["title"], "Windy Glade");
+    assert_eq!(stored["maps"][0]["file"], "epic-fights/windy-glade");
+
+    let response = client
+        .delete(format!("{}/map_packs/{}", &address, map_pack.id))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 204);
+
+    Creators::destroy(conn, creator.id).unwrap();
+    User::destroy(conn, creator.id).unwrap();
+}
+
+#[tokio::test]
+async fn missing_book_returns_404() {
+    let address = spawn_app();
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(format!("{}/books/{}", &address, i32::MAX))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 404);
+
+    let response = client
+        .delete(format!("{}/books/{}", &address, i32::MAX))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn writes_require_the_owning_creator() {
+    let address = spawn_app();
+    let owner = cookie_client();
+    let other = cookie_client();
+    let customer = cookie_client();
+    let conn = &mut connect::establish_connection();
+
+    let creator = sign_in_creator(&owner, &address, "owning_creator").await;
+    let intruder = sign_in_creator(&other, &address, "other_creator").await;
+    let reader = register(&customer, &address, "catalog_customer").await;
+
+    let body = format!(
+        r#"{{"creator_id": {}, "title": "Owned", "thumb": "thumb.jpg",
+            "summary": "Mine", "file": "file.pdf", "pages": 10,
+            "main_image": "image.jpg", "is_free": false, "price": 1999, "currency": "USD"}}"#,
+        creator.id
+    );
+
+    for client in [&other, &customer] {
+        let response = client
+            .post(format!("{}/books", &address))
+            .header("Content-Type", "application/json")
+            .body(body.clone())
+            .send()
+            .await
+            .expect("Failed to send request");
+
+        assert_eq!(response.status().as_u16(), 403);
+    }
+
+    let book: Created = owner
+        .post(format!("{}/books", &address))
+        .header("Content-Type", "application/json")
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse book");
+
+    // handing the book to another creator is refused however the JSON is labelled
+    let reassigned = format!(
+        r#"{{"id": {}, "creator_id": {}, "title": "Owned", "thumb": "thumb.jpg",
+            "summary": "Mine", "file": "file.pdf", "pages": 10, "main_image": "image.jpg",
+            "is_free": false, "price": 1999, "currency": "USD"}}"#,
+        book.id, intruder.id
+    );
+    for content_type in [
+        "application/json",
+        "application/json; charset=utf-8",
+        "application/vnd.x+json",
+    ] {
+        let response = owner
+            .put(format!("{}/books/{}", &address, book.id))
+            .header("Content-Type", content_type)
+            .body(reassigned.clone())
+            .send()
+            .await
+            .expect("Failed to send request");
+
+        assert_eq!(response.status().as_u16(), 403, "{}", content_type);
+    }
+
+    for client in [&other, &customer] {
+        let response = client
+            .delete(format!("{}/books/{}", &address, book.id))
+            .send()
+            .await
+            .expect("Failed to send request");
+
+        assert_eq!(response.status().as_u16(), 403);
+    }
+
+    let response = owner
+        .delete(format!("{}/books/{}", &address, book.id))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 204);
+
+    Creators::destroy(conn, creator.id).unwrap();
+    Creators::destroy(conn, intruder.id).unwrap();
+    User::destroy(conn, creator.id).unwrap();
+    User::destroy(conn, intruder.id).unwrap();
+    User::destroy(conn, reader.id).unwrap();
+}
+
+fn cookie_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .expect("Failed to build client")
+}
+
+async fn register(client: &reqwest::Client, address: &str, username: &str) -> Created {
+    client
+        .post(format!("{}/register", address))
+        .header("Content-Type", "application/json")
+        .body(format!(
+            r#"{{"username": "{}", "email": "{}@gmail.com", "password": "correct horse battery"}}"#,
+            username, username
+        ))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse user")
+}
+
+// Registers a user and opens a creator profile for them; creator ids match user ids
+async fn sign_in_creator(client: &reqwest::Client, address: &str, username: &str) -> Created {
+    register(client, address, username).await;
+
+    client
+        .post(format!("{}/creators", address))
+        .header("Content-Type", "application/json")
+        .body(r#"{"other_name": "Galator", "default_name": "other"}"#)
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse creator")
+}
+
+fn spawn_app() -> String {
+    env::set_var("PAYMENT_PROVIDER", "fake");
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
+    let port = listener.local_addr().unwrap().port();
+    let server =
+        alembic_head::run(listener, connect::establish_pool()).expect("Failed to bind address");
+
+    tokio::spawn(server);
+    format!("http://127.0.0.1:{}", port)
+}
@@ -0,0 +1,659 @@
+use alembic_head::handlers::connect;
+use alembic_head::handlers::creator::Creators;
+use alembic_head::handlers::ownership::books::UserBook;
+use alembic_head::handlers::user::User;
+use lopdf::{dictionary, Document, Object};
+use serde::Deserialize;
+use std::env;
+use std::io::{Cursor, Read};
+use std::net::TcpListener;
+
+#[derive(Deserialize)]
+struct Created {
+    id: i32,
+}
+
+#[derive(Deserialize)]
+struct Link {
+    url: String,
+    expires_at: i64,
+}
+
+#[tokio::test]
+async fn owned_files_download_through_signed_links() {
+    let root = env::temp_dir().join(format!("alembic-downloads-{}", std::process::id()));
+    env::set_var("STORAGE_ROOT", &root);
+    env::set_var("ARCHIVE_CACHE", "true");
+
+    let address = spawn_app();
+    let owner = cookie_client();
+    let reader = cookie_client();
+    let conn = &mut connect::establish_connection();
+
+    let creator = sign_in_creator(&owner, &address, "downloading_creator").await;
+    let reader_id = register(&reader, &address, "downloading_reader").await;
+
+    let free = create_book(&owner, &address, creator.id, true).await;
+    let paid = create_book(&owner, &address, creator.id, false).await;
+    let contents = pdf(2);
+
+    for book in [&free, &paid] {
+        let response = upload(
+            &owner,
+            &format!("{}/books/{}/files/file", &address, book.id),
+            "application/pdf",
+            &contents,
+        )
+        .await;
+
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    // another creator's free items cannot be pointed at the paid book's file
+    let thief = cookie_client();
+    let thieving = sign_in_creator(&thief, &address, "thieving_creator").await;
+    let paid_file = thief
+        .get(format!("{}/books/{}", &address, paid.id))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse book")["file"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+
+    assert!(paid_file.starts_with(&format!("books/{}/file-", paid.id)));
+
+    let decoy = create_book(&thief, &address, thieving.id, true).await;
+    let mut book = thief
+        .get(format!("{}/books/{}", &address, decoy.id))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse book");
+    let decoy_file = book["file"].to_owned();
+    book["file"] = paid_file.to_owned().into();
+    let updated = thief
+        .put(format!("{}/books/{}", &address, decoy.id))
+        .json(&book)
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse book");
+
+    assert_eq!(updated["file"], decoy_file);
+
+    let stored = thief
+        .get(format!("{}/books/{}", &address, decoy.id))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse book");
+
+    assert_eq!(stored["file"], decoy_file);
+    assert_ne!(
+        stored["file"],
+        serde_json::Value::from(paid_file.to_owned())
+    );
+    let decoy_stl: Created = thief
+        .post(format!("{}/stls", &address))
+        .json(&serde_json::json!({
+            "creator_id": thieving.id, "title": "Decoy", "thumb": "thumb.jpg",
+            "summary": "Free", "file": paid_file, "is_free": true,
+            "main_image": "image.jpg", "price": 0, "currency": "USD",
+        }))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse stl");
+    let stored = thief
+        .get(format!("{}/stls/{}", &address, decoy_stl.id))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse stl");
+
+    assert_eq!(stored["file"], "");
+
+    for download in [
+        format!("{}/downloads/book/{}", &address, decoy.id),
+        format!("{}/downloads/stl/{}", &address, decoy_stl.id),
+    ] {
+        let response = reader
+            .get(download)
+            .send()
+            .await
+            .expect("Failed to send request");
+
+        assert_eq!(response.status().as_u16(), 404);
+    }
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/downloads/book/{}", &address, free.id))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = reader
+        .get(format!("{}/downloads/book/{}", &address, paid.id))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 403);
+
+    // a preview of the paid book is open to anyone
+    let preview = format!("{}/books/{}/preview", &address, paid.id);
+    let response = reqwest::Client::new()
+        .get(&preview)
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 404);
+
+    for (client, range, status) in [
+        (&reader, r#"{"first_page": 1, "last_page": 1}"#, 403),
+        (&owner, r#"{"first_page": 2, "last_page": 1}"#, 400),
+        (&owner, r#"{"first_page": 2, "last_page": 5}"#, 400),
+        (&owner, r#"{"first_page": 2, "last_page": 2}"#, 200),
+    ] {
+        let response = client
+            .put(&preview)
+            .header("Content-Type", "application/json")
+            .body(range)
+            .send()
+            .await
+            .expect("Failed to send request");
+
+        assert_eq!(response.status().as_u16(), status);
+    }
+
+    let response = reqwest::Client::new()
+        .get(&preview)
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["content-type"], "application/pdf");
+    assert!(response.headers()["content-disposition"]
+        .to_str()
+        .unwrap()
+        .starts_with("inline"));
+
+    let excerpt =
+        Document::load_mem(&response.bytes().await.unwrap()).expect("Failed to parse preview");
+
+    assert_eq!(excerpt.get_pages().len(), 1);
+
+    // a new edition too short for the range ends the preview
+    let response = upload(
+        &owner,
+        &format!("{}/books/{}/files/file", &address, paid.id),
+        "application/pdf",
+        &pdf(1),
+    )
+    .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = reqwest::Client::new()
+        .get(&preview)
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 404);
+
+    let link: Link = reader
+        .get(format!("{}/downloads/book/{}", &address, free.id))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse link");
+
+    // each reader gets a copy stamped with their name
+    assert!(link.url.starts_with(&format!(
+        "/files/watermarks/books/{}/{}-",
+        free.id, reader_id
+    )));
+    assert!(link.expires_at > 0);
+
+    let again: Link = reader
+        .get(format!("{}/downloads/book/{}", &address, free.id))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse link");
+
+    assert_eq!(again.url.split('?').next(), link.url.split('?').next());
+    let stamped_key = link.url.split('?').next().unwrap()["/files/".len()..].to_owned();
+    assert!(root.join(&stamped_key).exists());
+
+    // the link itself needs no session
+    let client = reqwest::Client::new();
+    let url = format!("{}{}", &address, link.url);
+    let response = client
+        .get(&url)
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["content-type"], "application/pdf");
+    assert_eq!(response.headers()["accept-ranges"], "bytes");
+    let stamped = response.bytes().await.unwrap().to_vec();
+    let document = Document::load_mem(&stamped).expect("Failed to parse stamped book");
+
+    assert_eq!(document.get_pages().len(), 2);
+    for page in document.get_pages().into_values() {
+        let content = String::from_utf8_lossy(&document.get_page_content(page)).into_owned();
+        assert!(content.contains("Licensed to downloading_reader <"));
+    }
+
+    let response = client
+        .get(&url)
+        .header("Range", "bytes=9-11")
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 206);
+    assert_eq!(
+        response.headers()["content-range"],
+        format!("bytes 9-11/{}", stamped.len())
+    );
+    assert_eq!(response.bytes().await.unwrap().as_ref(), &stamped[9..12]);
+
+    let response = client
+        .get(&url)
+        .header("Range", format!("bytes={}-", stamped.len()))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 416);
+
+    let tampered = url.replace(
+        &format!("books/{}/", free.id),
+        &format!("books/{}/", paid.id),
+    );
+    let response = client
+        .get(&tampered)
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 403);
+
+    // other formats are downloaded on their own, the print PDF stamped too
+    let response = reader
+        .get(format!(
+            "{}/downloads/book/{}?format=epub",
+            &address, free.id
+        ))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 404);
+
+    let response = upload(
+        &owner,
+        &format!("{}/books/{}/formats/print_pdf", &address, free.id),
+        "application/pdf",
+        &pdf(1),
+    )
+    .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+
+    let print: Link = reader
+        .get(format!(
+            "{}/downloads/book/{}?format=print_pdf",
+            &address, free.id
+        ))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse link");
+
+    assert_ne!(print.url.split('?').next(), link.url.split('?').next());
+
+    let document = Document::load_mem(
+        &client
+            .get(format!("{}{}", &address, print.url))
+            .send()
+            .await
+            .expect("Failed to send request")
+            .bytes()
+            .await
+            .unwrap(),
+    )
+    .expect("Failed to parse stamped print book");
+
+    assert_eq!(document.get_pages().len(), 1);
+
+    let map_pack: Created = owner
+        .post(format!("{}/map_packs", &address))
+        .header("Content-Type", "application/json")
+        .body(format!(
+            r#"{{"creator_id": {}, "title": "Windy Glade", "thumb": "thumb.jpg",
+                "summary": "Breezy", "directory": "windy-glade", "is_free": true,
+                "main_image": "image.jpg", "price": 500, "currency": "USD"}}"#,
+            creator.id
+        ))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse map pack");
+
+    let mut maps = Vec::new();
+    for title in ["Clearing", "Clearing!"] {
+        let map: Created = owner
+            .post(format!("{}/map_packs/{}/maps", &address, map_pack.id))
+            .header("Content-Type", "application/json")
+            .body(format!(
+                r#"{{"title": "{}", "thumb": "thumb.jpg", "summary": "Trees",
+                    "height": null, "width": null, "main_image": "image.jpg", "is_free": false}}"#,
+                title
+            ))
+            .send()
+            .await
+            .expect("Failed to send request")
+            .json()
+            .await
+            .expect("Failed to parse map");
+        maps.push(map);
+    }
+
+    // large enough that the cached archive is sent in several chunks
+    let mut png = b"\x89PNG\r\n\x1a\nmap".to_vec();
+    png.resize(3 * 1024 * 1024, b'#');
+    for map in &maps {
+        let response = upload(
+            &owner,
+            &format!("{}/maps/{}/files/file", &address, map.id),
+            "image/png",
+            &png,
+        )
+        .await;
+
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    let link: Link = reader
+        .get(format!("{}/downloads/map_pack/{}", &address, map_pack.id))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse link");
+
+    assert!(link
+        .url
+        .starts_with(&format!("/archives/map_pack/{}?", map_pack.id)));
+
+    let url = format!("{}{}", &address, link.url);
+    let streamed = client
+        .get(&url)
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(streamed.status().as_u16(), 200);
+    assert_eq!(
+        streamed.headers()["content-disposition"],
+        "attachment; filename=\"windy-glade.zip\""
+    );
+    assert!(streamed.headers().get("accept-ranges").is_none());
+
+    let streamed = streamed.bytes().await.unwrap();
+    let mut zip = zip::ZipArchive::new(Cursor::new(streamed.to_vec())).unwrap();
+    let mut names = zip.file_names().collect::<Vec<&str>>();
+    names.sort();
+
+    assert_eq!(
+        names,
+        [
+            "windy-glade/README.txt",
+            "windy-glade/clearing-2.png",
+            "windy-glade/clearing.png"
+        ]
+    );
+
+    let mut readme = String::new();
+    zip.by_name("windy-glade/README.txt")
+        .unwrap()
+        .read_to_string(&mut readme)
+        .unwrap();
+
+    assert!(readme.starts_with("Windy Glade\n"));
+    assert!(readme.contains("License"));
+
+    // the second download is the cached copy, which supports ranges
+    let cached = client
+        .get(&url)
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(cached.status().as_u16(), 200);
+    assert_eq!(cached.headers()["accept-ranges"], "bytes");
+    assert_eq!(
+        cached.headers()["content-length"],
+        streamed.len().to_string()
+    );
+    assert_eq!(cached.bytes().await.unwrap(), streamed);
+
+    let response = client
+        .get(url.replace("map_pack", "token_pack"))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 403);
+
+    owner
+        .delete(format!("{}/map_packs/{}", &address, map_pack.id))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    // a refused delete keeps the stamped copies and their files
+    UserBook::new(reader_id, free.id).create(conn).unwrap();
+    let response = owner
+        .delete(format!("{}/books/{}", &address, free.id))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 409);
+    assert!(root.join(&stamped_key).exists());
+
+    UserBook::destroy(conn, reader_id, free.id).unwrap();
+    for book in [&free, &paid] {
+        let response = owner
+            .delete(format!("{}/books/{}", &address, book.id))
+            .send()
+            .await
+            .expect("Failed to send request");
+
+        assert_eq!(response.status().as_u16(), 204);
+    }
+
+    // stamped copies go with the book
+    assert!(!root.join(&stamped_key).exists());
+
+    thief
+        .delete(format!("{}/books/{}", &address, decoy.id))
+        .send()
+        .await
+        .expect("Failed to send request");
+    thief
+        .delete(format!("{}/stls/{}", &address, decoy_stl.id))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    Creators::destroy(conn, thieving.id).unwrap();
+    User::destroy(conn, thieving.id).unwrap();
+    Creators::destroy(conn, creator.id).unwrap();
+    User::destroy(conn, creator.id).unwrap();
+    User::destroy(conn, reader_id).unwrap();
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+async fn create_book(
+    client: &reqwest::Client,
+    address: &str,
+    creator_id: i32,
+    is_free: bool,
+) -> Created {
+    client
+        .post(format!("{}/books", address))
+        .header("Content-Type", "application/json")
+        .body(format!(
+            r#"{{"creator_id": {}, "title": "Downloadable", "thumb": "thumb.jpg",
+                "summary": "Read me", "file": "file.pdf", "pages": 10,
+                "main_image": "image.jpg", "is_free": {}, "price": 999, "currency": "USD"}}"#,
+            creator_id, is_free
+        ))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse book")
+}
+
+// A plain PDF of blank pages
+fn pdf(pages: usize) -> Vec<u8> {
+    let mut document = Document::with_version("1.5");
+    let pages_id = document.new_object_id();
+    let kids = (0..pages)
+        .map(|_| {
+            document
+                .add_object(dictionary! { "Type" => "Page", "Parent" => pages_id })
+                .into()
+        })
+        .collect::<Vec<Object>>();
+    document.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => kids,
+            "Count" => pages as i64,
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+        }),
+    );
+    let catalog = document.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+    document.trailer.set("Root", catalog);
+
+    let mut bytes = Vec::new();
+    document.save_to(&mut bytes).unwrap();
+    bytes
+}
+
+// A single part multipart body, built by hand
+async fn upload(
+    client: &reqwest::Client,
+    url: &str,
+    content_type: &str,
+    bytes: &[u8],
+) -> reqwest::Response {
+    let boundary = "alembic-download-boundary";
+    let mut body = format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"upload\"\r\nContent-Type: {}\r\n\r\n",
+        boundary, content_type
+    )
+    .into_bytes();
+    body.extend_from_slice(bytes);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+    client
+        .put(url)
+        .header(
+            "Content-Type",
+            format!("multipart/form-data; boundary={}", boundary),
+        )
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to send request")
+}
+
+fn cookie_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .expect("Failed to build client")
+}
+
+async fn register(client: &reqwest::Client, address: &str, username: &str) -> i32 {
+    let user: Created = client
+        .post(format!("{}/register", address))
+        .header("Content-Type", "application/json")
+        .body(format!(
+            r#"{{"username": "{}", "email": "{}@gmail.com", "password": "correct horse battery"}}"#,
+            username, username
+        ))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse user");
+
+    user.id
+}
+
+async fn sign_in_creator(client: &reqwest::Client, address: &str, username: &str) -> Created {
+    register(client, address, username).await;
+
+    client
+        .post(format!("{}/creators", address))
+        .header("Content-Type", "application/json")
+        .body(r#"{"other_name": "Galator", "default_name": "other"}"#)
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse creator")
+}
+
+fn spawn_app() -> String {
+    env::set_var("PAYMENT_PROVIDER", "fake");
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
+    let port = listener.local_addr().unwrap().port();
+    let server =
+        alembic_head::run(listener, connect::establish_pool()).expect("Failed to bind address");
+
+    tokio::spawn(server);
+    format!("http://127.0.0.1:{}", port)
+}
@@ -1,4 +1,7 @@
+use alembic_head::handlers::connect;
+use std::env;
 use std::net::TcpListener;
+use std::time::Duration;
 
 #[tokio::test]
 async fn health_check_works() {
@@ -6,7 +9,7 @@ async fn health_check_works() {
     let client = reqwest::Client::new();
 
     let response = client
-        .get(&format!("{}/health_check", &address))
+        .get(format!("{}/health_check", &address))
         .send()
         .await
         .expect("Failed to send requst");
@@ -16,10 +19,34 @@ async fn health_check_works() {
 }
 
 fn spawn_app() -> String {
+    env::set_var("PAYMENT_PROVIDER", "fake");
     let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
     let port = listener.local_addr().unwrap().port();
-    let server = alembic_head::run(listener).expect("Failed to bind address");
+    let server =
+        alembic_head::run(listener, connect::establish_pool()).expect("Failed to bind address");
 
-    let _ = tokio::spawn(server);
+    tokio::spawn(server);
     format!("http://127.0.0.1:{}", port)
 }
+
+#[tokio::test]
+async fn health_check_reports_unavailable_database() {
+    env::set_var("PAYMENT_PROVIDER", "fake");
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
+    let port = listener.local_addr().unwrap().port();
+    let settings = connect::PoolSettings {
+        connection_timeout: Duration::from_millis(250),
+        ..connect::PoolSettings::default()
+    };
+    let pool = connect::build_pool("postgres://nobody@127.0.0.1:1/missing", settings);
+    let server = alembic_head::run(listener, pool).expect("Failed to bind address");
+    tokio::spawn(server);
+
+    let response = reqwest::Client::new()
+        .get(format!("http://127.0.0.1:{}/health_check", port))
+        .send()
+        .await
+        .expect("Failed to send requst");
+
+    assert_eq!(response.status().as_u16(), 503);
+}
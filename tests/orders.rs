@@ -0,0 +1,172 @@
+use alembic_head::handlers::book::{Book, BookCreate};
+use alembic_head::handlers::connect;
+use alembic_head::handlers::creator::{CreatorNew, Creators};
+use alembic_head::handlers::orders::Order;
+use alembic_head::handlers::ownership::books::UserBook;
+use alembic_head::handlers::user::{User, UserNew};
+use alembic_head::types::asset::{Asset, Ownership};
+use alembic_head::types::price::{Currency, Price};
+use alembic_head::types::user::DisplayName;
+use serde_json::Value;
+use std::env;
+use std::net::TcpListener;
+
+#[tokio::test]
+async fn checkout_grants_ownership() {
+    let address = spawn_app();
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .expect("Failed to build client");
+    let conn = &mut connect::establish_connection();
+
+    let user = UserNew::create(
+        conn,
+        String::from("order_creator"),
+        String::from("order_creator@gmail.com"),
+        String::from("logo.svg"),
+    )
+    .unwrap();
+
+    let creator = CreatorNew::create(
+        conn,
+        user.id,
+        Some(String::from("Chris")),
+        Some(String::from("Hughes")),
+        None,
+        None,
+        DisplayName::Name,
+    )
+    .unwrap();
+
+    let book = BookCreate::new(
+        creator.id,
+        String::from("Dungeons and Dragons"),
+        String::from("thumb.jpg"),
+        String::from("What a book!"),
+        String::from("file.pdf"),
+        385,
+        String::from("image.jpg"),
+        false,
+        Price::new(1999, Currency::Usd),
+    )
+    .create(conn)
+    .unwrap();
+
+    let response = client
+        .post(format!("{}/checkout", &address))
+        .header("Content-Type", "application/json")
+        .body(r#"{"currency": "USD"}"#)
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 401);
+
+    let customer: User = client
+        .post(format!("{}/register", &address))
+        .header("Content-Type", "application/json")
+        .body(
+            r#"{"username": "order_customer", "email": "order_customer@gmail.com",
+                "password": "correct horse battery"}"#,
+        )
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse user");
+
+    let response = client
+        .post(format!("{}/cart/items", &address))
+        .header("Content-Type", "application/json")
+        .body(format!(
+            r#"{{"asset_type": "book", "asset_id": {}}}"#,
+            book.id
+        ))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 201);
+
+    let response = client
+        .post(format!("{}/checkout", &address))
+        .header("Content-Type", "application/json")
+        .body(r#"{"currency": "USD"}"#)
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 201);
+
+    let order: Value = response.json().await.expect("Failed to parse order");
+
+    assert_eq!(order["status"], "paid");
+    assert_eq!(order["total"]["amount"], 1999);
+    assert_eq!(order["items"][0]["asset_id"], book.id);
+    assert_eq!(
+        book.check_ownership(conn, customer.id).unwrap(),
+        Ownership::Owned
+    );
+
+    let orders: Value = client
+        .get(format!("{}/orders", &address))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse orders");
+
+    assert_eq!(orders[0]["id"], order["id"]);
+
+    let library: Value = client
+        .get(format!("{}/library?asset_type=book&sort=newest", &address))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse library");
+
+    assert_eq!(library["total"], 1);
+    assert_eq!(library["items"][0]["asset_id"], book.id);
+    assert_eq!(library["items"][0]["summary"]["ownership"], "owned");
+
+    let response = client
+        .get(format!("{}/library?per_page=0", &address))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = client
+        .get(format!("{}/orders/{}", &address, i32::MAX))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 404);
+
+    let order_id = order["id"].as_i64().unwrap() as i32;
+
+    Order::destroy(conn, customer.id, order_id).unwrap();
+    UserBook::destroy(conn, customer.id, book.id).unwrap();
+    Book::destroy(conn, book.id).unwrap();
+    Creators::destroy(conn, creator.id).unwrap();
+    User::destroy(conn, user.id).unwrap();
+    User::destroy(conn, customer.id).unwrap();
+}
+
+fn spawn_app() -> String {
+    env::set_var("PAYMENT_PROVIDER", "fake");
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
+    let port = listener.local_addr().unwrap().port();
+    let server =
+        alembic_head::run(listener, connect::establish_pool()).expect("Failed to bind address");
+
+    tokio::spawn(server);
+    format!("http://127.0.0.1:{}", port)
+}
@@ -0,0 +1,198 @@
+use alembic_head::handlers::connect;
+use alembic_head::handlers::creator::Creators;
+use alembic_head::handlers::user::User;
+use serde::Deserialize;
+use serde_json::Value;
+use std::env;
+use std::net::TcpListener;
+use std::time::Duration;
+
+#[derive(Deserialize)]
+struct Created {
+    id: i32,
+}
+
+#[tokio::test]
+async fn stl_uploads_render_previews() {
+    let root = env::temp_dir().join(format!("alembic-previews-{}", std::process::id()));
+    env::set_var("STORAGE_ROOT", &root);
+
+    let address = spawn_app();
+    let owner = cookie_client();
+    let conn = &mut connect::establish_connection();
+
+    let creator = sign_in_creator(&owner, &address, "preview_creator").await;
+
+    let stl: Created = owner
+        .post(format!("{}/stls", &address))
+        .header("Content-Type", "application/json")
+        .body(format!(
+            r#"{{"creator_id": {}, "title": "Pyramid", "thumb": "thumb.jpg",
+                "summary": "Pointy", "file": "pyramid.stl", "is_free": false,
+                "main_image": "image.jpg", "price": 499, "currency": "USD"}}"#,
+            creator.id
+        ))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse stl");
+
+    let response = upload(
+        &owner,
+        &format!("{}/stls/{}/files/file", &address, stl.id),
+        "model/stl",
+        &tetrahedron(),
+    )
+    .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+
+    // rendered in the background, and written in one transaction once every
+    // angle is done, so the gallery goes from empty to complete
+    let mut images = Vec::new();
+    for _ in 0..600 {
+        images = reqwest::get(format!("{}/stls/{}/images", &address, stl.id))
+            .await
+            .expect("Failed to send request")
+            .json::<Vec<Value>>()
+            .await
+            .expect("Failed to parse images");
+        if !images.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    assert_eq!(images.len(), 3);
+
+    for image in &images {
+        let file = image["file"].as_str().unwrap();
+
+        assert!(file.starts_with(&format!("stls/{}/preview-", stl.id)));
+        assert!(root.join(file).exists());
+    }
+
+    let stored: Value = reqwest::get(format!("{}/stls/{}", &address, stl.id))
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse stl");
+
+    assert_eq!(stored["main_image"], images[0]["file"]);
+    assert!(stored["thumb"]
+        .as_str()
+        .unwrap()
+        .starts_with(&format!("stls/{}/preview-thumb-", stl.id)));
+
+    let response = owner
+        .delete(format!("{}/stls/{}", &address, stl.id))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 204);
+
+    Creators::destroy(conn, creator.id).unwrap();
+    User::destroy(conn, creator.id).unwrap();
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+// A closed binary STL
+fn tetrahedron() -> Vec<u8> {
+    let corners = [
+        [0.0f32, 0.0, 0.0],
+        [10.0, 0.0, 0.0],
+        [0.0, 10.0, 0.0],
+        [0.0, 0.0, 10.0],
+    ];
+    let faces = [[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]];
+
+    let mut bytes = vec![0; 80];
+    bytes.extend_from_slice(&(faces.len() as u32).to_le_bytes());
+    for face in faces {
+        bytes.extend_from_slice(&[0; 12]);
+        for corner in face {
+            for value in corners[corner] {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        bytes.extend_from_slice(&[0; 2]);
+    }
+    bytes
+}
+
+// A single part multipart body, built by hand
+async fn upload(
+    client: &reqwest::Client,
+    url: &str,
+    content_type: &str,
+    bytes: &[u8],
+) -> reqwest::Response {
+    let boundary = "alembic-preview-boundary";
+    let mut body = format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"upload\"\r\nContent-Type: {}\r\n\r\n",
+        boundary, content_type
+    )
+    .into_bytes();
+    body.extend_from_slice(bytes);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+    client
+        .put(url)
+        .header(
+            "Content-Type",
+            format!("multipart/form-data; boundary={}", boundary),
+        )
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to send request")
+}
+
+// Rendering can outlast the server's keep-alive, so a connection left idle
+// meanwhile may be closed just as it is reused. Each request gets a new one
+fn cookie_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .cookie_store(true)
+        .pool_max_idle_per_host(0)
+        .build()
+        .expect("Failed to build client")
+}
+
+async fn sign_in_creator(client: &reqwest::Client, address: &str, username: &str) -> Created {
+    client
+        .post(format!("{}/register", address))
+        .header("Content-Type", "application/json")
+        .body(format!(
+            r#"{{"username": "{}", "email": "{}@gmail.com", "password": "correct horse battery"}}"#,
+            username, username
+        ))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    client
+        .post(format!("{}/creators", address))
+        .header("Content-Type", "application/json")
+        .body(r#"{"other_name": "Galator", "default_name": "other"}"#)
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse creator")
+}
+
+fn spawn_app() -> String {
+    env::set_var("PAYMENT_PROVIDER", "fake");
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
+    let port = listener.local_addr().unwrap().port();
+    let server =
+        alembic_head::run(listener, connect::establish_pool()).expect("Failed to bind address");
+
+    tokio::spawn(server);
+    format!("http://127.0.0.1:{}", port)
+}
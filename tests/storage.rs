@@ -0,0 +1,131 @@
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+use alembic_head::handlers::storage::{S3Storage, StorageBackend};
+use alembic_head::types::error::AppError;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+
+type Objects = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+#[tokio::test]
+async fn s3_backend_round_trip() {
+    let endpoint = spawn_fake_s3();
+
+    tokio::task::spawn_blocking(move || {
+        let storage = S3Storage::new(
+            endpoint,
+            String::from("assets"),
+            String::from("us-east-1"),
+            String::from("minio"),
+            String::from("minio-secret"),
+        );
+
+        let source = std::env::temp_dir().join(format!("alembic-s3-{}", std::process::id()));
+        std::fs::write(&source, b"map bytes").unwrap();
+        storage.put_file("maps/spooled.png", &source).unwrap();
+        std::fs::remove_file(source).unwrap();
+
+        assert_eq!(storage.get("maps/spooled.png").unwrap(), b"map bytes");
+
+        storage.put("maps/windy glade.png", b"map bytes").unwrap();
+
+        assert!(storage.exists("maps/windy glade.png").unwrap());
+        assert_eq!(storage.get("maps/windy glade.png").unwrap(), b"map bytes");
+        assert_eq!(storage.size("maps/windy glade.png").unwrap(), 9);
+        assert_eq!(
+            storage.get_range("maps/windy glade.png", 4, 9).unwrap(),
+            b"bytes"
+        );
+
+        storage.delete("maps/windy glade.png").unwrap();
+        storage.delete("maps/windy glade.png").unwrap();
+
+        assert!(!storage.exists("maps/windy glade.png").unwrap());
+        assert!(matches!(
+            storage.get("maps/windy glade.png"),
+            Err(AppError::NotFound(_))
+        ));
+        assert!(matches!(
+            storage.get("../outside"),
+            Err(AppError::Validation(_))
+        ));
+    })
+    .await
+    .unwrap();
+}
+
+// Stands in for MinIO: keeps objects in memory and rejects unsigned requests
+async fn object(
+    objects: web::Data<Objects>,
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    body: web::Bytes,
+) -> HttpResponse {
+    let (bucket, key) = path.into_inner();
+    let name = format!("{}/{}", bucket, key);
+
+    let signed = req
+        .headers()
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value.starts_with("AWS4-HMAC-SHA256 Credential=minio/")
+                && value.contains("/us-east-1/s3/aws4_request")
+        });
+    let hashed = req
+        .headers()
+        .get("x-amz-content-sha256")
+        .and_then(|value| value.to_str().ok())
+        == Some(hex::encode(Sha256::digest(&body)).as_str());
+
+    if !signed || !hashed {
+        return HttpResponse::Forbidden().finish();
+    }
+
+    let mut objects = objects.lock().unwrap();
+    match req.method().as_str() {
+        "PUT" => {
+            objects.insert(name, body.to_vec());
+            HttpResponse::Ok().finish()
+        }
+        "GET" | "HEAD" => match objects.get(&name) {
+            Some(bytes) => match range(&req, bytes.len()) {
+                Some((start, end)) => {
+                    HttpResponse::PartialContent().body(bytes[start..end].to_vec())
+                }
+                None => HttpResponse::Ok().body(bytes.clone()),
+            },
+            None => HttpResponse::NotFound().finish(),
+        },
+        "DELETE" => match objects.remove(&name) {
+            Some(_) => HttpResponse::NoContent().finish(),
+            None => HttpResponse::NotFound().finish(),
+        },
+        _ => HttpResponse::MethodNotAllowed().finish(),
+    }
+}
+
+// only the bytes=a-b form the backend sends
+fn range(req: &HttpRequest, len: usize) -> Option<(usize, usize)> {
+    let spec = req.headers().get("range")?.to_str().ok()?;
+    let (start, end) = spec.strip_prefix("bytes=")?.split_once('-')?;
+    Some((start.parse().ok()?, len.min(end.parse::<usize>().ok()? + 1)))
+}
+
+fn spawn_fake_s3() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
+    let port = listener.local_addr().unwrap().port();
+    let objects = web::Data::new(Objects::default());
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(objects.clone())
+            .route("/{bucket}/{key:.*}", web::route().to(object))
+    })
+    .listen(listener)
+    .expect("Failed to bind address")
+    .run();
+
+    tokio::spawn(server);
+    format!("http://127.0.0.1:{}", port)
+}
@@ -0,0 +1,295 @@
+use alembic_head::handlers::connect;
+use alembic_head::handlers::creator::Creators;
+use alembic_head::handlers::user::User;
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use serde::Deserialize;
+use serde_json::Value;
+use std::env;
+use std::io::Cursor;
+use std::net::TcpListener;
+use std::time::Duration;
+
+#[derive(Deserialize)]
+struct Created {
+    id: i32,
+}
+
+#[tokio::test]
+async fn map_uploads_generate_thumbnails() {
+    let root = env::temp_dir().join(format!("alembic-thumbnails-{}", std::process::id()));
+    env::set_var("STORAGE_ROOT", &root);
+
+    let address = spawn_app();
+    let owner = cookie_client();
+    let conn = &mut connect::establish_connection();
+
+    let creator = sign_in_creator(&owner, &address, "thumbnail_creator").await;
+
+    let map_pack: Created = owner
+        .post(format!("{}/map_packs", &address))
+        .header("Content-Type", "application/json")
+        .body(format!(
+            r#"{{"creator_id": {}, "title": "Thumbnailed", "thumb": "thumb.jpg",
+                "summary": "Small", "directory": "thumbnailed", "is_free": false,
+                "main_image": "image.jpg", "price": 500, "currency": "USD"}}"#,
+            creator.id
+        ))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse map pack");
+
+    let map: Created = owner
+        .post(format!("{}/map_packs/{}/maps", &address, map_pack.id))
+        .header("Content-Type", "application/json")
+        .body(
+            r#"{"title": "Overlook", "thumb": "thumb.jpg", "summary": "High up",
+                "height": null, "width": null, "main_image": "image.jpg", "is_free": false}"#,
+        )
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse map");
+
+    let mut png = Cursor::new(Vec::new());
+    DynamicImage::ImageRgba8(RgbaImage::new(600, 300))
+        .write_to(&mut png, ImageFormat::Png)
+        .unwrap();
+
+    let response = upload(
+        owner.put(format!("{}/maps/{}/files/file", &address, map.id)),
+        "image/png",
+        png.get_ref(),
+    )
+    .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let uploaded: Value = response.json().await.expect("Failed to parse upload");
+
+    // rendered in the background, so wait for them to appear
+    let mut thumbnails = Vec::new();
+    for _ in 0..50 {
+        thumbnails = reqwest::get(format!("{}/maps/{}/thumbnails", &address, map.id))
+            .await
+            .expect("Failed to send request")
+            .json::<Vec<Value>>()
+            .await
+            .expect("Failed to parse thumbnails");
+        if !thumbnails.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    let rendered = thumbnails
+        .iter()
+        .map(|thumbnail| {
+            (
+                thumbnail["size"].as_i64().unwrap(),
+                thumbnail["format"].as_str().unwrap(),
+                thumbnail["width"].as_i64().unwrap(),
+                thumbnail["height"].as_i64().unwrap(),
+            )
+        })
+        .collect::<Vec<(i64, &str, i64, i64)>>();
+
+    assert_eq!(
+        rendered,
+        [
+            (256, "png", 256, 128),
+            (256, "webp", 256, 128),
+            (512, "png", 512, 256),
+            (512, "webp", 512, 256)
+        ]
+    );
+
+    for thumbnail in &thumbnails {
+        assert!(root.join(thumbnail["file_key"].as_str().unwrap()).exists());
+    }
+
+    // thumbnails are public, the map itself is only handed out behind a link
+    let response = reqwest::get(format!(
+        "{}/images/{}",
+        &address,
+        thumbnails[1]["file_key"].as_str().unwrap()
+    ))
+    .await
+    .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["content-type"], "image/webp");
+    assert!(response.headers()["content-disposition"]
+        .to_str()
+        .unwrap()
+        .starts_with("inline"));
+
+    let response = reqwest::get(format!(
+        "{}/images/{}",
+        &address,
+        uploaded["file_key"].as_str().unwrap()
+    ))
+    .await
+    .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 404);
+
+    let stored: Value = reqwest::get(format!("{}/map_packs/{}", &address, map_pack.id))
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse map pack");
+
+    assert_eq!(stored["maps"][0]["thumb"], thumbnails[0]["file_key"]);
+    assert_eq!(stored["maps"][0]["width"], 600);
+    assert_eq!(stored["maps"][0]["height"], 300);
+
+    // gallery images are stored beside the thumbnails and just as public
+    let mut gallery_png = Cursor::new(Vec::new());
+    DynamicImage::ImageRgba8(RgbaImage::new(40, 30))
+        .write_to(&mut gallery_png, ImageFormat::Png)
+        .unwrap();
+
+    let response = upload(
+        reqwest::Client::new().post(format!(
+            "{}/map_packs/{}/images/upload",
+            &address, map_pack.id
+        )),
+        "image/png",
+        gallery_png.get_ref(),
+    )
+    .await;
+
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = upload(
+        owner.post(format!(
+            "{}/map_packs/{}/images/upload",
+            &address, map_pack.id
+        )),
+        "application/pdf",
+        b"%PDF-1.7",
+    )
+    .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = upload(
+        owner.post(format!(
+            "{}/map_packs/{}/images/upload",
+            &address, map_pack.id
+        )),
+        "image/png",
+        gallery_png.get_ref(),
+    )
+    .await;
+
+    assert_eq!(response.status().as_u16(), 201);
+
+    let gallery: Vec<Value> =
+        reqwest::get(format!("{}/map_packs/{}/images", &address, map_pack.id))
+            .await
+            .expect("Failed to send request")
+            .json()
+            .await
+            .expect("Failed to parse gallery");
+
+    assert_eq!(gallery.len(), 1);
+    let file = gallery[0]["file"].as_str().unwrap();
+    assert!(file.starts_with(&format!("map_packs/{}/gallery-", map_pack.id)));
+
+    let response = reqwest::get(format!("{}/images/{}", &address, file))
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["content-type"], "image/png");
+    assert_eq!(
+        response.bytes().await.expect("Failed to read image"),
+        gallery_png.get_ref().as_slice()
+    );
+
+    let response = owner
+        .delete(format!("{}/map_packs/{}", &address, map_pack.id))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 204);
+
+    Creators::destroy(conn, creator.id).unwrap();
+    User::destroy(conn, creator.id).unwrap();
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+// A single part multipart body, built by hand
+async fn upload(
+    request: reqwest::RequestBuilder,
+    content_type: &str,
+    bytes: &[u8],
+) -> reqwest::Response {
+    let boundary = "alembic-thumbnail-boundary";
+    let mut body = format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"upload\"\r\nContent-Type: {}\r\n\r\n",
+        boundary, content_type
+    )
+    .into_bytes();
+    body.extend_from_slice(bytes);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+    request
+        .header(
+            "Content-Type",
+            format!("multipart/form-data; boundary={}", boundary),
+        )
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to send request")
+}
+
+fn cookie_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .expect("Failed to build client")
+}
+
+async fn sign_in_creator(client: &reqwest::Client, address: &str, username: &str) -> Created {
+    client
+        .post(format!("{}/register", address))
+        .header("Content-Type", "application/json")
+        .body(format!(
+            r#"{{"username": "{}", "email": "{}@gmail.com", "password": "correct horse battery"}}"#,
+            username, username
+        ))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    client
+        .post(format!("{}/creators", address))
+        .header("Content-Type", "application/json")
+        .body(r#"{"other_name": "Galator", "default_name": "other"}"#)
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse creator")
+}
+
+fn spawn_app() -> String {
+    env::set_var("PAYMENT_PROVIDER", "fake");
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
+    let port = listener.local_addr().unwrap().port();
+    let server =
+        alembic_head::run(listener, connect::establish_pool()).expect("Failed to bind address");
+
+    tokio::spawn(server);
+    format!("http://127.0.0.1:{}", port)
+}
@@ -0,0 +1,584 @@
+use alembic_head::handlers::connect;
+use alembic_head::handlers::creator::Creators;
+use alembic_head::handlers::user::User;
+use lopdf::{dictionary, Bookmark, Document, Object, ObjectId};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::io::{Cursor, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+
+#[derive(Deserialize)]
+struct Created {
+    id: i32,
+}
+
+#[tokio::test]
+async fn creators_upload_book_files() {
+    let root = env::temp_dir().join(format!("alembic-uploads-{}", std::process::id()));
+    env::set_var("STORAGE_ROOT", &root);
+
+    let address = spawn_app();
+    let owner = cookie_client();
+    let other = cookie_client();
+    let conn = &mut connect::establish_connection();
+
+    let creator = sign_in_creator(&owner, &address, "uploading_creator").await;
+    let intruder = sign_in_creator(&other, &address, "snooping_creator").await;
+
+    let book: Created = owner
+        .post(format!("{}/books", &address))
+        .header("Content-Type", "application/json")
+        .body(format!(
+            r#"{{"creator_id": {}, "title": "Uploaded", "thumb": "thumb.jpg",
+                "summary": "Mine", "file": "file.pdf", "pages": 10,
+                "main_image": "image.jpg", "is_free": false, "price": 1999, "currency": "USD"}}"#,
+            creator.id
+        ))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse book");
+
+    let url = format!("{}/books/{}/files/file", &address, book.id);
+    let first = pdf(3);
+
+    let response = upload(&other, &url, "application/pdf", &first).await;
+
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = upload(&owner, &url, "image/png", &first).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = upload(&owner, &url, "application/pdf", b"not a pdf").await;
+
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = upload(&owner, &url, "application/pdf", &first).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+
+    let uploaded: Value = response.json().await.expect("Failed to parse upload");
+    let first_key = String::from(uploaded["file_key"].as_str().unwrap());
+
+    assert_eq!(uploaded["sha256"], hex::encode(Sha256::digest(&first)));
+    assert_eq!(uploaded["size"], first.len());
+    assert!(first_key.starts_with(&format!("books/{}/file-", book.id)));
+    assert_eq!(std::fs::read(root.join(&first_key)).unwrap(), first);
+
+    let stored: Value = owner
+        .get(format!("{}/books/{}", &address, book.id))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse book");
+
+    assert_eq!(stored["file"], first_key);
+    assert_eq!(stored["pages"], 3);
+
+    let response = upload(&owner, &url, "application/pdf", &pdf(4)).await;
+    let uploaded: Value = response.json().await.expect("Failed to parse upload");
+
+    let second_key = uploaded["file_key"].to_owned();
+
+    assert_ne!(second_key, first_key);
+    assert!(!PathBuf::from(&root).join(&first_key).exists());
+
+    let formats = format!("{}/books/{}/formats", &address, book.id);
+    let response = upload(
+        &owner,
+        &format!("{}/epub", &formats),
+        "application/epub+zip",
+        &epub("Another Book Entirely"),
+    )
+    .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = upload(
+        &other,
+        &format!("{}/epub", &formats),
+        "application/epub+zip",
+        &epub("Uploaded: The Sequel"),
+    )
+    .await;
+
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = upload(
+        &owner,
+        &format!("{}/epub", &formats),
+        "application/epub+zip",
+        &epub("Uploaded: The Sequel"),
+    )
+    .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+
+    let uploaded: Value = response.json().await.expect("Failed to parse format");
+    let epub_key = String::from(uploaded["file_key"].as_str().unwrap());
+
+    assert!(epub_key.starts_with(&format!("books/{}/epub-", book.id)));
+    assert_eq!(uploaded["author"], "Nao Kotani");
+    assert_eq!(uploaded["chapters"][1]["title"], "Monsters");
+
+    let response = upload(
+        &owner,
+        &format!("{}/print_pdf", &formats),
+        "application/pdf",
+        &pdf(2),
+    )
+    .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+
+    let listed: Value = owner
+        .get(&formats)
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse formats");
+
+    assert_eq!(
+        listed
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|format| format["format"].as_str().unwrap())
+            .collect::<Vec<&str>>(),
+        ["pdf", "epub", "print_pdf"]
+    );
+    assert_eq!(listed[0]["file_key"], second_key);
+
+    // the book's page shows its contents, fonts and formats to anyone
+    let page: Value = reqwest::get(format!("{}/books/{}/page", &address, book.id))
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse page");
+    let details = &page["details"]["book"];
+
+    assert_eq!(details["page_count"], 4);
+    assert_eq!(
+        details["contents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| (
+                entry["title"].as_str().unwrap(),
+                entry["page"].as_i64().unwrap()
+            ))
+            .collect::<Vec<(&str, i64)>>(),
+        [
+            ("Chapter 1", 1),
+            ("Chapter 2", 2),
+            ("Chapter 3", 3),
+            ("Chapter 4", 4)
+        ]
+    );
+    assert_eq!(details["fonts"][0]["name"], "Helvetica");
+    assert_eq!(details["fonts"][0]["is_embedded"], false);
+    assert_eq!(
+        details["formats"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|format| format["format"].as_str().unwrap())
+            .collect::<Vec<&str>>(),
+        ["pdf", "epub", "print_pdf"]
+    );
+
+    let response = owner
+        .delete(format!("{}/pdf", &formats))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = owner
+        .delete(format!("{}/epub", &formats))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 204);
+    assert!(!PathBuf::from(&root).join(&epub_key).exists());
+
+    let listed: Value = owner
+        .get(&formats)
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse formats");
+
+    assert_eq!(listed.as_array().unwrap().len(), 2);
+
+    let stl: Created = owner
+        .post(format!("{}/stls", &address))
+        .header("Content-Type", "application/json")
+        .body(format!(
+            r#"{{"creator_id": {}, "title": "Pyramid", "thumb": "thumb.jpg",
+                "summary": "Pointy", "file": "pyramid.stl", "is_free": false,
+                "main_image": "image.jpg", "price": 499, "currency": "USD"}}"#,
+            creator.id
+        ))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse stl");
+
+    let url = format!("{}/stls/{}/files/file", &address, stl.id);
+    let response = upload(&owner, &url, "model/stl", b"solid nothing\nendsolid\n").await;
+
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = upload(&owner, &url, "model/stl", &tetrahedron()).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+
+    let models = format!("{}/stls/{}/models", &address, stl.id);
+    let obj = b"o tetrahedron\nv 0 0 0\nv 10 0 0\nv 0 10 0\nv 0 0 10\n\
+        f 1 3 2\nf 1 2 4\nf 1 4 3\nf 2 3 4\n";
+    let response = multipart(
+        owner.post(format!("{}?supported=true&label=With%20supports", &models)),
+        "model/obj",
+        obj,
+    )
+    .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = multipart(owner.post(&models), "model/obj", b"v 0 0 0\nf 1 2 3\n").await;
+
+    assert_eq!(response.status().as_u16(), 400);
+
+    let listing: serde_json::Value = owner
+        .get(&models)
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse models");
+
+    assert_eq!(listing["supported"][0]["label"], "With supports");
+    assert_eq!(listing["supported"][0]["format"], "obj");
+    assert_eq!(listing["supported"][0]["is_watertight"], true);
+    assert_eq!(listing["unsupported"][0]["format"], "stl");
+    assert_eq!(listing["unsupported"][0]["triangles"], 4);
+
+    let response = owner
+        .delete(format!("{}/{}", &models, listing["supported"][0]["id"]))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 204);
+
+    let album: Created = owner
+        .post(format!("{}/albums", &address))
+        .header("Content-Type", "application/json")
+        .body(format!(
+            r#"{{"creator_id": {}, "title": "Tavern Songs", "thumb": "thumb.jpg",
+                "summary": "Loud", "directory": "tavern/", "is_free": false,
+                "main_image": "image.jpg", "price": 999, "currency": "USD"}}"#,
+            creator.id
+        ))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse album");
+
+    let track: Created = owner
+        .post(format!("{}/albums/{}/tracks", &address, album.id))
+        .header("Content-Type", "application/json")
+        .body(r#"{"title": "Last Call", "main_image": "track.jpg"}"#)
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse track");
+
+    let url = format!("{}/tracks/{}/files/file", &address, track.id);
+    let response = upload(&owner, &url, "audio/wav", b"RIFF\x04\x00\x00\x00WAVE").await;
+
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = upload(&owner, &url, "audio/wav", &wav(3)).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+
+    // the album's page shows how long it runs
+    let page: Value = reqwest::get(format!("{}/albums/{}/page", &address, album.id))
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse page");
+    let details = &page["details"]["album"];
+
+    assert_eq!(details["runtime_ms"], 3000);
+    assert_eq!(details["tracks"][0]["format"], "wav");
+    assert_eq!(details["tracks"][0]["sample_rate"], 8000);
+
+    let response = owner
+        .delete(format!("{}/albums/{}", &address, album.id))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 204);
+
+    let response = owner
+        .delete(format!("{}/stls/{}", &address, stl.id))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 204);
+
+    let response = owner
+        .delete(format!("{}/books/{}", &address, book.id))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status().as_u16(), 204);
+
+    Creators::destroy(conn, creator.id).unwrap();
+    Creators::destroy(conn, intruder.id).unwrap();
+    User::destroy(conn, creator.id).unwrap();
+    User::destroy(conn, intruder.id).unwrap();
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+// A plain PDF of blank pages
+fn pdf(pages: usize) -> Vec<u8> {
+    let mut document = Document::with_version("1.5");
+    let pages_id = document.new_object_id();
+    let helvetica = document.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+    });
+    let page_ids = (0..pages)
+        .map(|_| {
+            document.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "Resources" => dictionary! { "Font" => dictionary! { "F1" => helvetica } },
+            })
+        })
+        .collect::<Vec<ObjectId>>();
+    let kids = page_ids
+        .iter()
+        .map(|&page| page.into())
+        .collect::<Vec<Object>>();
+    document.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => kids,
+            "Count" => pages as i64,
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+        }),
+    );
+    let catalog = document.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+    document.trailer.set("Root", catalog);
+
+    // a chapter to a page
+    for (number, &page) in page_ids.iter().enumerate() {
+        document.add_bookmark(
+            Bookmark::new(format!("Chapter {}", number + 1), [0.0; 3], 0, page),
+            None,
+        );
+    }
+    if let Some(outline) = document.build_outline() {
+        document
+            .get_dictionary_mut(catalog)
+            .unwrap()
+            .set("Outlines", outline);
+    }
+
+    let mut bytes = Vec::new();
+    document.save_to(&mut bytes).unwrap();
+    bytes
+}
+
+// An EPUB 3 with two chapters
+fn epub(title: &str) -> Vec<u8> {
+    let parts = [
+        ("mimetype", String::from("application/epub+zip")),
+        (
+            "META-INF/container.xml",
+            String::from(
+                r#"<container xmlns="urn:oasis:names:tc:opendocument:xmlns:container"><rootfiles>
+                <rootfile full-path="content.opf" media-type="application/oebps-package+xml"/>
+                </rootfiles></container>"#,
+            ),
+        ),
+        (
+            "content.opf",
+            format!(
+                r#"<package xmlns="http://www.idpf.org/2007/opf" version="3.0">
+                <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+                <dc:title>{}</dc:title><dc:creator>Nao Kotani</dc:creator></metadata>
+                <manifest><item id="nav" href="nav.xhtml" properties="nav"/></manifest>
+                </package>"#,
+                title
+            ),
+        ),
+        (
+            "nav.xhtml",
+            String::from(
+                r#"<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+                <body><nav epub:type="toc"><ol>
+                <li><a href="spells.xhtml">Spells</a></li><li><a href="monsters.xhtml">Monsters</a></li>
+                </ol></nav></body></html>"#,
+            ),
+        ),
+    ];
+
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, text) in parts {
+        zip.start_file(name, zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(text.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+// Seconds of 8kHz mono 8 bit silence
+fn wav(seconds: u32) -> Vec<u8> {
+    let mut bytes = b"RIFF".to_vec();
+    bytes.extend_from_slice(&(36 + 8000 * seconds).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&[1, 0, 1, 0]);
+    bytes.extend_from_slice(&8000u32.to_le_bytes());
+    bytes.extend_from_slice(&8000u32.to_le_bytes());
+    bytes.extend_from_slice(&[1, 0, 8, 0]);
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&(8000 * seconds).to_le_bytes());
+    bytes.extend(std::iter::repeat_n(128, 8000 * seconds as usize));
+    bytes
+}
+
+// A closed binary STL
+fn tetrahedron() -> Vec<u8> {
+    let corners = [
+        [0.0f32, 0.0, 0.0],
+        [10.0, 0.0, 0.0],
+        [0.0, 10.0, 0.0],
+        [0.0, 0.0, 10.0],
+    ];
+    let faces = [[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]];
+
+    let mut bytes = vec![0; 80];
+    bytes.extend_from_slice(&(faces.len() as u32).to_le_bytes());
+    for face in faces {
+        bytes.extend_from_slice(&[0; 12]);
+        for corner in face {
+            for value in corners[corner] {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        bytes.extend_from_slice(&[0; 2]);
+    }
+    bytes
+}
+
+async fn upload(
+    client: &reqwest::Client,
+    url: &str,
+    content_type: &str,
+    bytes: &[u8],
+) -> reqwest::Response {
+    multipart(client.put(url), content_type, bytes).await
+}
+
+// A single part multipart body, built by hand
+async fn multipart(
+    request: reqwest::RequestBuilder,
+    content_type: &str,
+    bytes: &[u8],
+) -> reqwest::Response {
+    let boundary = "alembic-upload-boundary";
+    let mut body = format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"upload\"\r\nContent-Type: {}\r\n\r\n",
+        boundary, content_type
+    )
+    .into_bytes();
+    body.extend_from_slice(bytes);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+    request
+        .header(
+            "Content-Type",
+            format!("multipart/form-data; boundary={}", boundary),
+        )
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to send request")
+}
+
+fn cookie_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .expect("Failed to build client")
+}
+
+async fn sign_in_creator(client: &reqwest::Client, address: &str, username: &str) -> Created {
+    client
+        .post(format!("{}/register", address))
+        .header("Content-Type", "application/json")
+        .body(format!(
+            r#"{{"username": "{}", "email": "{}@gmail.com", "password": "correct horse battery"}}"#,
+            username, username
+        ))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    client
+        .post(format!("{}/creators", address))
+        .header("Content-Type", "application/json")
+        .body(r#"{"other_name": "Galator", "default_name": "other"}"#)
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse creator")
+}
+
+fn spawn_app() -> String {
+    env::set_var("PAYMENT_PROVIDER", "fake");
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
+    let port = listener.local_addr().unwrap().port();
+    let server =
+        alembic_head::run(listener, connect::establish_pool()).expect("Failed to bind address");
+
+    tokio::spawn(server);
+    format!("http://127.0.0.1:{}", port)
+}